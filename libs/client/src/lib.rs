@@ -131,6 +131,8 @@ pub struct KanidmClientBuilder {
     use_system_proxies: bool,
     /// Where to store auth tokens, only use in testing!
     token_cache_path: Option<String>,
+    /// Headers sent with every request, on top of the ones the client sets itself.
+    default_headers: reqwest::header::HeaderMap,
 }
 
 impl Display for KanidmClientBuilder {
@@ -179,6 +181,7 @@ fn test_kanidmclientbuilder_display() {
         request_timeout: Some(69),
         use_system_proxies: true,
         token_cache_path: Some(CLIENT_TOKEN_CACHE.to_string()),
+        default_headers: reqwest::header::HeaderMap::new(),
     };
     println!("testclient {}", testclient);
     assert!(testclient.to_string().contains("verify_ca: true"));
@@ -227,6 +230,7 @@ impl KanidmClientBuilder {
             request_timeout: None,
             use_system_proxies: true,
             token_cache_path: None,
+            default_headers: reqwest::header::HeaderMap::new(),
         }
     }
 
@@ -284,6 +288,7 @@ impl KanidmClientBuilder {
             request_timeout,
             use_system_proxies,
             token_cache_path,
+            default_headers,
         } = self;
         // Process and apply all our options if they exist.
         let address = match kcc.uri {
@@ -309,6 +314,7 @@ impl KanidmClientBuilder {
             request_timeout,
             use_system_proxies,
             token_cache_path,
+            default_headers,
         })
     }
 
@@ -446,6 +452,14 @@ impl KanidmClientBuilder {
         }
     }
 
+    /// Send these headers with every request, such as to be told apart by a proxy.
+    pub fn default_headers(self, default_headers: reqwest::header::HeaderMap) -> Self {
+        KanidmClientBuilder {
+            default_headers,
+            ..self
+        }
+    }
+
     #[allow(clippy::result_unit_err)]
     pub fn add_root_certificate_filepath(self, ca_path: &str) -> Result<Self, ClientError> {
         //Okay we have a ca to add. Let's read it in and setup.
@@ -514,7 +528,8 @@ impl KanidmClientBuilder {
             .cookie_store(true)
             .cookie_provider(client_cookies.clone())
             .danger_accept_invalid_hostnames(!self.verify_hostnames)
-            .danger_accept_invalid_certs(!self.verify_ca);
+            .danger_accept_invalid_certs(!self.verify_ca)
+            .default_headers(self.default_headers.clone());
 
         let client_builder = match self.use_system_proxies {
             true => client_builder,
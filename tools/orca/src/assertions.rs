@@ -0,0 +1,184 @@
+// Check the server against its states once the load has stopped, so that a long churn run is
+// also a regression test of the integrity of the data.
+use crate::error::Error;
+use crate::kani::KanidmOrcaClient;
+use crate::profile::Assertion;
+use crate::state::{PreflightState, State};
+
+use std::collections::BTreeSet;
+
+/// What the assertions of a cohort check the server against, taken from its state before
+/// the run consumes it.
+pub struct Expected {
+    cohort: String,
+    assertions: Vec<Assertion>,
+    client: KanidmOrcaClient,
+    // The persons that should exist.
+    persons: BTreeSet<String>,
+    groups: Vec<(String, BTreeSet<String>)>,
+}
+
+impl Expected {
+    /// The client is connected up front, so that a run that can't be checked fails before
+    /// it starts rather than after.
+    pub async fn new(cohort: &str, state: &State) -> Result<Option<Self>, Error> {
+        if state.profile.assertions().is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Expected {
+            cohort: cohort.to_string(),
+            assertions: state.profile.assertions().to_vec(),
+            client: KanidmOrcaClient::new(&state.profile).await?,
+            persons: state
+                .persons
+                .iter()
+                .filter(|person| !matches!(person.preflight_state, PreflightState::Absent))
+                .map(|person| person.username.clone())
+                .collect(),
+            groups: state
+                .groups
+                .iter()
+                .filter(|group| !matches!(group.preflight_state, PreflightState::Absent))
+                .map(|group| (group.name.to_string(), group.members.clone()))
+                .collect(),
+        }))
+    }
+
+    // What is wrong with the server, as far as the assertion is concerned.
+    async fn violations(&self, assertion: Assertion) -> Result<Vec<String>, Error> {
+        let mut violations = Vec::new();
+        match assertion {
+            Assertion::PersonsPresent => {
+                for username in self.persons.iter() {
+                    if !self.client.person_exists(username).await? {
+                        violations.push(format!("person {username} is missing"));
+                    }
+                }
+            }
+            Assertion::GroupMembersMatchState => {
+                for (group_name, expected) in self.groups.iter() {
+                    let Some(members) = self.client.group_members(group_name).await? else {
+                        violations.push(format!("group {group_name} is missing"));
+                        continue;
+                    };
+                    let members = members.into_iter().collect::<BTreeSet<_>>();
+                    let missing = expected.difference(&members).collect::<Vec<_>>();
+                    let unexpected = members.difference(expected).collect::<Vec<_>>();
+                    if !missing.is_empty() || !unexpected.is_empty() {
+                        violations.push(format!(
+                            "group {group_name} is missing {missing:?} and has {unexpected:?} too"
+                        ));
+                    }
+                }
+            }
+            Assertion::RecycleBinClean => {
+                let names = self.client.recycle_bin_names().await?;
+                violations.extend(
+                    recycled_test_entries(&names, &self.persons, &self.groups)
+                        .map(|name| format!("{name} is in the recycle bin")),
+                );
+            }
+        }
+        Ok(violations)
+    }
+}
+
+// The entries the actors create are named after the person they run as, such as
+// `<person>-churn-group-3`.
+fn recycled_test_entries<'a>(
+    names: &'a [String],
+    persons: &'a BTreeSet<String>,
+    groups: &'a [(String, BTreeSet<String>)],
+) -> impl Iterator<Item = &'a String> {
+    names.iter().filter(|name| {
+        persons.contains(name.as_str())
+            || groups.iter().any(|(group_name, _)| group_name == *name)
+            || name
+                .match_indices('-')
+                .any(|(index, _)| persons.contains(&name[..index]))
+    })
+}
+
+/// Check every assertion of the cohorts, and fail if any of them doesn't hold.
+pub async fn verify(expected: Vec<Expected>) -> Result<(), Error> {
+    let mut failed = false;
+    for cohort in expected.iter() {
+        for assertion in cohort.assertions.iter() {
+            let violations = cohort.violations(*assertion).await?;
+            if violations.is_empty() {
+                info!(cohort = %cohort.cohort, "Assertion {assertion:?} holds");
+                continue;
+            }
+            failed = true;
+            for violation in violations.iter() {
+                error!(cohort = %cohort.cohort, "Assertion {assertion:?} failed: {violation}");
+            }
+        }
+    }
+
+    if failed {
+        Err(Error::AssertionFailed)
+    } else {
+        Ok(())
+    }
+}
+
+/// The outcome of a run whose assertions were checked. A run that broke the data failed,
+/// whatever its latencies were, so a failed assertion outranks a missed latency goal.
+pub fn outcome(measured: Result<(), Error>, asserted: Result<(), Error>) -> Result<(), Error> {
+    match (measured, asserted) {
+        (Ok(()) | Err(Error::LatencyGoalMissed), Err(err)) => Err(err),
+        (measured, _) => measured,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{outcome, recycled_test_entries};
+    use crate::error::Error;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_recycled_test_entries_are_found_by_name() {
+        let persons = ["ada".to_string(), "grace".to_string()]
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        let groups = vec![("role_people_self_read_profile".to_string(), BTreeSet::new())];
+        let names = [
+            "ada",
+            "grace-churn-group-3",
+            "role_people_self_read_profile",
+            "adam-churn-group-1",
+            "someone_else",
+        ]
+        .map(str::to_string);
+
+        let recycled = recycled_test_entries(&names, &persons, &groups).collect::<Vec<_>>();
+        assert_eq!(
+            recycled,
+            [
+                "ada",
+                "grace-churn-group-3",
+                "role_people_self_read_profile"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed_assertion_outranks_missed_latency_goal() {
+        assert!(matches!(
+            outcome(Err(Error::LatencyGoalMissed), Err(Error::AssertionFailed)),
+            Err(Error::AssertionFailed)
+        ));
+        assert!(matches!(
+            outcome(Err(Error::LatencyGoalMissed), Ok(())),
+            Err(Error::LatencyGoalMissed)
+        ));
+        assert!(matches!(
+            outcome(Ok(()), Err(Error::AssertionFailed)),
+            Err(Error::AssertionFailed)
+        ));
+        assert!(outcome(Ok(()), Ok(())).is_ok());
+    }
+}
@@ -0,0 +1,222 @@
+// Run the actors, their scheduling and the statistics of a profile against a stub that
+// completes every action at once, so that the events per second orca can sustain on a host is
+// known before a slow run is blamed on the server.
+use crate::clock::ManualClock;
+use crate::error::Error;
+use crate::generate;
+use crate::model::{TransitionAction, TransitionResult};
+use crate::profile::{Profile, ScriptedProperties};
+use crate::run::{self, Cohort, CohortEventRecord, EventDetail, EventRecord, Signal};
+use crate::state::Person;
+use crate::stats::{RunSummary, TestPhase};
+
+use crossbeam::queue::{ArrayQueue, SegQueue};
+use reqwest::header::HeaderMap;
+use tokio::sync::broadcast;
+
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+// The event a request of the action would have been recorded as.
+fn stub_event(action: TransitionAction) -> EventDetail {
+    match action {
+        TransitionAction::Login => EventDetail::Login,
+        TransitionAction::Logout => EventDetail::Logout,
+        TransitionAction::PrivilegeReauth => EventDetail::PersonReauth,
+        TransitionAction::WriteAttributePersonMail => EventDetail::PersonSetSelfMail,
+        TransitionAction::ReadSelfAccount => EventDetail::PersonGetSelfAccount,
+        TransitionAction::ReadSelfMemberOf => EventDetail::PersonGetSelfMemberOf,
+        TransitionAction::WriteSelfPassword => EventDetail::PersonSetSelfPassword,
+        TransitionAction::CreateGroup => EventDetail::PersonCreateGroup,
+        TransitionAction::AddGroupMembers => EventDetail::PersonAddGroupMembers,
+        TransitionAction::RenameGroup => EventDetail::PersonRenameGroup,
+        TransitionAction::DeleteGroup => EventDetail::PersonDeleteGroup,
+        TransitionAction::CreatePerson => EventDetail::PersonCreatePerson,
+        TransitionAction::ToggleAccountValidity => EventDetail::PersonSetAccountValidity,
+        TransitionAction::LoginValidityCanary => EventDetail::LoginRejectedValidity,
+        TransitionAction::EnrollMfa => EventDetail::PersonEnrollTotp,
+        TransitionAction::ReadGroup => EventDetail::PersonGetGroup,
+        TransitionAction::RemoveGroupMembers => EventDetail::PersonRemoveGroupMembers,
+        TransitionAction::UnixAuth => EventDetail::UnixAuth,
+        TransitionAction::Whoami => EventDetail::PersonWhoami,
+        TransitionAction::Oauth2Authorise => EventDetail::Oauth2Authorise,
+        TransitionAction::Oauth2ExchangeCode => EventDetail::Oauth2Token,
+        TransitionAction::Oauth2Userinfo => EventDetail::Oauth2Userinfo,
+        TransitionAction::ReadStatus => EventDetail::Status,
+        TransitionAction::ReadOauth2Discovery => EventDetail::Oauth2Discovery,
+        TransitionAction::ReadOauth2Jwks => EventDetail::Oauth2Jwks,
+    }
+}
+
+/// How much load orca generated and aggregated against the stub.
+pub struct BenchmarkResult {
+    pub actors: usize,
+    pub elapsed: Duration,
+    /// Every event the actors pushed, including the schedule lag and the transitions.
+    pub generated: u64,
+    /// The requests the statistics counted in the measured window.
+    pub aggregated: usize,
+    /// How long the statistics took to process the events once the window ended.
+    pub aggregation_time: Duration,
+}
+
+impl BenchmarkResult {
+    pub fn events_per_second(&self) -> f64 {
+        self.generated as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+// The loop of an actor, with the waits left out and every action completing at once.
+#[allow(clippy::too_many_arguments)]
+async fn actor_stub(
+    person: Arc<Person>,
+    cohort: usize,
+    groups: Arc<[String]>,
+    stats_queue: Arc<SegQueue<CohortEventRecord>>,
+    mut actor_rx: broadcast::Receiver<Signal>,
+    rng_seed: u64,
+    warmup_time: Duration,
+    scripted: Arc<ScriptedProperties>,
+) -> Result<u64, Error> {
+    // The models that look at the time see it go by as if they had waited.
+    let clock = ManualClock::new();
+    let mut model = person.model.as_dyn_object(
+        rng_seed,
+        Vec::new(),
+        &person.username,
+        warmup_time,
+        clock.clone(),
+        &scripted,
+        &HeaderMap::new(),
+    )?;
+
+    let username: Arc<str> = Arc::from(person.username.as_str());
+    let mut generated = 0;
+    let mut push_event = |start: Instant, details: EventDetail| {
+        stats_queue.push(CohortEventRecord {
+            cohort,
+            groups: groups.clone(),
+            credential: Some(person.credential.kind()),
+            person: Some(username.clone()),
+            phase: None,
+            target: None,
+            record: EventRecord {
+                start,
+                duration: Instant::now().saturating_duration_since(start),
+                details,
+            },
+        });
+        generated += 1;
+    };
+
+    while let Err(broadcast::error::TryRecvError::Empty) = actor_rx.try_recv() {
+        let intended = Instant::now();
+        let delay = model.next_delay(&person).unwrap_or_default();
+        clock.advance(delay);
+        push_event(intended, EventDetail::ScheduleLag);
+
+        let Some(action) = model.pending_action() else {
+            return Err(Error::actor(&person.username, Error::NoPendingAction));
+        };
+        let start = Instant::now();
+        push_event(start, EventDetail::Transition(action));
+        model.assume_transition(TransitionResult::Ok)?;
+        push_event(start, stub_event(action));
+
+        // The actors share the threads of the runtime, as they do in a run.
+        tokio::task::yield_now().await;
+    }
+    Ok(generated)
+}
+
+/// Run `actors` of the persons of the profile against the stub for `duration`, with their
+/// events aggregated by the statistics of a run.
+pub async fn run(
+    profile: Profile,
+    actors: usize,
+    duration: Duration,
+) -> Result<BenchmarkResult, Error> {
+    let seed = profile.seed();
+    let warmup_time = profile.warmup_time();
+    let scripted = Arc::new(profile.scripted().clone());
+    let state = generate::generate(profile, None)?;
+    let mut cohorts = vec![Cohort {
+        name: "benchmark".to_string(),
+        state,
+    }];
+
+    let stats_queue = Arc::new(SegQueue::new());
+    let stats_ctrl = Arc::new(ArrayQueue::new(4));
+    let summary: Arc<OnceLock<RunSummary>> = Arc::new(OnceLock::new());
+    let mut data_collector = run::statistics(&cohorts, summary.clone());
+    let persons = std::mem::take(&mut cohorts[0].state.persons);
+    let c_stats_queue = stats_queue.clone();
+    let c_stats_ctrl = stats_ctrl.clone();
+    let stats_task =
+        tokio::task::spawn_blocking(move || data_collector.run(c_stats_queue, c_stats_ctrl, false));
+
+    let (actor_tx, _actor_rx) = broadcast::channel(1);
+    let groups = &cohorts[0].state.groups;
+    let mut tasks = Vec::new();
+    for person in persons.into_iter().take(actors) {
+        let person_groups = groups
+            .iter()
+            .filter(|group| group.members.contains(&person.username))
+            .map(|group| group.name.to_string())
+            .collect();
+        tasks.push(tokio::spawn(actor_stub(
+            Arc::new(person),
+            0,
+            person_groups,
+            stats_queue.clone(),
+            actor_tx.subscribe(),
+            seed,
+            warmup_time,
+            scripted.clone(),
+        )));
+    }
+    let actors = tasks.len();
+    info!(actors, ?duration, "Running the actors against the stub");
+
+    let start = Instant::now();
+    if stats_ctrl.push(TestPhase::Start(start)).is_err() {
+        return Err(Error::Crossbeam("start"));
+    }
+    tokio::time::sleep(duration).await;
+    let end = Instant::now();
+    run::end_statistics(&stats_ctrl, end)?;
+
+    actor_tx
+        .send(Signal::Stop)
+        .map_err(|broadcast_err| Error::tokio("signal the actors to stop", broadcast_err))?;
+    let mut generated = 0;
+    for task in tasks {
+        generated += task
+            .await
+            .map_err(|tokio_err| Error::tokio("wait for an actor", tokio_err))??;
+    }
+
+    stats_task
+        .await
+        .map_err(|tokio_err| Error::tokio("wait for the statistics collector", tokio_err))??;
+    let aggregation_time = end.elapsed();
+
+    let aggregated = summary
+        .get()
+        .map(|summary| {
+            summary.read_events
+                + summary.write_events
+                + summary.auth_events
+                + summary.error_events
+                + summary.rejected_events
+        })
+        .unwrap_or_default();
+
+    Ok(BenchmarkResult {
+        actors,
+        elapsed: end.duration_since(start),
+        generated,
+        aggregated,
+        aggregation_time,
+    })
+}
@@ -0,0 +1,148 @@
+use crate::error::Error;
+use crate::kani::KanidmOrcaClient;
+use crate::stats::percentile;
+
+use rand::distributions::{Alphanumeric, DistString};
+use rand::thread_rng;
+
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const BULK_PERSON_PREFIX: &str = "orca_bulk";
+
+/// The shape of a bulk creation of persons.
+pub struct BulkCreateOptions {
+    pub person_count: u64,
+    /// How many requests are in flight at once.
+    pub concurrency: u64,
+    /// How many persons are created by each request.
+    pub batch_size: u64,
+}
+
+/// The throughput of a bulk creation of persons.
+pub struct BulkCreateResult {
+    created: u64,
+    failed: u64,
+    elapsed: Duration,
+    // The time each successful request took, in seconds.
+    batch_times: Vec<f64>,
+}
+
+impl BulkCreateResult {
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+
+    pub fn persons_per_second(&self) -> f64 {
+        self.created as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl Display for BulkCreateResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "persons created:         {}", self.created)?;
+        writeln!(f, "persons failed:          {}", self.failed)?;
+        writeln!(
+            f,
+            "elapsed:                 {:.3} s",
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "batch latency:           95%: {:.3} s, max: {:.3} s",
+            percentile(&self.batch_times, 0.95).unwrap_or_default(),
+            percentile(&self.batch_times, 1.).unwrap_or_default()
+        )?;
+        write!(
+            f,
+            "persons/sec:             {:.1}",
+            self.persons_per_second()
+        )
+    }
+}
+
+/// Create persons as fast as the server allows, and measure how fast that is. The persons
+/// get a prefix that is unique to the invocation, and are left on the server.
+pub async fn execute(
+    client: Arc<KanidmOrcaClient>,
+    options: &BulkCreateOptions,
+) -> Result<BulkCreateResult, Error> {
+    if options.concurrency == 0 || options.batch_size == 0 {
+        return Err(Error::invalid(
+            "the concurrency and the batch size must be at least 1",
+        ));
+    }
+
+    let prefix = format!(
+        "{}_{}",
+        BULK_PERSON_PREFIX,
+        Alphanumeric
+            .sample_string(&mut thread_rng(), 6)
+            .to_lowercase()
+    );
+    info!(
+        "Creating {} persons named {prefix}_<n>, {} at a time in batches of {}",
+        options.person_count, options.concurrency, options.batch_size
+    );
+
+    // Each worker takes the next batch until all the persons have been handed out.
+    let next_index = Arc::new(AtomicU64::new(0));
+    let start = Instant::now();
+
+    let workers = (0..options.concurrency)
+        .map(|_| {
+            let client = client.clone();
+            let next_index = next_index.clone();
+            let prefix = prefix.clone();
+            let person_count = options.person_count;
+            let batch_size = options.batch_size;
+            tokio::spawn(async move {
+                let mut batch_times = Vec::new();
+                let mut failed = 0;
+                loop {
+                    let first = next_index.fetch_add(batch_size, Ordering::Relaxed);
+                    if first >= person_count {
+                        break;
+                    }
+                    let persons = (first..(first + batch_size).min(person_count))
+                        .map(|index| {
+                            let username = format!("{prefix}_{index}");
+                            let display_name = username.replace('_', " ");
+                            (username, display_name)
+                        })
+                        .collect::<Vec<_>>();
+
+                    let batch_start = Instant::now();
+                    match client.person_create_batch(&persons).await {
+                        Ok(()) => batch_times.push(batch_start.elapsed().as_secs_f64()),
+                        Err(err) => {
+                            error!("{}", err.report());
+                            failed += persons.len() as u64;
+                        }
+                    }
+                }
+                (batch_times, failed)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut batch_times = Vec::new();
+    let mut failed = 0;
+    for worker in workers {
+        let (mut worker_batch_times, worker_failed) = worker
+            .await
+            .map_err(|tokio_err| Error::tokio("wait for a worker", tokio_err))?;
+        batch_times.append(&mut worker_batch_times);
+        failed += worker_failed;
+    }
+
+    let elapsed = start.elapsed();
+    Ok(BulkCreateResult {
+        created: options.person_count - failed,
+        failed,
+        elapsed,
+        batch_times,
+    })
+}
@@ -0,0 +1,314 @@
+// Save the progress of a run as it goes, so that a run that crashed or was killed can carry
+// on with the rest of its test time rather than start over.
+//
+// The checkpoint is a file of JSON lines, with the events of the measured window and, every
+// so often, how far into the window the run got. When the run resumes, only the events up to
+// the last of those are taken back, and the checkpoint is rewritten without the rest.
+use crate::distributed::WireEvent;
+use crate::error::Error;
+use crate::run::{self, Cohort, CohortEventRecord, Signal};
+use crate::stats::{DataCollector, RunSummary, TestPhase};
+
+use crossbeam::queue::{ArrayQueue, SegQueue};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Line {
+    /// An event of the measured window, timed from its start.
+    Event(WireEvent),
+    /// How far into the measured window the run got. The events before it are all saved.
+    Elapsed { elapsed_ms: u64 },
+}
+
+/// What a run saved of itself before it stopped.
+#[derive(Debug, Default)]
+struct Progress {
+    elapsed: Duration,
+    events: Vec<WireEvent>,
+}
+
+fn read(reader: impl BufRead) -> Result<Progress, Error> {
+    let lines = reader
+        .lines()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|io_err| Error::io("read the checkpoint", io_err))?;
+
+    let mut progress = Progress::default();
+    for (index, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(Line::Event(event)) => progress.events.push(event),
+            Ok(Line::Elapsed { elapsed_ms }) => {
+                progress.elapsed = Duration::from_millis(elapsed_ms)
+            }
+            // The run may have been killed halfway through writing the last line.
+            Err(json_err) if index + 1 == lines.len() => {
+                warn!(
+                    ?json_err,
+                    "Leaving out the incomplete last line of the checkpoint"
+                );
+            }
+            Err(json_err) => {
+                return Err(Error::json(
+                    format!("read line {} of the checkpoint", index + 1),
+                    json_err,
+                ));
+            }
+        }
+    }
+
+    let elapsed = progress.elapsed;
+    progress
+        .events
+        .retain(|event| event.start_offset() <= elapsed);
+    Ok(progress)
+}
+
+fn write_line(writer: &mut impl Write, line: &Line) -> Result<(), Error> {
+    serde_json::to_writer(&mut *writer, line)
+        .map_err(|json_err| Error::json("write to the checkpoint", json_err))?;
+    writer
+        .write_all(b"\n")
+        .map_err(|io_err| Error::io("write to the checkpoint", io_err))
+}
+
+fn write_elapsed(writer: &mut BufWriter<File>, elapsed: Duration) -> Result<(), Error> {
+    write_line(
+        writer,
+        &Line::Elapsed {
+            elapsed_ms: elapsed.as_millis() as u64,
+        },
+    )?;
+    writer
+        .flush()
+        .map_err(|io_err| Error::io("write to the checkpoint", io_err))
+}
+
+// Load the progress saved at `path`, if any, and start the checkpoint over with only that
+// progress in it. Returns the progress, and the checkpoint to carry on writing to.
+fn open(path: &Path) -> Result<(Progress, BufWriter<File>), Error> {
+    let progress = match File::open(path) {
+        Ok(file) => read(BufReader::new(file))?,
+        Err(io_err) if io_err.kind() == std::io::ErrorKind::NotFound => Progress::default(),
+        Err(io_err) => return Err(Error::file("open the checkpoint", path, io_err)),
+    };
+
+    // Written aside and then moved over the checkpoint, so that it's never left half written.
+    let mut rewritten = path.as_os_str().to_owned();
+    rewritten.push(".tmp");
+    let rewritten = PathBuf::from(rewritten);
+    let file = File::create(&rewritten)
+        .map_err(|io_err| Error::file("create the checkpoint", &rewritten, io_err))?;
+    let mut writer = BufWriter::new(file);
+    for event in progress.events.iter() {
+        write_line(&mut writer, &Line::Event(event.clone()))?;
+    }
+    write_elapsed(&mut writer, progress.elapsed)?;
+    std::fs::rename(&rewritten, path)
+        .map_err(|io_err| Error::file("replace the checkpoint", path, io_err))?;
+
+    let file = OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map_err(|io_err| Error::file("open the checkpoint", path, io_err))?;
+    Ok((progress, BufWriter::new(file)))
+}
+
+/// Saves the events of the measured window on their way to the statistics, and how far into
+/// the window the run got every `interval`. The events of the run being resumed are handed
+/// to the statistics as if they'd just happened, in the part of the window they were in.
+struct Checkpointer {
+    statistics: Box<dyn DataCollector + Send>,
+    // Gone once it failed to be written to.
+    writer: Option<BufWriter<File>>,
+    interval: Duration,
+    resumed: Vec<WireEvent>,
+}
+
+// The run goes on if the checkpoint can't be written, there's just less of it to resume.
+fn save(
+    writer: &mut Option<BufWriter<File>>,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), Error>,
+) {
+    if let Some(inner) = writer.as_mut() {
+        if let Err(err) = write(inner) {
+            error!(
+                "{}, the progress of the run isn't saved to the checkpoint from now on",
+                err.report()
+            );
+            *writer = None;
+        }
+    }
+}
+
+impl DataCollector for Checkpointer {
+    fn run(
+        &mut self,
+        stats_queue: Arc<SegQueue<CohortEventRecord>>,
+        ctrl: Arc<ArrayQueue<TestPhase>>,
+        dump_raw_data: bool,
+    ) -> Result<(), Error> {
+        let Checkpointer {
+            statistics,
+            writer,
+            interval,
+            resumed,
+        } = self;
+
+        let statistics_queue = Arc::new(SegQueue::new());
+        let statistics_ctrl = Arc::new(ArrayQueue::new(4));
+
+        thread::scope(|scope| {
+            let c_statistics_queue = statistics_queue.clone();
+            let c_statistics_ctrl = statistics_ctrl.clone();
+            let collector = scope.spawn(move || {
+                statistics.run(c_statistics_queue, c_statistics_ctrl, dump_raw_data)
+            });
+
+            let mut started = None;
+            let mut last_saved = Instant::now();
+            let forwarded = loop {
+                // Checked first, so that the events up to the end of the test are still saved.
+                let phase = ctrl.pop();
+                if let Some(TestPhase::Start(start)) = phase {
+                    started = Some(start);
+                    // The statistics drop the events that reach them before they've started, as
+                    // those are the warmup's, so they're started before the resumed events and
+                    // the measured ones are passed on.
+                    if statistics_ctrl.push(TestPhase::Start(start)).is_err() {
+                        break Err(Error::Crossbeam("start"));
+                    }
+                    for event in resumed.drain(..) {
+                        statistics_queue.push(event.into_record(start));
+                    }
+                }
+
+                while let Some(cohort_event_record) = stats_queue.pop() {
+                    if let Some(started) =
+                        started.filter(|started| cohort_event_record.record.start >= *started)
+                    {
+                        let event = WireEvent::new(&cohort_event_record, started);
+                        save(writer, |writer| write_line(writer, &Line::Event(event)));
+                    }
+                    statistics_queue.push(cohort_event_record);
+                }
+
+                let phase = match phase {
+                    // Forwarded already.
+                    Some(TestPhase::Start(_)) => continue,
+                    Some(phase) => phase,
+                    None => {
+                        if let Some(started) = started {
+                            if last_saved.elapsed() >= *interval {
+                                save(writer, |writer| write_elapsed(writer, started.elapsed()));
+                                last_saved = Instant::now();
+                            }
+                        }
+                        thread::sleep(Duration::from_millis(100));
+                        continue;
+                    }
+                };
+
+                let elapsed = match (started, &phase) {
+                    (Some(started), TestPhase::End(end)) => Some(end.duration_since(started)),
+                    (Some(started), TestPhase::StopNow) => Some(started.elapsed()),
+                    _ => None,
+                };
+                if let Some(elapsed) = elapsed {
+                    save(writer, |writer| write_elapsed(writer, elapsed));
+                }
+
+                let signal = phase.signal();
+                if statistics_ctrl.push(phase).is_err() {
+                    break Err(Error::Crossbeam(signal));
+                }
+                break Ok(());
+            };
+
+            // The statistics can't be left waiting for a phase that won't come.
+            if forwarded.is_err() {
+                let _ = statistics_ctrl.force_push(TestPhase::StopNow);
+            }
+            let collected = collector
+                .join()
+                .map_err(|_| Error::invalid("the statistics collector panicked"))?;
+            forwarded.and(collected)
+        })
+    }
+}
+
+/// Run the test, saving its progress to the checkpoint at `path` every `interval`. If the
+/// checkpoint already holds the progress of an earlier attempt at the run, the run carries
+/// on from there, with the rest of its test time and its load schedule where it was.
+pub async fn execute(
+    cohorts: Vec<Cohort>,
+    control_rx: broadcast::Receiver<Signal>,
+    path: &Path,
+    interval: Duration,
+    summary: Arc<OnceLock<RunSummary>>,
+) -> Result<(), Error> {
+    let (progress, writer) = open(path)?;
+    if !progress.elapsed.is_zero() {
+        info!(
+            elapsed_s = progress.elapsed.as_secs(),
+            events = progress.events.len(),
+            "Resuming the run from the checkpoint"
+        );
+    }
+
+    let checkpointer = Checkpointer {
+        statistics: run::statistics(&cohorts, summary),
+        writer: Some(writer),
+        interval,
+        resumed: progress.events,
+    };
+    run::execute_with_collector(
+        cohorts,
+        control_rx,
+        Box::new(checkpointer),
+        true,
+        progress.elapsed,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::read;
+    use std::time::Duration;
+
+    #[test]
+    fn test_progress_leaves_out_what_was_not_saved() {
+        let event = |start_us| {
+            format!(
+                r#"{{"Event":{{"cohort":0,"groups":[],"credential":null,"phase":null,"start_us":{start_us},"duration_us":1000,"details":"Login"}}}}"#
+            )
+        };
+        let checkpoint = [
+            event(1_000_000),
+            r#"{"Elapsed":{"elapsed_ms":1500}}"#.to_string(),
+            event(1_400_000),
+            // Past the last time the run saved how far it got.
+            event(1_600_000),
+            r#"{"Event":{"cohort":0,"gro"#.to_string(),
+        ]
+        .join("\n");
+
+        let Ok(progress) = read(checkpoint.as_bytes()) else {
+            panic!("The checkpoint is read");
+        };
+        assert_eq!(progress.elapsed, Duration::from_millis(1500));
+        assert_eq!(progress.events.len(), 2);
+
+        // Only the last line may be incomplete.
+        let checkpoint = format!("{}\n{}", r#"{"Event":{"cohort":0,"gro"#, event(1));
+        assert!(read(checkpoint.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+// Take a profile through to a pass or a fail in one go, so that a release pipeline runs orca as
+// a single step: generate the state, populate the server, run the test, write the summary and
+// compare it to the baseline.
+use crate::error::Error;
+use crate::generate;
+use crate::kani::KanidmOrcaClient;
+use crate::populate::{self, PreflightOptions};
+use crate::profile::Profile;
+use crate::run::{self, Cohort, Signal};
+use crate::state::State;
+use crate::stats::RunSummary;
+use crate::summary::{self, SummaryFormat};
+
+use tokio::sync::broadcast;
+
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+pub struct CiOptions {
+    /// Where the state and the summaries are written.
+    pub output_dir: PathBuf,
+    pub baseline: Option<RunSummary>,
+    /// How much worse than the baseline, in percent, the run may do before it fails.
+    pub max_regression_pct: f64,
+}
+
+pub async fn run(
+    profile: Profile,
+    options: &CiOptions,
+    control_rx: broadcast::Receiver<Signal>,
+) -> Result<(), Error> {
+    std::fs::create_dir_all(&options.output_dir).map_err(|io_err| {
+        Error::file("create the output directory", &options.output_dir, io_err)
+    })?;
+
+    info!("Generating the state");
+    let client = KanidmOrcaClient::new(&profile).await?;
+    let state = generate::populate(&client, profile, None).await?;
+    let state_path = options.output_dir.join("state.json");
+    state.write_to_path(&state_path)?;
+
+    info!("Populating the server");
+    populate::preflight(state, &PreflightOptions::default()).await?;
+
+    // Read back, so that the run is of the state as it was saved.
+    let state = State::try_from(state_path.as_path())?;
+    info!("Running the test");
+    let summary = Arc::new(OnceLock::new());
+    let result = run::execute_with_summary(
+        vec![Cohort {
+            name: "ci".to_string(),
+            state,
+        }],
+        control_rx,
+        summary.clone(),
+    )
+    .await;
+
+    // A run that missed its goals or failed its assertions still has a summary.
+    let Some(summary) = summary.get() else {
+        return result;
+    };
+    summary::write(
+        &options.output_dir.join("summary.json"),
+        SummaryFormat::Json,
+        summary,
+        None,
+    )?;
+    summary::write(
+        &options.output_dir.join("summary.md"),
+        SummaryFormat::Markdown,
+        summary,
+        options.baseline.as_ref(),
+    )?;
+    result?;
+
+    let Some(baseline) = &options.baseline else {
+        info!("The run passed, there's no baseline to compare it to");
+        return Ok(());
+    };
+    let regressions = summary::regressions(summary, baseline, options.max_regression_pct);
+    if regressions.is_empty() {
+        info!("The run passed, and didn't regress from the baseline");
+        return Ok(());
+    }
+    for regression in regressions.iter() {
+        error!("The run regressed from the baseline: {regression}");
+    }
+    Err(Error::Regressed)
+}
@@ -0,0 +1,53 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Where the models and the statistics get the current time from, so that their timing can
+/// be tested without sleeping.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The clock of the host, which the runner uses.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// A clock that only moves when it's told to, for the models to be walked through faster
+/// than they would run.
+pub struct ManualClock {
+    base: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> Arc<Self> {
+        Arc::new(ManualClock {
+            base: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        })
+    }
+
+    pub fn advance(&self, by: Duration) {
+        #[allow(clippy::unwrap_used)]
+        let mut elapsed = self.elapsed.lock().unwrap();
+        *elapsed += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        #[allow(clippy::unwrap_used)]
+        let elapsed = self.elapsed.lock().unwrap();
+        self.base + *elapsed
+    }
+}
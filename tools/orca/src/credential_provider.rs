@@ -0,0 +1,132 @@
+// Where the passwords of the persons come from. They're kept in the state by default, but the
+// state of a very large population can leave them out, and have them resolved only when
+// they're needed, as populate sets them and as the actors start.
+use crate::error::Error;
+use crate::generate;
+use crate::profile::{CredentialProviderProperties, CredentialSource};
+use crate::state::Person;
+
+use async_trait::async_trait;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use std::sync::Arc;
+
+#[async_trait]
+pub trait CredentialProvider: Send + Sync {
+    /// The password of the person, if their credential has one.
+    async fn password(&self, person: &Person) -> Result<Option<String>, Error>;
+}
+
+pub fn from_properties(
+    properties: &CredentialProviderProperties,
+    seed: u64,
+) -> Arc<dyn CredentialProvider> {
+    match properties.source {
+        CredentialSource::State => Arc::new(StateProvider),
+        CredentialSource::Derived => Arc::new(Derived { seed }),
+        CredentialSource::Command => Arc::new(Command {
+            command: properties.command.clone(),
+        }),
+    }
+}
+
+/// Resolve the password of the person through the provider, once however many actors run as
+/// them, for `Person::password` to return from then on.
+pub async fn resolve(provider: &dyn CredentialProvider, person: &Person) -> Result<(), Error> {
+    person
+        .provided_password
+        .get_or_try_init(|| provider.password(person))
+        .await
+        .map(|_| ())
+}
+
+struct StateProvider;
+
+#[async_trait]
+impl CredentialProvider for StateProvider {
+    async fn password(&self, person: &Person) -> Result<Option<String>, Error> {
+        Ok(person.credential.password().map(str::to_string))
+    }
+}
+
+struct Derived {
+    seed: u64,
+}
+
+// The hasher of std isn't guaranteed to stay the same between releases, and the password has
+// to come out the same when populate sets it and when the actors log in with it.
+fn derive_password(seed: u64, username: &str) -> String {
+    let hash = username
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325 ^ seed, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    generate::random_password(&mut ChaCha8Rng::seed_from_u64(hash))
+}
+
+#[async_trait]
+impl CredentialProvider for Derived {
+    async fn password(&self, person: &Person) -> Result<Option<String>, Error> {
+        Ok(person
+            .credential
+            .password()
+            .map(|_| derive_password(self.seed, &person.username)))
+    }
+}
+
+struct Command {
+    command: Vec<String>,
+}
+
+#[async_trait]
+impl CredentialProvider for Command {
+    async fn password(&self, person: &Person) -> Result<Option<String>, Error> {
+        if person.credential.password().is_none() {
+            return Ok(None);
+        }
+        let Some((program, args)) = self.command.split_first() else {
+            return Err(Error::profile(
+                "the credential provider has no command to run",
+            ));
+        };
+
+        let output = tokio::process::Command::new(program)
+            .args(args)
+            .arg(&person.username)
+            .output()
+            .await
+            .map_err(|io_err| {
+                Error::io(
+                    format!("run the credential command {:?}", self.command),
+                    io_err,
+                )
+            })?;
+        if !output.status.success() {
+            return Err(Error::invalid(format!(
+                "the credential command failed for '{}' with {}",
+                person.username, output.status
+            )));
+        }
+
+        match String::from_utf8_lossy(&output.stdout).lines().next() {
+            Some(plain) if !plain.is_empty() => Ok(Some(plain.to_string())),
+            _ => Err(Error::invalid(format!(
+                "the credential command printed no password for '{}'",
+                person.username
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::derive_password;
+
+    #[test]
+    fn test_derived_passwords_are_stable_per_person() {
+        assert_eq!(derive_password(7, "alice"), derive_password(7, "alice"));
+        assert_ne!(derive_password(7, "alice"), derive_password(7, "bob"));
+        assert_ne!(derive_password(7, "alice"), derive_password(8, "alice"));
+    }
+}
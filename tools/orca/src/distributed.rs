@@ -0,0 +1,472 @@
+// Spread the actors of a run over several orca instances. The coordinator shards the persons
+// of the cohorts between the workers that connect to it, starts them together, and collects
+// the events they stream back into the statistics of the run.
+//
+// The messages are lines of JSON over a plain TCP connection.
+use crate::error::Error;
+use crate::run::{self, Cohort, CohortEventRecord, EventDetail, EventRecord, Signal};
+use crate::state::{Credential, Person, State};
+use crate::stats::{DataCollector, RunSummary, TestPhase};
+
+use crossbeam::queue::{ArrayQueue, SegQueue};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, Lines};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often a worker sends the events its actors recorded since the last time.
+const FORWARD_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Message {
+    /// The share of the persons the worker runs. It's followed by a line for each cohort,
+    /// with its name and the state holding the persons of the worker.
+    Shard {
+        worker: usize,
+        cohorts: usize,
+    },
+    /// The worker read its shard, and is ready to start.
+    Ready,
+    /// Start the actors. The time of the events is counted from here.
+    Start,
+    Events(Vec<WireEvent>),
+    /// Stop the actors, the test is over or was interrupted.
+    Stop,
+    /// The worker sent all of its events.
+    Done,
+}
+
+/// An event as it's sent to the coordinator or saved to a checkpoint, timed from when the
+/// coordinator started the workers, or from the start of the measured window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct WireEvent {
+    cohort: usize,
+    groups: Vec<String>,
+    credential: Option<String>,
+    #[serde(default)]
+    person: Option<String>,
+    phase: Option<String>,
+    #[serde(default)]
+    target: Option<String>,
+    start_us: u64,
+    duration_us: u64,
+    details: EventDetail,
+}
+
+impl WireEvent {
+    pub(crate) fn new(cohort_event_record: &CohortEventRecord, started: Instant) -> Self {
+        let CohortEventRecord {
+            cohort,
+            groups,
+            credential,
+            person,
+            phase,
+            target,
+            record,
+        } = cohort_event_record;
+        WireEvent {
+            cohort: *cohort,
+            groups: groups.to_vec(),
+            credential: credential.map(str::to_string),
+            person: person.as_ref().map(|person| person.to_string()),
+            phase: phase.as_ref().map(|phase| phase.to_string()),
+            target: target.as_ref().map(|target| target.to_string()),
+            start_us: record.start.saturating_duration_since(started).as_micros() as u64,
+            duration_us: record.duration.as_micros() as u64,
+            details: record.details.clone(),
+        }
+    }
+
+    /// How long after the time it's counted from the event started.
+    pub(crate) fn start_offset(&self) -> Duration {
+        Duration::from_micros(self.start_us)
+    }
+
+    pub(crate) fn into_record(self, started: Instant) -> CohortEventRecord {
+        CohortEventRecord {
+            cohort: self.cohort,
+            groups: Arc::from(self.groups),
+            credential: self.credential.as_deref().and_then(Credential::kind_named),
+            person: self.person.map(Arc::from),
+            phase: self.phase.map(Arc::from),
+            target: self.target.map(Arc::from),
+            record: EventRecord {
+                start: started + Duration::from_micros(self.start_us),
+                duration: Duration::from_micros(self.duration_us),
+                details: self.details,
+            },
+        }
+    }
+}
+
+/// Sends the events of the actors of a worker to the coordinator, in place of the
+/// statistics, which the coordinator keeps for the whole run.
+struct Forwarder {
+    started: Instant,
+    messages: mpsc::UnboundedSender<Message>,
+}
+
+impl DataCollector for Forwarder {
+    fn run(
+        &mut self,
+        stats_queue: Arc<SegQueue<CohortEventRecord>>,
+        ctrl: Arc<ArrayQueue<TestPhase>>,
+        _dump_raw_data: bool,
+    ) -> Result<(), Error> {
+        loop {
+            // Checked first, so that the events up to the end of the test are still sent.
+            let phase = ctrl.pop();
+
+            let events = std::iter::from_fn(|| stats_queue.pop())
+                .map(|cohort_event_record| WireEvent::new(&cohort_event_record, self.started))
+                .collect::<Vec<_>>();
+            if !events.is_empty() && self.messages.send(Message::Events(events)).is_err() {
+                return Err(Error::invalid("lost the connection to the coordinator"));
+            }
+
+            match phase {
+                Some(TestPhase::End(_)) | Some(TestPhase::StopNow) => return Ok(()),
+                Some(TestPhase::Start(_)) | None => thread::sleep(FORWARD_INTERVAL),
+            }
+        }
+    }
+}
+
+async fn send<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &impl Serialize,
+) -> Result<(), Error> {
+    let mut line = serde_json::to_vec(message)
+        .map_err(|json_err| Error::json("encode a message", json_err))?;
+    line.push(b'\n');
+    writer
+        .write_all(&line)
+        .await
+        .map_err(|io_err| Error::io("send a message", io_err))?;
+    writer
+        .flush()
+        .await
+        .map_err(|io_err| Error::io("send a message", io_err))
+}
+
+// The next message of the connection, or None once it's closed.
+async fn receive<T: DeserializeOwned>(
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+) -> Result<Option<T>, Error> {
+    let line = lines
+        .next_line()
+        .await
+        .map_err(|io_err| Error::io("receive a message", io_err))?;
+    line.map(|line| {
+        serde_json::from_str(&line).map_err(|json_err| Error::json("decode a message", json_err))
+    })
+    .transpose()
+}
+
+// Split the persons of each cohort between the workers, in turn. Only the first worker
+// runs the sync agent.
+fn shard(cohorts: &mut [Cohort], workers: usize) -> Vec<Vec<Vec<Person>>> {
+    cohorts
+        .iter_mut()
+        .map(|cohort| {
+            let mut shards = (0..workers).map(|_| Vec::new()).collect::<Vec<_>>();
+            for (index, person) in std::mem::take(&mut cohort.state.persons)
+                .into_iter()
+                .enumerate()
+            {
+                shards[index % workers].push(person);
+            }
+            shards
+        })
+        .collect()
+}
+
+/// Run the cohorts over `workers` orca instances, which connect to `listen`. The events
+/// of all the workers are reported together, as if the run had been local.
+pub async fn coordinate(
+    mut cohorts: Vec<Cohort>,
+    listen: SocketAddr,
+    workers: usize,
+    mut control_rx: broadcast::Receiver<Signal>,
+    summary: Arc<OnceLock<RunSummary>>,
+) -> Result<(), Error> {
+    let mut dyn_data_collector = run::statistics(&cohorts, summary);
+    let dump_raw_data = cohorts
+        .iter()
+        .any(|cohort| cohort.state.profile.dump_raw_data());
+    let warmup = cohorts
+        .iter()
+        .map(|cohort| cohort.state.profile.warmup_time())
+        .max()
+        .unwrap_or_default();
+    let test_time = cohorts
+        .iter()
+        .map(|cohort| cohort.state.profile.test_time())
+        .collect::<Option<Vec<_>>>()
+        .and_then(|test_times| test_times.into_iter().max());
+
+    let listener = TcpListener::bind(listen)
+        .await
+        .map_err(|io_err| Error::io(format!("listen for workers on {listen}"), io_err))?;
+    info!("Waiting for {workers} workers to connect on {listen}");
+
+    let mut connections = Vec::with_capacity(workers);
+    while connections.len() < workers {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted.map_err(|io_err| Error::io("accept a worker", io_err))?;
+                info!(%peer, "Worker {} connected", connections.len());
+                let (reader, writer) = stream.into_split();
+                connections.push((BufReader::new(reader).lines(), BufWriter::new(writer)));
+            }
+            _ = control_rx.recv() => return Err(Error::Interrupt),
+        }
+    }
+
+    let mut shards = shard(&mut cohorts, workers);
+    let mut sync_accounts = cohorts
+        .iter_mut()
+        .map(|cohort| cohort.state.sync_account.take())
+        .collect::<Vec<_>>();
+    for (worker, (_, writer)) in connections.iter_mut().enumerate() {
+        send(
+            writer,
+            &Message::Shard {
+                worker,
+                cohorts: cohorts.len(),
+            },
+        )
+        .await?;
+        for (index, cohort) in cohorts.iter_mut().enumerate() {
+            cohort.state.persons = std::mem::take(&mut shards[index][worker]);
+            cohort.state.sync_account = if worker == 0 {
+                sync_accounts[index].take()
+            } else {
+                None
+            };
+            send(writer, &(&cohort.name, &cohort.state)).await?;
+        }
+    }
+
+    for (worker, (lines, _)) in connections.iter_mut().enumerate() {
+        match receive(lines).await? {
+            Some(Message::Ready) => {}
+            message => {
+                return Err(Error::invalid(format!(
+                    "worker {worker} isn't ready, it sent {message:?}"
+                )));
+            }
+        }
+    }
+
+    let stats_queue = Arc::new(SegQueue::new());
+    let stats_ctrl = Arc::new(ArrayQueue::new(4));
+    let c_stats_queue = stats_queue.clone();
+    let c_stats_ctrl = stats_ctrl.clone();
+    let stats_task = tokio::task::spawn_blocking(move || {
+        dyn_data_collector.run(c_stats_queue, c_stats_ctrl, dump_raw_data)
+    });
+
+    let mut writers = Vec::with_capacity(workers);
+    let mut readers = Vec::with_capacity(workers);
+    for (_, writer) in connections.iter_mut() {
+        send(writer, &Message::Start).await?;
+    }
+    let started = Instant::now();
+    info!("Started {workers} workers");
+
+    for (worker, (mut lines, writer)) in connections.into_iter().enumerate() {
+        writers.push(writer);
+        let stats_queue = stats_queue.clone();
+        readers.push(tokio::spawn(async move {
+            loop {
+                match receive(&mut lines).await? {
+                    Some(Message::Events(events)) => {
+                        for event in events {
+                            stats_queue.push(event.into_record(started));
+                        }
+                    }
+                    Some(Message::Done) => return Ok(()),
+                    None => {
+                        warn!("Worker {worker} left before sending all of its events");
+                        return Ok(());
+                    }
+                    Some(message) => {
+                        return Err(Error::invalid(format!(
+                            "unexpected message from worker {worker}: {message:?}"
+                        )));
+                    }
+                }
+            }
+        }));
+    }
+
+    let test_result = run::execute_inner(
+        warmup,
+        test_time,
+        Duration::ZERO,
+        control_rx,
+        stats_ctrl.clone(),
+    )
+    .await;
+
+    info!("stopping workers");
+    for writer in writers.iter_mut() {
+        // A worker whose test time is over may have gone already.
+        let _ = send(writer, &Message::Stop).await;
+    }
+
+    // The window only ends once the workers sent the events that happened in it.
+    for reader in readers {
+        reader
+            .await
+            .map_err(|tokio_err| Error::tokio("wait for the events of a worker", tokio_err))??;
+    }
+
+    let test_result = test_result.and_then(|end| run::end_statistics(&stats_ctrl, end));
+    if stats_ctrl.push(TestPhase::StopNow).is_err() {
+        return Err(Error::Crossbeam("stop"));
+    }
+
+    stats_task
+        .await
+        .map_err(|tokio_err| Error::tokio("wait for the statistics collector", tokio_err))??;
+
+    test_result
+}
+
+/// Connect to the coordinator at `coordinator`, and run the share of the persons it gives
+/// this worker. The run stops when the coordinator says so, or on `control_tx`.
+pub async fn work(coordinator: &str, control_tx: broadcast::Sender<Signal>) -> Result<(), Error> {
+    let stream = TcpStream::connect(coordinator)
+        .await
+        .map_err(|io_err| Error::io(format!("connect to the coordinator {coordinator}"), io_err))?;
+    let (reader, writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut writer = BufWriter::new(writer);
+
+    let Some(Message::Shard { worker, cohorts }) = receive(&mut lines).await? else {
+        return Err(Error::invalid("the coordinator didn't send a shard"));
+    };
+    let mut shard = Vec::with_capacity(cohorts);
+    for _ in 0..cohorts {
+        let Some((name, state)) = receive::<(String, State)>(&mut lines).await? else {
+            return Err(Error::invalid(
+                "the coordinator left before sending the shard",
+            ));
+        };
+        shard.push(Cohort { name, state });
+    }
+    info!(
+        "Running {} persons as worker {worker}",
+        shard
+            .iter()
+            .map(|cohort| cohort.state.persons.len())
+            .sum::<usize>()
+    );
+
+    send(&mut writer, &Message::Ready).await?;
+    match receive(&mut lines).await? {
+        Some(Message::Start) => {}
+        message => {
+            return Err(Error::invalid(format!(
+                "the coordinator didn't start the run, it sent {message:?}"
+            )));
+        }
+    }
+    let started = Instant::now();
+
+    // The coordinator is only told about the events from here on.
+    let (messages_tx, mut messages_rx) = mpsc::unbounded_channel();
+    let sender = tokio::spawn(async move {
+        while let Some(message) = messages_rx.recv().await {
+            send(&mut writer, &message).await?;
+        }
+        Ok::<_, Error>(())
+    });
+
+    let c_control_tx = control_tx.clone();
+    let stopper = tokio::spawn(async move {
+        // The coordinator leaving stops the run as well.
+        let _ = receive::<Message>(&mut lines).await;
+        let _ = c_control_tx.send(Signal::Stop);
+    });
+
+    let forwarder = Forwarder {
+        started,
+        messages: messages_tx.clone(),
+    };
+    let result = run::execute_with_collector(
+        shard,
+        control_tx.subscribe(),
+        Box::new(forwarder),
+        worker == 0,
+        Duration::ZERO,
+    )
+    .await;
+
+    let _ = messages_tx.send(Message::Done);
+    drop(messages_tx);
+    sender.await.map_err(|tokio_err| {
+        Error::tokio(
+            "wait for the messages to the coordinator to be sent",
+            tokio_err,
+        )
+    })??;
+    stopper.abort();
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::WireEvent;
+    use crate::run::{CohortEventRecord, EventDetail, EventRecord, Failure};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_event_round_trips_between_instances() {
+        let worker_started = Instant::now();
+        let record = CohortEventRecord {
+            cohort: 1,
+            groups: Arc::from(["role_people_self_read".to_string()]),
+            credential: Some("password_totp"),
+            person: Some(Arc::from("person_7")),
+            phase: Some(Arc::from("spike")),
+            target: Some(Arc::from("https://idm-2.example.com")),
+            record: EventRecord {
+                start: worker_started + Duration::from_millis(1500),
+                duration: Duration::from_millis(20),
+                details: EventDetail::Error(Failure::new(EventDetail::Login, "Http", "busy")),
+            },
+        };
+
+        let line = serde_json::to_string(&WireEvent::new(&record, worker_started))
+            .expect("The event is encoded");
+        let event: WireEvent = serde_json::from_str(&line).expect("The event is decoded");
+
+        let coordinator_started = Instant::now();
+        let record = event.into_record(coordinator_started);
+        assert_eq!(record.cohort, 1);
+        assert_eq!(record.credential, Some("password_totp"));
+        assert_eq!(record.phase.as_deref(), Some("spike"));
+        assert_eq!(record.target.as_deref(), Some("https://idm-2.example.com"));
+        assert_eq!(
+            record.record.start,
+            coordinator_started + Duration::from_millis(1500)
+        );
+        let EventDetail::Error(failure) = record.record.details else {
+            panic!("The failure is kept");
+        };
+        assert_eq!(failure.class, "Http");
+        assert!(matches!(failure.event, EventDetail::Login));
+    }
+}
@@ -1,11 +1,248 @@
+// Each error says what orca was doing when it failed, and on which file, person or group, and
+// keeps what it failed with as its source. The message orca exits with then explains the
+// failure on its own, without going back through the logs.
+use crate::model::TransitionAction;
+
+use kanidm_client::ClientError;
+
+use std::borrow::Cow;
+use std::error::Error as StdError;
+use std::path::{Path, PathBuf};
+
+/// What orca was doing, worded to follow "unable to", or why something isn't valid.
+pub type Context = Cow<'static, str>;
+
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
-    Io,
-    SerdeToml,
-    SerdeJson,
-    KanidmClient,
-    ProfileBuilder,
-    Tokio,
+    /// A file couldn't be opened, created, read or written.
+    #[error("unable to {operation} '{}'", path.display())]
+    File {
+        operation: Context,
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// I/O on anything but a file, such as a connection or a command.
+    #[error("unable to {operation}")]
+    Io {
+        operation: Context,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("unable to {operation}")]
+    Csv {
+        operation: Context,
+        #[source]
+        source: csv::Error,
+    },
+    #[error("unable to {operation}")]
+    SerdeToml {
+        operation: Context,
+        #[source]
+        source: Box<dyn StdError + Send + Sync>,
+    },
+    #[error("unable to {operation}")]
+    SerdeJson {
+        operation: Context,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// A request to the server failed. The errors of the client only describe themselves
+    /// through `Debug`, so they're part of the message rather than its source.
+    #[error("unable to {operation}{}: {client_err:?}", of_entry(.entry))]
+    KanidmClient {
+        operation: Context,
+        /// The person or group the request was about.
+        entry: Option<String>,
+        client_err: Box<ClientError>,
+    },
+    /// A request orca makes without the kanidm client, as the browser of an OAuth2 client
+    /// would.
+    #[error("unable to {operation}")]
+    Http {
+        operation: Context,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// The actor of a person stopped, on what its model or the server did.
+    #[error("the actor of '{username}' failed")]
+    Actor {
+        username: String,
+        #[source]
+        source: Box<Error>,
+    },
+    /// The model was asked to perform or move on from an action it didn't decide on.
+    #[error("the model has no action pending")]
+    NoPendingAction,
+    #[error("the model doesn't perform {0:?}")]
+    UnsupportedAction(TransitionAction),
+    /// The profile asks for a test that can't be run.
+    #[error("invalid profile: {0}")]
+    InvalidProfile(Context),
+    /// The state, a message or anything else orca was handed isn't what it expects.
+    #[error("{0}")]
+    InvalidState(Context),
+    /// Another run is going on against the server, see `reservation`.
+    #[error("the server is reserved by {holder}, pass --force to run anyway")]
+    Reserved { holder: String },
+    #[error("unable to {operation}")]
+    Tokio {
+        operation: Context,
+        #[source]
+        source: Box<dyn StdError + Send + Sync>,
+    },
+    #[error("interrupted")]
     Interrupt,
-    Crossbeam,
-    InvalidState,
+    /// The statistics collector couldn't be told to start, end or stop.
+    #[error("unable to signal the statistics collector to {0}")]
+    Crossbeam(&'static str),
+    /// The run completed, but missed at least one of its latency goals.
+    #[error("the run missed at least one of its latency goals")]
+    LatencyGoalMissed,
+    /// The run completed, but the server failed at least one of the assertions checked
+    /// after it.
+    #[error("the server failed at least one of the assertions checked after the run")]
+    AssertionFailed,
+    /// The run completed, but did worse than its baseline by more than it was allowed to.
+    #[error("the run regressed from its baseline")]
+    Regressed,
+}
+
+fn of_entry(entry: &Option<String>) -> String {
+    entry
+        .as_ref()
+        .map(|entry| format!(" '{entry}'"))
+        .unwrap_or_default()
+}
+
+impl Error {
+    pub fn file(
+        operation: impl Into<Context>,
+        path: impl AsRef<Path>,
+        source: std::io::Error,
+    ) -> Self {
+        Error::File {
+            operation: operation.into(),
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn io(operation: impl Into<Context>, source: std::io::Error) -> Self {
+        Error::Io {
+            operation: operation.into(),
+            source,
+        }
+    }
+
+    pub fn csv(operation: impl Into<Context>, source: csv::Error) -> Self {
+        Error::Csv {
+            operation: operation.into(),
+            source,
+        }
+    }
+
+    pub fn toml(
+        operation: impl Into<Context>,
+        source: impl StdError + Send + Sync + 'static,
+    ) -> Self {
+        Error::SerdeToml {
+            operation: operation.into(),
+            source: Box::new(source),
+        }
+    }
+
+    pub fn json(operation: impl Into<Context>, source: serde_json::Error) -> Self {
+        Error::SerdeJson {
+            operation: operation.into(),
+            source,
+        }
+    }
+
+    pub fn client(operation: impl Into<Context>, source: ClientError) -> Self {
+        Error::KanidmClient {
+            operation: operation.into(),
+            entry: None,
+            client_err: Box::new(source),
+        }
+    }
+
+    /// A request about the person or group `entry` failed.
+    pub fn client_for(
+        operation: impl Into<Context>,
+        entry: impl Into<String>,
+        source: ClientError,
+    ) -> Self {
+        Error::KanidmClient {
+            operation: operation.into(),
+            entry: Some(entry.into()),
+            client_err: Box::new(source),
+        }
+    }
+
+    pub fn http(operation: impl Into<Context>, source: reqwest::Error) -> Self {
+        Error::Http {
+            operation: operation.into(),
+            source,
+        }
+    }
+
+    pub fn actor(username: impl Into<String>, source: Error) -> Self {
+        Error::Actor {
+            username: username.into(),
+            source: Box::new(source),
+        }
+    }
+
+    pub fn profile(reason: impl Into<Context>) -> Self {
+        Error::InvalidProfile(reason.into())
+    }
+
+    pub fn invalid(reason: impl Into<Context>) -> Self {
+        Error::InvalidState(reason.into())
+    }
+
+    pub fn tokio(
+        operation: impl Into<Context>,
+        source: impl StdError + Send + Sync + 'static,
+    ) -> Self {
+        Error::Tokio {
+            operation: operation.into(),
+            source: Box::new(source),
+        }
+    }
+
+    /// The error, followed by each of the errors that caused it in turn.
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut source = self.source();
+        while let Some(cause) = source {
+            report.push_str(": ");
+            report.push_str(&cause.to_string());
+            source = cause.source();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Error;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_report_explains_the_failure() {
+        let err = Error::actor(
+            "person_1",
+            Error::file(
+                "open the state",
+                "/tmp/state.json",
+                ErrorKind::NotFound.into(),
+            ),
+        );
+        assert_eq!(
+            err.report(),
+            "the actor of 'person_1' failed: unable to open the state '/tmp/state.json': entity not found"
+        );
+    }
 }
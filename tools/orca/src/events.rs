@@ -0,0 +1,179 @@
+use crate::error::Error;
+
+use csv::{Reader, Writer};
+use serde::{Deserialize, Serialize};
+
+use std::io::Write;
+use std::path::Path;
+
+/// Whether an event succeeded. Timeouts count as errors, since the action never completed.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    Error,
+    /// The server refused the action under its access policy.
+    Rejected,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+/// An event of the raw data a run saves with `dump_raw_data`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RawEvent {
+    // The identifiers are missing from the raw data of the runs from before there were any.
+    #[serde(default)]
+    pub run_id: Option<String>,
+    #[serde(default)]
+    pub cohort_id: Option<String>,
+    pub cohort: String,
+    #[serde(default)]
+    pub phase_id: Option<String>,
+    #[serde(default)]
+    pub phase: Option<String>,
+    pub time_from_start_ms: u64,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub event_id: Option<String>,
+    pub details: String,
+    #[serde(default)]
+    pub error_event_id: Option<String>,
+    #[serde(default)]
+    pub error_event: Option<String>,
+}
+
+impl RawEvent {
+    fn outcome(&self) -> Outcome {
+        match self.details.as_str() {
+            "Error" | "Timeout" => Outcome::Error,
+            "Rejected" => Outcome::Rejected,
+            _ => Outcome::Ok,
+        }
+    }
+}
+
+/// Which raw events to keep. Every filter that is set has to match.
+#[derive(Debug, Default)]
+pub struct EventQuery {
+    /// An event matches either by its type, or by the type of event it failed as.
+    pub events: Vec<String>,
+    pub from_s: Option<u64>,
+    pub to_s: Option<u64>,
+    pub outcome: Option<Outcome>,
+    pub cohort: Option<String>,
+    pub phase: Option<String>,
+}
+
+impl EventQuery {
+    fn matches(&self, event: &RawEvent) -> bool {
+        let event_matches = self.events.is_empty()
+            || self.events.iter().any(|wanted| {
+                [
+                    Some(&event.details),
+                    event.event_id.as_ref(),
+                    event.error_event.as_ref(),
+                    event.error_event_id.as_ref(),
+                ]
+                .into_iter()
+                .flatten()
+                .any(|name| name.eq_ignore_ascii_case(wanted))
+            });
+        event_matches
+            && self
+                .from_s
+                .map_or(true, |from_s| event.time_from_start_ms >= from_s * 1000)
+            && self
+                .to_s
+                .map_or(true, |to_s| event.time_from_start_ms < to_s * 1000)
+            && self
+                .outcome
+                .map_or(true, |outcome| event.outcome() == outcome)
+            && self
+                .cohort
+                .as_ref()
+                .map_or(true, |cohort| *cohort == event.cohort)
+            && self
+                .phase
+                .as_ref()
+                .map_or(true, |phase| event.phase.as_ref() == Some(phase))
+    }
+}
+
+/// Write the events of the raw data at `input` that match the query to `output`, and
+/// return how many there were.
+pub fn query(
+    input: &Path,
+    query: &EventQuery,
+    format: OutputFormat,
+    output: impl Write,
+) -> Result<usize, Error> {
+    let mut reader = Reader::from_path(input).map_err(|csv_err| {
+        Error::csv(format!("open the raw data '{}'", input.display()), csv_err)
+    })?;
+
+    let mut matched = Vec::new();
+    for event in reader.deserialize::<RawEvent>() {
+        let event = event.map_err(|csv_err| {
+            Error::csv(format!("read the raw data '{}'", input.display()), csv_err)
+        })?;
+        if query.matches(&event) {
+            matched.push(event);
+        }
+    }
+
+    match format {
+        OutputFormat::Csv => {
+            let mut wrt = Writer::from_writer(output);
+            for event in matched.iter() {
+                wrt.serialize(event)
+                    .map_err(|csv_err| Error::csv("write the events", csv_err))?;
+            }
+            wrt.flush()
+                .map_err(|io_err| Error::io("write the events", io_err))?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(output, &matched)
+                .map_err(|json_err| Error::json("write the events", json_err))?;
+        }
+    }
+
+    Ok(matched.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EventQuery, Outcome, RawEvent};
+
+    #[test]
+    fn test_query_matches_failed_events_in_time_range() {
+        let event = |time_from_start_ms, details: &str, error_event: Option<&str>| RawEvent {
+            run_id: None,
+            cohort_id: None,
+            cohort: "state".to_string(),
+            phase_id: None,
+            phase: None,
+            time_from_start_ms,
+            duration_ms: 10,
+            event_id: None,
+            details: details.to_string(),
+            error_event_id: None,
+            error_event: error_event.map(str::to_string),
+        };
+        let query = EventQuery {
+            events: vec!["login".to_string()],
+            from_s: Some(2400),
+            to_s: Some(2700),
+            outcome: Some(Outcome::Error),
+            ..Default::default()
+        };
+
+        assert!(query.matches(&event(2_500_000, "Error", Some("Login"))));
+        assert!(!query.matches(&event(2_500_000, "Login", None)));
+        assert!(!query.matches(&event(2_500_000, "Error", Some("PersonWhoami"))));
+        assert!(!query.matches(&event(2_700_000, "Error", Some("Login"))));
+    }
+}
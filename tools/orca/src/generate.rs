@@ -1,8 +1,13 @@
 use crate::error::Error;
 use crate::kani::KanidmOrcaClient;
 use crate::model::ActorRole;
-use crate::profile::Profile;
-use crate::state::{Credential, Flag, Group, GroupName, Person, PreflightState, State};
+use crate::profile::{CredentialSource, Profile};
+use crate::state::{
+    AccessControl, Credential, Flag, Group, GroupManagement, GroupName, ManagedGroup,
+    MfaEnrollment, MfaEnrollmentMethod, Model, Oauth2ClaimMap, Oauth2Client, Oauth2Login,
+    Oauth2ScopeMap, PasswordExpiry, Person, PreflightState, SharedLogin, State, SyncAccount,
+    SyncPerson, UnixCredential,
+};
 use hashbrown::HashMap;
 use rand::distributions::{Alphanumeric, DistString, Uniform};
 use rand::seq::{index, SliceRandom};
@@ -10,8 +15,29 @@ use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 
 use std::collections::BTreeSet;
+use uuid::Uuid;
 
 const PEOPLE_PREFIX: &str = "person";
+const ACCESS_CONTROL_PREFIX: &str = "orca_acp";
+const OAUTH2_CLIENT_PREFIX: &str = "orca_oauth2";
+const MANAGED_GROUP_PREFIX: &str = "orca_managed_group";
+const SYNC_ACCOUNT_NAME: &str = "orca_sync";
+const SYNC_PERSON_PREFIX: &str = "orca_sync_person";
+
+// How many persons are generated from each RNG stream. The streams don't depend on the
+// number of threads, so a seed generates the same persons whatever the thread count is.
+const PERSONS_PER_STREAM: u64 = 10_000;
+
+// The attributes our generated access controls may grant search rights to.
+const ACCESS_CONTROL_SEARCH_ATTRS: &[&str] = &[
+    "name",
+    "spn",
+    "displayname",
+    "mail",
+    "memberof",
+    "uuid",
+    "class",
+];
 
 // #[derive(Debug)]
 // pub struct PartialGroup {
@@ -24,15 +50,12 @@ fn random_name(prefix: &str, rng: &mut ChaCha8Rng) -> String {
     format!("{}_{}", prefix, suffix)
 }
 
-fn random_password(rng: &mut ChaCha8Rng) -> String {
+pub(crate) fn random_password(rng: &mut ChaCha8Rng) -> String {
     Alphanumeric.sample_string(rng, 24)
 }
 
-pub async fn populate(_client: &KanidmOrcaClient, profile: Profile) -> Result<State, Error> {
-    // IMPORTANT: We have to perform these steps in order so that the RNG is deterministic between
-    // multiple invocations.
-    let mut seeded_rng = ChaCha8Rng::seed_from_u64(profile.seed());
-
+// The given names and surnames persons are generated from.
+fn name_pools() -> (Vec<&'static str>, Vec<&'static str>) {
     let female_given_names = std::include_str!("../names-dataset/dataset/Female_given_names.txt");
     let male_given_names = std::include_str!("../names-dataset/dataset/Male_given_names.txt");
 
@@ -51,19 +74,265 @@ pub async fn populate(_client: &KanidmOrcaClient, profile: Profile) -> Result<St
         surnames.len()
     );
 
-    let thread_count = profile.thread_count();
+    (given_names, surnames)
+}
 
-    // PHASE 0 - For now, set require MFA off and extend the privilege expiry.
-    let preflight_flags = vec![
-        Flag::DisableAllPersonsMFAPolicy,
-        Flag::ExtendPrivilegedAuthExpiry,
-    ];
+fn generate_person(
+    seeded_rng: &mut ChaCha8Rng,
+    given_names: &[&str],
+    surnames: &[&str],
+    model: Model,
+) -> Person {
+    let given_name = given_names.choose(seeded_rng).expect("name set corrupted");
+    let surname = surnames.choose(seeded_rng).expect("name set corrupted");
 
-    // PHASE 1 - generate a pool of persons that are not-yet created for future import.
+    let display_name = format!("{} {}", given_name, surname);
 
-    // PHASE 2 - generate groups for integration access, assign roles to groups.
-    // These decide what each person is supposed to do with their life.
-    let mut groups = vec![
+    let username = display_name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+
+    let username = if username.is_empty() {
+        random_name(PEOPLE_PREFIX, seeded_rng)
+    } else {
+        username
+    };
+
+    let password = random_password(seeded_rng);
+
+    let roles = BTreeSet::new();
+
+    // Data is ready, make changes to the server. These should be idempotent if possible.
+    Person {
+        preflight_state: PreflightState::Present,
+        username,
+        display_name,
+        roles,
+        credential: Credential::Password { plain: password },
+        model,
+        mfa_enrollment: None,
+        login_storm_delay: None,
+        unix_credential: None,
+        login_as: None,
+        read_attributes: None,
+        oauth2_login: None,
+        manages: None,
+        password_expiry: None,
+        registered_credential: Default::default(),
+        provided_password: Default::default(),
+    }
+}
+
+// Rename the person until their username isn't taken yet, and then take it.
+fn take_username(
+    seeded_rng: &mut ChaCha8Rng,
+    person_usernames: &mut BTreeSet<String>,
+    person: &mut Person,
+) {
+    while person_usernames.contains(&person.username) {
+        person.username = random_name(PEOPLE_PREFIX, seeded_rng);
+    }
+    person_usernames.insert(person.username.clone());
+}
+
+// Generates the names and passwords of the persons over a number of threads. Each block of
+// PERSONS_PER_STREAM persons is generated from an RNG stream of its own, and the blocks are
+// put back in order, so the persons only depend on the seed.
+fn generate_persons(
+    seed: u64,
+    person_count: u64,
+    model: Model,
+    threads: usize,
+) -> Result<Vec<Person>, Error> {
+    let (given_names, surnames) = name_pools();
+    let (given_names, surnames) = (given_names.as_slice(), surnames.as_slice());
+    let stream_count = person_count.div_ceil(PERSONS_PER_STREAM);
+    let threads = threads.max(1) as u64;
+
+    let mut blocks = std::thread::scope(|scope| {
+        let handles = (0..threads)
+            .map(|thread| {
+                scope.spawn(move || {
+                    (thread..stream_count)
+                        .step_by(threads as usize)
+                        .map(|stream| {
+                            // Stream 0 is left to the rest of the generation.
+                            let mut stream_rng = ChaCha8Rng::seed_from_u64(seed);
+                            stream_rng.set_stream(stream + 1);
+                            let first = stream * PERSONS_PER_STREAM;
+                            let last = (first + PERSONS_PER_STREAM).min(person_count);
+                            let persons = (first..last)
+                                .map(|_| {
+                                    generate_person(&mut stream_rng, given_names, surnames, model)
+                                })
+                                .collect::<Vec<_>>();
+                            (stream, persons)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join())
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .map_err(|_| Error::invalid("a thread generating persons panicked"))?
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+
+    blocks.sort_by_key(|(stream, _)| *stream);
+    Ok(blocks
+        .into_iter()
+        .flat_map(|(_, persons)| persons)
+        .collect())
+}
+
+// Decides whether a person enrolls MFA during the run, when and how (phase 10).
+fn assign_mfa_enrollment(profile: &Profile, seeded_rng: &mut ChaCha8Rng, person: &mut Person) {
+    let mfa_enrollment = profile.mfa_enrollment();
+    if !seeded_rng.gen_bool(mfa_enrollment.fraction) {
+        return;
+    }
+
+    let delay = seeded_rng.gen_range(0..=mfa_enrollment.wave_time);
+    let method = if seeded_rng.gen_bool(mfa_enrollment.passkey_fraction) {
+        MfaEnrollmentMethod::Passkey(profile.choose_passkey_properties(seeded_rng))
+    } else {
+        MfaEnrollmentMethod::Totp {
+            clock_skew: profile.totp_clock_skew().for_actor(seeded_rng),
+        }
+    };
+
+    person.mfa_enrollment = Some(MfaEnrollment { delay, method });
+}
+
+fn assign_credential(profile: &Profile, seeded_rng: &mut ChaCha8Rng, person: &mut Person) {
+    // These persons need the password of their credential to do what they do.
+    if person.mfa_enrollment.is_some() || person.roles.contains(&ActorRole::PeopleSelfSetPassword) {
+        return;
+    }
+    let Some(plain) = person.credential.password() else {
+        return;
+    };
+
+    let mix = profile.credential_mix();
+    let draw: f64 = seeded_rng.gen();
+    if draw < mix.password_totp {
+        person.credential = Credential::PasswordTotp {
+            plain: plain.to_string(),
+            clock_skew: profile.totp_clock_skew().for_actor(seeded_rng),
+        };
+    } else if draw < mix.password_totp + mix.passkey {
+        person.credential = Credential::Passkey(profile.choose_passkey_properties(seeded_rng));
+    }
+}
+
+// Persons can only sign in to a client that maps a scope to one of their groups, persons
+// without such a client are left without one.
+fn assign_oauth2_login(
+    seeded_rng: &mut ChaCha8Rng,
+    oauth2_clients: &[Oauth2Client],
+    groups: &[Group],
+    person: &mut Person,
+) {
+    let person_groups = groups
+        .iter()
+        .filter(|group| group.members.contains(&person.username))
+        .map(|group| &group.name)
+        .collect::<Vec<_>>();
+    let candidates = oauth2_clients
+        .iter()
+        .filter(|client| {
+            client
+                .scope_maps
+                .iter()
+                .any(|scope_map| person_groups.contains(&&scope_map.group))
+        })
+        .collect::<Vec<_>>();
+
+    person.oauth2_login = candidates.choose(seeded_rng).map(|client| Oauth2Login {
+        client: client.name.clone(),
+        redirect_uri: format!("{}/oauth2/callback", client.origin),
+        client_secret: None,
+    });
+}
+
+fn assign_password_expiry(profile: &Profile, seeded_rng: &mut ChaCha8Rng, person: &mut Person) {
+    // Only a password can expire.
+    if person.credential.password().is_none() {
+        return;
+    }
+    let password_expiry = profile.password_expiry();
+    if !seeded_rng.gen_bool(password_expiry.fraction) {
+        return;
+    }
+
+    person.password_expiry = Some(PasswordExpiry {
+        delay: seeded_rng.gen_range(0..=password_expiry.wave_time),
+        rotated: random_password(seeded_rng),
+        done: Default::default(),
+    });
+}
+
+fn assign_login_storm_delay(profile: &Profile, seeded_rng: &mut ChaCha8Rng, person: &mut Person) {
+    let login_storm = profile.login_storm();
+    if seeded_rng.gen_bool(login_storm.fraction) {
+        person.login_storm_delay = Some(seeded_rng.gen_range(0..=login_storm.spread));
+    }
+}
+
+// Choose the persons whose accounts are shared by everyone in the duplicate login model.
+fn shared_logins(
+    profile: &Profile,
+    seeded_rng: &mut ChaCha8Rng,
+    persons: &[Person],
+) -> Vec<SharedLogin> {
+    // A second factor is registered by each actor for itself, so only passwords are shared.
+    let candidates = persons
+        .iter()
+        .filter(|person| matches!(person.credential, Credential::Password { .. }))
+        .collect::<Vec<_>>();
+    let account_count = (profile.duplicate_login().account_count as usize).min(candidates.len());
+    index::sample(seeded_rng, candidates.len(), account_count)
+        .into_iter()
+        .map(|index| SharedLogin {
+            username: candidates[index].username.clone(),
+            credential: candidates[index].credential.clone(),
+        })
+        .collect()
+}
+
+fn assign_shared_login(
+    seeded_rng: &mut ChaCha8Rng,
+    shared_logins: &[SharedLogin],
+    person: &mut Person,
+) {
+    person.login_as = shared_logins.choose(seeded_rng).cloned();
+}
+
+fn assign_read_attributes(profile: &Profile, seeded_rng: &mut ChaCha8Rng, person: &mut Person) {
+    let attribute_reads = profile.attribute_reads();
+    if seeded_rng.gen_bool(attribute_reads.fraction) {
+        person.read_attributes = attribute_reads.sets.choose(seeded_rng).cloned();
+    }
+}
+
+fn assign_unix_credential(profile: &Profile, seeded_rng: &mut ChaCha8Rng, person: &mut Person) {
+    if seeded_rng.gen_bool(profile.unix_password_fraction()) {
+        person.unix_credential = Some(UnixCredential::Password {
+            plain: random_password(seeded_rng),
+        });
+    }
+}
+
+// The groups that give persons their roles.
+fn role_groups() -> Vec<Group> {
+    vec![
         Group {
             name: GroupName::RolePeopleSelfSetPassword,
             role: ActorRole::PeopleSelfSetPassword,
@@ -94,67 +363,24 @@ pub async fn populate(_client: &KanidmOrcaClient, profile: Profile) -> Result<St
             role: ActorRole::PeopleGroupAdmin,
             ..Default::default()
         },
-    ];
-
-    // PHASE 3 - generate persons
-    //         - assign them credentials of various types.
-    let mut persons = Vec::with_capacity(profile.person_count() as usize);
-    let mut person_usernames = BTreeSet::new();
-
-    let model = *profile.model();
-
-    for _ in 0..profile.person_count() {
-        let given_name = given_names
-            .choose(&mut seeded_rng)
-            .expect("name set corrupted");
-        let surname = surnames
-            .choose(&mut seeded_rng)
-            .expect("name set corrupted");
-
-        let display_name = format!("{} {}", given_name, surname);
-
-        let username = display_name
-            .chars()
-            .filter(|c| c.is_ascii_alphanumeric())
-            .collect::<String>()
-            .to_lowercase();
-
-        let mut username = if username.is_empty() {
-            random_name(PEOPLE_PREFIX, &mut seeded_rng)
-        } else {
-            username
-        };
-
-        while person_usernames.contains(&username) {
-            username = random_name(PEOPLE_PREFIX, &mut seeded_rng);
-        }
-
-        let password = random_password(&mut seeded_rng);
-
-        let roles = BTreeSet::new();
-
-        // Data is ready, make changes to the server. These should be idempotent if possible.
-        let p = Person {
-            preflight_state: PreflightState::Present,
-            username: username.clone(),
-            display_name,
-            roles,
-            credential: Credential::Password { plain: password },
-            model,
-        };
-
-        debug!(?p);
-
-        person_usernames.insert(username.clone());
-        persons.push(p);
-    }
+        Group {
+            name: GroupName::RolePeopleAccountPolicyAdmin,
+            role: ActorRole::PeopleAccountPolicyAdmin,
+            ..Default::default()
+        },
+    ]
+}
 
-    // Now, assign persons to roles.
-    //
-    // We do this by iterating through our roles, and then assigning
-    // them a baseline of required accounts with some variation. This
-    // way in each test it's guaranteed that *at least* one person
-    // to each role always will exist and be operational.
+// We do this by iterating through our roles, and then assigning
+// them a baseline of required accounts with some variation. This
+// way in each test it's guaranteed that *at least* one person
+// to each role always will exist and be operational.
+fn assign_group_members(
+    profile: &Profile,
+    seeded_rng: &mut ChaCha8Rng,
+    groups: &mut [Group],
+    persons: &mut [Person],
+) {
     let member_count_by_group: HashMap<GroupName, u64> = profile
         .get_properties_by_group()
         .iter()
@@ -180,7 +406,7 @@ pub async fn populate(_client: &KanidmOrcaClient, profile: Profile) -> Result<St
 
         debug!(?persons_to_choose);
 
-        let person_index = index::sample(&mut seeded_rng, persons.len(), persons_to_choose);
+        let person_index = index::sample(seeded_rng, persons.len(), persons_to_choose);
 
         // Order doesn't matter, lets optimise for linear lookup.
         let mut person_index = person_index.into_vec();
@@ -197,27 +423,642 @@ pub async fn populate(_client: &KanidmOrcaClient, profile: Profile) -> Result<St
             person.roles.insert(group.role.clone());
         }
     }
+}
+
+// The phases that decide what each person does during the test, once the groups and
+// integrations are known. The persons that extend a state go through them as well. The
+// persons of the duplicate login race share the `shared_logins` accounts, or ones chosen
+// from `persons` without them.
+fn assign_behaviours(
+    profile: &Profile,
+    seeded_rng: &mut ChaCha8Rng,
+    persons: &mut [Person],
+    groups: &[Group],
+    oauth2_clients: &[Oauth2Client],
+    shared_logins: Option<&[SharedLogin]>,
+) {
+    let model = *profile.model();
+
+    // PHASE 9 - given the integrations and groupings, choose the OAuth2 client each person
+    // signs in to, or reads the metadata of.
+    if matches!(model, Model::Oauth2 | Model::Anonymous) {
+        for person in persons.iter_mut() {
+            assign_oauth2_login(seeded_rng, oauth2_clients, groups, person);
+        }
+    }
+
+    // PHASE 10 - choose the persons that take part in the MFA onboarding wave. Enabling it
+    // leaves what the earlier phases chose as it was, but changes what the later ones draw.
+    if profile.mfa_enrollment().fraction > 0.0 {
+        for person in persons.iter_mut() {
+            assign_mfa_enrollment(profile, seeded_rng, person);
+        }
+    }
+
+    // PHASE 11 - choose the persons that take part in the login storm, and when.
+    if matches!(model, Model::LoginStorm) {
+        for person in persons.iter_mut() {
+            assign_login_storm_delay(profile, seeded_rng, person);
+        }
+    }
+
+    // PHASE 12 - choose the persons that are given a unix password.
+    if profile.unix_password_fraction() > 0.0 {
+        for person in persons.iter_mut() {
+            assign_unix_credential(profile, seeded_rng, person);
+        }
+    }
+
+    // PHASE 13 - choose the type of credential of each person, according to the mix.
+    if profile.credential_mix().password < 1.0 {
+        for person in persons.iter_mut() {
+            assign_credential(profile, seeded_rng, person);
+        }
+    }
+
+    // PHASE 14 - choose the shared accounts of the duplicate login race, and who logs in
+    // as which.
+    if matches!(model, Model::DuplicateLogin) {
+        let shared_logins = match shared_logins {
+            Some(shared_logins) => shared_logins.to_vec(),
+            None => self::shared_logins(profile, seeded_rng, persons),
+        };
+        for person in persons.iter_mut() {
+            assign_shared_login(seeded_rng, &shared_logins, person);
+        }
+    }
+
+    // PHASE 15 - choose the persons that read a set of their attributes rather than their
+    // whole entry, and which set.
+    if profile.attribute_reads().fraction > 0.0 {
+        for person in persons.iter_mut() {
+            assign_read_attributes(profile, seeded_rng, person);
+        }
+    }
+
+    // PHASE 16 - choose the persons whose password expires during the run, once their
+    // type of credential is known.
+    if profile.password_expiry().fraction > 0.0 {
+        for person in persons.iter_mut() {
+            assign_password_expiry(profile, seeded_rng, person);
+        }
+    }
 
-    // PHASE 4 - generate groups for user modification rights
+    // PHASE 17 - leave the passwords out if they come from a credential provider. They're
+    // still drawn above, so that the rest of the state comes out the same either way.
+    if profile.credential_provider().source != CredentialSource::State {
+        for person in persons.iter_mut() {
+            person.credential.forget_password();
+        }
+    }
+}
 
-    // PHASE 5 - generate excess groups with nesting. Randomly assign persons.
+/// Generate the state of a test from its profile. The persons are generated over `threads`
+/// threads, or as many as there are cores, which doesn't change what is generated.
+pub async fn populate(
+    _client: &KanidmOrcaClient,
+    profile: Profile,
+    threads: Option<usize>,
+) -> Result<State, Error> {
+    generate(profile, threads)
+}
 
-    // PHASE 6 - generate integrations -
+/// Generate the state of a test without a server, for what only needs its persons.
+pub fn generate(profile: Profile, threads: Option<usize>) -> Result<State, Error> {
+    // IMPORTANT: We have to perform these steps in order so that the RNG is deterministic between
+    // multiple invocations.
+    let mut seeded_rng = ChaCha8Rng::seed_from_u64(profile.seed());
 
-    // PHASE 7 - given the integrations and groupings,
+    let thread_count = profile.thread_count();
 
-    drop(member_count_by_group); // it looks ugly but we have to do this to reassure the borrow checker we can return profile, as we were borrowing
-                                 //the group names from it
+    // PHASE 0 - For now, set require MFA off and extend the privilege expiry.
+    let preflight_flags = vec![
+        Flag::DisableAllPersonsMFAPolicy,
+        Flag::ExtendPrivilegedAuthExpiry,
+    ];
+
+    // PHASE 1 - generate a pool of persons that are not-yet created for future import.
+
+    // PHASE 2 - generate groups for integration access, assign roles to groups.
+    // These decide what each person is supposed to do with their life.
+    let mut groups = role_groups();
+
+    // PHASE 3 - generate persons
+    //         - assign them credentials of various types.
+    let model = *profile.model();
+    let threads =
+        threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from));
+    debug!(threads, "Generating persons");
+
+    let mut persons = generate_persons(profile.seed(), profile.person_count(), model, threads)?;
+
+    // The persons of different streams may share a name, so the later ones are renamed.
+    let mut person_usernames = BTreeSet::new();
+    for p in persons.iter_mut() {
+        take_username(&mut seeded_rng, &mut person_usernames, p);
+        debug!(?p);
+    }
+
+    // Now, assign persons to roles.
+    assign_group_members(&profile, &mut seeded_rng, &mut groups, &mut persons);
+
+    // PHASE 4 - generate access controls between the groups, so that access evaluation
+    // during the test has to consider many more profiles than the builtin set.
+    let mut access_controls = Vec::with_capacity(profile.access_control_count() as usize);
+
+    for index in 0..profile.access_control_count() {
+        let receiver = groups
+            .choose(&mut seeded_rng)
+            .map(|group| group.name.clone())
+            .expect("group set corrupted");
+        let target = groups
+            .choose(&mut seeded_rng)
+            .map(|group| group.name.clone())
+            .expect("group set corrupted");
+
+        // Always allow searching the name, plus a random subset of the rest.
+        let attr_count = seeded_rng.gen_range(1..=ACCESS_CONTROL_SEARCH_ATTRS.len());
+        let search_attrs = ACCESS_CONTROL_SEARCH_ATTRS
+            .choose_multiple(&mut seeded_rng, attr_count)
+            .chain(std::iter::once(&"name"))
+            .map(|attr| attr.to_string())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        access_controls.push(AccessControl {
+            name: format!("{}_{}", ACCESS_CONTROL_PREFIX, index),
+            preflight_state: PreflightState::Present,
+            receiver,
+            target,
+            search_attrs,
+        });
+    }
+
+    // PHASE 5 - generate groups for user modification rights. Each is managed by a person
+    // of its own, who keeps adding another person to it.
+    let managed_group_properties = profile.managed_groups();
+    let mut managed_groups = Vec::with_capacity(managed_group_properties.count as usize);
+
+    let managers = index::sample(
+        &mut seeded_rng,
+        persons.len(),
+        managed_group_properties.count as usize,
+    );
+    for (group_index, manager_index) in managers.into_iter().enumerate() {
+        let name = format!("{}_{}", MANAGED_GROUP_PREFIX, group_index);
+        let managed_by = persons[manager_index].username.clone();
+
+        let mut others = persons
+            .iter()
+            .map(|person| person.username.as_str())
+            .filter(|username| *username != managed_by)
+            .collect::<Vec<_>>()
+            .choose_multiple(
+                &mut seeded_rng,
+                managed_group_properties.member_count as usize + 1,
+            )
+            .map(|username| username.to_string())
+            .collect::<Vec<_>>();
+        let member = others.pop().expect("person set corrupted");
+
+        persons[manager_index].manages = Some(GroupManagement {
+            group: name.clone(),
+            member,
+        });
+        managed_groups.push(ManagedGroup {
+            name,
+            preflight_state: PreflightState::Present,
+            managed_by,
+            members: others.into_iter().collect(),
+        });
+    }
+
+    // PHASE 6 - generate excess groups with nesting. Randomly assign persons.
+
+    // PHASE 7 - generate integrations - OAuth2 clients with scope and claim maps bound
+    // to the groups.
+    let oauth2_properties = profile.oauth2();
+    let mut oauth2_clients = Vec::with_capacity(oauth2_properties.client_count as usize);
+
+    for client_index in 0..oauth2_properties.client_count {
+        let name = format!("{}_{}", OAUTH2_CLIENT_PREFIX, client_index);
+        let origin = format!("https://{}.example.com", name.replace('_', "-"));
+
+        // A group can only have one scope map per client.
+        let scope_map_count = (oauth2_properties.scope_map_count as usize).min(groups.len());
+        let scope_maps = groups
+            .choose_multiple(&mut seeded_rng, scope_map_count)
+            .enumerate()
+            .map(|(map_index, group)| Oauth2ScopeMap {
+                group: group.name.clone(),
+                scopes: vec![
+                    "openid".to_string(),
+                    format!("{}_scope_{}", OAUTH2_CLIENT_PREFIX, map_index),
+                ],
+            })
+            .collect();
+
+        let claim_maps = (0..oauth2_properties.claim_map_count)
+            .map(|claim_index| {
+                let group = groups
+                    .choose(&mut seeded_rng)
+                    .map(|group| group.name.clone())
+                    .expect("group set corrupted");
+                Oauth2ClaimMap {
+                    claim: format!("{}_claim_{}", OAUTH2_CLIENT_PREFIX, claim_index),
+                    group,
+                    values: vec![random_name("value", &mut seeded_rng)],
+                }
+            })
+            .collect();
+
+        oauth2_clients.push(Oauth2Client {
+            name,
+            preflight_state: PreflightState::Present,
+            origin,
+            scope_maps,
+            claim_maps,
+        });
+    }
+
+    // PHASE 8 - generate the persons owned by the simulated external IDM.
+    let sync_person_count = profile.sync_agent().person_count;
+    let sync_account = if sync_person_count > 0 {
+        let persons = (0..sync_person_count)
+            .map(|index| {
+                let username = format!("{}_{}", SYNC_PERSON_PREFIX, index);
+                SyncPerson {
+                    uuid: Uuid::from_u128(seeded_rng.gen()),
+                    display_name: username.replace('_', " "),
+                    username,
+                }
+            })
+            .collect();
+
+        Some(SyncAccount {
+            name: SYNC_ACCOUNT_NAME.to_string(),
+            preflight_state: PreflightState::Present,
+            persons,
+        })
+    } else {
+        None
+    };
+
+    // PHASES 9 to 17 - decide what each person does during the test.
+    assign_behaviours(
+        &profile,
+        &mut seeded_rng,
+        &mut persons,
+        &groups,
+        &oauth2_clients,
+        None,
+    );
 
     // Return the state.
     let state = State {
         profile,
         // ---------------
         groups,
+        access_controls,
+        oauth2_clients,
+        managed_groups,
+        sync_account,
         preflight_flags,
         persons,
         thread_count,
+        rng_word_pos: Some(seeded_rng.get_word_pos()),
     };
 
     Ok(state)
 }
+
+/// Append more persons to a generated state, continuing the RNG stream from where the
+/// generation left off. The existing entries are left as they are, and the new persons
+/// join the groups and managed groups in about the same proportion as the existing ones.
+pub fn extend(state: &mut State, person_count: u64) -> Result<(), Error> {
+    let Some(rng_word_pos) = state.rng_word_pos else {
+        return Err(Error::invalid(
+            "the state was generated by an older orca, it has to be generated again to be extended",
+        ));
+    };
+
+    let profile = &state.profile;
+    let mut seeded_rng = ChaCha8Rng::seed_from_u64(profile.seed());
+    seeded_rng.set_word_pos(rng_word_pos);
+
+    let (given_names, surnames) = name_pools();
+
+    let mut person_usernames = state
+        .persons
+        .iter()
+        .map(|person| person.username.clone())
+        .collect::<BTreeSet<_>>();
+    let existing_count = state.persons.len();
+    let model = *profile.model();
+
+    let mut persons = Vec::with_capacity(person_count as usize);
+    for _ in 0..person_count {
+        let mut p = generate_person(&mut seeded_rng, &given_names, &surnames, model);
+        take_username(&mut seeded_rng, &mut person_usernames, &mut p);
+
+        debug!(?p);
+
+        persons.push(p);
+    }
+
+    // Groups with a configured member count keep it, the others grow with the persons.
+    let fixed_groups = profile
+        .get_properties_by_group()
+        .iter()
+        .filter(|(_, properties)| properties.member_count.is_some())
+        .filter_map(|(name, _)| GroupName::try_from(name).ok())
+        .collect::<BTreeSet<_>>();
+
+    // The scope maps of the OAuth2 clients are bound to these groups as well.
+    for group in state.groups.iter_mut() {
+        if fixed_groups.contains(&group.name) || existing_count == 0 {
+            continue;
+        }
+
+        let proportion = (group.members.len() as f64 / existing_count as f64).min(1.0);
+        for person in persons.iter_mut() {
+            if seeded_rng.gen_bool(proportion) {
+                group.members.insert(person.username.clone());
+                person.roles.insert(group.role.clone());
+            }
+        }
+    }
+
+    // The managers keep their person to add, the new persons are only members.
+    for managed_group in state.managed_groups.iter_mut() {
+        if existing_count == 0 {
+            continue;
+        }
+
+        let proportion = (managed_group.members.len() as f64 / existing_count as f64).min(1.0);
+        for person in persons.iter() {
+            if seeded_rng.gen_bool(proportion) {
+                managed_group.members.insert(person.username.clone());
+            }
+        }
+    }
+
+    // The new persons race for the accounts that are already shared.
+    let mut shared_logins = state
+        .persons
+        .iter()
+        .filter_map(|person| person.login_as.clone())
+        .collect::<Vec<_>>();
+    shared_logins.sort_by(|a, b| a.username.cmp(&b.username));
+    shared_logins.dedup_by(|a, b| a.username == b.username);
+
+    assign_behaviours(
+        profile,
+        &mut seeded_rng,
+        &mut persons,
+        &state.groups,
+        &state.oauth2_clients,
+        Some(&shared_logins),
+    );
+
+    info!(
+        "Extended the state from {} to {} persons",
+        existing_count,
+        existing_count + persons.len()
+    );
+
+    state.persons.append(&mut persons);
+    state.rng_word_pos = Some(seeded_rng.get_word_pos());
+
+    Ok(())
+}
+
+/// Read the persons of an existing server into a state, so that the load is generated
+/// against its dataset. The credentials of the persons are left alone, unless
+/// `reset_credentials` gives them placeholders, which replace their real ones when the
+/// state is populated. The role groups are captured if orca created them before,
+/// otherwise their members are chosen as if generated.
+pub async fn capture(
+    client: &KanidmOrcaClient,
+    profile: Profile,
+    reset_credentials: bool,
+) -> Result<State, Error> {
+    let mut seeded_rng = ChaCha8Rng::seed_from_u64(profile.seed());
+
+    let thread_count = profile.thread_count();
+    let preflight_flags = vec![
+        Flag::DisableAllPersonsMFAPolicy,
+        Flag::ExtendPrivilegedAuthExpiry,
+    ];
+
+    let model = *profile.model();
+    let mut persons = client
+        .person_list()
+        .await?
+        .into_iter()
+        .map(|(username, display_name)| Person {
+            // Without placeholders, the password comes from the credential provider, and
+            // populate doesn't touch the person.
+            preflight_state: if reset_credentials {
+                PreflightState::Captured
+            } else {
+                PreflightState::Present
+            },
+            username,
+            display_name,
+            roles: BTreeSet::new(),
+            credential: Credential::Password {
+                plain: if reset_credentials {
+                    random_password(&mut seeded_rng)
+                } else {
+                    String::new()
+                },
+            },
+            model,
+            mfa_enrollment: None,
+            login_storm_delay: None,
+            unix_credential: None,
+            login_as: None,
+            read_attributes: None,
+            oauth2_login: None,
+            manages: None,
+            password_expiry: None,
+            registered_credential: Default::default(),
+            provided_password: Default::default(),
+        })
+        .collect::<Vec<_>>();
+
+    if persons.is_empty() {
+        return Err(Error::invalid("the server has no persons to capture"));
+    }
+
+    for (group_name, properties) in profile.get_properties_by_group().iter() {
+        if let Some(member_count) = properties.member_count {
+            if member_count as usize > persons.len() {
+                return Err(Error::invalid(format!(
+                    "group {group_name} has a member count of {member_count}, but only {} persons were captured",
+                    persons.len()
+                )));
+            }
+        }
+    }
+
+    let mut groups = Vec::new();
+    let mut missing_groups = Vec::new();
+    for mut group in role_groups() {
+        let Some(members) = client.group_members(&group.name.to_string()).await? else {
+            missing_groups.push(group);
+            continue;
+        };
+
+        for person in persons.iter_mut() {
+            if members.contains(&person.username) {
+                group.members.insert(person.username.clone());
+                person.roles.insert(group.role.clone());
+            }
+        }
+        groups.push(group);
+    }
+
+    let captured_group_count = groups.len();
+    assign_group_members(&profile, &mut seeded_rng, &mut missing_groups, &mut persons);
+    groups.append(&mut missing_groups);
+
+    // Enrolling MFA or a second factor, or setting a unix password, would change the
+    // credentials of the persons too.
+    if reset_credentials && profile.mfa_enrollment().fraction > 0.0 {
+        for person in persons.iter_mut() {
+            assign_mfa_enrollment(&profile, &mut seeded_rng, person);
+        }
+    }
+
+    if matches!(model, Model::LoginStorm) {
+        for person in persons.iter_mut() {
+            assign_login_storm_delay(&profile, &mut seeded_rng, person);
+        }
+    }
+
+    if reset_credentials && profile.unix_password_fraction() > 0.0 {
+        for person in persons.iter_mut() {
+            assign_unix_credential(&profile, &mut seeded_rng, person);
+        }
+    }
+
+    if reset_credentials && profile.credential_mix().password < 1.0 {
+        for person in persons.iter_mut() {
+            assign_credential(&profile, &mut seeded_rng, person);
+        }
+    }
+
+    if matches!(model, Model::DuplicateLogin) {
+        let shared_logins = shared_logins(&profile, &mut seeded_rng, &persons);
+        for person in persons.iter_mut() {
+            assign_shared_login(&mut seeded_rng, &shared_logins, person);
+        }
+    }
+
+    if profile.attribute_reads().fraction > 0.0 {
+        for person in persons.iter_mut() {
+            assign_read_attributes(&profile, &mut seeded_rng, person);
+        }
+    }
+
+    info!(
+        "Captured {} persons and {} role groups",
+        persons.len(),
+        captured_group_count
+    );
+
+    Ok(State {
+        profile,
+        groups,
+        access_controls: Vec::new(),
+        oauth2_clients: Vec::new(),
+        managed_groups: Vec::new(),
+        sync_account: None,
+        preflight_flags,
+        persons,
+        thread_count,
+        rng_word_pos: Some(seeded_rng.get_word_pos()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{assign_password_expiry, extend, generate, generate_persons, PERSONS_PER_STREAM};
+    use crate::profile::{PasskeyProperties, Profile};
+    use crate::state::{Credential, Model};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_persons_do_not_depend_on_the_thread_count() {
+        let person_count = PERSONS_PER_STREAM * 2 + 10;
+        let generate = |threads| {
+            let Ok(persons) = generate_persons(7, person_count, Model::Basic, threads) else {
+                panic!("The persons are generated");
+            };
+            persons
+                .into_iter()
+                .map(|person| {
+                    (
+                        person.username,
+                        person.credential.password().map(str::to_string),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let single = generate(1);
+        assert_eq!(single.len(), person_count as usize);
+        assert_eq!(single, generate(3));
+    }
+
+    #[test]
+    fn test_extended_persons_join_the_managed_groups() {
+        let profile: Profile = toml::from_str(&format!(
+            "{}\n[managed_groups]\ncount = 1\nmember_count = 250\n",
+            include_str!("../profile-sample.toml")
+        ))
+        .unwrap();
+        let Ok(mut state) = generate(profile, Some(1)) else {
+            panic!("The state is generated");
+        };
+        let existing_count = state.persons.len();
+        assert!(extend(&mut state, 100).is_ok());
+
+        let new_persons = &state.persons[existing_count..];
+        assert_eq!(new_persons.len(), 100);
+        assert!(new_persons
+            .iter()
+            .any(|person| state.managed_groups[0].members.contains(&person.username)));
+    }
+
+    #[test]
+    fn test_expired_password_is_logged_in_with_once_rotated() {
+        let profile: Profile = toml::from_str(&format!(
+            "{}\n[password_expiry]\nfraction = 1.0\n",
+            include_str!("../profile-sample.toml")
+        ))
+        .unwrap();
+        let Ok(mut persons) = generate_persons(7, 2, Model::Basic, 1) else {
+            panic!("The persons are generated");
+        };
+        persons[1].credential = Credential::Passkey(PasskeyProperties::default());
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        for person in persons.iter_mut() {
+            assign_password_expiry(&profile, &mut rng, person);
+        }
+
+        // Only a password can expire.
+        assert!(persons[1].password_expiry.is_none());
+        let person = &persons[0];
+        let Some(expiry) = &person.password_expiry else {
+            panic!("The password expires");
+        };
+        assert_eq!(person.password(), person.credential.password());
+        assert!(expiry.done.set(()).is_ok());
+        assert_eq!(person.password(), Some(expiry.rotated.as_str()));
+    }
+}
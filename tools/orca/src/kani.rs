@@ -1,13 +1,16 @@
-use kanidm_client::{KanidmClient, KanidmClientBuilder};
+use kanidm_client::{ClientError, KanidmClient, KanidmClientBuilder};
+use kanidm_proto::internal::{CURegState, Filter, TotpSecret};
+use kanidm_proto::v1::Entry;
+use std::collections::BTreeMap;
 
 use crate::error::Error;
-use crate::profile::Profile;
+use crate::model::{self, SoftAuthenticator};
+use crate::profile::{PasskeyProperties, Profile};
 
 // This client contains our admin and idm_admin connections that are
 // pre-authenticated for use against the kanidm server. In addition,
 // new clients can be requested for our test actors.
 pub struct KanidmOrcaClient {
-    #[allow(dead_code)]
     admin_client: KanidmClient,
     idm_admin_client: KanidmClient,
     // In future we probably need a way to connect to all the nodes?
@@ -21,32 +24,23 @@ impl KanidmOrcaClient {
             .danger_accept_invalid_hostnames(true)
             .danger_accept_invalid_certs(true)
             .request_timeout(1200)
+            .default_headers(profile.headers()?)
             .build()
-            .map_err(|err| {
-                error!(?err, "Unable to create kanidm client");
-                Error::KanidmClient
-            })?;
+            .map_err(|err| Error::client("create the kanidm client", err))?;
 
         admin_client
             .auth_simple_password("admin", profile.admin_password())
             .await
-            .map_err(|err| {
-                error!(?err, "Unable to authenticate as admin");
-                Error::KanidmClient
-            })?;
+            .map_err(|err| Error::client("authenticate as admin", err))?;
 
-        let idm_admin_client = admin_client.new_session().map_err(|err| {
-            error!(?err, "Unable to create new session");
-            Error::KanidmClient
-        })?;
+        let idm_admin_client = admin_client
+            .new_session()
+            .map_err(|err| Error::client("create a new session", err))?;
 
         idm_admin_client
             .auth_simple_password("idm_admin", profile.idm_admin_password())
             .await
-            .map_err(|err| {
-                error!(?err, "Unable to authenticate as idm_admin");
-                Error::KanidmClient
-            })?;
+            .map_err(|err| Error::client("authenticate as idm_admin", err))?;
 
         Ok(KanidmOrcaClient {
             admin_client,
@@ -58,28 +52,19 @@ impl KanidmOrcaClient {
         self.idm_admin_client
             .group_account_policy_credential_type_minimum_set("idm_all_persons", "any")
             .await
-            .map_err(|err| {
-                error!(?err, "Unable to modify idm_all_persons policy");
-                Error::KanidmClient
-            })
+            .map_err(|err| Error::client("modify idm_all_persons policy", err))
     }
 
     pub async fn extend_privilege_expiry(&self) -> Result<(), Error> {
         self.idm_admin_client
             .group_account_policy_privilege_expiry_set("idm_all_persons", 3600)
             .await
-            .map_err(|err| {
-                error!(?err, "Unable to modify idm_all_persons policy");
-                Error::KanidmClient
-            })?;
+            .map_err(|err| Error::client("modify idm_all_persons policy", err))?;
 
         self.idm_admin_client
             .group_account_policy_privilege_expiry_set("idm_all_accounts", 3600)
             .await
-            .map_err(|err| {
-                error!(?err, "Unable to modify idm_all_accounts policy");
-                Error::KanidmClient
-            })
+            .map_err(|err| Error::client("modify idm_all_accounts policy", err))
     }
 
     pub async fn person_exists(&self, username: &str) -> Result<bool, Error> {
@@ -87,20 +72,95 @@ impl KanidmOrcaClient {
             .idm_person_account_get(username)
             .await
             .map(|e| e.is_some())
-            .map_err(|err| {
-                error!(?err, ?username, "Unable to check person");
-                Error::KanidmClient
+            .map_err(|err| Error::client_for("check for person", username, err))
+    }
+
+    pub async fn person_delete(&self, username: &str) -> Result<(), Error> {
+        self.idm_admin_client
+            .idm_person_account_delete(username)
+            .await
+            .map_err(|err| Error::client_for("delete person", username, err))
+    }
+
+    /// The name and display name of every person on the server.
+    pub async fn person_list(&self) -> Result<Vec<(String, String)>, Error> {
+        let entries = self
+            .idm_admin_client
+            .idm_person_account_list()
+            .await
+            .map_err(|err| Error::client("list persons", err))?;
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|mut entry| {
+                let name = entry.attrs.remove("name")?.into_iter().next()?;
+                let display_name = entry
+                    .attrs
+                    .remove("displayname")
+                    .and_then(|values| values.into_iter().next())
+                    .unwrap_or_else(|| name.clone());
+                Some((name, display_name))
             })
+            .collect())
+    }
+
+    /// How many persons and groups there are on the server. This lists all of them, so it
+    /// isn't cheap.
+    pub async fn entry_counts(&self) -> Result<(u64, u64), Error> {
+        let persons = self
+            .idm_admin_client
+            .idm_person_account_list()
+            .await
+            .map_err(|err| Error::client("list persons", err))?;
+        let groups = self
+            .idm_admin_client
+            .idm_group_list()
+            .await
+            .map_err(|err| Error::client("list groups", err))?;
+        Ok((persons.len() as u64, groups.len() as u64))
+    }
+
+    /// How many sessions the person holds, including the ones that were never logged out of.
+    pub async fn person_session_count(&self, username: &str) -> Result<u64, Error> {
+        self.idm_admin_client
+            .idm_account_list_user_auth_token(username)
+            .await
+            .map(|sessions| sessions.len() as u64)
+            .map_err(|err| Error::client_for("list the sessions of person", username, err))
     }
 
     pub async fn person_create(&self, username: &str, display_name: &str) -> Result<(), Error> {
         self.idm_admin_client
             .idm_person_account_create(username, display_name)
             .await
-            .map_err(|err| {
-                error!(?err, ?username, "Unable to create person");
-                Error::KanidmClient
+            .map_err(|err| Error::client_for("create person", username, err))
+    }
+
+    /// Create several persons with a single request. Each person is a username and a
+    /// display name.
+    pub async fn person_create_batch(&self, persons: &[(String, String)]) -> Result<(), Error> {
+        let entries = persons
+            .iter()
+            .map(|(username, display_name)| {
+                let mut attrs = BTreeMap::new();
+                attrs.insert(
+                    "class".to_string(),
+                    vec![
+                        "object".to_string(),
+                        "account".to_string(),
+                        "person".to_string(),
+                    ],
+                );
+                attrs.insert("name".to_string(), vec![username.clone()]);
+                attrs.insert("displayname".to_string(), vec![display_name.clone()]);
+                Entry { attrs }
             })
+            .collect();
+
+        self.idm_admin_client
+            .create(entries)
+            .await
+            .map_err(|err| Error::client(format!("create {} persons", persons.len()), err))
     }
 
     pub async fn person_set_primary_password_only(
@@ -111,30 +171,175 @@ impl KanidmOrcaClient {
         self.idm_admin_client
             .idm_person_account_primary_credential_set_password(username, password)
             .await
-            .map_err(|err| {
-                error!(?err, ?username, "Unable to set person password");
-                Error::KanidmClient
-            })
+            .map_err(|err| Error::client_for("set the password of person", username, err))
+    }
+
+    /// Replace the primary credential of the person with their password and a new TOTP,
+    /// returning the secret the server picked for it.
+    pub async fn person_register_password_totp(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<TotpSecret, Error> {
+        let client = &self.idm_admin_client;
+        async {
+            let (session_token, _status) =
+                client.idm_account_credential_update_begin(username).await?;
+
+            // Start over, since the TOTP of the previous run is still there.
+            client
+                .idm_account_credential_update_primary_remove(&session_token)
+                .await?;
+            client
+                .idm_account_credential_update_set_password(&session_token, password)
+                .await?;
+
+            let status = client
+                .idm_account_credential_update_init_totp(&session_token)
+                .await?;
+            let CURegState::TotpCheck(secret) = status.mfaregstate else {
+                return Err(ClientError::InvalidResponseFormat(format!(
+                    "Unexpected TOTP registration state {:?}",
+                    status.mfaregstate
+                )));
+            };
+
+            let totp = model::totp_code(&secret, 0)?;
+            client
+                .idm_account_credential_update_check_totp(&session_token, totp, "orca")
+                .await?;
+            client
+                .idm_account_credential_update_commit(&session_token)
+                .await?;
+
+            Ok(secret)
+        }
+        .await
+        .map_err(|err| Error::client_for("register TOTP for person", username, err))
+    }
+
+    /// Replace the passkeys of the person with a new soft passkey.
+    pub async fn person_register_passkey(
+        &self,
+        username: &str,
+        properties: PasskeyProperties,
+    ) -> Result<SoftAuthenticator, Error> {
+        let client = &self.idm_admin_client;
+        async {
+            let mut authenticator = SoftAuthenticator::new(properties).map_err(|err| {
+                error!(?err, "Soft passkey failed to be created");
+                ClientError::SystemError
+            })?;
+
+            let (session_token, status) =
+                client.idm_account_credential_update_begin(username).await?;
+
+            // The passkeys of the previous runs can't be used anymore, their keys are gone.
+            for passkey in status.passkeys {
+                client
+                    .idm_account_credential_update_passkey_remove(&session_token, passkey.uuid)
+                    .await?;
+            }
+
+            let status = client
+                .idm_account_credential_update_passkey_init(&session_token)
+                .await?;
+            let CURegState::Passkey(challenge) = status.mfaregstate else {
+                return Err(ClientError::InvalidResponseFormat(format!(
+                    "Unexpected passkey registration state {:?}",
+                    status.mfaregstate
+                )));
+            };
+
+            let registration = authenticator
+                .do_registration(client.get_origin().clone(), challenge)
+                .map_err(|err| {
+                    error!(?err, "Soft passkey failed to register");
+                    ClientError::SystemError
+                })?;
+            client
+                .idm_account_credential_update_passkey_finish(
+                    &session_token,
+                    "orca".to_string(),
+                    registration,
+                )
+                .await?;
+            client
+                .idm_account_credential_update_commit(&session_token)
+                .await?;
+            Ok(authenticator)
+        }
+        .await
+        .map_err(|err| Error::client_for("register a passkey for person", username, err))
+    }
+
+    pub async fn person_set_unix_password(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<(), Error> {
+        self.idm_admin_client
+            .idm_person_account_unix_extend(username, None, None)
+            .await
+            .map_err(|err| Error::client_for("add posix attributes to person", username, err))?;
+
+        self.idm_admin_client
+            .idm_person_account_unix_cred_put(username, password)
+            .await
+            .map_err(|err| Error::client_for("set the unix password of person", username, err))
     }
 
     pub async fn group_set_members(&self, group_name: &str, members: &[&str]) -> Result<(), Error> {
         self.idm_admin_client
             .idm_group_set_members(group_name, members)
             .await
-            .map_err(|err| {
-                error!(?err, ?group_name, "Unable to set group members");
-                Error::KanidmClient
-            })
+            .map_err(|err| Error::client_for("set the members of group", group_name, err))
     }
 
     pub async fn group_add_members(&self, group_name: &str, members: &[&str]) -> Result<(), Error> {
         self.idm_admin_client
             .idm_group_add_members(group_name, members)
             .await
-            .map_err(|err| {
-                error!(?err, ?group_name, "Unable to add group members");
-                Error::KanidmClient
-            })
+            .map_err(|err| Error::client_for("add members to group", group_name, err))
+    }
+
+    /// The names of the direct members of a group, or None if it doesn't exist.
+    pub async fn group_members(&self, group_name: &str) -> Result<Option<Vec<String>>, Error> {
+        if !self.group_exists(group_name).await? {
+            return Ok(None);
+        }
+
+        let members = self
+            .idm_admin_client
+            .idm_group_get_members(group_name)
+            .await
+            .map_err(|err| Error::client_for("get the members of group", group_name, err))?
+            .unwrap_or_default();
+
+        // Members are returned as their spn, so strip the domain.
+        Ok(Some(
+            members
+                .into_iter()
+                .map(|member| match member.split_once('@') {
+                    Some((name, _)) => name.to_string(),
+                    None => member,
+                })
+                .collect(),
+        ))
+    }
+
+    /// The names of the entries in the recycle bin.
+    pub async fn recycle_bin_names(&self) -> Result<Vec<String>, Error> {
+        let entries = self
+            .admin_client
+            .recycle_bin_list()
+            .await
+            .map_err(|err| Error::client("list the recycle bin", err))?;
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|mut entry| entry.attrs.remove("name")?.into_iter().next())
+            .collect())
     }
 
     pub async fn group_exists(&self, group_name: &str) -> Result<bool, Error> {
@@ -142,19 +347,243 @@ impl KanidmOrcaClient {
             .idm_group_get(group_name)
             .await
             .map(|e| e.is_some())
-            .map_err(|err| {
-                error!(?err, ?group_name, "Unable to check group");
-                Error::KanidmClient
-            })
+            .map_err(|err| Error::client_for("check for group", group_name, err))
     }
 
     pub async fn group_create(&self, group_name: &str) -> Result<(), Error> {
         self.idm_admin_client
             .idm_group_create(group_name, Some("idm_admins"))
             .await
+            .map_err(|err| Error::client_for("create group", group_name, err))
+    }
+
+    /// The description of the group, or None if there is no such group.
+    pub async fn group_description(&self, group_name: &str) -> Result<Option<String>, Error> {
+        let entry = self
+            .idm_admin_client
+            .idm_group_get(group_name)
+            .await
+            .map_err(|err| Error::client_for("read group", group_name, err))?;
+        Ok(entry.map(|mut entry| {
+            entry
+                .attrs
+                .remove("description")
+                .and_then(|values| values.into_iter().next())
+                .unwrap_or_default()
+        }))
+    }
+
+    /// Create a group that is only there for its description, such as the reservation of the
+    /// server by a run.
+    pub async fn group_create_with_description(
+        &self,
+        group_name: &str,
+        description: &str,
+    ) -> Result<(), Error> {
+        let mut attrs = BTreeMap::new();
+        attrs.insert(
+            "class".to_string(),
+            vec!["object".to_string(), "group".to_string()],
+        );
+        attrs.insert("name".to_string(), vec![group_name.to_string()]);
+        attrs.insert("description".to_string(), vec![description.to_string()]);
+        attrs.insert(
+            "entry_managed_by".to_string(),
+            vec!["idm_admins".to_string()],
+        );
+
+        self.idm_admin_client
+            .create(vec![Entry { attrs }])
+            .await
+            .map_err(|err| Error::client_for("create group", group_name, err))
+    }
+
+    pub async fn group_delete(&self, group_name: &str) -> Result<(), Error> {
+        self.idm_admin_client
+            .idm_group_delete(group_name)
+            .await
+            .map_err(|err| Error::client_for("delete group", group_name, err))
+    }
+
+    pub async fn managed_group_create(
+        &self,
+        group_name: &str,
+        managed_by: &str,
+    ) -> Result<(), Error> {
+        self.idm_admin_client
+            .idm_group_create(group_name, Some(managed_by))
+            .await
+            .map_err(|err| {
+                Error::client_for(
+                    format!("create the group managed by '{managed_by}'"),
+                    group_name,
+                    err,
+                )
+            })
+    }
+
+    pub async fn group_set_managed_by(
+        &self,
+        group_name: &str,
+        managed_by: &str,
+    ) -> Result<(), Error> {
+        self.idm_admin_client
+            .idm_group_set_entry_managed_by(group_name, managed_by)
+            .await
             .map_err(|err| {
-                error!(?err, ?group_name, "Unable to create group");
-                Error::KanidmClient
+                Error::client_for(
+                    format!("set '{managed_by}' as the manager of group"),
+                    group_name,
+                    err,
+                )
             })
     }
+
+    pub async fn access_control_exists(&self, name: &str) -> Result<bool, Error> {
+        let filter = Filter::And(vec![
+            Filter::Eq("class".to_string(), "access_control_profile".to_string()),
+            Filter::Eq("name".to_string(), name.to_string()),
+        ]);
+
+        self.admin_client
+            .search(filter)
+            .await
+            .map(|entries| !entries.is_empty())
+            .map_err(|err| Error::client_for("check for access control", name, err))
+    }
+
+    /// Create an access control that lets members of `receiver_group` search `search_attrs`
+    /// of the persons that are members of `target_group`.
+    pub async fn access_control_search_create(
+        &self,
+        name: &str,
+        receiver_group: &str,
+        target_group: &str,
+        search_attrs: &[String],
+    ) -> Result<(), Error> {
+        let target_scope = Filter::And(vec![
+            Filter::Eq("class".to_string(), "person".to_string()),
+            Filter::Eq("memberof".to_string(), target_group.to_string()),
+        ]);
+        let target_scope = serde_json::to_string(&target_scope).map_err(|json_err| {
+            Error::json(
+                format!("serialise the target scope of access control '{name}'"),
+                json_err,
+            )
+        })?;
+
+        let mut attrs = BTreeMap::new();
+        attrs.insert(
+            "class".to_string(),
+            vec![
+                "object".to_string(),
+                "access_control_profile".to_string(),
+                "access_control_receiver_group".to_string(),
+                "access_control_target_scope".to_string(),
+                "access_control_search".to_string(),
+            ],
+        );
+        attrs.insert("name".to_string(), vec![name.to_string()]);
+        attrs.insert(
+            "description".to_string(),
+            vec!["Orca generated access control".to_string()],
+        );
+        attrs.insert(
+            "acp_receiver_group".to_string(),
+            vec![receiver_group.to_string()],
+        );
+        attrs.insert("acp_targetscope".to_string(), vec![target_scope]);
+        attrs.insert("acp_search_attr".to_string(), search_attrs.to_vec());
+
+        self.admin_client
+            .create(vec![Entry { attrs }])
+            .await
+            .map_err(|err| Error::client_for("create access control", name, err))
+    }
+
+    pub async fn oauth2_client_exists(&self, name: &str) -> Result<bool, Error> {
+        self.idm_admin_client
+            .idm_oauth2_rs_get(name)
+            .await
+            .map(|e| e.is_some())
+            .map_err(|err| Error::client_for("check for oauth2 client", name, err))
+    }
+
+    pub async fn oauth2_client_create(&self, name: &str, origin: &str) -> Result<(), Error> {
+        self.idm_admin_client
+            .idm_oauth2_rs_basic_create(name, name, origin)
+            .await
+            .map_err(|err| Error::client_for("create oauth2 client", name, err))
+    }
+
+    pub async fn oauth2_client_basic_secret(&self, name: &str) -> Result<String, Error> {
+        self.idm_admin_client
+            .idm_oauth2_rs_get_basic_secret(name)
+            .await
+            .map_err(|err| Error::client_for("get the secret of oauth2 client", name, err))?
+            .ok_or_else(|| {
+                Error::invalid(format!("the oauth2 client '{name}' has no basic secret"))
+            })
+    }
+
+    pub async fn oauth2_client_set_scope_map(
+        &self,
+        name: &str,
+        group_name: &str,
+        scopes: &[String],
+    ) -> Result<(), Error> {
+        let scopes = scopes.iter().map(|s| s.as_str()).collect();
+        self.idm_admin_client
+            .idm_oauth2_rs_update_scope_map(name, group_name, scopes)
+            .await
+            .map_err(|err| {
+                Error::client_for(
+                    format!("set the scope map of '{group_name}' on oauth2 client"),
+                    name,
+                    err,
+                )
+            })
+    }
+
+    pub async fn oauth2_client_set_claim_map(
+        &self,
+        name: &str,
+        claim_name: &str,
+        group_name: &str,
+        values: &[String],
+    ) -> Result<(), Error> {
+        self.idm_admin_client
+            .idm_oauth2_rs_update_claim_map(name, claim_name, group_name, values)
+            .await
+            .map_err(|err| {
+                Error::client_for(
+                    format!("set the claim map of '{claim_name}' on oauth2 client"),
+                    name,
+                    err,
+                )
+            })
+    }
+
+    pub async fn sync_account_exists(&self, name: &str) -> Result<bool, Error> {
+        self.idm_admin_client
+            .idm_sync_account_get(name)
+            .await
+            .map(|e| e.is_some())
+            .map_err(|err| Error::client_for("check for sync account", name, err))
+    }
+
+    pub async fn sync_account_create(&self, name: &str) -> Result<(), Error> {
+        self.idm_admin_client
+            .idm_sync_account_create(name, Some("orca simulated external idm"))
+            .await
+            .map_err(|err| Error::client_for("create sync account", name, err))
+    }
+
+    /// Issue a new sync token for the account. This replaces any token issued before.
+    pub async fn sync_account_generate_token(&self, name: &str) -> Result<String, Error> {
+        self.idm_admin_client
+            .idm_sync_account_generate_token(name, "orca")
+            .await
+            .map_err(|err| Error::client_for("generate a token for sync account", name, err))
+    }
 }
@@ -0,0 +1,160 @@
+use crate::profile::LoadPhaseProperties;
+use crate::run::Signal;
+
+use tokio::sync::{broadcast, Semaphore};
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// How often a paused actor checks whether the schedule lets it run again.
+const PAUSED_POLL: Duration = Duration::from_millis(250);
+
+struct Phase {
+    name: Arc<str>,
+    start: Duration,
+    duration: Duration,
+    actors_before: usize,
+    actors: usize,
+    ramp: bool,
+    // Shared by the actors of the cohort, for as long as their actions run.
+    permits: Option<Arc<Semaphore>>,
+}
+
+/// How many of the actors of a cohort run at each point of the test. Actors are numbered
+/// in the order they were started, and the ones numbered past what the current phase allows
+/// wait until a later phase lets them run again.
+pub struct LoadSchedule {
+    // The end of the warmup, where the first phase starts.
+    start: Instant,
+    phases: Vec<Phase>,
+}
+
+impl LoadSchedule {
+    pub fn new(phases: &[LoadPhaseProperties], start: Instant) -> Self {
+        let mut offset = Duration::ZERO;
+        let mut actors_before = 0;
+        let phases = phases
+            .iter()
+            .map(|properties| {
+                let phase = Phase {
+                    name: Arc::from(properties.name.as_str()),
+                    start: offset,
+                    duration: Duration::from_secs(properties.duration),
+                    actors_before,
+                    actors: properties.actors,
+                    ramp: properties.ramp,
+                    permits: properties
+                        .max_concurrency
+                        .map(|max_concurrency| Arc::new(Semaphore::new(max_concurrency))),
+                };
+                offset += phase.duration;
+                actors_before = properties.actors;
+                phase
+            })
+            .collect();
+        LoadSchedule { start, phases }
+    }
+
+    // The phase at `at`. The first one also covers the warmup, and the last one carries on
+    // until the test ends.
+    fn phase(&self, at: Instant) -> Option<&Phase> {
+        let elapsed = at.saturating_duration_since(self.start);
+        self.phases
+            .iter()
+            .take_while(|phase| phase.start <= elapsed)
+            .last()
+            .or_else(|| self.phases.first())
+    }
+
+    /// The name of the phase the test was in at `at`, to tag the events with.
+    pub fn phase_name(&self, at: Instant) -> Option<Arc<str>> {
+        self.phase(at).map(|phase| phase.name.clone())
+    }
+
+    /// The permits an action that starts at `at` takes one of, if the phase limits how many
+    /// actions are in flight at once.
+    pub fn phase_permits(&self, at: Instant) -> Option<Arc<Semaphore>> {
+        self.phase(at).and_then(|phase| phase.permits.clone())
+    }
+
+    /// How many actors may run at `at`.
+    pub fn active_actors(&self, at: Instant) -> usize {
+        let Some(phase) = self.phase(at) else {
+            return 0;
+        };
+        if !phase.ramp {
+            return phase.actors;
+        }
+
+        let into_phase = at
+            .saturating_duration_since(self.start)
+            .saturating_sub(phase.start)
+            .min(phase.duration);
+        let progress = into_phase.as_secs_f64() / phase.duration.as_secs_f64();
+        let change = (phase.actors as f64 - phase.actors_before as f64) * progress;
+        (phase.actors_before as f64 + change).round() as usize
+    }
+
+    /// Wait until the actor numbered `actor` may run. Returns false if the actors were told
+    /// to stop meanwhile.
+    pub async fn wait_until_active(
+        &self,
+        actor: usize,
+        actor_rx: &mut broadcast::Receiver<Signal>,
+    ) -> bool {
+        while actor >= self.active_actors(Instant::now()) {
+            tokio::select! {
+                _ = tokio::time::sleep(PAUSED_POLL) => {}
+                _ = actor_rx.recv() => return false,
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LoadSchedule;
+    use crate::profile::LoadPhaseProperties;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_schedule_ramps_holds_and_spikes() {
+        let phase = |name: &str, duration, actors, ramp| LoadPhaseProperties {
+            name: name.to_string(),
+            duration,
+            actors,
+            ramp,
+            max_concurrency: None,
+        };
+        let start = Instant::now();
+        let mut spike = phase("spike", 10, 1000, false);
+        spike.max_concurrency = Some(50);
+        let schedule = LoadSchedule::new(
+            &[
+                phase("ramp", 100, 500, true),
+                phase("hold", 100, 500, false),
+                spike,
+            ],
+            start,
+        );
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // The warmup is part of the first phase, before its ramp starts.
+        assert_eq!(schedule.active_actors(start - Duration::from_secs(5)), 0);
+        assert_eq!(schedule.active_actors(at(50)), 250);
+        assert_eq!(schedule.active_actors(at(150)), 500);
+        assert_eq!(schedule.phase_name(at(150)).as_deref(), Some("hold"));
+        assert_eq!(schedule.active_actors(at(205)), 1000);
+        // Only the spike limits how many actions are in flight.
+        assert!(schedule.phase_permits(at(150)).is_none());
+        assert_eq!(
+            schedule
+                .phase_permits(at(205))
+                .map(|permits| permits.available_permits()),
+            Some(50)
+        );
+        // The last phase carries on until the test ends.
+        assert_eq!(schedule.phase_name(at(500)).as_deref(), Some("spike"));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::error::Error;
+use crate::profile::LoggingProperties;
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// The file sink of the tracing events. It's installed along with the terminal output, but
+/// stays disabled until a profile asks for it, and is filtered independently of RUST_LOG.
+pub struct LogFile {
+    filter: reload::Handle<EnvFilter, Registry>,
+    writer: FileWriter,
+}
+
+/// Install the global subscriber. The terminal keeps logging what RUST_LOG asks for.
+pub fn init() -> LogFile {
+    let (file_filter, filter) = reload::Layer::new(EnvFilter::new("off"));
+    let writer = FileWriter::default();
+
+    tracing_subscriber::registry()
+        .with(
+            fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer.clone())
+                .with_filter(file_filter),
+        )
+        .with(fmt::layer().with_filter(EnvFilter::from_default_env()))
+        .init();
+
+    LogFile { filter, writer }
+}
+
+impl LogFile {
+    /// Start writing to the file of the profile, if it has one. Only one file is written
+    /// to per invocation, so any later ones are ignored.
+    pub fn start(&self, properties: &LoggingProperties) -> Result<(), Error> {
+        let Some(path) = properties.file.as_deref() else {
+            return Ok(());
+        };
+
+        let filter = EnvFilter::try_new(&properties.filter).map_err(|filter_err| {
+            Error::profile(format!(
+                "invalid log file filter '{}': {filter_err}",
+                properties.filter
+            ))
+        })?;
+
+        let file = RotatingFile::open(
+            path,
+            properties.max_file_size_mb * 1024 * 1024,
+            properties.max_files,
+        )
+        .map_err(|io_err| Error::file("open the log file", path, io_err))?;
+
+        if self.writer.0.set(Mutex::new(file)).is_err() {
+            warn!(path = %path.display(), "Already logging to a file, ignoring this one");
+            return Ok(());
+        }
+
+        self.filter.reload(filter).map_err(|reload_err| {
+            Error::invalid(format!("unable to enable the log file: {reload_err}"))
+        })?;
+
+        info!("Logging to {}", path.display());
+        Ok(())
+    }
+}
+
+// Events are dropped until the file has been opened.
+#[derive(Clone, Default)]
+struct FileWriter(Arc<OnceLock<Mutex<RotatingFile>>>);
+
+impl<'a> MakeWriter<'a> for FileWriter {
+    type Writer = FileWriterGuard<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        // A poisoned lock only means that another thread panicked mid-write.
+        FileWriterGuard(
+            self.0
+                .get()
+                .map(|file| file.lock().unwrap_or_else(|poisoned| poisoned.into_inner())),
+        )
+    }
+}
+
+struct FileWriterGuard<'a>(Option<MutexGuard<'a, RotatingFile>>);
+
+impl Write for FileWriterGuard<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A file that is moved aside once it grows too large, keeping a number of the previous
+/// ones as `<path>.1` (the most recent) to `<path>.<max_files>`.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: u32,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: u64, max_files: u32) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        // The oldest file is overwritten by the one before it.
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Each event is written at once, so events aren't split across files.
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RotatingFile;
+    use std::io::Write;
+
+    #[test]
+    fn test_rotating_file_keeps_max_files() {
+        let dir = std::env::temp_dir().join(format!("orca-logging-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("orca.log");
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in [
+            "first line\n",
+            "second line\n",
+            "third line\n",
+            "fourth line\n",
+        ] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth line\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("orca.log.1")).unwrap(),
+            "third line\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("orca.log.2")).unwrap(),
+            "second line\n"
+        );
+        assert!(!dir.join("orca.log.3").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -14,25 +14,61 @@ static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 extern crate tracing;
 
 use std::process::ExitCode;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
-use clap::Parser;
-use opt::OrcaOpt;
+use clap::{Parser, ValueEnum};
+use opt::{EventsOpt, ModelOpt, OrcaOpt, ScenariosOpt, StateOpt};
 
+use crate::error::Error;
 use crate::profile::{Profile, ProfileBuilder};
+use crate::reservation::Reservation;
 
 use tokio::{runtime::Runtime, sync::broadcast};
 
+mod assertions;
+mod benchmark;
+mod bulk_create;
+mod checkpoint;
+mod ci;
+mod clock;
+mod credential_provider;
+mod distributed;
 mod error;
+mod events;
 mod generate;
 mod kani;
+mod load_schedule;
+mod logging;
+mod metrics;
 mod model;
+mod model_verify;
 mod models;
 mod opt;
+mod person_selection;
 mod populate;
 mod profile;
+mod report;
+mod reservation;
 mod run;
+mod scaling_curve;
+mod scenario;
+mod schedule;
+mod schema;
+mod self_test;
+mod slow_link;
+mod spool;
 mod state;
+mod state_stats;
 mod stats;
+mod summary;
+
+/// The exit code of a run that completed, but missed one of its latency goals.
+const LATENCY_GOAL_MISSED_EXIT_CODE: u8 = 2;
+/// The exit code of a run that completed, but left the data in a state it shouldn't be in.
+const ASSERTION_FAILED_EXIT_CODE: u8 = 3;
+/// The exit code of a CI run that completed, but regressed from its baseline.
+const REGRESSED_EXIT_CODE: u8 = 4;
 
 impl OrcaOpt {
     fn debug(&self) -> bool {
@@ -42,7 +78,19 @@ impl OrcaOpt {
             | OrcaOpt::TestConnection { common, .. }
             | OrcaOpt::GenerateData { common, .. }
             | OrcaOpt::PopulateData { common, .. }
-            | OrcaOpt::Run { common, .. } => common.debug,
+            | OrcaOpt::BulkCreate { common, .. }
+            | OrcaOpt::Run { common, .. }
+            | OrcaOpt::Ci { common, .. }
+            | OrcaOpt::Worker { common, .. }
+            | OrcaOpt::ScalingCurve { common, .. }
+            | OrcaOpt::SelfTest { common, .. }
+            | OrcaOpt::Benchmark { common, .. }
+            | OrcaOpt::Scenarios(ScenariosOpt::List { common })
+            | OrcaOpt::Events(EventsOpt::Query { common, .. })
+            | OrcaOpt::Model(ModelOpt::Verify { common, .. })
+            | OrcaOpt::State(StateOpt::Extend { common, .. })
+            | OrcaOpt::State(StateOpt::Capture { common, .. })
+            | OrcaOpt::State(StateOpt::Stats { common, .. }) => common.debug,
         }
     }
 }
@@ -57,7 +105,7 @@ fn main() -> ExitCode {
         );
     }
 
-    tracing_subscriber::fmt::init();
+    let log_file = logging::init();
 
     info!("Orca - the Kanidm Load Testing Utility.");
     debug!("cli -> {:?}", opt);
@@ -67,6 +115,22 @@ fn main() -> ExitCode {
             ExitCode::SUCCESS
         }
 
+        OrcaOpt::Scenarios(ScenariosOpt::List { .. }) => {
+            for scenario in scenario::Scenario::value_variants() {
+                if let Some(value) = scenario.to_possible_value() {
+                    println!(
+                        "{:<24} {}",
+                        value.get_name(),
+                        value
+                            .get_help()
+                            .map(|help| help.to_string())
+                            .unwrap_or_default()
+                    );
+                }
+            }
+            ExitCode::SUCCESS
+        }
+
         // Build the profile and the test dimensions.
         OrcaOpt::SetupWizard {
             common: _,
@@ -78,6 +142,7 @@ fn main() -> ExitCode {
             threads,
             model,
             dump_raw_data,
+            scenario,
         } => {
             // For now I hardcoded some dimensions, but we should prompt
             // the user for these later.
@@ -101,18 +166,17 @@ fn main() -> ExitCode {
                 threads,
                 dump_raw_data,
             )
-            .seed(seed);
+            .seed(seed)
+            .scenario(scenario);
 
             let profile = match builder.build() {
                 Ok(p) => p,
-                Err(_err) => {
-                    return ExitCode::FAILURE;
-                }
+                Err(err) => return failure(&err),
             };
 
             match profile.write_to_path(&profile_path) {
                 Ok(_) => ExitCode::SUCCESS,
-                Err(_err) => ExitCode::FAILURE,
+                Err(err) => failure(&err),
             }
         }
 
@@ -123,9 +187,7 @@ fn main() -> ExitCode {
         } => {
             let profile = match Profile::try_from(profile_path.as_path()) {
                 Ok(p) => p,
-                Err(_err) => {
-                    return ExitCode::FAILURE;
-                }
+                Err(err) => return failure(&err),
             };
 
             info!("Performing conntest of {}", profile.control_uri());
@@ -138,7 +200,7 @@ fn main() -> ExitCode {
                         info!("success");
                         ExitCode::SUCCESS
                     }
-                    Err(_err) => ExitCode::FAILURE,
+                    Err(err) => failure(&err),
                 }
             })
         }
@@ -148,36 +210,35 @@ fn main() -> ExitCode {
             common: _,
             profile_path,
             state_path,
+            threads,
         } => {
             let profile = match Profile::try_from(profile_path.as_path()) {
                 Ok(p) => p,
-                Err(_err) => {
-                    return ExitCode::FAILURE;
-                }
+                Err(err) => return failure(&err),
             };
 
-            // This is single threaded.
+            if let Err(err) = log_file.start(profile.logging()) {
+                return failure(&err);
+            }
+
+            // The persons are generated on threads of their own, outside of the runtime.
             let runtime = build_tokio_runtime(Some(1));
 
             runtime.block_on(async {
                 let client = match kani::KanidmOrcaClient::new(&profile).await {
                     Ok(client) => client,
-                    Err(_err) => {
-                        return ExitCode::FAILURE;
-                    }
+                    Err(err) => return failure(&err),
                 };
 
                 // do-it.
-                let state = match generate::populate(&client, profile).await {
+                let state = match generate::populate(&client, profile, threads).await {
                     Ok(s) => s,
-                    Err(_err) => {
-                        return ExitCode::FAILURE;
-                    }
+                    Err(err) => return failure(&err),
                 };
 
                 match state.write_to_path(&state_path) {
                     Ok(_) => ExitCode::SUCCESS,
-                    Err(_err) => ExitCode::FAILURE,
+                    Err(err) => failure(&err),
                 }
             })
         }
@@ -186,39 +247,464 @@ fn main() -> ExitCode {
         OrcaOpt::PopulateData {
             common: _,
             state_path,
+            concurrency,
+            checkpoint_path,
+            dry_run,
         } => {
             let state = match state::State::try_from(state_path.as_path()) {
                 Ok(p) => p,
-                Err(_err) => {
-                    return ExitCode::FAILURE;
-                }
+                Err(err) => return failure(&err),
             };
 
+            if let Err(err) = log_file.start(state.profile.logging()) {
+                return failure(&err);
+            }
+
             // here we want all threads available to speed up the process.
             let runtime = build_tokio_runtime(state.thread_count);
 
             runtime.block_on(async {
-                match populate::preflight(state).await {
+                let options = populate::PreflightOptions {
+                    concurrency,
+                    checkpoint: checkpoint_path,
+                    dry_run,
+                };
+                match populate::preflight(state, &options).await {
                     Ok(_) => ExitCode::SUCCESS,
-                    Err(_err) => ExitCode::FAILURE,
+                    Err(err) => failure(&err),
                 }
             })
         }
 
-        // Run the test based on the state file.
-        OrcaOpt::Run {
+        OrcaOpt::BulkCreate {
+            common: _,
+            profile_path,
+            persons,
+            concurrency,
+            batch_size,
+        } => {
+            let profile = match Profile::try_from(profile_path.as_path()) {
+                Ok(p) => p,
+                Err(err) => return failure(&err),
+            };
+
+            if let Err(err) = log_file.start(profile.logging()) {
+                return failure(&err);
+            }
+
+            let runtime = build_tokio_runtime(profile.thread_count());
+
+            runtime.block_on(async {
+                let client = match kani::KanidmOrcaClient::new(&profile).await {
+                    Ok(client) => std::sync::Arc::new(client),
+                    Err(err) => return failure(&err),
+                };
+
+                let options = bulk_create::BulkCreateOptions {
+                    person_count: persons,
+                    concurrency,
+                    batch_size,
+                };
+                match bulk_create::execute(client, &options).await {
+                    Ok(result) => {
+                        println!("{result}");
+                        ExitCode::SUCCESS
+                    }
+                    Err(err) => failure(&err),
+                }
+            })
+        }
+
+        OrcaOpt::State(StateOpt::Extend {
             common: _,
             state_path,
+            persons,
+        }) => {
+            let mut state = match state::State::try_from(state_path.as_path()) {
+                Ok(p) => p,
+                Err(err) => return failure(&err),
+            };
+
+            if let Err(err) = generate::extend(&mut state, persons) {
+                return failure(&err);
+            }
+
+            match state.write_to_path(&state_path) {
+                Ok(_) => ExitCode::SUCCESS,
+                Err(err) => failure(&err),
+            }
+        }
+
+        OrcaOpt::Events(EventsOpt::Query {
+            common: _,
+            input_path,
+            events,
+            from_s,
+            to_s,
+            outcome,
+            cohort,
+            phase,
+            format,
+            output_path,
+        }) => {
+            let query = events::EventQuery {
+                events,
+                from_s,
+                to_s,
+                outcome,
+                cohort,
+                phase,
+            };
+            let result = match output_path {
+                Some(output_path) => match std::fs::File::create(&output_path) {
+                    Ok(output) => events::query(&input_path, &query, format, output),
+                    Err(io_err) => {
+                        return failure(&Error::file(
+                            "create the output file",
+                            output_path,
+                            io_err,
+                        ));
+                    }
+                },
+                None => events::query(&input_path, &query, format, std::io::stdout().lock()),
+            };
+
+            match result {
+                Ok(matched) => {
+                    info!("{matched} events matched");
+                    ExitCode::SUCCESS
+                }
+                Err(err) => failure(&err),
+            }
+        }
+
+        OrcaOpt::Model(ModelOpt::Verify {
+            common: _,
+            profile_path,
+            actors,
+            steps,
+        }) => {
+            let profile = match Profile::try_from(profile_path.as_path()) {
+                Ok(p) => p,
+                Err(err) => return failure(&err),
+            };
+
+            match model_verify::verify(profile, actors, steps) {
+                Ok(report) => {
+                    println!("{report}");
+                    if report.is_valid() {
+                        ExitCode::SUCCESS
+                    } else {
+                        ExitCode::FAILURE
+                    }
+                }
+                Err(err) => failure(&err),
+            }
+        }
+
+        OrcaOpt::State(StateOpt::Stats {
+            common: _,
+            state_path,
+        }) => {
+            let state = match state::State::try_from(state_path.as_path()) {
+                Ok(p) => p,
+                Err(err) => return failure(&err),
+            };
+
+            println!("{}", state_stats::StateStats::from(&state));
+            ExitCode::SUCCESS
+        }
+
+        OrcaOpt::State(StateOpt::Capture {
+            common: _,
+            profile_path,
+            state_path,
+            reset_credentials,
+        }) => {
+            let profile = match Profile::try_from(profile_path.as_path()) {
+                Ok(p) => p,
+                Err(err) => return failure(&err),
+            };
+
+            if let Err(err) = log_file.start(profile.logging()) {
+                return failure(&err);
+            }
+
+            let runtime = build_tokio_runtime(Some(1));
+
+            runtime.block_on(async {
+                let client = match kani::KanidmOrcaClient::new(&profile).await {
+                    Ok(client) => client,
+                    Err(err) => return failure(&err),
+                };
+
+                let state = match generate::capture(&client, profile, reset_credentials).await {
+                    Ok(s) => s,
+                    Err(err) => return failure(&err),
+                };
+
+                match state.write_to_path(&state_path) {
+                    Ok(_) => ExitCode::SUCCESS,
+                    Err(err) => failure(&err),
+                }
+            })
+        }
+
+        OrcaOpt::SelfTest {
+            common: _,
+            requests,
+            concurrency,
+            tolerance_ms,
+        } => {
+            if requests == 0 || concurrency == 0 {
+                error!("The self test needs at least one request, and one in flight");
+                return ExitCode::FAILURE;
+            }
+
+            let runtime = build_tokio_runtime(None);
+            runtime.block_on(async {
+                match self_test::run(requests, concurrency, tolerance_ms).await {
+                    Ok(()) => ExitCode::SUCCESS,
+                    Err(err) => failure(&err),
+                }
+            })
+        }
+
+        OrcaOpt::Benchmark {
+            common: _,
+            profile_path,
+            actors,
+            duration,
+        } => {
+            let profile = match Profile::try_from(profile_path.as_path()) {
+                Ok(p) => p,
+                Err(err) => return failure(&err),
+            };
+
+            let runtime = build_tokio_runtime(None);
+            runtime.block_on(async {
+                match benchmark::run(profile, actors, Duration::from_secs(duration)).await {
+                    Ok(result) => {
+                        println!(
+                            "{} actors generated {} events in {:.1}s, {:.0} events/s",
+                            result.actors,
+                            result.generated,
+                            result.elapsed.as_secs_f64(),
+                            result.events_per_second()
+                        );
+                        println!(
+                            "the statistics counted {} requests, in {:.1}s after the window ended",
+                            result.aggregated,
+                            result.aggregation_time.as_secs_f64()
+                        );
+                        ExitCode::SUCCESS
+                    }
+                    Err(err) => failure(&err),
+                }
+            })
+        }
+
+        OrcaOpt::ScalingCurve {
+            common: _,
+            state_path,
+            sizes,
+            force,
         } => {
             let state = match state::State::try_from(state_path.as_path()) {
                 Ok(p) => p,
-                Err(_err) => {
-                    return ExitCode::FAILURE;
+                Err(err) => return failure(&err),
+            };
+
+            if let Err(err) = log_file.start(state.profile.logging()) {
+                return failure(&err);
+            }
+
+            let runtime = build_tokio_runtime(state.thread_count);
+            runtime.block_on(async {
+                let (control_tx, control_rx) = broadcast::channel(8);
+
+                let reservation = match Reservation::take(&state.profile, None, force).await {
+                    Ok(reservation) => reservation,
+                    Err(err) => return failure(&err),
+                };
+
+                let mut curve_execute = tokio::task::spawn(reservation.hold(async move {
+                    scaling_curve::execute(&state_path, &sizes, control_rx).await
+                }));
+
+                loop {
+                    tokio::select! {
+                        result = &mut curve_execute => {
+                            return match result {
+                                Ok(Ok(())) => ExitCode::SUCCESS,
+                                Ok(Err(err)) => failure(&err),
+                                Err(join_err) => failure(&Error::tokio("complete the task", join_err)),
+                            };
+                        }
+                        Ok(()) = tokio::signal::ctrl_c() => {
+                            info!("Stopping Task ...");
+                            let _ = control_tx.send(run::Signal::Stop);
+                        }
+                    }
+                }
+            })
+        }
+
+        OrcaOpt::Ci {
+            common: _,
+            profile_path,
+            output_dir,
+            baseline_path,
+            max_regression_pct,
+            force,
+        } => {
+            let profile = match Profile::try_from(profile_path.as_path()) {
+                Ok(p) => p,
+                Err(err) => return failure(&err),
+            };
+            // Read up front, so that a run isn't wasted on a baseline that can't be read.
+            let baseline = match baseline_path.as_deref().map(summary::read_baseline) {
+                Some(Ok(baseline)) => Some(baseline),
+                Some(Err(err)) => return failure(&err),
+                None => None,
+            };
+
+            if let Err(err) = log_file.start(profile.logging()) {
+                return failure(&err);
+            }
+
+            let runtime = build_tokio_runtime(profile.thread_count());
+            runtime.block_on(async {
+                let (control_tx, control_rx) = broadcast::channel(8);
+                let options = ci::CiOptions {
+                    output_dir,
+                    baseline,
+                    max_regression_pct,
+                };
+
+                let reservation = match Reservation::take(&profile, None, force).await {
+                    Ok(reservation) => reservation,
+                    Err(err) => return failure(&err),
+                };
+
+                let mut ci_execute = tokio::task::spawn(
+                    reservation.hold(async move { ci::run(profile, &options, control_rx).await }),
+                );
+
+                loop {
+                    tokio::select! {
+                        result = &mut ci_execute => {
+                            return match result {
+                                Ok(Ok(())) => ExitCode::SUCCESS,
+                                Ok(Err(Error::LatencyGoalMissed)) => {
+                                    ExitCode::from(LATENCY_GOAL_MISSED_EXIT_CODE)
+                                }
+                                Ok(Err(Error::AssertionFailed)) => {
+                                    ExitCode::from(ASSERTION_FAILED_EXIT_CODE)
+                                }
+                                Ok(Err(Error::Regressed)) => ExitCode::from(REGRESSED_EXIT_CODE),
+                                Ok(Err(err)) => failure(&err),
+                                Err(join_err) => failure(&Error::tokio("complete the task", join_err)),
+                            };
+                        }
+                        Ok(()) = tokio::signal::ctrl_c() => {
+                            info!("Stopping Task ...");
+                            let _ = control_tx.send(run::Signal::Stop);
+                        }
+                    }
+                }
+            })
+        }
+
+        // Run the persons a coordinator gives us.
+        OrcaOpt::Worker {
+            common: _,
+            coordinator,
+            threads,
+        } => {
+            let runtime = build_tokio_runtime(threads);
+            runtime.block_on(async {
+                let (control_tx, _control_rx) = broadcast::channel(8);
+
+                let c_control_tx = control_tx.clone();
+                let mut work_execute = tokio::task::spawn(async move {
+                    distributed::work(&coordinator, c_control_tx).await
+                });
+
+                loop {
+                    tokio::select! {
+                        result = &mut work_execute => {
+                            return match result {
+                                Ok(Ok(())) => ExitCode::SUCCESS,
+                                Ok(Err(err)) => failure(&err),
+                                Err(join_err) => failure(&Error::tokio("complete the task", join_err)),
+                            };
+                        }
+                        Ok(()) = tokio::signal::ctrl_c() => {
+                            info!("Stopping Task ...");
+                            let _ = control_tx.send(run::Signal::Stop);
+                        }
+                    }
                 }
+            })
+        }
+
+        // Run the test based on the state file.
+        OrcaOpt::Run {
+            common: _,
+            state_paths,
+            start_at,
+            ntp_server,
+            max_clock_offset_ms,
+            coordinate,
+            workers,
+            checkpoint_path,
+            checkpoint_interval,
+            summary_path,
+            summary_format,
+            baseline_path,
+            force,
+        } => {
+            // Read up front, so that a run isn't wasted on a baseline that can't be read.
+            let baseline = match baseline_path.as_deref().map(summary::read_baseline) {
+                Some(Ok(baseline)) => Some(baseline),
+                Some(Err(err)) => return failure(&err),
+                None => None,
             };
+
+            let mut cohorts = Vec::with_capacity(state_paths.len());
+            for state_path in state_paths.iter() {
+                let state = match state::State::try_from(state_path.as_path()) {
+                    Ok(p) => p,
+                    Err(err) => return failure(&err),
+                };
+                // Each state is reported as a cohort named after its file.
+                let name = state_path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| state_path.display().to_string());
+                cohorts.push(run::Cohort { name, state });
+            }
+
+            for cohort in cohorts.iter() {
+                if let Err(err) = log_file.start(cohort.state.profile.logging()) {
+                    return failure(&err);
+                }
+            }
+
+            // The cohorts run side by side, so they get the threads they each asked for.
+            let thread_count = cohorts
+                .iter()
+                .map(|cohort| cohort.state.thread_count)
+                .sum::<Option<usize>>();
+            let warmup = cohorts
+                .iter()
+                .map(|cohort| cohort.state.profile.warmup_time())
+                .max()
+                .unwrap_or_default();
             // here we need to create one less worker compared to the desired amount since we later call `spawn_blocking`, which consumes
             // an extra thread all on its own
-            let runtime = build_tokio_runtime(state.thread_count);
+            let runtime = build_tokio_runtime(thread_count);
             // We have a broadcast channel setup for controlling the state of
             // various actors and parts.
             //
@@ -227,7 +713,58 @@ fn main() -> ExitCode {
             runtime.block_on(async {
                 let (control_tx, control_rx) = broadcast::channel(8);
 
-                let mut run_execute = tokio::task::spawn(run::execute(state, control_rx));
+                // Taken before waiting for the start, so that a clashing run is refused up front.
+                let Some(profile) = cohorts.first().map(|cohort| &cohort.state.profile) else {
+                    return failure(&Error::invalid("the run has no states"));
+                };
+                let reservation = match Reservation::take(profile, start_at, force).await {
+                    Ok(reservation) => reservation,
+                    Err(err) => return failure(&err),
+                };
+
+                let mut run_execute = tokio::task::spawn(reservation.hold(async move {
+                    let mut control_rx = control_rx;
+                    if let Some(start_at) = start_at {
+                        if let Some(ntp_server) = ntp_server {
+                            schedule::check_clock(
+                                &ntp_server,
+                                Duration::from_millis(max_clock_offset_ms),
+                            )
+                            .await?;
+                        }
+                        schedule::wait_for_start(start_at, warmup, &mut control_rx).await?;
+                    }
+                    let summary = Arc::new(OnceLock::new());
+                    let result = match (coordinate, workers, checkpoint_path) {
+                        (Some(listen), Some(workers), _) => {
+                            distributed::coordinate(
+                                cohorts,
+                                listen,
+                                workers,
+                                control_rx,
+                                summary.clone(),
+                            )
+                            .await
+                        }
+                        (_, _, Some(checkpoint_path)) => {
+                            checkpoint::execute(
+                                cohorts,
+                                control_rx,
+                                &checkpoint_path,
+                                Duration::from_secs(checkpoint_interval),
+                                summary.clone(),
+                            )
+                            .await
+                        }
+                        _ => run::execute_with_summary(cohorts, control_rx, summary.clone()).await,
+                    };
+
+                    // A run that missed its goals or failed its assertions still has a summary.
+                    if let (Some(summary_path), Some(summary)) = (summary_path, summary.get()) {
+                        summary::write(&summary_path, summary_format, summary, baseline.as_ref())?;
+                    }
+                    result
+                }));
 
                 loop {
                     tokio::select! {
@@ -235,11 +772,19 @@ fn main() -> ExitCode {
                         // but not be consumed each loop iteration.
                         result = &mut run_execute => {
                             match result {
-                                Ok(_) => {
+                                Ok(Ok(())) => {
                                     return ExitCode::SUCCESS;
                                 }
-                                Err(_err) => {
-                                    return ExitCode::FAILURE;
+                                // Set apart, so that a CI job can tell a slow server from a broken run.
+                                Ok(Err(Error::LatencyGoalMissed)) => {
+                                    return ExitCode::from(LATENCY_GOAL_MISSED_EXIT_CODE);
+                                }
+                                Ok(Err(Error::AssertionFailed)) => {
+                                    return ExitCode::from(ASSERTION_FAILED_EXIT_CODE);
+                                }
+                                Ok(Err(err)) => return failure(&err),
+                                Err(join_err) => {
+                                    return failure(&Error::tokio("complete the task", join_err));
                                 }
                             };
                         }
@@ -291,6 +836,12 @@ fn main() -> ExitCode {
     }
 }
 
+// The error explains the failure on its own, so it's logged once, as orca exits on it.
+fn failure(err: &Error) -> ExitCode {
+    error!("{}", err.report());
+    ExitCode::FAILURE
+}
+
 /// Build the tokio runtime with the configured number of threads. If set to None, then the maximum
 /// of the system is used.
 fn build_tokio_runtime(threads: Option<usize>) -> Runtime {
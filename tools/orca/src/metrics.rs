@@ -0,0 +1,272 @@
+use crate::error::Error;
+use crate::run::{EventDetail, EventRecord};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// The upper bounds of the latency buckets, in seconds.
+const LATENCY_BUCKETS_S: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10.];
+// Scrape requests are tiny, anything bigger isn't a scrape.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// Metrics of the run that are kept up to date as the actors produce their events, so that
+/// a long run can be watched while it goes on rather than only once it's over.
+#[derive(Default)]
+pub struct LiveMetrics {
+    actors_running: AtomicI64,
+    counters: Mutex<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    transitions: BTreeMap<String, u64>,
+    latencies: BTreeMap<String, LatencyHistogram>,
+    errors: BTreeMap<(String, Cow<'static, str>), u64>,
+    rejections: BTreeMap<String, u64>,
+}
+
+#[derive(Default)]
+struct LatencyHistogram {
+    // One per bucket, counting the events up to its bound only.
+    buckets: Vec<u64>,
+    sum_s: f64,
+    count: u64,
+}
+
+pub struct ActorRunning<'a>(&'a AtomicI64);
+
+impl Drop for ActorRunning<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, duration_s: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS_S.len()];
+        }
+        if let Some(bucket) = LATENCY_BUCKETS_S
+            .iter()
+            .position(|bound| duration_s <= *bound)
+        {
+            self.buckets[bucket] += 1;
+        }
+        self.sum_s += duration_s;
+        self.count += 1;
+    }
+}
+
+impl LiveMetrics {
+    /// Count an actor as running until the returned guard is dropped.
+    pub fn actor_running(&self) -> ActorRunning<'_> {
+        self.actors_running.fetch_add(1, Ordering::Relaxed);
+        ActorRunning(&self.actors_running)
+    }
+
+    pub fn record(&self, record: &EventRecord) {
+        #[allow(clippy::unwrap_used)]
+        let mut counters = self.counters.lock().unwrap();
+        match &record.details {
+            EventDetail::Transition(action) => {
+                *counters
+                    .transitions
+                    .entry(format!("{action:?}"))
+                    .or_default() += 1;
+            }
+            EventDetail::Error(failure) | EventDetail::Timeout(failure) => {
+                *counters
+                    .errors
+                    .entry((format!("{:?}", failure.event), failure.class.clone()))
+                    .or_default() += 1;
+            }
+            EventDetail::Rejected(failure) => {
+                *counters
+                    .rejections
+                    .entry(format!("{:?}", failure.event))
+                    .or_default() += 1;
+            }
+            EventDetail::DataSize(_)
+            | EventDetail::Attempts(_)
+            | EventDetail::BackoffRespected
+            | EventDetail::BackoffViolated => {}
+            details => {
+                counters
+                    .latencies
+                    .entry(format!("{details:?}"))
+                    .or_default()
+                    .record(record.duration.as_secs_f64());
+            }
+        }
+    }
+
+    /// The metrics in the Prometheus text format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP orca_actors_running The actors that are running."
+        );
+        let _ = writeln!(out, "# TYPE orca_actors_running gauge");
+        let _ = writeln!(
+            out,
+            "orca_actors_running {}",
+            self.actors_running.load(Ordering::Relaxed)
+        );
+
+        #[allow(clippy::unwrap_used)]
+        let counters = self.counters.lock().unwrap();
+
+        let _ = writeln!(
+            out,
+            "# HELP orca_transitions_total The actions the models went through."
+        );
+        let _ = writeln!(out, "# TYPE orca_transitions_total counter");
+        for (action, count) in counters.transitions.iter() {
+            let _ = writeln!(out, "orca_transitions_total{{action=\"{action}\"}} {count}");
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP orca_event_duration_seconds How long the events that succeeded took."
+        );
+        let _ = writeln!(out, "# TYPE orca_event_duration_seconds histogram");
+        for (event, histogram) in counters.latencies.iter() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS_S.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "orca_event_duration_seconds_bucket{{event=\"{event}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "orca_event_duration_seconds_bucket{{event=\"{event}\",le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "orca_event_duration_seconds_sum{{event=\"{event}\"}} {}",
+                histogram.sum_s
+            );
+            let _ = writeln!(
+                out,
+                "orca_event_duration_seconds_count{{event=\"{event}\"}} {}",
+                histogram.count
+            );
+        }
+
+        let _ = writeln!(out, "# HELP orca_errors_total The events that failed.");
+        let _ = writeln!(out, "# TYPE orca_errors_total counter");
+        for ((event, class), count) in counters.errors.iter() {
+            let _ = writeln!(
+                out,
+                "orca_errors_total{{event=\"{event}\",class=\"{class}\"}} {count}"
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP orca_rejections_total The events the server refused under its access policy."
+        );
+        let _ = writeln!(out, "# TYPE orca_rejections_total counter");
+        for (event, count) in counters.rejections.iter() {
+            let _ = writeln!(out, "orca_rejections_total{{event=\"{event}\"}} {count}");
+        }
+
+        out
+    }
+}
+
+/// Serve the metrics for Prometheus to scrape, on any path, until orca exits.
+pub async fn start(listen: SocketAddr, metrics: Arc<LiveMetrics>) -> Result<(), Error> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .map_err(|io_err| Error::io(format!("bind the metrics endpoint {listen}"), io_err))?;
+
+    tokio::spawn(async move {
+        loop {
+            let client = match listener.accept().await {
+                Ok((client, _)) => client,
+                Err(io_err) => {
+                    error!(?io_err, "Metrics endpoint stopped accepting connections");
+                    return;
+                }
+            };
+            let metrics = metrics.clone();
+            tokio::spawn(async move {
+                if let Err(io_err) = respond(client, &metrics).await {
+                    debug!(?io_err, "Metrics scrape failed");
+                }
+            });
+        }
+    });
+
+    info!("Serving live metrics on http://{listen}/metrics");
+    Ok(())
+}
+
+async fn respond(mut client: TcpStream, metrics: &LiveMetrics) -> std::io::Result<()> {
+    // Whatever was asked for, the answer is the same, so the request is only read to its end.
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = client.read(&mut buf).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_LEN {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+
+    let body = metrics.render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    client.write_all(response.as_bytes()).await?;
+    client.shutdown().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::LiveMetrics;
+    use crate::model::TransitionAction;
+    use crate::run::{EventDetail, EventRecord, Failure};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_render_counts_events_into_cumulative_buckets() {
+        let metrics = LiveMetrics::default();
+        let _running = metrics.actor_running();
+        let record = |duration_ms, details| EventRecord {
+            start: Instant::now(),
+            duration: Duration::from_millis(duration_ms),
+            details,
+        };
+        metrics.record(&record(3, EventDetail::Login));
+        metrics.record(&record(40, EventDetail::Login));
+        metrics.record(&record(0, EventDetail::Transition(TransitionAction::Login)));
+        metrics.record(&record(
+            1,
+            EventDetail::Error(Failure::new(EventDetail::Login, "Http", "")),
+        ));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("orca_actors_running 1\n"));
+        assert!(rendered.contains("orca_transitions_total{action=\"Login\"} 1\n"));
+        assert!(rendered
+            .contains("orca_event_duration_seconds_bucket{event=\"Login\",le=\"0.005\"} 1\n"));
+        assert!(rendered
+            .contains("orca_event_duration_seconds_bucket{event=\"Login\",le=\"0.05\"} 2\n"));
+        assert!(rendered.contains("orca_event_duration_seconds_count{event=\"Login\"} 2\n"));
+        assert!(rendered.contains("orca_errors_total{event=\"Login\",class=\"Http\"} 1\n"));
+    }
+}
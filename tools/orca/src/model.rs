@@ -1,13 +1,38 @@
 use crate::error::Error;
-use crate::run::{EventDetail, EventRecord};
+use crate::profile::{PasskeyAttestation, PasskeyProperties, RetryProperties};
+use crate::run::{AttemptsSample, EventDetail, EventRecord, Failure};
 use crate::state::*;
-use std::time::{Duration, Instant};
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime};
 
 use kanidm_client::{ClientError, KanidmClient};
+use kanidm_proto::constants::uri::{OAUTH2_AUTHORISE, OAUTH2_AUTHORISE_PERMIT};
+use kanidm_proto::constants::{ATTR_ACCOUNT_EXPIRE, ATTR_ACCOUNT_VALID_FROM, KOPID};
+use kanidm_proto::internal::{CURegState, Filter, Modify, ModifyList, TotpAlgo, TotpSecret};
+use kanidm_proto::oauth2::{
+    AccessTokenRequest, AccessTokenResponse, AuthorisationRequest, AuthorisationResponse,
+    CodeChallengeMethod, GrantTypeReq, PkceRequest,
+};
+use kanidm_proto::v1::{AuthAllowed, AuthMech, AuthState};
+use kanidm_proto::webauthn::{
+    CreationChallengeResponse, PublicKeyCredential, RegisterPublicKeyCredential,
+    RequestChallengeResponse, ResidentKeyRequirement,
+};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use rand::distributions::{Alphanumeric, DistString};
+use url::Url;
+use webauthn_authenticator_rs::softpasskey::SoftPasskey;
+use webauthn_authenticator_rs::softtoken::SoftToken;
+use webauthn_authenticator_rs::{WebauthnAuthenticator, WebauthnCError};
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TransitionAction {
     Login,
     Logout,
@@ -16,6 +41,124 @@ pub enum TransitionAction {
     ReadSelfAccount,
     ReadSelfMemberOf,
     WriteSelfPassword,
+    CreateGroup,
+    AddGroupMembers,
+    RenameGroup,
+    DeleteGroup,
+    CreatePerson,
+    ToggleAccountValidity,
+    LoginValidityCanary,
+    EnrollMfa,
+    ReadGroup,
+    RemoveGroupMembers,
+    UnixAuth,
+    Whoami,
+    Oauth2Authorise,
+    Oauth2ExchangeCode,
+    Oauth2Userinfo,
+    ReadStatus,
+    ReadOauth2Discovery,
+    ReadOauth2Jwks,
+}
+
+/// The identifiers of every action, see `TransitionAction::id`.
+pub const ACTION_IDS: [&str; 25] = [
+    "login",
+    "logout",
+    "privilege_reauth",
+    "write_attribute_person_mail",
+    "read_self_account",
+    "read_self_member_of",
+    "write_self_password",
+    "create_group",
+    "add_group_members",
+    "rename_group",
+    "delete_group",
+    "create_person",
+    "toggle_account_validity",
+    "login_validity_canary",
+    "enroll_mfa",
+    "read_group",
+    "remove_group_members",
+    "unix_auth",
+    "whoami",
+    "oauth2_authorise",
+    "oauth2_exchange_code",
+    "oauth2_userinfo",
+    "read_status",
+    "read_oauth2_discovery",
+    "read_oauth2_jwks",
+];
+
+impl TransitionAction {
+    /// The identifier the action is exported under, which is part of the results schema in
+    /// the same way as `EventDetail::id`. It's the name the action has in a profile.
+    pub fn id(self) -> &'static str {
+        match self {
+            TransitionAction::Login => "login",
+            TransitionAction::Logout => "logout",
+            TransitionAction::PrivilegeReauth => "privilege_reauth",
+            TransitionAction::WriteAttributePersonMail => "write_attribute_person_mail",
+            TransitionAction::ReadSelfAccount => "read_self_account",
+            TransitionAction::ReadSelfMemberOf => "read_self_member_of",
+            TransitionAction::WriteSelfPassword => "write_self_password",
+            TransitionAction::CreateGroup => "create_group",
+            TransitionAction::AddGroupMembers => "add_group_members",
+            TransitionAction::RenameGroup => "rename_group",
+            TransitionAction::DeleteGroup => "delete_group",
+            TransitionAction::CreatePerson => "create_person",
+            TransitionAction::ToggleAccountValidity => "toggle_account_validity",
+            TransitionAction::LoginValidityCanary => "login_validity_canary",
+            TransitionAction::EnrollMfa => "enroll_mfa",
+            TransitionAction::ReadGroup => "read_group",
+            TransitionAction::RemoveGroupMembers => "remove_group_members",
+            TransitionAction::UnixAuth => "unix_auth",
+            TransitionAction::Whoami => "whoami",
+            TransitionAction::Oauth2Authorise => "oauth2_authorise",
+            TransitionAction::Oauth2ExchangeCode => "oauth2_exchange_code",
+            TransitionAction::Oauth2Userinfo => "oauth2_userinfo",
+            TransitionAction::ReadStatus => "read_status",
+            TransitionAction::ReadOauth2Discovery => "read_oauth2_discovery",
+            TransitionAction::ReadOauth2Jwks => "read_oauth2_jwks",
+        }
+    }
+
+    /// The event the action is recorded as when it succeeds, which a failure of the action as
+    /// a whole is charged to.
+    pub fn event(self, person: &Person) -> EventDetail {
+        match self {
+            TransitionAction::Login => EventDetail::Login,
+            TransitionAction::Logout => EventDetail::Logout,
+            TransitionAction::PrivilegeReauth => EventDetail::PersonReauth,
+            TransitionAction::WriteAttributePersonMail => EventDetail::PersonSetSelfMail,
+            TransitionAction::ReadSelfAccount => EventDetail::PersonGetSelfAccount,
+            TransitionAction::ReadSelfMemberOf => EventDetail::PersonGetSelfMemberOf,
+            TransitionAction::WriteSelfPassword => EventDetail::PersonSetSelfPassword,
+            TransitionAction::CreateGroup => EventDetail::PersonCreateGroup,
+            TransitionAction::AddGroupMembers => EventDetail::PersonAddGroupMembers,
+            TransitionAction::RenameGroup => EventDetail::PersonRenameGroup,
+            TransitionAction::DeleteGroup => EventDetail::PersonDeleteGroup,
+            TransitionAction::CreatePerson => EventDetail::PersonCreatePerson,
+            TransitionAction::ToggleAccountValidity => EventDetail::PersonSetAccountValidity,
+            TransitionAction::LoginValidityCanary => EventDetail::LoginRejectedValidity,
+            TransitionAction::EnrollMfa => {
+                match person.mfa_enrollment.as_ref().map(|e| &e.method) {
+                    Some(MfaEnrollmentMethod::Passkey(_)) => EventDetail::PersonEnrollPasskey,
+                    _ => EventDetail::PersonEnrollTotp,
+                }
+            }
+            TransitionAction::ReadGroup => EventDetail::PersonGetGroup,
+            TransitionAction::RemoveGroupMembers => EventDetail::PersonRemoveGroupMembers,
+            TransitionAction::UnixAuth => EventDetail::UnixAuth,
+            TransitionAction::Whoami => EventDetail::PersonWhoami,
+            TransitionAction::Oauth2Authorise => EventDetail::Oauth2Authorise,
+            TransitionAction::Oauth2ExchangeCode => EventDetail::Oauth2Token,
+            TransitionAction::Oauth2Userinfo => EventDetail::Oauth2Userinfo,
+            TransitionAction::ReadStatus => EventDetail::Status,
+            TransitionAction::ReadOauth2Discovery => EventDetail::Oauth2Discovery,
+            TransitionAction::ReadOauth2Jwks => EventDetail::Oauth2Jwks,
+        }
+    }
 }
 
 // Is this the right way? Should transitions/delay be part of the actor model? Should
@@ -36,8 +179,12 @@ impl Transition {
 pub enum TransitionResult {
     // Success
     Ok,
-    // We need to re-authenticate, the session expired.
-    // AuthenticationNeeded,
+    // We need to re-authenticate, the session expired. The runner does this, and the model
+    // stays where it was so that the action is tried again on the new session.
+    AuthenticationNeeded,
+    // The server refused the action under its access policy. The session is still good, and
+    // the refusal is what the server is meant to do for an actor without the rights to it.
+    Rejected,
     // An error occurred.
     Error,
 }
@@ -52,6 +199,7 @@ pub enum ActorRole {
     PeopleSelfReadMemberOf,
     PeopleSelfSetPassword,
     PeopleGroupAdmin,
+    PeopleAccountPolicyAdmin,
 }
 
 impl ActorRole {
@@ -64,59 +212,985 @@ impl ActorRole {
             ActorRole::PeoplePiiReader => Some(&["idm_people_pii_read"]),
             ActorRole::PeopleSelfMailWrite => Some(&["idm_people_self_mail_write"]),
             ActorRole::PeopleGroupAdmin => Some(&["idm_group_admins"]),
+            ActorRole::PeopleAccountPolicyAdmin => Some(&["idm_people_admins"]),
+        }
+    }
+}
+
+#[async_trait]
+pub trait ActorModel {
+    /// Called once before the first transition, for example to log in ahead of time.
+    async fn on_start(
+        &mut self,
+        _client: &KanidmClient,
+        _person: &Person,
+        _context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        Ok(Vec::new())
+    }
+
+    /// Decide on the next transition, and return how long to wait before it's performed.
+    /// The runner waits it out, so that the wait doesn't count against the action timeout.
+    fn next_delay(&mut self, person: &Person) -> Option<Duration>;
+
+    /// The action decided on by the last call to `next_delay`, so that the runner can
+    /// report the mix of actions the model actually went through.
+    fn pending_action(&self) -> Option<TransitionAction>;
+
+    /// Move on from the transition decided on by the last call to `next_delay` as if it had
+    /// ended with `result`, without performing it, so that the model can be walked through
+    /// without a server.
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error>;
+
+    /// Perform the transition decided on by the last call to `next_delay`. This may be
+    /// cancelled by the runner if it takes too long, in which case it is decided on again.
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error>;
+
+    /// Renew the session of the actor. This is done on a fixed cadence if the profile
+    /// asks for it, regardless of what the actor is doing at the time.
+    async fn refresh_session(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        _context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let (_, event_records) = refresh_session(client, person, None).await?;
+        Ok(event_records)
+    }
+
+    /// Authenticate again once the server has rejected the session, so that the actor can
+    /// carry on where it was.
+    async fn reauthenticate(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let (result, event_records) = reauthenticate(client, person, None).await?;
+        context.has_session = result == TransitionResult::Ok;
+        Ok(event_records)
+    }
+
+    /// Called once the actor has been told to stop, for example to log out or to remove
+    /// the entries the actor created so the server is left clean.
+    async fn on_stop(
+        &mut self,
+        _client: &KanidmClient,
+        _person: &Person,
+        _context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        Ok(Vec::new())
+    }
+}
+
+/// Scratch state of a single actor that outlives its transitions. The runner owns it for
+/// the whole run and hands it to every call of the model, so that a model can act in one
+/// transition and follow up on it in a later one, like writing a value and reading it back.
+#[derive(Default)]
+pub struct ActorContext {
+    /// Entries the actor created that are expected to still exist on the server.
+    pub created_entries: BTreeSet<CreatedEntry>,
+    /// The values the actor last wrote to the attributes of its own entry, by attribute.
+    pub written_attributes: BTreeMap<String, Vec<String>>,
+    /// Whether the session is kept across the cycles of the model, see `SessionLifetime`.
+    pub reuse_session: bool,
+    /// Whether the actor holds a session that it can reuse.
+    pub has_session: bool,
+    /// How the requests that failed in a way that may not last are retried.
+    pub retry: RetryProperties,
+}
+
+impl ActorContext {
+    pub fn created_persons(&self) -> impl Iterator<Item = &str> {
+        self.created_entries.iter().filter_map(|entry| match entry {
+            CreatedEntry::Person(name) => Some(name.as_str()),
+            CreatedEntry::Group(_) => None,
+        })
+    }
+
+    pub fn created_groups(&self) -> impl Iterator<Item = &str> {
+        self.created_entries.iter().filter_map(|entry| match entry {
+            CreatedEntry::Group(name) => Some(name.as_str()),
+            CreatedEntry::Person(_) => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum CreatedEntry {
+    Person(String),
+    Group(String),
+}
+
+/// A credential that an actor enrolled while the test is running. From then on it
+/// replaces the generated credential of the person when logging in.
+pub enum EnrolledCredential {
+    PasswordTotp { secret: TotpSecret, clock_skew: i64 },
+    Passkey(Box<SoftAuthenticator>),
+}
+
+/// A soft authenticator with the properties of a generated passkey. A packed attestation
+/// takes a soft token, which signs it with a CA of its own.
+pub struct SoftAuthenticator {
+    backend: SoftBackend,
+    resident_key: bool,
+}
+
+enum SoftBackend {
+    Passkey(WebauthnAuthenticator<SoftPasskey>),
+    Token(WebauthnAuthenticator<SoftToken>),
+}
+
+impl SoftAuthenticator {
+    pub fn new(properties: PasskeyProperties) -> Result<Self, WebauthnCError> {
+        let backend = match properties.attestation {
+            PasskeyAttestation::None => SoftBackend::Passkey(WebauthnAuthenticator::new(
+                SoftPasskey::new(properties.user_verification),
+            )),
+            PasskeyAttestation::Packed => {
+                let (token, _ca) = SoftToken::new(properties.user_verification)?;
+                SoftBackend::Token(WebauthnAuthenticator::new(token))
+            }
+        };
+        Ok(SoftAuthenticator {
+            backend,
+            resident_key: properties.resident_key,
+        })
+    }
+
+    pub fn do_registration(
+        &mut self,
+        origin: Url,
+        mut challenge: CreationChallengeResponse,
+    ) -> Result<RegisterPublicKeyCredential, WebauthnCError> {
+        // The server doesn't ask for a discoverable credential, so this only changes what the
+        // authenticator creates, not how the server registers it.
+        if self.resident_key {
+            if let Some(selection) = challenge.public_key.authenticator_selection.as_mut() {
+                selection.resident_key = Some(ResidentKeyRequirement::Required);
+                selection.require_resident_key = true;
+            }
+        }
+        match &mut self.backend {
+            SoftBackend::Passkey(authenticator) => authenticator.do_registration(origin, challenge),
+            SoftBackend::Token(authenticator) => authenticator.do_registration(origin, challenge),
+        }
+    }
+
+    pub fn do_authentication(
+        &mut self,
+        origin: Url,
+        challenge: RequestChallengeResponse,
+    ) -> Result<PublicKeyCredential, WebauthnCError> {
+        match &mut self.backend {
+            SoftBackend::Passkey(authenticator) => {
+                authenticator.do_authentication(origin, challenge)
+            }
+            SoftBackend::Token(authenticator) => authenticator.do_authentication(origin, challenge),
+        }
+    }
+}
+
+/// The second factor of the credential of a person, registered as the run starts. The models
+/// only see the person, and a passkey counts its uses, hence the lock.
+pub struct RegisteredCredential(tokio::sync::Mutex<EnrolledCredential>);
+
+impl RegisteredCredential {
+    pub fn new(enrolled: EnrolledCredential) -> Self {
+        RegisteredCredential(tokio::sync::Mutex::new(enrolled))
+    }
+}
+
+impl std::fmt::Debug for RegisteredCredential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RegisteredCredential")
+    }
+}
+
+pub async fn login(
+    client: &KanidmClient,
+    person: &Person,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    // Each step of the auth state machine gets its own record, so that regressions can
+    // be localised to a single step. The total is recorded as the Login event.
+    let mut event_records = Vec::with_capacity(5);
+
+    let start = Instant::now();
+    let result = login_steps(client, person, &mut event_records).await;
+
+    Ok(record_login_total(
+        result,
+        start,
+        event_records,
+        EventDetail::Login,
+    ))
+}
+
+pub async fn login_enrolled(
+    client: &KanidmClient,
+    person: &Person,
+    enrolled: &mut EnrolledCredential,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let mut event_records = Vec::with_capacity(5);
+
+    let start = Instant::now();
+    let result = login_enrolled_steps(client, person, enrolled, &mut event_records).await;
+
+    Ok(record_login_total(
+        result,
+        start,
+        event_records,
+        EventDetail::Login,
+    ))
+}
+
+/// Log in at the start of a cycle of the model, unless the session of the last cycle is
+/// kept.
+pub async fn start_session(
+    client: &KanidmClient,
+    person: &Person,
+    context: &mut ActorContext,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    if context.reuse_session && context.has_session {
+        return Ok((TransitionResult::Ok, Vec::new()));
+    }
+
+    let (result, event_records) = login(client, person).await?;
+    context.has_session = result == TransitionResult::Ok;
+    Ok((result, event_records))
+}
+
+/// Log out at the end of a cycle of the model, unless the session is kept for the next one.
+pub async fn end_session(
+    client: &KanidmClient,
+    person: &Person,
+    context: &mut ActorContext,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    if context.reuse_session {
+        return Ok((TransitionResult::Ok, Vec::new()));
+    }
+
+    context.has_session = false;
+    logout(client, person).await
+}
+
+/// Authenticate again on a session that may still be valid, as long lived agents do on a
+/// fixed cadence regardless of their activity. The total is recorded as a session refresh.
+pub async fn refresh_session(
+    client: &KanidmClient,
+    person: &Person,
+    enrolled: Option<&mut EnrolledCredential>,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    renew_session(client, person, enrolled, EventDetail::SessionRefresh).await
+}
+
+/// Authenticate again after the server rejected the session, recorded as a
+/// reauthentication so that it can be told apart from the logins the models ask for.
+pub async fn reauthenticate(
+    client: &KanidmClient,
+    person: &Person,
+    enrolled: Option<&mut EnrolledCredential>,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    renew_session(client, person, enrolled, EventDetail::Reauthentication).await
+}
+
+async fn renew_session(
+    client: &KanidmClient,
+    person: &Person,
+    enrolled: Option<&mut EnrolledCredential>,
+    details: EventDetail,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let mut event_records = Vec::with_capacity(5);
+
+    let start = Instant::now();
+    let result = match (enrolled, &person.credential) {
+        (Some(enrolled), _) => {
+            login_enrolled_steps(client, person, enrolled, &mut event_records).await
         }
+        (None, _) => login_steps(client, person, &mut event_records).await,
+    };
+
+    Ok(record_login_total(result, start, event_records, details))
+}
+
+// Log in with the credential of the person, using the second factor registered for it.
+async fn login_steps(
+    client: &KanidmClient,
+    person: &Person,
+    event_records: &mut Vec<EventRecord>,
+) -> Result<(), ClientError> {
+    match (&person.credential, person.registered_credential.get()) {
+        (Credential::Password { .. }, _) => {
+            let plain = person.password().unwrap_or_default();
+            login_password(client, person.username.as_str(), plain, event_records).await
+        }
+        (_, Some(registered)) => {
+            let mut enrolled = registered.0.lock().await;
+            login_enrolled_steps(client, person, &mut enrolled, event_records).await
+        }
+        (_, None) => {
+            debug!(username = %person.username, "The second factor of the person isn't registered");
+            Err(ClientError::AuthenticationFailed)
+        }
+    }
+}
+
+async fn login_enrolled_steps(
+    client: &KanidmClient,
+    person: &Person,
+    enrolled: &mut EnrolledCredential,
+    event_records: &mut Vec<EventRecord>,
+) -> Result<(), ClientError> {
+    let username = person.username.as_str();
+    match enrolled {
+        EnrolledCredential::PasswordTotp { secret, clock_skew } => {
+            let Some(plain) = person.password() else {
+                debug!(%username, "The person has no password to go with the TOTP");
+                return Err(ClientError::AuthenticationFailed);
+            };
+            login_password_totp(client, username, plain, secret, *clock_skew, event_records).await
+        }
+        EnrolledCredential::Passkey(authenticator) => {
+            login_passkey(client, username, authenticator, event_records).await
+        }
+    }
+}
+
+fn record_login_total(
+    result: Result<(), ClientError>,
+    start: Instant,
+    mut event_records: Vec<EventRecord>,
+    details: EventDetail,
+) -> (TransitionResult, Vec<EventRecord>) {
+    let duration = Instant::now().duration_since(start);
+
+    let (transition_result, mut total_record) =
+        parse_call_result_into_transition_result_and_event_record(result, details, start, duration);
+    event_records.append(&mut total_record);
+
+    // A rejected login is a failure in itself, rather than a sign the session expired.
+    match transition_result {
+        TransitionResult::AuthenticationNeeded => (TransitionResult::Error, event_records),
+        transition_result => (transition_result, event_records),
     }
 }
 
-#[async_trait]
-pub trait ActorModel {
-    async fn transition(
-        &mut self,
-        client: &KanidmClient,
-        person: &Person,
-    ) -> Result<Vec<EventRecord>, Error>;
+/// Log in as the shared account of the person, racing the other persons that share it. A
+/// failure here is the server mishandling concurrent logins to one entry, so it's recorded
+/// as a conflict rather than as a generic error.
+pub async fn login_shared(
+    client: &KanidmClient,
+    person: &Person,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let Some(shared_login) = &person.login_as else {
+        return Err(Error::invalid(format!(
+            "the person '{}' has no shared account to log in as",
+            person.username
+        )));
+    };
+
+    let mut event_records = Vec::with_capacity(4);
+
+    let start = Instant::now();
+    let result = match &shared_login.credential {
+        Credential::Password { plain } => {
+            login_password(
+                client,
+                shared_login.username.as_str(),
+                plain,
+                &mut event_records,
+            )
+            .await
+        }
+        // A second factor is registered per actor, so it can't be shared.
+        Credential::PasswordTotp { .. } | Credential::Passkey(_) => {
+            return Err(Error::invalid(format!(
+                "the shared account '{}' can only have a password",
+                shared_login.username
+            )));
+        }
+    };
+    let duration = Instant::now().duration_since(start);
+
+    let (transition_result, details) = match result {
+        Ok(()) => (TransitionResult::Ok, EventDetail::Login),
+        Err(client_err) => {
+            debug!(?client_err, shared_username = %shared_login.username);
+            (TransitionResult::Error, EventDetail::LoginConflict)
+        }
+    };
+    event_records.push(EventRecord {
+        start,
+        duration,
+        details,
+    });
+
+    Ok((transition_result, event_records))
+}
+
+/// Attempt a login that the server is expected to reject because the account is outside
+/// of its validity window. The rejection is the successful outcome here, so it is recorded
+/// as its own event rather than as an error.
+pub async fn login_rejected_by_validity(
+    client: &KanidmClient,
+    person: &Person,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let mut event_records = Vec::with_capacity(5);
+
+    let start = Instant::now();
+    let result = login_steps(client, person, &mut event_records).await;
+    let duration = Instant::now().duration_since(start);
+
+    // An account outside of its validity is denied during init, which the client reports
+    // as a failed authentication of that step. A denial later on is a failure of the login.
+    let denied_at_init = matches!(
+        event_records.last(),
+        Some(EventRecord { details: EventDetail::Error(failure), .. })
+            if matches!(failure.event, EventDetail::AuthInit)
+                && failure.class == "AuthenticationFailed"
+    );
+
+    let (transition_result, details) = match result {
+        Err(ClientError::AuthenticationFailed) if denied_at_init => {
+            // The denial is what the canary expects, so it isn't a failed step.
+            event_records.pop();
+            (TransitionResult::Ok, EventDetail::LoginRejectedValidity)
+        }
+        Ok(()) => {
+            warn!(username = %person.username, "Login succeeded outside of the account validity");
+            (
+                TransitionResult::Error,
+                EventDetail::Error(Failure::new(
+                    EventDetail::LoginRejectedValidity,
+                    "UnexpectedSuccess",
+                    "Login succeeded outside of the account validity",
+                )),
+            )
+        }
+        Err(client_err) => {
+            debug!(?client_err);
+            (
+                TransitionResult::Error,
+                EventDetail::Error(Failure::from_client_error(
+                    EventDetail::LoginRejectedValidity,
+                    &client_err,
+                )),
+            )
+        }
+    };
+    event_records.push(EventRecord {
+        start,
+        duration,
+        details,
+    });
+
+    Ok((transition_result, event_records))
+}
+
+async fn login_password(
+    client: &KanidmClient,
+    username: &str,
+    password: &str,
+    event_records: &mut Vec<EventRecord>,
+) -> Result<(), ClientError> {
+    let mechs = timed_auth_step(
+        client.auth_step_init(username),
+        EventDetail::AuthInit,
+        event_records,
+    )
+    .await?;
+
+    if !mechs.contains(&AuthMech::Password) {
+        debug!("Password mech not presented");
+        return Err(ClientError::AuthenticationFailed);
+    }
+
+    timed_auth_step(
+        client.auth_step_begin(AuthMech::Password),
+        EventDetail::AuthBegin,
+        event_records,
+    )
+    .await?;
+
+    let response = timed_auth_step(
+        client.auth_step_password(password),
+        EventDetail::AuthCredential,
+        event_records,
+    )
+    .await?;
+
+    match response.state {
+        AuthState::Success(_) => Ok(()),
+        _ => Err(ClientError::AuthenticationFailed),
+    }
+}
+
+// Start a login with a password and TOTP, up to whether the server accepted the TOTP, which
+// it asks for before the password.
+async fn begin_password_totp(
+    client: &KanidmClient,
+    username: &str,
+    totp: u32,
+    details: EventDetail,
+    event_records: &mut Vec<EventRecord>,
+) -> Result<bool, ClientError> {
+    let mechs = timed_auth_step(
+        client.auth_step_init(username),
+        EventDetail::AuthInit,
+        event_records,
+    )
+    .await?;
+
+    if !mechs.contains(&AuthMech::PasswordTotp) {
+        debug!("PasswordTotp mech not presented");
+        return Err(ClientError::AuthenticationFailed);
+    }
+
+    timed_auth_step(
+        client.auth_step_begin(AuthMech::PasswordTotp),
+        EventDetail::AuthBegin,
+        event_records,
+    )
+    .await?;
+
+    let response = timed_auth_step(client.auth_step_totp(totp), details, event_records).await?;
+    Ok(matches!(response.state, AuthState::Continue(_)))
+}
+
+async fn login_password_totp(
+    client: &KanidmClient,
+    username: &str,
+    password: &str,
+    secret: &TotpSecret,
+    clock_skew: i64,
+    event_records: &mut Vec<EventRecord>,
+) -> Result<(), ClientError> {
+    let totp = totp_code(secret, clock_skew)?;
+    let mut accepted = begin_password_totp(
+        client,
+        username,
+        totp,
+        EventDetail::AuthCredential,
+        event_records,
+    )
+    .await?;
+
+    // The code may have been generated at the end of its time step, and the server denies
+    // the whole session when it rejects it, so log in again with the code of the next step.
+    if !accepted {
+        let next_step = clock_skew.saturating_add_unsigned(secret.step.max(1));
+        let totp = totp_code(secret, next_step)?;
+        accepted = begin_password_totp(
+            client,
+            username,
+            totp,
+            EventDetail::AuthTotpRetry,
+            event_records,
+        )
+        .await?;
+    }
+
+    if !accepted {
+        return Err(ClientError::AuthenticationFailed);
+    }
+
+    let response = timed_auth_step(
+        client.auth_step_password(password),
+        EventDetail::AuthCredential,
+        event_records,
+    )
+    .await?;
+
+    match response.state {
+        AuthState::Success(_) => Ok(()),
+        _ => Err(ClientError::AuthenticationFailed),
+    }
+}
+
+async fn login_passkey(
+    client: &KanidmClient,
+    username: &str,
+    authenticator: &mut SoftAuthenticator,
+    event_records: &mut Vec<EventRecord>,
+) -> Result<(), ClientError> {
+    let mechs = timed_auth_step(
+        client.auth_step_init(username),
+        EventDetail::AuthInit,
+        event_records,
+    )
+    .await?;
+
+    if !mechs.contains(&AuthMech::Passkey) {
+        debug!("Passkey mech not presented");
+        return Err(ClientError::AuthenticationFailed);
+    }
+
+    let mut allowed = timed_auth_step(
+        client.auth_step_begin(AuthMech::Passkey),
+        EventDetail::AuthBegin,
+        event_records,
+    )
+    .await?;
+
+    let challenge = match allowed.pop() {
+        Some(AuthAllowed::Passkey(challenge)) => challenge,
+        _ => return Err(ClientError::AuthenticationFailed),
+    };
+
+    let credential = authenticator
+        .do_authentication(client.get_origin().clone(), challenge)
+        .map_err(|err| {
+            debug!(?err, "Soft passkey failed to sign the challenge");
+            ClientError::AuthenticationFailed
+        })?;
+
+    let response = timed_auth_step(
+        client.auth_step_passkey_complete(Box::new(credential)),
+        EventDetail::AuthCredential,
+        event_records,
+    )
+    .await?;
+
+    match response.state {
+        AuthState::Success(_) => Ok(()),
+        _ => Err(ClientError::AuthenticationFailed),
+    }
+}
+
+/// Calculate the current TOTP code for a secret, as seen by a client whose clock is
+/// `clock_skew` seconds off.
+pub(crate) fn totp_code(secret: &TotpSecret, clock_skew: i64) -> Result<u32, ClientError> {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| ClientError::SystemError)?
+        .as_secs();
+    let counter = now.saturating_add_signed(clock_skew) / secret.step.max(1);
+
+    let digest = match secret.algo {
+        TotpAlgo::Sha1 => MessageDigest::sha1(),
+        TotpAlgo::Sha256 => MessageDigest::sha256(),
+        TotpAlgo::Sha512 => MessageDigest::sha512(),
+    };
+    let hmac = PKey::hmac(&secret.secret)
+        .and_then(|key| {
+            let mut signer = Signer::new(digest, &key)?;
+            signer.update(&counter.to_be_bytes())?;
+            signer.sign_to_vec()
+        })
+        .map_err(|err| {
+            error!(?err, "Unable to calculate TOTP hmac");
+            ClientError::SystemError
+        })?;
+
+    // https://tools.ietf.org/html/rfc4226#page-7
+    let offset = hmac
+        .last()
+        .map(|v| (v & 0xf) as usize)
+        .ok_or(ClientError::SystemError)?;
+    let bytes: [u8; 4] = hmac
+        .get(offset..offset + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ClientError::SystemError)?;
+
+    Ok((u32::from_be_bytes(bytes) & 0x7fff_ffff) % 10_u32.pow(secret.digits as u32))
+}
+
+/// Time a single step of a multi-step authentication. A failed step is recorded against the
+/// step, so it shows where the flow broke off, while the total of the whole flow accounts for
+/// the failure of the login.
+async fn timed_auth_step<T>(
+    call: impl Future<Output = Result<T, ClientError>>,
+    details: EventDetail,
+    event_records: &mut Vec<EventRecord>,
+) -> Result<T, ClientError> {
+    let start = Instant::now();
+    let result = call.await;
+    let duration = Instant::now().duration_since(start);
+
+    let details = match &result {
+        Ok(_) => details,
+        Err(client_err) => EventDetail::Error(Failure::from_client_error(details, client_err)),
+    };
+    event_records.push(EventRecord {
+        start,
+        duration,
+        details,
+    });
+
+    result
+}
+
+pub async fn person_set_self_mail(
+    client: &KanidmClient,
+    person: &Person,
+    values: &[&str],
+    context: &ActorContext,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    // Should we measure the time of each call rather than the time with multiple calls?
+    let person_username = person.username.as_str();
+
+    Ok(retried(context, EventDetail::PersonSetSelfMail, || {
+        client.idm_person_account_set_attr(person_username, "mail", values)
+    })
+    .await)
+}
+
+pub async fn person_create_person(
+    client: &KanidmClient,
+    person: &Person,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    // Should we measure the time of each call rather than the time with multiple calls?
+    let person_username = person.username.as_str();
+
+    let start = Instant::now();
+    let result = async {
+        client
+            .idm_person_account_create(person_username, &person.display_name)
+            .await?;
+        match person.credential.password() {
+            Some(plain) => {
+                client
+                    .idm_person_account_primary_credential_set_password(person_username, plain)
+                    .await
+            }
+            // Without a password the created person has no credential, which is enough since
+            // nobody logs in as them.
+            None => Ok(()),
+        }
+    }
+    .await;
+
+    let duration = Instant::now().duration_since(start);
+    let parsed_result = parse_call_result_into_transition_result_and_event_record(
+        result,
+        EventDetail::PersonCreatePerson,
+        start,
+        duration,
+    );
+
+    Ok(parsed_result)
+}
+
+pub async fn person_delete_person(
+    client: &KanidmClient,
+    username: &str,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let start = Instant::now();
+    let result = client.idm_person_account_delete(username).await;
+
+    let duration = Instant::now().duration_since(start);
+    let parsed_result = parse_call_result_into_transition_result_and_event_record(
+        result,
+        EventDetail::PersonDeletePerson,
+        start,
+        duration,
+    );
+
+    Ok(parsed_result)
+}
+
+/// Set a single validity attribute (`account_expire` or `account_valid_from`) on a person.
+/// When `attr_and_value` is `None` both attributes are removed, making the account valid again.
+pub async fn person_set_account_validity(
+    client: &KanidmClient,
+    username: &str,
+    attr_and_value: Option<(&str, &str)>,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let start = Instant::now();
+    let result = match attr_and_value {
+        Some((attr, value)) => {
+            client
+                .idm_person_account_set_attr(username, attr, &[value])
+                .await
+        }
+        None => {
+            async {
+                client
+                    .idm_person_account_purge_attr(username, ATTR_ACCOUNT_EXPIRE)
+                    .await?;
+                client
+                    .idm_person_account_purge_attr(username, ATTR_ACCOUNT_VALID_FROM)
+                    .await
+            }
+            .await
+        }
+    };
+
+    let duration = Instant::now().duration_since(start);
+    let parsed_result = parse_call_result_into_transition_result_and_event_record(
+        result,
+        EventDetail::PersonSetAccountValidity,
+        start,
+        duration,
+    );
+
+    Ok(parsed_result)
+}
+
+pub async fn person_enroll_totp(
+    client: &KanidmClient,
+    person: &Person,
+    clock_skew: i64,
+    enrolled: &mut Option<EnrolledCredential>,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let start = Instant::now();
+    let result = async {
+        let (session_token, _status) = client
+            .idm_account_credential_update_begin(&person.username)
+            .await?;
+
+        let status = client
+            .idm_account_credential_update_init_totp(&session_token)
+            .await?;
+        let secret = match status.mfaregstate {
+            CURegState::TotpCheck(secret) => secret,
+            state => {
+                return Err(ClientError::InvalidResponseFormat(format!(
+                    "Unexpected TOTP registration state {state:?}"
+                )))
+            }
+        };
+
+        let totp = totp_code(&secret, clock_skew)?;
+        let status = client
+            .idm_account_credential_update_check_totp(&session_token, totp, "orca")
+            .await?;
+        if !matches!(status.mfaregstate, CURegState::None) {
+            return Err(ClientError::InvalidResponseFormat(format!(
+                "TOTP code was not accepted {:?}",
+                status.mfaregstate
+            )));
+        }
+
+        client
+            .idm_account_credential_update_commit(&session_token)
+            .await?;
+
+        Ok(secret)
+    }
+    .await;
+    let duration = Instant::now().duration_since(start);
+
+    let result = result.map(|secret| {
+        *enrolled = Some(EnrolledCredential::PasswordTotp { secret, clock_skew });
+    });
+
+    Ok(parse_call_result_into_transition_result_and_event_record(
+        result,
+        EventDetail::PersonEnrollTotp,
+        start,
+        duration,
+    ))
+}
+
+pub async fn person_enroll_passkey(
+    client: &KanidmClient,
+    person: &Person,
+    properties: &PasskeyProperties,
+    enrolled: &mut Option<EnrolledCredential>,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let start = Instant::now();
+    let result = async {
+        let mut authenticator = SoftAuthenticator::new(*properties).map_err(|err| {
+            error!(?err, "Soft passkey failed to be created");
+            ClientError::SystemError
+        })?;
+
+        let (session_token, _status) = client
+            .idm_account_credential_update_begin(&person.username)
+            .await?;
+
+        let status = client
+            .idm_account_credential_update_passkey_init(&session_token)
+            .await?;
+        let challenge = match status.mfaregstate {
+            CURegState::Passkey(challenge) => challenge,
+            state => {
+                return Err(ClientError::InvalidResponseFormat(format!(
+                    "Unexpected passkey registration state {state:?}"
+                )))
+            }
+        };
+
+        let registration = authenticator
+            .do_registration(client.get_origin().clone(), challenge)
+            .map_err(|err| {
+                error!(?err, "Soft passkey failed to register");
+                ClientError::SystemError
+            })?;
+
+        client
+            .idm_account_credential_update_passkey_finish(
+                &session_token,
+                "orca".to_string(),
+                registration,
+            )
+            .await?;
+
+        client
+            .idm_account_credential_update_commit(&session_token)
+            .await?;
+        Ok(authenticator)
+    }
+    .await
+    .map(|authenticator| {
+        *enrolled = Some(EnrolledCredential::Passkey(Box::new(authenticator)));
+    });
+    let duration = Instant::now().duration_since(start);
+
+    Ok(parse_call_result_into_transition_result_and_event_record(
+        result,
+        EventDetail::PersonEnrollPasskey,
+        start,
+        duration,
+    ))
 }
 
-pub async fn login(
+pub async fn person_create_group(
     client: &KanidmClient,
-    person: &Person,
+    group_name: &str,
 ) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
-    // Should we measure the time of each call rather than the time with multiple calls?
     let start = Instant::now();
-    let result = match &person.credential {
-        Credential::Password { plain } => {
-            client
-                .auth_simple_password(person.username.as_str(), plain.as_str())
-                .await
-        }
-    };
+    let result = client.idm_group_create(group_name, None).await;
 
     let duration = Instant::now().duration_since(start);
-    Ok(parse_call_result_into_transition_result_and_event_record(
+    let parsed_result = parse_call_result_into_transition_result_and_event_record(
         result,
-        EventDetail::Login,
+        EventDetail::PersonCreateGroup,
         start,
         duration,
-    ))
+    );
+
+    Ok(parsed_result)
 }
 
-pub async fn person_set_self_mail(
+pub async fn person_rename_group(
     client: &KanidmClient,
-    person: &Person,
-    values: &[&str],
+    group_name: &str,
+    new_group_name: &str,
 ) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
-    // Should we measure the time of each call rather than the time with multiple calls?
-    let person_username = person.username.as_str();
+    // There is no dedicated rename endpoint, so we replace the name directly.
+    let filter = Filter::And(vec![
+        Filter::Eq("class".to_string(), "group".to_string()),
+        Filter::Eq("name".to_string(), group_name.to_string()),
+    ]);
+    let modlist = ModifyList::new_list(vec![
+        Modify::Purged("name".to_string()),
+        Modify::Present("name".to_string(), new_group_name.to_string()),
+    ]);
 
     let start = Instant::now();
-    let result = client
-        .idm_person_account_set_attr(person_username, "mail", values)
-        .await;
+    let result = client.modify(filter, modlist).await;
 
     let duration = Instant::now().duration_since(start);
     let parsed_result = parse_call_result_into_transition_result_and_event_record(
         result,
-        EventDetail::PersonSetSelfMail,
+        EventDetail::PersonRenameGroup,
         start,
         duration,
     );
@@ -124,17 +1198,17 @@ pub async fn person_set_self_mail(
     Ok(parsed_result)
 }
 
-pub async fn person_create_group(
+pub async fn person_delete_group(
     client: &KanidmClient,
     group_name: &str,
 ) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
     let start = Instant::now();
-    let result = client.idm_group_create(group_name, None).await;
+    let result = client.idm_group_delete(group_name).await;
 
     let duration = Instant::now().duration_since(start);
     let parsed_result = parse_call_result_into_transition_result_and_event_record(
         result,
-        EventDetail::PersonCreateGroup,
+        EventDetail::PersonDeleteGroup,
         start,
         duration,
     );
@@ -163,28 +1237,111 @@ pub async fn person_add_group_members(
     Ok(parsed_result)
 }
 
+pub async fn person_remove_group_members(
+    client: &KanidmClient,
+    group_name: &str,
+    group_members: &[&str],
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let start = Instant::now();
+    let result = client
+        .idm_group_remove_members(group_name, group_members)
+        .await;
+
+    let duration = Instant::now().duration_since(start);
+    let parsed_result = parse_call_result_into_transition_result_and_event_record(
+        result,
+        EventDetail::PersonRemoveGroupMembers,
+        start,
+        duration,
+    );
+
+    Ok(parsed_result)
+}
+
+pub async fn person_get_group(
+    client: &KanidmClient,
+    group_name: &str,
+    context: &ActorContext,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    Ok(retried(context, EventDetail::PersonGetGroup, || {
+        client.idm_group_get(group_name)
+    })
+    .await)
+}
+
 pub async fn person_set_self_password(
     client: &KanidmClient,
     person: &Person,
     pw: &str,
+    context: &ActorContext,
 ) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
     // Should we measure the time of each call rather than the time with multiple calls?
     let person_username = person.username.as_str();
 
-    let start = Instant::now();
-    let result = client
-        .idm_person_account_primary_credential_set_password(person_username, pw)
-        .await;
+    Ok(retried(context, EventDetail::PersonSetSelfPassword, || {
+        client.idm_person_account_primary_credential_set_password(person_username, pw)
+    })
+    .await)
+}
+
+/// Log in with the expired password of the person and set the one it's rotated to, as a
+/// rotation forced at login does. Only the update of the credential is recorded as the
+/// rotation, the login and re-authentication are recorded as they always are.
+pub async fn rotate_password(
+    client: &KanidmClient,
+    person: &Person,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let Some(expiry) = &person.password_expiry else {
+        return Err(Error::invalid(format!(
+            "the password of the person '{}' doesn't expire",
+            person.username
+        )));
+    };
+
+    let (result, mut event_records) = login_with_privileges(client, person).await?;
+    if result != TransitionResult::Ok {
+        return Ok((result, event_records));
+    }
 
+    let start = Instant::now();
+    let result = async {
+        let (session_token, _status) = client
+            .idm_account_credential_update_begin(&person.username)
+            .await?;
+        client
+            .idm_account_credential_update_set_password(&session_token, &expiry.rotated)
+            .await?;
+        client
+            .idm_account_credential_update_commit(&session_token)
+            .await
+    }
+    .await;
     let duration = Instant::now().duration_since(start);
-    let parsed_result = parse_call_result_into_transition_result_and_event_record(
+
+    let (result, mut records) = parse_call_result_into_transition_result_and_event_record(
         result,
-        EventDetail::PersonSetSelfPassword,
+        EventDetail::PersonRotatePassword,
         start,
         duration,
     );
+    event_records.append(&mut records);
+    Ok((result, event_records))
+}
 
-    Ok(parsed_result)
+/// Log in and re-authenticate for privileges, whatever state the session was left in. This
+/// lets an actor clean up after itself once the test has stopped.
+pub async fn login_with_privileges(
+    client: &KanidmClient,
+    person: &Person,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let (result, mut event_records) = login(client, person).await?;
+    if result != TransitionResult::Ok {
+        return Ok((result, event_records));
+    }
+
+    let (result, mut records) = privilege_reauth(client, person).await?;
+    event_records.append(&mut records);
+    Ok((result, event_records))
 }
 
 pub async fn privilege_reauth(
@@ -193,8 +1350,17 @@ pub async fn privilege_reauth(
 ) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
     let start = Instant::now();
 
-    let result = match &person.credential {
-        Credential::Password { plain } => client.reauth_simple_password(plain.as_str()).await,
+    let result = match (&person.credential, person.registered_credential.get()) {
+        (Credential::Password { .. }, _) => {
+            client
+                .reauth_simple_password(person.password().unwrap_or_default())
+                .await
+        }
+        (_, Some(registered)) => {
+            let mut enrolled = registered.0.lock().await;
+            reauth_enrolled(client, person, &mut enrolled).await
+        }
+        (_, None) => Err(ClientError::AuthenticationFailed),
     };
 
     let duration = Instant::now().duration_since(start);
@@ -208,6 +1374,33 @@ pub async fn privilege_reauth(
     Ok(parsed_result)
 }
 
+async fn reauth_enrolled(
+    client: &KanidmClient,
+    person: &Person,
+    enrolled: &mut EnrolledCredential,
+) -> Result<(), ClientError> {
+    match enrolled {
+        EnrolledCredential::PasswordTotp { secret, clock_skew } => {
+            let Some(plain) = person.password() else {
+                return Err(ClientError::AuthenticationFailed);
+            };
+            client
+                .reauth_password_totp(plain, totp_code(secret, *clock_skew)?)
+                .await
+        }
+        EnrolledCredential::Passkey(authenticator) => {
+            let challenge = client.reauth_passkey_begin().await?;
+            let credential = authenticator
+                .do_authentication(client.get_origin().clone(), challenge)
+                .map_err(|err| {
+                    debug!(?err, "Soft passkey failed to sign the challenge");
+                    ClientError::AuthenticationFailed
+                })?;
+            client.reauth_passkey_complete(Box::new(credential)).await
+        }
+    }
+}
+
 pub async fn logout(
     client: &KanidmClient,
     _person: &Person,
@@ -227,10 +1420,73 @@ pub async fn logout(
 pub async fn person_get_self_account(
     client: &KanidmClient,
     person: &Person,
+    context: &ActorContext,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    if let Some(attrs) = &person.read_attributes {
+        return person_get_self_attributes(client, person, attrs).await;
+    }
+
+    Ok(retried(context, EventDetail::PersonGetSelfAccount, || {
+        client.idm_person_account_get(&person.username)
+    })
+    .await)
+}
+
+// The API only offers partial reads of one attribute at a time, so each attribute is its
+// own request, which can be compared with a read of the whole entry.
+async fn person_get_self_attributes(
+    client: &KanidmClient,
+    person: &Person,
+    attrs: &[String],
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let mut event_records = Vec::with_capacity(attrs.len());
+    for attr in attrs.iter() {
+        let start = Instant::now();
+        let result = client
+            .idm_person_account_get_attr(&person.username, attr)
+            .await;
+        let duration = Instant::now().duration_since(start);
+
+        let (transition_result, mut records) =
+            parse_call_result_into_transition_result_and_event_record(
+                result,
+                EventDetail::PersonGetSelfAttribute,
+                start,
+                duration,
+            );
+        event_records.append(&mut records);
+        if transition_result != TransitionResult::Ok {
+            return Ok((transition_result, event_records));
+        }
+    }
+
+    Ok((TransitionResult::Ok, event_records))
+}
+
+/// Read an attribute of the person back, and check it holds the values we expect. A
+/// mismatch is counted as an error, since a write we were told succeeded was lost.
+pub async fn person_verify_self_attr(
+    client: &KanidmClient,
+    person: &Person,
+    attr: &str,
+    expected: &[String],
 ) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
     let start = Instant::now();
-    let result = client.idm_person_account_get(&person.username).await;
+    let result = client
+        .idm_person_account_get_attr(&person.username, attr)
+        .await;
     let duration = Instant::now().duration_since(start);
+
+    let result = result.and_then(|values| {
+        if values.as_deref().unwrap_or_default() == expected {
+            Ok(())
+        } else {
+            Err(ClientError::InvalidResponseFormat(format!(
+                "{attr} holds {values:?}, expected {expected:?}"
+            )))
+        }
+    });
+
     Ok(parse_call_result_into_transition_result_and_event_record(
         result,
         EventDetail::PersonGetSelfAccount,
@@ -239,23 +1495,451 @@ pub async fn person_get_self_account(
     ))
 }
 
+/// Validate the session and read back who it belongs to, which is what most integrations
+/// do before anything else.
+pub async fn person_whoami(
+    client: &KanidmClient,
+    context: &ActorContext,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    Ok(retried(context, EventDetail::PersonWhoami, || async {
+        // No entry means the session wasn't valid.
+        client
+            .whoami()
+            .await
+            .and_then(|entry| entry.ok_or(ClientError::AuthenticationFailed))
+    })
+    .await)
+}
+
+/// Check that the session is still valid and nothing more, as the agents and dashboards
+/// that poll in the background do.
+pub async fn keepalive(
+    client: &KanidmClient,
+    context: &ActorContext,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    Ok(retried(context, EventDetail::Keepalive, || client.auth_valid()).await)
+}
+
+/// The code the server issued at the end of an OAuth2 authorisation, and the PKCE verifier
+/// it has to be exchanged with.
+pub struct AuthorisationCode {
+    code: String,
+    code_verifier: String,
+}
+
+fn person_oauth2_login(person: &Person) -> Result<&Oauth2Login, Error> {
+    person.oauth2_login.as_ref().ok_or_else(|| {
+        Error::invalid(format!(
+            "the person '{}' has no OAuth2 client to sign in to",
+            person.username
+        ))
+    })
+}
+
+// The server answers with a status rather than with the errors of its own API.
+async fn oauth2_response(
+    response: Result<reqwest::Response, reqwest::Error>,
+) -> Result<reqwest::Response, ClientError> {
+    let response = response.map_err(ClientError::Transport)?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let opid = response
+        .headers()
+        .get(KOPID)
+        .and_then(|opid| opid.to_str().ok())
+        .unwrap_or("missing")
+        .to_string();
+    Err(ClientError::Http(status, None, opid))
+}
+
+// Once the person has consented, the server redirects to the client with the code.
+fn oauth2_code_from_redirect(response: &reqwest::Response) -> Result<Option<String>, ClientError> {
+    let Some(location) = response.headers().get(reqwest::header::LOCATION) else {
+        return Ok(None);
+    };
+    let location = location
+        .to_str()
+        .ok()
+        .and_then(|location| Url::parse(location).ok())
+        .ok_or_else(|| ClientError::InvalidResponseFormat("Invalid OAuth2 redirect".to_string()))?;
+    Ok(location
+        .query_pairs()
+        .find(|(key, _)| key == "code")
+        .map(|(_, code)| code.into_owned()))
+}
+
+/// Ask the server to authorise the person for their OAuth2 client, as their browser would,
+/// consenting to the scopes if the server asks for it.
+pub async fn person_oauth2_authorise(
+    client: &KanidmClient,
+    http_client: &reqwest::Client,
+    person: &Person,
+    authorisation_code: &mut Option<AuthorisationCode>,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let oauth2_login = person_oauth2_login(person)?;
+    let redirect_uri = Url::parse(&oauth2_login.redirect_uri).map_err(|err| {
+        Error::invalid(format!(
+            "invalid OAuth2 redirect uri '{}': {err}",
+            oauth2_login.redirect_uri
+        ))
+    })?;
+
+    let code_verifier = Alphanumeric.sample_string(&mut rand::thread_rng(), 64);
+    let request = AuthorisationRequest {
+        response_type: "code".to_string(),
+        client_id: oauth2_login.client.clone(),
+        state: Alphanumeric.sample_string(&mut rand::thread_rng(), 16),
+        pkce_request: Some(PkceRequest {
+            code_challenge: openssl::sha::sha256(code_verifier.as_bytes())
+                .to_vec()
+                .into(),
+            code_challenge_method: CodeChallengeMethod::S256,
+        }),
+        redirect_uri,
+        scope: "openid".to_string(),
+        nonce: None,
+        oidc_ext: Default::default(),
+        unknown_keys: Default::default(),
+    };
+
+    let start = Instant::now();
+    let result = async {
+        let token = client.get_token().await.ok_or(ClientError::Unauthorized)?;
+        let response = oauth2_response(
+            http_client
+                .post(client.make_url(OAUTH2_AUTHORISE))
+                .bearer_auth(&token)
+                .json(&request)
+                .send()
+                .await,
+        )
+        .await?;
+        if let Some(code) = oauth2_code_from_redirect(&response)? {
+            return Ok(code);
+        }
+
+        let consent_token = match response.json::<AuthorisationResponse>().await {
+            Ok(AuthorisationResponse::ConsentRequested { consent_token, .. }) => consent_token,
+            Ok(AuthorisationResponse::Permitted) => {
+                return Err(ClientError::InvalidResponseFormat(
+                    "OAuth2 authorisation permitted without a code".to_string(),
+                ))
+            }
+            Err(err) => {
+                return Err(ClientError::JsonDecode(
+                    err,
+                    "AuthorisationResponse".to_string(),
+                ))
+            }
+        };
+        let response = oauth2_response(
+            http_client
+                .post(client.make_url(OAUTH2_AUTHORISE_PERMIT))
+                .bearer_auth(&token)
+                .json(&consent_token)
+                .send()
+                .await,
+        )
+        .await?;
+        oauth2_code_from_redirect(&response)?.ok_or_else(|| {
+            ClientError::InvalidResponseFormat("No OAuth2 code after consent".to_string())
+        })
+    }
+    .await;
+    let duration = Instant::now().duration_since(start);
+
+    let result = result.map(|code| {
+        *authorisation_code = Some(AuthorisationCode {
+            code,
+            code_verifier,
+        });
+    });
+
+    Ok(parse_call_result_into_transition_result_and_event_record(
+        result,
+        EventDetail::Oauth2Authorise,
+        start,
+        duration,
+    ))
+}
+
+/// Exchange an authorisation code for an access token, as the OAuth2 client of the person
+/// would.
+pub async fn person_oauth2_exchange_code(
+    client: &KanidmClient,
+    http_client: &reqwest::Client,
+    person: &Person,
+    authorisation_code: AuthorisationCode,
+    access_token: &mut Option<String>,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let oauth2_login = person_oauth2_login(person)?;
+    let Some(client_secret) = &oauth2_login.client_secret else {
+        return Err(Error::invalid(format!(
+            "the secret of the OAuth2 client '{}' wasn't fetched",
+            oauth2_login.client
+        )));
+    };
+    let redirect_uri = Url::parse(&oauth2_login.redirect_uri).map_err(|err| {
+        Error::invalid(format!(
+            "invalid OAuth2 redirect uri '{}': {err}",
+            oauth2_login.redirect_uri
+        ))
+    })?;
+
+    let request = AccessTokenRequest::from(GrantTypeReq::AuthorizationCode {
+        code: authorisation_code.code,
+        redirect_uri,
+        code_verifier: Some(authorisation_code.code_verifier),
+    });
+
+    let start = Instant::now();
+    let result = async {
+        let response = oauth2_response(
+            http_client
+                .post(client.make_url("/oauth2/token"))
+                .basic_auth(&oauth2_login.client, Some(client_secret))
+                .form(&request)
+                .send()
+                .await,
+        )
+        .await?;
+        response
+            .json::<AccessTokenResponse>()
+            .await
+            .map_err(|err| ClientError::JsonDecode(err, "AccessTokenResponse".to_string()))
+    }
+    .await;
+    let duration = Instant::now().duration_since(start);
+
+    let result = result.map(|response| {
+        *access_token = Some(response.access_token);
+    });
+
+    Ok(parse_call_result_into_transition_result_and_event_record(
+        result,
+        EventDetail::Oauth2Token,
+        start,
+        duration,
+    ))
+}
+
+/// Read the userinfo of the person with the access token their OAuth2 client was issued.
+pub async fn person_oauth2_userinfo(
+    client: &KanidmClient,
+    http_client: &reqwest::Client,
+    person: &Person,
+    access_token: &str,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let oauth2_login = person_oauth2_login(person)?;
+    let userinfo_url = client.make_url(&format!("/oauth2/openid/{}/userinfo", oauth2_login.client));
+
+    let start = Instant::now();
+    let result = oauth2_response(
+        http_client
+            .get(userinfo_url)
+            .bearer_auth(access_token)
+            .send()
+            .await,
+    )
+    .await;
+    let duration = Instant::now().duration_since(start);
+
+    Ok(parse_call_result_into_transition_result_and_event_record(
+        result,
+        EventDetail::Oauth2Userinfo,
+        start,
+        duration,
+    ))
+}
+
+/// Read an endpoint that doesn't need a session, including the body of the response as a
+/// client would.
+pub async fn anonymous_get(
+    client: &KanidmClient,
+    http_client: &reqwest::Client,
+    path: &str,
+    details: EventDetail,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let url = client.make_url(path);
+
+    let start = Instant::now();
+    let result = match oauth2_response(http_client.get(url).send().await).await {
+        Ok(response) => response.bytes().await.map_err(ClientError::Transport),
+        Err(client_err) => Err(client_err),
+    };
+    let duration = Instant::now().duration_since(start);
+
+    Ok(parse_call_result_into_transition_result_and_event_record(
+        result, details, start, duration,
+    ))
+}
+
 pub async fn person_get_self_memberof(
     client: &KanidmClient,
     person: &Person,
+    context: &ActorContext,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    Ok(retried(context, EventDetail::PersonGetSelfMemberOf, || {
+        client.idm_person_account_get_attr(&person.username, "memberof")
+    })
+    .await)
+}
+
+/// Verify the unix password of the person, as a host authenticating a posix login would.
+pub async fn person_unix_auth(
+    client: &KanidmClient,
+    person: &Person,
 ) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let Some(UnixCredential::Password { plain }) = &person.unix_credential else {
+        return Err(Error::invalid(format!(
+            "the person '{}' has no unix password",
+            person.username
+        )));
+    };
+
     let start = Instant::now();
     let result = client
-        .idm_person_account_get_attr(&person.username, "memberof")
+        .idm_account_unix_cred_verify(&person.username, plain)
         .await;
     let duration = Instant::now().duration_since(start);
+
+    // A rejected password isn't an error of the call itself, but it is for us.
+    let result = result.and_then(|token| token.ok_or(ClientError::AuthenticationFailed));
+
     Ok(parse_call_result_into_transition_result_and_event_record(
         result,
-        EventDetail::PersonGetSelfMemberOf,
+        EventDetail::UnixAuth,
         start,
         duration,
     ))
 }
 
+/// Read the groups the person is a member of one by one, as someone browsing the directory
+/// by group would. With `expand_members` the members of each group are fetched as well.
+pub async fn person_read_groups(
+    client: &KanidmClient,
+    person: &Person,
+    expand_members: bool,
+) -> Result<(TransitionResult, Vec<EventRecord>), Error> {
+    let start = Instant::now();
+    let result = client
+        .idm_person_account_get_attr(&person.username, "memberof")
+        .await;
+    let duration = Instant::now().duration_since(start);
+
+    let groups = match result {
+        Ok(groups) => groups.unwrap_or_default(),
+        Err(client_err) => {
+            return Ok(parse_call_result_into_transition_result_and_event_record(
+                Err::<(), _>(client_err),
+                EventDetail::PersonGetSelfMemberOf,
+                start,
+                duration,
+            ))
+        }
+    };
+
+    let mut event_records = vec![EventRecord {
+        start,
+        duration,
+        details: EventDetail::PersonGetSelfMemberOf,
+    }];
+
+    for group in groups.iter() {
+        let start = Instant::now();
+        let result = client.idm_group_get(group).await;
+        let duration = Instant::now().duration_since(start);
+        let (transition_result, mut records) =
+            parse_call_result_into_transition_result_and_event_record(
+                result,
+                EventDetail::PersonGetGroup,
+                start,
+                duration,
+            );
+        event_records.append(&mut records);
+        if transition_result != TransitionResult::Ok {
+            return Ok((transition_result, event_records));
+        }
+
+        if expand_members {
+            let start = Instant::now();
+            let result = client.idm_group_get_members(group).await;
+            let duration = Instant::now().duration_since(start);
+            let (transition_result, mut records) =
+                parse_call_result_into_transition_result_and_event_record(
+                    result,
+                    EventDetail::PersonGetGroupMembers,
+                    start,
+                    duration,
+                );
+            event_records.append(&mut records);
+            if transition_result != TransitionResult::Ok {
+                return Ok((transition_result, event_records));
+            }
+        }
+    }
+
+    Ok((TransitionResult::Ok, event_records))
+}
+
+/// Perform a request, and try it again for as long as it fails in a way that may not last and
+/// the retry policy of the actor allows. Every failed attempt is recorded as an error, and
+/// when retries are enabled, so is how many attempts the request took in all.
+async fn retried<T, F, Fut>(
+    context: &ActorContext,
+    details: EventDetail,
+    mut request: F,
+) -> (TransitionResult, Vec<EventRecord>)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ClientError>>,
+{
+    let first_start = Instant::now();
+    let mut first_attempt = None;
+    let mut event_records = Vec::new();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let start = Instant::now();
+        let result = request().await;
+        let duration = Instant::now().duration_since(start);
+        first_attempt.get_or_insert(duration);
+
+        let (transition_result, mut records) =
+            parse_call_result_into_transition_result_and_event_record(
+                result,
+                details.clone(),
+                start,
+                duration,
+            );
+        let transient = records.iter().any(|record| {
+            matches!(&record.details, EventDetail::Error(failure) if failure.is_transient())
+        });
+        event_records.append(&mut records);
+
+        if transient && attempts <= context.retry.max_retries {
+            tokio::time::sleep(Duration::from_millis(context.retry.backoff_ms)).await;
+            continue;
+        }
+
+        if context.retry.max_retries > 0 {
+            event_records.push(EventRecord {
+                start: first_start,
+                duration: Instant::now().duration_since(first_start),
+                details: EventDetail::Attempts(Box::new(AttemptsSample {
+                    event: details,
+                    attempts,
+                    first_attempt: first_attempt.unwrap_or_default(),
+                    succeeded: transition_result == TransitionResult::Ok,
+                })),
+            });
+        }
+        return (transition_result, event_records);
+    }
+}
+
 fn parse_call_result_into_transition_result_and_event_record<T>(
     result: Result<T, ClientError>,
     details: EventDetail,
@@ -273,12 +1957,23 @@ fn parse_call_result_into_transition_result_and_event_record<T>(
         ),
         Err(client_err) => {
             debug!(?client_err);
+            let failure = Failure::from_client_error(details, &client_err);
+            let (transition_result, details) = if failure.needs_authentication() {
+                (
+                    TransitionResult::AuthenticationNeeded,
+                    EventDetail::Error(failure),
+                )
+            } else if failure.is_policy_rejection() {
+                (TransitionResult::Rejected, EventDetail::Rejected(failure))
+            } else {
+                (TransitionResult::Error, EventDetail::Error(failure))
+            };
             (
-                TransitionResult::Error,
+                transition_result,
                 vec![EventRecord {
                     start,
                     duration,
-                    details: EventDetail::Error,
+                    details,
                 }],
             )
         }
@@ -0,0 +1,154 @@
+// Walk the models of a profile through their transitions without a server, as if every one of
+// them succeeded, so that the mix of actions and delays a profile makes can be checked before a
+// real run.
+use crate::clock::ManualClock;
+use crate::error::Error;
+use crate::generate;
+use crate::model::TransitionResult;
+use crate::profile::Profile;
+
+use reqwest::header::HeaderMap;
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+/// The actions the models of a profile went through, the delays before them, and the
+/// transitions that couldn't be made.
+pub struct ModelReport {
+    actors: usize,
+    steps: usize,
+    actions: BTreeMap<String, usize>,
+    delays: Vec<Duration>,
+    invalid: BTreeMap<String, usize>,
+}
+
+impl ModelReport {
+    pub fn is_valid(&self) -> bool {
+        self.invalid.is_empty()
+    }
+}
+
+/// Run the models of the first `actors` persons of the profile for `steps` transitions each.
+pub fn verify(profile: Profile, actors: usize, steps: usize) -> Result<ModelReport, Error> {
+    let seed = profile.seed();
+    let warmup_time = profile.warmup_time();
+    let scripted = profile.scripted().clone();
+    let state = generate::generate(profile, None)?;
+
+    let mut report = ModelReport {
+        actors: 0,
+        steps,
+        actions: BTreeMap::new(),
+        delays: Vec::new(),
+        invalid: BTreeMap::new(),
+    };
+    for person in state.persons.iter().take(actors) {
+        report.actors += 1;
+        let clock = ManualClock::new();
+        let mut model = match person.model.as_dyn_object(
+            seed,
+            Vec::new(),
+            &person.username,
+            warmup_time,
+            clock.clone(),
+            &scripted,
+            &HeaderMap::new(),
+        ) {
+            Ok(model) => model,
+            Err(_) => {
+                *report
+                    .invalid
+                    .entry(format!("{:?} can't be set up", person.model))
+                    .or_default() += 1;
+                continue;
+            }
+        };
+
+        for _ in 0..steps {
+            let delay = model.next_delay(person).unwrap_or_default();
+            let Some(action) = model.pending_action() else {
+                *report
+                    .invalid
+                    .entry(format!("{:?} decided on no action", person.model))
+                    .or_default() += 1;
+                break;
+            };
+            *report.actions.entry(format!("{action:?}")).or_default() += 1;
+            report.delays.push(delay);
+            clock.advance(delay);
+
+            if model.assume_transition(TransitionResult::Ok).is_err() {
+                *report
+                    .invalid
+                    .entry(format!("{:?} can't move on from {action:?}", person.model))
+                    .or_default() += 1;
+                break;
+            }
+        }
+    }
+    Ok(report)
+}
+
+impl Display for ModelReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "actors: {}, steps: {}", self.actors, self.steps)?;
+
+        let total = self.actions.values().sum::<usize>().max(1);
+        writeln!(f, "actions:")?;
+        for (action, count) in self.actions.iter() {
+            writeln!(
+                f,
+                "  {:<24} {:>8} {:>6.1}%",
+                action,
+                count,
+                *count as f64 * 100. / total as f64
+            )?;
+        }
+
+        let mut delays = self.delays.clone();
+        delays.sort();
+        let at = |quantile: f64| {
+            delays
+                .get(
+                    ((delays.len() as f64 * quantile) as usize).min(delays.len().saturating_sub(1)),
+                )
+                .copied()
+                .unwrap_or_default()
+        };
+        writeln!(
+            f,
+            "delays:                   p50 {:?}, p95 {:?}, max {:?}",
+            at(0.5),
+            at(0.95),
+            delays.last().copied().unwrap_or_default()
+        )?;
+
+        if self.invalid.is_empty() {
+            write!(f, "invalid transitions:      none")
+        } else {
+            write!(f, "invalid transitions:")?;
+            for (invalid, count) in self.invalid.iter() {
+                write!(f, "\n  {:<48} {}", invalid, count)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::verify;
+    use crate::profile::Profile;
+
+    #[test]
+    fn test_sample_profile_models_make_valid_transitions() {
+        let profile: Profile = toml::from_str(include_str!("../profile-sample.toml")).unwrap();
+        let Ok(report) = verify(profile, 3, 50) else {
+            panic!("The models are walked through");
+        };
+        assert!(report.is_valid());
+        assert_eq!(report.actors, 3);
+        assert_eq!(report.actions.values().sum::<usize>(), 150);
+    }
+}
@@ -0,0 +1,324 @@
+use crate::model::{
+    self, ActorContext, ActorModel, ActorRole, CreatedEntry, Transition, TransitionAction,
+    TransitionResult,
+};
+
+use crate::error::Error;
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+use kanidm_proto::constants::{ATTR_ACCOUNT_EXPIRE, ATTR_ACCOUNT_VALID_FROM};
+
+use async_trait::async_trait;
+use chrono::{SecondsFormat, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+// How far in the past/future we move the validity bounds when closing the window.
+const VALIDITY_OFFSET_HOURS: i64 = 1;
+
+enum State {
+    Unauthenticated,
+    Authenticated,
+    AuthenticatedWithReauth,
+    CanaryReady,
+    CanaryChecked,
+}
+
+pub struct ActorAccountValidity {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    randomised_backoff_time: Duration,
+    canary_name_prefix: String,
+    canary_index: u64,
+    canary_password: String,
+    // The canary logs in on its own session, so the admin session stays intact.
+    canary_client: Option<KanidmClient>,
+    window_open: bool,
+    // Alternate between an expired account and one that is not yet valid.
+    close_with_expiry: bool,
+}
+
+impl ActorAccountValidity {
+    pub fn new(mut cha_rng: ChaCha8Rng, person_name: &str, warmup_time_ms: u64) -> Self {
+        let max_backoff_time_in_ms = 2 * warmup_time_ms / 3;
+        let randomised_backoff_time =
+            Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
+        ActorAccountValidity {
+            state: State::Unauthenticated,
+            pending: None,
+            randomised_backoff_time,
+            canary_name_prefix: format!("{person_name}-validity-canary"),
+            canary_index: 0,
+            canary_password: Alphanumeric.sample_string(&mut cha_rng, 24),
+            canary_client: None,
+            window_open: true,
+            close_with_expiry: true,
+        }
+    }
+
+    fn canary(&self) -> Person {
+        let username = format!("{}-{}", self.canary_name_prefix, self.canary_index);
+        Person {
+            preflight_state: PreflightState::Present,
+            display_name: username.clone(),
+            username,
+            roles: BTreeSet::new(),
+            credential: Credential::Password {
+                plain: self.canary_password.clone(),
+            },
+            model: Model::AuthOnly,
+            mfa_enrollment: None,
+            login_storm_delay: None,
+            unix_credential: None,
+            login_as: None,
+            read_attributes: None,
+            oauth2_login: None,
+            manages: None,
+            password_expiry: None,
+            registered_credential: Default::default(),
+            provided_password: Default::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorAccountValidity {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(&person.roles);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        if self.canary_client.is_none() {
+            let canary_client = client
+                .new_session()
+                .map_err(|err| Error::client("create a new kanidm client session", err))?;
+            self.canary_client = Some(canary_client);
+        }
+
+        let canary = self.canary();
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match transition.action {
+            TransitionAction::Login => model::login(client, person).await,
+            TransitionAction::Logout => model::logout(client, person).await,
+            TransitionAction::PrivilegeReauth => model::privilege_reauth(client, person).await,
+            TransitionAction::CreatePerson => {
+                context
+                    .created_entries
+                    .insert(CreatedEntry::Person(canary.username.clone()));
+                model::person_create_person(client, &canary).await
+            }
+            TransitionAction::ToggleAccountValidity => {
+                if self.window_open {
+                    let (attr, at) = if self.close_with_expiry {
+                        (
+                            ATTR_ACCOUNT_EXPIRE,
+                            Utc::now() - chrono::Duration::hours(VALIDITY_OFFSET_HOURS),
+                        )
+                    } else {
+                        (
+                            ATTR_ACCOUNT_VALID_FROM,
+                            Utc::now() + chrono::Duration::hours(VALIDITY_OFFSET_HOURS),
+                        )
+                    };
+                    let at = at.to_rfc3339_opts(SecondsFormat::Secs, true);
+                    model::person_set_account_validity(
+                        client,
+                        &canary.username,
+                        Some((attr, at.as_str())),
+                    )
+                    .await
+                } else {
+                    model::person_set_account_validity(client, &canary.username, None).await
+                }
+            }
+            TransitionAction::LoginValidityCanary => {
+                let canary_client = self
+                    .canary_client
+                    .as_ref()
+                    .ok_or_else(|| Error::invalid("the canary has no client to log in with"))?;
+                if self.window_open {
+                    model::login(canary_client, &canary).await
+                } else {
+                    model::login_rejected_by_validity(canary_client, &canary).await
+                }
+            }
+            TransitionAction::WriteAttributePersonMail
+            | TransitionAction::ReadSelfAccount
+            | TransitionAction::ReadSelfMemberOf
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::CreateGroup
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::EnrollMfa
+            | TransitionAction::ReadGroup
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::Whoami => return Err(Error::UnsupportedAction(transition.action)),
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+
+    async fn on_stop(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let created_canaries: Vec<String> = context.created_persons().map(String::from).collect();
+        if created_canaries.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (result, mut event_records) = model::login_with_privileges(client, person).await?;
+        if result == TransitionResult::Ok {
+            for canary in created_canaries {
+                let (result, mut records) = model::person_delete_person(client, &canary).await?;
+                if result == TransitionResult::Ok {
+                    context
+                        .created_entries
+                        .remove(&CreatedEntry::Person(canary));
+                }
+                event_records.append(&mut records);
+            }
+        }
+
+        Ok(event_records)
+    }
+}
+
+impl ActorAccountValidity {
+    pub fn configured_mix(person: &Person) -> Vec<(TransitionAction, u32)> {
+        if person.roles.contains(&ActorRole::PeopleAccountPolicyAdmin) {
+            vec![
+                (TransitionAction::LoginValidityCanary, 1),
+                (TransitionAction::ToggleAccountValidity, 1),
+            ]
+        } else {
+            vec![(TransitionAction::Login, 1), (TransitionAction::Logout, 1)]
+        }
+    }
+
+    fn next_transition(&mut self, roles: &BTreeSet<ActorRole>) -> Transition {
+        match self.state {
+            State::Unauthenticated => Transition {
+                delay: Some(self.randomised_backoff_time),
+                action: TransitionAction::Login,
+            },
+            // Only account policy admins can change the validity, everyone else just
+            // cycles their session.
+            State::Authenticated if !roles.contains(&ActorRole::PeopleAccountPolicyAdmin) => {
+                Transition {
+                    delay: Some(Duration::from_secs(5)),
+                    action: TransitionAction::Logout,
+                }
+            }
+            State::Authenticated => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::PrivilegeReauth,
+            },
+            State::AuthenticatedWithReauth => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::CreatePerson,
+            },
+            State::CanaryReady => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::LoginValidityCanary,
+            },
+            State::CanaryChecked => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::ToggleAccountValidity,
+            },
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (&self.state, action, result) {
+            (State::Unauthenticated, TransitionAction::Login, TransitionResult::Ok) => {
+                self.state = State::Authenticated;
+            }
+            (State::Authenticated, TransitionAction::PrivilegeReauth, TransitionResult::Ok) => {
+                self.state = State::AuthenticatedWithReauth;
+            }
+            (
+                State::AuthenticatedWithReauth,
+                TransitionAction::CreatePerson,
+                TransitionResult::Ok,
+            ) => {
+                self.window_open = true;
+                self.state = State::CanaryReady;
+            }
+            (State::CanaryReady, TransitionAction::LoginValidityCanary, TransitionResult::Ok) => {
+                self.state = State::CanaryChecked;
+            }
+            (
+                State::CanaryChecked,
+                TransitionAction::ToggleAccountValidity,
+                TransitionResult::Ok,
+            ) => {
+                if self.window_open {
+                    self.close_with_expiry = !self.close_with_expiry;
+                }
+                self.window_open = !self.window_open;
+                self.state = State::CanaryReady;
+            }
+            (_, TransitionAction::Logout, TransitionResult::Ok) => {
+                self.state = State::Unauthenticated;
+            }
+            #[allow(clippy::unreachable)]
+            (_, _, TransitionResult::Ok) => {
+                unreachable!();
+            }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+            (
+                State::AuthenticatedWithReauth | State::CanaryReady | State::CanaryChecked,
+                _,
+                TransitionResult::Error,
+            ) => {
+                // We don't know what validity the canary was left with, so leave it
+                // behind and start over with a fresh one.
+                self.canary_index += 1;
+                self.state = State::Unauthenticated;
+            }
+            (_, _, TransitionResult::Error) => {
+                self.state = State::Unauthenticated;
+            }
+        }
+    }
+}
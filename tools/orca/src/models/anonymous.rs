@@ -0,0 +1,167 @@
+use crate::model::{
+    self, ActorContext, ActorModel, Transition, TransitionAction, TransitionResult,
+};
+
+use crate::error::Error;
+use crate::run::{EventDetail, EventRecord};
+use crate::state::*;
+use kanidm_client::KanidmClient;
+use reqwest::header::HeaderMap;
+
+use async_trait::async_trait;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use std::time::Duration;
+
+// Much faster than a person would, as a client polling in a tight loop does.
+const POLL_DELAY: Duration = Duration::from_millis(100);
+
+enum State {
+    Started,
+    ReadStatus,
+    ReadDiscovery,
+    ReadJwks,
+}
+
+pub struct ActorAnonymous {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    randomised_backoff_time: Duration,
+    // Nothing is authenticated, so the requests don't go through the client API.
+    http_client: reqwest::Client,
+}
+
+impl ActorAnonymous {
+    pub fn new(
+        mut cha_rng: ChaCha8Rng,
+        warmup_time_ms: u64,
+        headers: &HeaderMap,
+    ) -> Result<Self, Error> {
+        let max_backoff_time_in_ms = 2 * warmup_time_ms / 3;
+        let randomised_backoff_time =
+            Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
+        let http_client = reqwest::Client::builder()
+            .danger_accept_invalid_hostnames(true)
+            .danger_accept_invalid_certs(true)
+            .default_headers(headers.clone())
+            .build()
+            .map_err(|err| Error::http("create the anonymous http client", err))?;
+        Ok(ActorAnonymous {
+            state: State::Started,
+            pending: None,
+            randomised_backoff_time,
+            http_client,
+        })
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorAnonymous {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(person);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, _result: TransitionResult) -> Result<(), Error> {
+        // Every action is decided on afresh, so there's nothing to move on from.
+        self.pending.take().ok_or(Error::NoPendingAction)?;
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        _context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        let oauth2_client = person
+            .oauth2_login
+            .as_ref()
+            .map(|oauth2_login| oauth2_login.client.as_str());
+
+        // Once we get to here, we want the transition to go ahead.
+        let (_, event) = match (transition.action, oauth2_client) {
+            (TransitionAction::ReadStatus, _) => {
+                model::anonymous_get(client, &self.http_client, "/status", EventDetail::Status)
+                    .await
+            }
+            (TransitionAction::ReadOauth2Discovery, Some(oauth2_client)) => {
+                model::anonymous_get(
+                    client,
+                    &self.http_client,
+                    &format!("/oauth2/openid/{oauth2_client}/.well-known/openid-configuration"),
+                    EventDetail::Oauth2Discovery,
+                )
+                .await
+            }
+            (TransitionAction::ReadOauth2Jwks, Some(oauth2_client)) => {
+                model::anonymous_get(
+                    client,
+                    &self.http_client,
+                    &format!("/oauth2/openid/{oauth2_client}/public_key.jwk"),
+                    EventDetail::Oauth2Jwks,
+                )
+                .await
+            }
+            _ => return Err(Error::UnsupportedAction(transition.action)),
+        }?;
+
+        // Polling carries on whatever the outcome, as the clients we imitate do.
+        self.state = match transition.action {
+            TransitionAction::ReadOauth2Discovery => State::ReadDiscovery,
+            TransitionAction::ReadOauth2Jwks => State::ReadJwks,
+            _ => State::ReadStatus,
+        };
+
+        Ok(event)
+    }
+}
+
+impl ActorAnonymous {
+    pub fn configured_mix(person: &Person) -> Vec<(TransitionAction, u32)> {
+        if person.oauth2_login.is_none() {
+            vec![(TransitionAction::ReadStatus, 1)]
+        } else {
+            vec![
+                (TransitionAction::ReadStatus, 1),
+                (TransitionAction::ReadOauth2Discovery, 1),
+                (TransitionAction::ReadOauth2Jwks, 1),
+            ]
+        }
+    }
+
+    fn next_transition(&mut self, person: &Person) -> Transition {
+        match self.state {
+            State::Started => Transition {
+                delay: Some(self.randomised_backoff_time),
+                action: TransitionAction::ReadStatus,
+            },
+            // Persons without an OAuth2 client only have the status to read.
+            State::ReadStatus if person.oauth2_login.is_none() => Transition {
+                delay: Some(POLL_DELAY),
+                action: TransitionAction::ReadStatus,
+            },
+            State::ReadStatus => Transition {
+                delay: Some(POLL_DELAY),
+                action: TransitionAction::ReadOauth2Discovery,
+            },
+            State::ReadDiscovery => Transition {
+                delay: Some(POLL_DELAY),
+                action: TransitionAction::ReadOauth2Jwks,
+            },
+            State::ReadJwks => Transition {
+                delay: Some(POLL_DELAY),
+                action: TransitionAction::ReadStatus,
+            },
+        }
+    }
+}
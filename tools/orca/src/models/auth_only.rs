@@ -1,4 +1,6 @@
-use crate::model::{self, ActorModel, Transition, TransitionAction, TransitionResult};
+use crate::model::{
+    self, ActorContext, ActorModel, Transition, TransitionAction, TransitionResult,
+};
 
 use crate::error::Error;
 use crate::run::EventRecord;
@@ -12,38 +14,57 @@ use std::time::Duration;
 enum State {
     Unauthenticated,
     Authenticated,
+    UnixAuthenticated,
 }
 
 pub struct ActorAuthOnly {
     state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
 }
 
 impl ActorAuthOnly {
     pub fn new() -> Self {
         ActorAuthOnly {
             state: State::Unauthenticated,
+            pending: None,
         }
     }
 }
 
 #[async_trait]
 impl ActorModel for ActorAuthOnly {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(person);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
     async fn transition(
         &mut self,
         client: &KanidmClient,
         person: &Person,
+        _context: &mut ActorContext,
     ) -> Result<Vec<EventRecord>, Error> {
-        let transition = self.next_transition();
-
-        if let Some(delay) = transition.delay {
-            tokio::time::sleep(delay).await;
-        }
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
 
         // Once we get to here, we want the transition to go ahead.
         let (result, event) = match transition.action {
             TransitionAction::Login => model::login(client, person).await,
             TransitionAction::Logout => model::logout(client, person).await,
-            _ => Err(Error::InvalidState),
+            TransitionAction::UnixAuth => model::person_unix_auth(client, person).await,
+            _ => Err(Error::UnsupportedAction(transition.action)),
         }?;
 
         self.next_state(transition.action, result);
@@ -53,13 +74,26 @@ impl ActorModel for ActorAuthOnly {
 }
 
 impl ActorAuthOnly {
-    fn next_transition(&mut self) -> Transition {
+    pub fn configured_mix(person: &Person) -> Vec<(TransitionAction, u32)> {
+        let mut mix = vec![(TransitionAction::Login, 1), (TransitionAction::Logout, 1)];
+        if person.unix_credential.is_some() {
+            mix.push((TransitionAction::UnixAuth, 1));
+        }
+        mix
+    }
+
+    fn next_transition(&mut self, person: &Person) -> Transition {
         match self.state {
             State::Unauthenticated => Transition {
                 delay: None,
                 action: TransitionAction::Login,
             },
-            State::Authenticated => Transition {
+            // Persons with a unix password also authenticate like a posix host would.
+            State::Authenticated if person.unix_credential.is_some() => Transition {
+                delay: Some(Duration::from_millis(100)),
+                action: TransitionAction::UnixAuth,
+            },
+            State::Authenticated | State::UnixAuthenticated => Transition {
                 delay: Some(Duration::from_millis(100)),
                 action: TransitionAction::Logout,
             },
@@ -71,7 +105,14 @@ impl ActorAuthOnly {
             (State::Unauthenticated, TransitionAction::Login, TransitionResult::Ok) => {
                 self.state = State::Authenticated;
             }
-            (State::Authenticated, TransitionAction::Logout, TransitionResult::Ok) => {
+            (State::Authenticated, TransitionAction::UnixAuth, TransitionResult::Ok) => {
+                self.state = State::UnixAuthenticated;
+            }
+            (
+                State::Authenticated | State::UnixAuthenticated,
+                TransitionAction::Logout,
+                TransitionResult::Ok,
+            ) => {
                 self.state = State::Unauthenticated;
             }
             // Shouldn't be reachable?
@@ -79,6 +120,7 @@ impl ActorAuthOnly {
             (_, _, TransitionResult::Ok) => {
                 unreachable!();
             }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
             (_, _, TransitionResult::Error) => {
                 self.state = State::Unauthenticated;
             }
@@ -1,4 +1,6 @@
-use crate::model::{self, ActorModel, ActorRole, Transition, TransitionAction, TransitionResult};
+use crate::model::{
+    self, ActorContext, ActorModel, ActorRole, Transition, TransitionAction, TransitionResult,
+};
 
 use crate::error::Error;
 use crate::run::EventRecord;
@@ -20,6 +22,8 @@ enum State {
 
 pub struct ActorBasic {
     state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
     randomised_backoff_time: Duration,
 }
 
@@ -30,6 +34,7 @@ impl ActorBasic {
             Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
         ActorBasic {
             state: State::Unauthenticated,
+            pending: None,
             randomised_backoff_time,
         }
     }
@@ -37,37 +42,76 @@ impl ActorBasic {
 
 #[async_trait]
 impl ActorModel for ActorBasic {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(&person.roles);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
     async fn transition(
         &mut self,
         client: &KanidmClient,
         person: &Person,
+        context: &mut ActorContext,
     ) -> Result<Vec<EventRecord>, Error> {
-        let transition = self.next_transition(&person.roles);
-
-        if let Some(delay) = transition.delay {
-            tokio::time::sleep(delay).await;
-        }
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
 
         // Once we get to here, we want the transition to go ahead.
         let (result, event) = match transition.action {
-            TransitionAction::Login => model::login(client, person).await,
-            TransitionAction::Logout => model::logout(client, person).await,
+            TransitionAction::Login => model::start_session(client, person, context).await,
+            TransitionAction::Logout => model::end_session(client, person, context).await,
             TransitionAction::PrivilegeReauth => model::privilege_reauth(client, person).await,
             TransitionAction::WriteAttributePersonMail => {
                 let mail = format!("{}@example.com", person.username);
                 let values = &[mail.as_str()];
-                model::person_set_self_mail(client, person, values).await
+                model::person_set_self_mail(client, person, values, context).await
             }
             TransitionAction::ReadSelfAccount => {
-                model::person_get_self_account(client, person).await
+                model::person_get_self_account(client, person, context).await
             }
             TransitionAction::ReadSelfMemberOf => {
-                model::person_get_self_memberof(client, person).await
+                model::person_get_self_memberof(client, person, context).await
             }
+            TransitionAction::ReadGroup => model::person_read_groups(client, person, true).await,
+            TransitionAction::Whoami => model::person_whoami(client, context).await,
             TransitionAction::WriteSelfPassword => {
                 // I know it's dumb but here we just re-set the same password because it's the simplest thing to do
-                let Credential::Password { plain } = &person.credential;
-                model::person_set_self_password(client, person, plain).await
+                let Some(plain) = person.password() else {
+                    return Err(Error::invalid(format!(
+                        "the person '{}' has no password to set",
+                        person.username
+                    )));
+                };
+                model::person_set_self_password(client, person, plain, context).await
+            }
+            TransitionAction::CreateGroup
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo => {
+                return Err(Error::UnsupportedAction(transition.action))
             }
         }?;
 
@@ -78,6 +122,28 @@ impl ActorModel for ActorBasic {
 }
 
 impl ActorBasic {
+    pub fn configured_mix(person: &Person) -> Vec<(TransitionAction, u32)> {
+        // Once reauthenticated, the person only performs the action of their first role.
+        let action = match person.roles.first() {
+            Some(ActorRole::PeopleSelfMailWrite) => TransitionAction::WriteAttributePersonMail,
+            Some(ActorRole::PeopleSelfReadProfile) => TransitionAction::ReadSelfAccount,
+            Some(ActorRole::PeopleSelfReadMemberOf) => TransitionAction::ReadSelfMemberOf,
+            Some(ActorRole::PeopleSelfSetPassword) => TransitionAction::WriteSelfPassword,
+            Some(ActorRole::PeoplePiiReader) => TransitionAction::ReadGroup,
+            Some(
+                ActorRole::PeopleGroupAdmin | ActorRole::PeopleAccountPolicyAdmin | ActorRole::None,
+            ) => {
+                return vec![
+                    (TransitionAction::Login, 1),
+                    (TransitionAction::PrivilegeReauth, 1),
+                    (TransitionAction::Logout, 1),
+                ]
+            }
+            None => TransitionAction::Whoami,
+        };
+        vec![(action, 1)]
+    }
+
     fn next_transition(&mut self, roles: &BTreeSet<ActorRole>) -> Transition {
         let logout_transition = Transition {
             delay: Some(Duration::from_secs(5)),
@@ -113,11 +179,20 @@ impl ActorBasic {
                         delay: Some(Duration::from_secs(3)),
                         action: TransitionAction::WriteSelfPassword,
                     },
-                    ActorRole::PeoplePiiReader | ActorRole::PeopleGroupAdmin | ActorRole::None => {
-                        logout_transition
-                    }
+                    // PII readers browse the directory, group by group.
+                    ActorRole::PeoplePiiReader => Transition {
+                        delay: Some(Duration::from_secs(2)),
+                        action: TransitionAction::ReadGroup,
+                    },
+                    ActorRole::PeopleGroupAdmin
+                    | ActorRole::PeopleAccountPolicyAdmin
+                    | ActorRole::None => logout_transition,
+                },
+                // Persons without a role only check who they are, like most integrations.
+                None => Transition {
+                    delay: Some(Duration::from_secs(2)),
+                    action: TransitionAction::Whoami,
                 },
-                None => logout_transition,
             },
         }
     }
@@ -137,6 +212,8 @@ impl ActorBasic {
                 TransitionAction::WriteAttributePersonMail
                 | TransitionAction::ReadSelfAccount
                 | TransitionAction::ReadSelfMemberOf
+                | TransitionAction::ReadGroup
+                | TransitionAction::Whoami
                 | TransitionAction::WriteSelfPassword,
                 TransitionResult::Ok,
             ) => {
@@ -149,6 +226,7 @@ impl ActorBasic {
             (_, _, TransitionResult::Ok) => {
                 unreachable!();
             }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
             (_, _, TransitionResult::Error) => {
                 self.state = State::Unauthenticated;
             }
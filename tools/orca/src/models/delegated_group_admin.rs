@@ -0,0 +1,194 @@
+use crate::model::{
+    self, ActorContext, ActorModel, Transition, TransitionAction, TransitionResult,
+};
+
+use crate::error::Error;
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+
+use async_trait::async_trait;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use std::time::Duration;
+
+enum State {
+    Unauthenticated,
+    Authenticated,
+    ReadGroups,
+    AuthenticatedWithReauth,
+    NotMember,
+    NotMemberRead,
+    Member,
+    MemberRead,
+}
+
+pub struct ActorDelegatedGroupAdmin {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    randomised_backoff_time: Duration,
+}
+
+impl ActorDelegatedGroupAdmin {
+    pub fn new(mut cha_rng: ChaCha8Rng, warmup_time_ms: u64) -> Self {
+        let max_backoff_time_in_ms = 2 * warmup_time_ms / 3;
+        let randomised_backoff_time =
+            Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
+        ActorDelegatedGroupAdmin {
+            state: State::Unauthenticated,
+            pending: None,
+            randomised_backoff_time,
+        }
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorDelegatedGroupAdmin {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(person);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match (transition.action, &person.manages) {
+            (TransitionAction::Login, _) => model::start_session(client, person, context).await,
+            (TransitionAction::Logout, _) => model::end_session(client, person, context).await,
+            (TransitionAction::PrivilegeReauth, _) => model::privilege_reauth(client, person).await,
+            // Managers read the group they manage, everyone else the groups they're in.
+            (TransitionAction::ReadGroup, Some(manages)) => {
+                model::person_get_group(client, &manages.group, context).await
+            }
+            (TransitionAction::ReadGroup, None) => {
+                model::person_read_groups(client, person, true).await
+            }
+            (TransitionAction::AddGroupMembers, Some(manages)) => {
+                model::person_add_group_members(client, &manages.group, &[&manages.member]).await
+            }
+            (TransitionAction::RemoveGroupMembers, Some(manages)) => {
+                model::person_remove_group_members(client, &manages.group, &[&manages.member]).await
+            }
+            _ => return Err(Error::UnsupportedAction(transition.action)),
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+}
+
+impl ActorDelegatedGroupAdmin {
+    pub fn configured_mix(person: &Person) -> Vec<(TransitionAction, u32)> {
+        if person.manages.is_none() {
+            vec![
+                (TransitionAction::Login, 1),
+                (TransitionAction::ReadGroup, 1),
+                (TransitionAction::Logout, 1),
+            ]
+        } else {
+            // The group is read after each change of the membership.
+            vec![
+                (TransitionAction::AddGroupMembers, 1),
+                (TransitionAction::RemoveGroupMembers, 1),
+                (TransitionAction::ReadGroup, 2),
+            ]
+        }
+    }
+
+    fn next_transition(&mut self, person: &Person) -> Transition {
+        match self.state {
+            State::Unauthenticated => Transition {
+                delay: Some(self.randomised_backoff_time),
+                action: TransitionAction::Login,
+            },
+            // Only managers change a group, everyone else reads their groups.
+            State::Authenticated if person.manages.is_none() => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::ReadGroup,
+            },
+            State::ReadGroups => Transition {
+                delay: Some(Duration::from_secs(5)),
+                action: TransitionAction::Logout,
+            },
+            State::Authenticated => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::PrivilegeReauth,
+            },
+            // The membership may be unknown after an error, and adding it again is harmless.
+            State::AuthenticatedWithReauth | State::NotMemberRead => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::AddGroupMembers,
+            },
+            State::NotMember | State::Member => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::ReadGroup,
+            },
+            State::MemberRead => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::RemoveGroupMembers,
+            },
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (&self.state, action, result) {
+            (State::Unauthenticated, TransitionAction::Login, TransitionResult::Ok) => {
+                self.state = State::Authenticated;
+            }
+            (State::Authenticated, TransitionAction::ReadGroup, TransitionResult::Ok) => {
+                self.state = State::ReadGroups;
+            }
+            (State::Authenticated, TransitionAction::PrivilegeReauth, TransitionResult::Ok) => {
+                self.state = State::AuthenticatedWithReauth;
+            }
+            (
+                State::AuthenticatedWithReauth | State::NotMemberRead,
+                TransitionAction::AddGroupMembers,
+                TransitionResult::Ok,
+            ) => {
+                self.state = State::Member;
+            }
+            (State::NotMember, TransitionAction::ReadGroup, TransitionResult::Ok) => {
+                self.state = State::NotMemberRead;
+            }
+            (State::Member, TransitionAction::ReadGroup, TransitionResult::Ok) => {
+                self.state = State::MemberRead;
+            }
+            (State::MemberRead, TransitionAction::RemoveGroupMembers, TransitionResult::Ok) => {
+                self.state = State::NotMember;
+            }
+            (_, TransitionAction::Logout, TransitionResult::Ok) => {
+                self.state = State::Unauthenticated;
+            }
+            #[allow(clippy::unreachable)]
+            (_, _, TransitionResult::Ok) => {
+                unreachable!();
+            }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+            (_, _, TransitionResult::Error) => {
+                self.state = State::Unauthenticated;
+            }
+        }
+    }
+}
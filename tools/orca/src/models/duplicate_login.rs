@@ -0,0 +1,190 @@
+use crate::model::{
+    self, ActorContext, ActorModel, Transition, TransitionAction, TransitionResult,
+};
+
+use crate::clock::SharedClock;
+use crate::error::Error;
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+
+use async_trait::async_trait;
+
+use std::time::{Duration, Instant};
+
+// Kept short, so that the persons sharing an account keep overlapping after the first race.
+const RELOGIN_DELAY: Duration = Duration::from_millis(500);
+
+enum State {
+    Unauthenticated,
+    Authenticated,
+    Verified,
+}
+
+pub struct ActorDuplicateLogin {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    // Everyone makes their first login at the end of the warmup, so that they all race.
+    race_start: Instant,
+    clock: SharedClock,
+    raced: bool,
+}
+
+impl ActorDuplicateLogin {
+    pub fn new(warmup_time: Duration, clock: SharedClock) -> Self {
+        ActorDuplicateLogin {
+            state: State::Unauthenticated,
+            pending: None,
+            race_start: clock.now() + warmup_time,
+            clock,
+            raced: false,
+        }
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorDuplicateLogin {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(person);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match transition.action {
+            TransitionAction::Login => {
+                self.raced = true;
+                model::login_shared(client, person).await
+            }
+            TransitionAction::Logout => model::logout(client, person).await,
+            TransitionAction::Whoami => model::person_whoami(client, context).await,
+            TransitionAction::ReadSelfAccount
+            | TransitionAction::PrivilegeReauth
+            | TransitionAction::WriteAttributePersonMail
+            | TransitionAction::ReadSelfMemberOf
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::CreateGroup
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::ReadGroup
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo => {
+                return Err(Error::UnsupportedAction(transition.action))
+            }
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+
+    // The session belongs to the shared account, so that's the one that is renewed.
+    async fn refresh_session(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        _context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let (_, event_records) = model::login_shared(client, person).await?;
+        Ok(event_records)
+    }
+
+    async fn reauthenticate(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let (result, event_records) = model::login_shared(client, person).await?;
+        context.has_session = result == TransitionResult::Ok;
+        Ok(event_records)
+    }
+}
+
+impl ActorDuplicateLogin {
+    pub fn configured_mix(_person: &Person) -> Vec<(TransitionAction, u32)> {
+        vec![
+            (TransitionAction::Login, 1),
+            (TransitionAction::Whoami, 1),
+            (TransitionAction::Logout, 1),
+        ]
+    }
+
+    fn next_transition(&mut self, _person: &Person) -> Transition {
+        match self.state {
+            State::Unauthenticated => {
+                let delay = if self.raced {
+                    RELOGIN_DELAY
+                } else {
+                    self.race_start.saturating_duration_since(self.clock.now())
+                };
+                Transition {
+                    delay: Some(delay),
+                    action: TransitionAction::Login,
+                }
+            }
+            // Check that the session that was issued is usable.
+            State::Authenticated => Transition {
+                delay: Some(RELOGIN_DELAY),
+                action: TransitionAction::Whoami,
+            },
+            State::Verified => Transition {
+                delay: Some(RELOGIN_DELAY),
+                action: TransitionAction::Logout,
+            },
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (&self.state, action, result) {
+            (State::Unauthenticated, TransitionAction::Login, TransitionResult::Ok) => {
+                self.state = State::Authenticated;
+            }
+            (State::Authenticated, TransitionAction::Whoami, TransitionResult::Ok) => {
+                self.state = State::Verified;
+            }
+            (State::Verified, TransitionAction::Logout, TransitionResult::Ok) => {
+                self.state = State::Unauthenticated;
+            }
+            #[allow(clippy::unreachable)]
+            (_, _, TransitionResult::Ok) => {
+                unreachable!();
+            }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+            (_, _, TransitionResult::Error) => {
+                self.state = State::Unauthenticated;
+            }
+        }
+    }
+}
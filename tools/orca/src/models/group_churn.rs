@@ -0,0 +1,294 @@
+use crate::model::{
+    self, ActorContext, ActorModel, ActorRole, CreatedEntry, Transition, TransitionAction,
+    TransitionResult,
+};
+
+use crate::error::Error;
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+
+use async_trait::async_trait;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+// The churned groups contain these role groups. Since the members of these groups are
+// the ones reading their own memberof during the test, every change we make to the churned
+// group forces the server to rewrite memberof on entries that are concurrently being read.
+const NESTED_GROUPS: &[GroupName] = &[
+    GroupName::RolePeopleSelfReadMemberOf,
+    GroupName::RolePeopleSelfReadProfile,
+];
+
+enum State {
+    Unauthenticated,
+    Authenticated,
+    AuthenticatedWithReauth,
+    CreatedGroup,
+    PopulatedGroup,
+    RenamedGroup,
+}
+
+pub struct ActorGroupChurn {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    randomised_backoff_time: Duration,
+    group_name_prefix: String,
+    group_index: u64,
+}
+
+impl ActorGroupChurn {
+    pub fn new(mut cha_rng: ChaCha8Rng, person_name: &str, warmup_time_ms: u64) -> Self {
+        let max_backoff_time_in_ms = 2 * warmup_time_ms / 3;
+        let randomised_backoff_time =
+            Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
+        ActorGroupChurn {
+            state: State::Unauthenticated,
+            pending: None,
+            randomised_backoff_time,
+            group_name_prefix: format!("{person_name}-churn-group"),
+            group_index: 0,
+        }
+    }
+
+    fn group_name(&self) -> String {
+        format!("{}-{}", self.group_name_prefix, self.group_index)
+    }
+
+    fn renamed_group_name(&self) -> String {
+        format!("{}-{}-renamed", self.group_name_prefix, self.group_index)
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorGroupChurn {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(&person.roles);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match transition.action {
+            TransitionAction::Login => model::start_session(client, person, context).await,
+            TransitionAction::Logout => model::end_session(client, person, context).await,
+            TransitionAction::PrivilegeReauth => model::privilege_reauth(client, person).await,
+            TransitionAction::CreateGroup => {
+                // Tracked before the call, since the group may exist even if we see an error.
+                context
+                    .created_entries
+                    .insert(CreatedEntry::Group(self.group_name()));
+                model::person_create_group(client, &self.group_name()).await
+            }
+            TransitionAction::AddGroupMembers => {
+                let members = NESTED_GROUPS
+                    .iter()
+                    .map(|group_name| group_name.to_string())
+                    .collect::<Vec<_>>();
+                let members = members.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+                model::person_add_group_members(client, &self.group_name(), &members).await
+            }
+            TransitionAction::RenameGroup => {
+                context
+                    .created_entries
+                    .insert(CreatedEntry::Group(self.renamed_group_name()));
+                let result = model::person_rename_group(
+                    client,
+                    &self.group_name(),
+                    &self.renamed_group_name(),
+                )
+                .await?;
+                if result.0 == TransitionResult::Ok {
+                    context
+                        .created_entries
+                        .remove(&CreatedEntry::Group(self.group_name()));
+                }
+                Ok(result)
+            }
+            TransitionAction::DeleteGroup => {
+                let result = model::person_delete_group(client, &self.renamed_group_name()).await?;
+                if result.0 == TransitionResult::Ok {
+                    context
+                        .created_entries
+                        .remove(&CreatedEntry::Group(self.renamed_group_name()));
+                }
+                Ok(result)
+            }
+            TransitionAction::WriteAttributePersonMail
+            | TransitionAction::ReadSelfAccount
+            | TransitionAction::ReadSelfMemberOf
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::ReadGroup
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::Whoami => return Err(Error::UnsupportedAction(transition.action)),
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+
+    async fn on_stop(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let leftover_groups: Vec<String> = context.created_groups().map(String::from).collect();
+        if leftover_groups.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (result, mut event_records) = model::login_with_privileges(client, person).await?;
+        if result == TransitionResult::Ok {
+            for group_name in leftover_groups {
+                let (result, mut records) = model::person_delete_group(client, &group_name).await?;
+                if result == TransitionResult::Ok {
+                    context
+                        .created_entries
+                        .remove(&CreatedEntry::Group(group_name));
+                }
+                event_records.append(&mut records);
+            }
+        }
+
+        Ok(event_records)
+    }
+}
+
+impl ActorGroupChurn {
+    pub fn configured_mix(person: &Person) -> Vec<(TransitionAction, u32)> {
+        if person.roles.contains(&ActorRole::PeopleGroupAdmin) {
+            vec![
+                (TransitionAction::CreateGroup, 1),
+                (TransitionAction::AddGroupMembers, 1),
+                (TransitionAction::RenameGroup, 1),
+                (TransitionAction::DeleteGroup, 1),
+            ]
+        } else {
+            vec![(TransitionAction::Login, 1), (TransitionAction::Logout, 1)]
+        }
+    }
+
+    fn next_transition(&mut self, roles: &BTreeSet<ActorRole>) -> Transition {
+        match self.state {
+            State::Unauthenticated => Transition {
+                delay: Some(self.randomised_backoff_time),
+                action: TransitionAction::Login,
+            },
+            // Only group admins can churn groups, everyone else just cycles their session.
+            State::Authenticated if !roles.contains(&ActorRole::PeopleGroupAdmin) => Transition {
+                delay: Some(Duration::from_secs(5)),
+                action: TransitionAction::Logout,
+            },
+            State::Authenticated => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::PrivilegeReauth,
+            },
+            State::AuthenticatedWithReauth => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::CreateGroup,
+            },
+            State::CreatedGroup => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::AddGroupMembers,
+            },
+            State::PopulatedGroup => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::RenameGroup,
+            },
+            State::RenamedGroup => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::DeleteGroup,
+            },
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (&self.state, action, result) {
+            (State::Unauthenticated, TransitionAction::Login, TransitionResult::Ok) => {
+                self.state = State::Authenticated;
+            }
+            (State::Authenticated, TransitionAction::PrivilegeReauth, TransitionResult::Ok) => {
+                self.state = State::AuthenticatedWithReauth;
+            }
+            (
+                State::AuthenticatedWithReauth,
+                TransitionAction::CreateGroup,
+                TransitionResult::Ok,
+            ) => {
+                self.state = State::CreatedGroup;
+            }
+            (State::CreatedGroup, TransitionAction::AddGroupMembers, TransitionResult::Ok) => {
+                self.state = State::PopulatedGroup;
+            }
+            (State::PopulatedGroup, TransitionAction::RenameGroup, TransitionResult::Ok) => {
+                self.state = State::RenamedGroup;
+            }
+            (State::RenamedGroup, TransitionAction::DeleteGroup, TransitionResult::Ok) => {
+                // Recreate under a new name, so we never collide with a group that is
+                // still being cleaned up.
+                self.group_index += 1;
+                self.state = State::AuthenticatedWithReauth;
+            }
+            (_, TransitionAction::Logout, TransitionResult::Ok) => {
+                self.state = State::Unauthenticated;
+            }
+            #[allow(clippy::unreachable)]
+            (_, _, TransitionResult::Ok) => {
+                unreachable!();
+            }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+            (
+                State::AuthenticatedWithReauth
+                | State::CreatedGroup
+                | State::PopulatedGroup
+                | State::RenamedGroup,
+                _,
+                TransitionResult::Error,
+            ) => {
+                // We don't know how far the group got, so leave it behind and start
+                // over with a fresh name. It's still tracked, and removed when we stop.
+                self.group_index += 1;
+                self.state = State::Unauthenticated;
+            }
+            (_, _, TransitionResult::Error) => {
+                self.state = State::Unauthenticated;
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use crate::model::{
+    self, ActorContext, ActorModel, ActorRole, CreatedEntry, Transition, TransitionAction,
+    TransitionResult,
+};
+
+use crate::error::Error;
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+
+use async_trait::async_trait;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+enum State {
+    Unauthenticated,
+    Authenticated,
+    ReadGroups,
+    AuthenticatedWithReauth,
+    NotMember,
+    NotMemberRead,
+    Member,
+    MemberRead,
+}
+
+pub struct ActorGroupMembership {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    randomised_backoff_time: Duration,
+    group_name_prefix: String,
+    group_index: u64,
+    group_created: bool,
+}
+
+impl ActorGroupMembership {
+    pub fn new(mut cha_rng: ChaCha8Rng, person_name: &str, warmup_time_ms: u64) -> Self {
+        let max_backoff_time_in_ms = 2 * warmup_time_ms / 3;
+        let randomised_backoff_time =
+            Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
+        ActorGroupMembership {
+            state: State::Unauthenticated,
+            pending: None,
+            randomised_backoff_time,
+            group_name_prefix: format!("{person_name}-membership-group"),
+            group_index: 0,
+            group_created: false,
+        }
+    }
+
+    fn group_name(&self) -> String {
+        format!("{}-{}", self.group_name_prefix, self.group_index)
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorGroupMembership {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(&person.roles);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match transition.action {
+            TransitionAction::Login => model::start_session(client, person, context).await,
+            TransitionAction::Logout => model::end_session(client, person, context).await,
+            TransitionAction::PrivilegeReauth => model::privilege_reauth(client, person).await,
+            TransitionAction::CreateGroup => {
+                // Tracked before the call, since the group may exist even if we see an error.
+                context
+                    .created_entries
+                    .insert(CreatedEntry::Group(self.group_name()));
+                model::person_create_group(client, &self.group_name()).await
+            }
+            // Group admins read the group they manage, everyone else the groups they're in.
+            TransitionAction::ReadGroup if self.group_created => {
+                model::person_get_group(client, &self.group_name(), context).await
+            }
+            TransitionAction::ReadGroup => model::person_read_groups(client, person, true).await,
+            TransitionAction::AddGroupMembers => {
+                model::person_add_group_members(client, &self.group_name(), &[&person.username])
+                    .await
+            }
+            TransitionAction::RemoveGroupMembers => {
+                model::person_remove_group_members(client, &self.group_name(), &[&person.username])
+                    .await
+            }
+            TransitionAction::WriteAttributePersonMail
+            | TransitionAction::ReadSelfAccount
+            | TransitionAction::ReadSelfMemberOf
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo
+            | TransitionAction::Whoami => return Err(Error::UnsupportedAction(transition.action)),
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+
+    async fn on_stop(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let leftover_groups: Vec<String> = context.created_groups().map(String::from).collect();
+        if leftover_groups.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (result, mut event_records) = model::login_with_privileges(client, person).await?;
+        if result == TransitionResult::Ok {
+            for group_name in leftover_groups {
+                let (result, mut records) = model::person_delete_group(client, &group_name).await?;
+                if result == TransitionResult::Ok {
+                    context
+                        .created_entries
+                        .remove(&CreatedEntry::Group(group_name));
+                }
+                event_records.append(&mut records);
+            }
+        }
+
+        Ok(event_records)
+    }
+}
+
+impl ActorGroupMembership {
+    pub fn configured_mix(person: &Person) -> Vec<(TransitionAction, u32)> {
+        if person.roles.contains(&ActorRole::PeopleGroupAdmin) {
+            // The group is read after each change of the membership.
+            vec![
+                (TransitionAction::AddGroupMembers, 1),
+                (TransitionAction::RemoveGroupMembers, 1),
+                (TransitionAction::ReadGroup, 2),
+            ]
+        } else {
+            vec![
+                (TransitionAction::Login, 1),
+                (TransitionAction::ReadGroup, 1),
+                (TransitionAction::Logout, 1),
+            ]
+        }
+    }
+
+    fn next_transition(&mut self, roles: &BTreeSet<ActorRole>) -> Transition {
+        match self.state {
+            State::Unauthenticated => Transition {
+                delay: Some(self.randomised_backoff_time),
+                action: TransitionAction::Login,
+            },
+            // Only group admins can change memberships, everyone else reads their groups.
+            State::Authenticated if !roles.contains(&ActorRole::PeopleGroupAdmin) => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::ReadGroup,
+            },
+            State::ReadGroups => Transition {
+                delay: Some(Duration::from_secs(5)),
+                action: TransitionAction::Logout,
+            },
+            State::Authenticated => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::PrivilegeReauth,
+            },
+            State::AuthenticatedWithReauth if !self.group_created => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::CreateGroup,
+            },
+            // The membership may be unknown after an error, and adding it again is harmless.
+            State::AuthenticatedWithReauth | State::NotMemberRead => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::AddGroupMembers,
+            },
+            State::NotMember | State::Member => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::ReadGroup,
+            },
+            State::MemberRead => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::RemoveGroupMembers,
+            },
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (&self.state, action, result) {
+            (State::Unauthenticated, TransitionAction::Login, TransitionResult::Ok) => {
+                self.state = State::Authenticated;
+            }
+            (State::Authenticated, TransitionAction::ReadGroup, TransitionResult::Ok) => {
+                self.state = State::ReadGroups;
+            }
+            (State::Authenticated, TransitionAction::PrivilegeReauth, TransitionResult::Ok) => {
+                self.state = State::AuthenticatedWithReauth;
+            }
+            (
+                State::AuthenticatedWithReauth,
+                TransitionAction::CreateGroup,
+                TransitionResult::Ok,
+            ) => {
+                self.group_created = true;
+                self.state = State::NotMember;
+            }
+            (
+                State::AuthenticatedWithReauth | State::NotMemberRead,
+                TransitionAction::AddGroupMembers,
+                TransitionResult::Ok,
+            ) => {
+                self.state = State::Member;
+            }
+            (State::NotMember, TransitionAction::ReadGroup, TransitionResult::Ok) => {
+                self.state = State::NotMemberRead;
+            }
+            (State::Member, TransitionAction::ReadGroup, TransitionResult::Ok) => {
+                self.state = State::MemberRead;
+            }
+            (State::MemberRead, TransitionAction::RemoveGroupMembers, TransitionResult::Ok) => {
+                self.state = State::NotMember;
+            }
+            (_, TransitionAction::Logout, TransitionResult::Ok) => {
+                self.state = State::Unauthenticated;
+            }
+            #[allow(clippy::unreachable)]
+            (_, _, TransitionResult::Ok) => {
+                unreachable!();
+            }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+            (State::AuthenticatedWithReauth, TransitionAction::CreateGroup, _) => {
+                // We don't know whether the group was created, so start over with a fresh
+                // name. It's still tracked, and removed when we stop.
+                self.group_index += 1;
+                self.state = State::Unauthenticated;
+            }
+            (_, _, TransitionResult::Error) => {
+                self.state = State::Unauthenticated;
+            }
+        }
+    }
+}
@@ -14,8 +14,8 @@ use rand_chacha::ChaCha8Rng;
 
 use crate::{
     error::Error,
-    model::{self, ActorModel, TransitionResult},
-    run::{EventDetail, EventRecord},
+    model::{self, ActorContext, ActorModel, TransitionResult},
+    run::{EventDetail, EventRecord, Failure},
     state::Person,
 };
 
@@ -54,6 +54,8 @@ enum State {
 
 pub struct ActorLatencyMeasurer {
     state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
     randomised_backoff_time: Duration,
     additional_clients: Vec<KanidmClient>,
     group_index: u64,
@@ -70,7 +72,9 @@ impl ActorLatencyMeasurer {
         warmup_time_ms: u64,
     ) -> Result<Self, Error> {
         if additional_clients.is_empty() {
-            return Err(Error::InvalidState);
+            return Err(Error::profile(
+                "the latency measurer needs the extra URIs of other replicas",
+            ));
         };
         let additional_clients_len = additional_clients.len();
 
@@ -79,6 +83,7 @@ impl ActorLatencyMeasurer {
             Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
         Ok(ActorLatencyMeasurer {
             state: State::Unauthenticated,
+            pending: None,
             randomised_backoff_time,
             additional_clients,
             group_index: 0,
@@ -91,16 +96,45 @@ impl ActorLatencyMeasurer {
 
 #[async_trait]
 impl ActorModel for ActorLatencyMeasurer {
+    fn next_delay(&mut self, _person: &Person) -> Option<Duration> {
+        let transition = self.next_transition();
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    // Reported as the closest of the shared actions.
+    fn pending_action(&self) -> Option<model::TransitionAction> {
+        self.pending
+            .as_ref()
+            .map(|transition| match transition.action {
+                TransitionAction::Login => model::TransitionAction::Login,
+                TransitionAction::PrivilegeReauth => model::TransitionAction::PrivilegeReauth,
+                TransitionAction::CreatePersonalGroup | TransitionAction::CreateGroup => {
+                    model::TransitionAction::CreateGroup
+                }
+                TransitionAction::AddCreatedGroupToPersonalGroup => {
+                    model::TransitionAction::AddGroupMembers
+                }
+                TransitionAction::CheckPersonalGroupReplicationStatus => {
+                    model::TransitionAction::ReadGroup
+                }
+            })
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
     async fn transition(
         &mut self,
         client: &KanidmClient,
         person: &Person,
+        _context: &mut ActorContext,
     ) -> Result<Vec<EventRecord>, Error> {
-        let transition = self.next_transition();
-
-        if let Some(delay) = transition.delay {
-            tokio::time::sleep(delay).await;
-        }
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
 
         let (result, event) = match transition.action {
             TransitionAction::Login => {
@@ -112,7 +146,7 @@ impl ActorModel for ActorLatencyMeasurer {
                     let (res, more_records) = model::login(client, person).await?;
                     final_res = res;
                     event_records.extend(more_records);
-                    if let TransitionResult::Error = final_res {
+                    if final_res != TransitionResult::Ok {
                         break;
                     }
                 }
@@ -128,7 +162,7 @@ impl ActorModel for ActorLatencyMeasurer {
                 let outcome = model::person_create_group(client, &self.get_group_name()).await;
                 // We need to check if the group was successfully created or not, and act accordingly!
                 if let Ok((transition_result, _)) = &outcome {
-                    if let TransitionResult::Error = transition_result {
+                    if *transition_result != TransitionResult::Ok {
                         self.rollback_new_group_name()
                     } else {
                         self.commit_new_group_name()
@@ -212,7 +246,10 @@ impl ActorLatencyMeasurer {
                 Err(EventRecord {
                     start,
                     duration,
-                    details: EventDetail::Error,
+                    details: EventDetail::Error(Failure::from_client_error(
+                        EventDetail::GroupReplicationDelay,
+                        &client_err,
+                    )),
                 })
             }
             Ok(maybe_replicated_groups) => Ok(maybe_replicated_groups.unwrap_or_default()),
@@ -270,6 +307,15 @@ impl ActorLatencyMeasurer {
         }
     }
 
+    pub fn configured_mix(_person: &Person) -> Vec<(model::TransitionAction, u32)> {
+        // As the actions are reported, see `pending_action`.
+        vec![
+            (model::TransitionAction::CreateGroup, 1),
+            (model::TransitionAction::AddGroupMembers, 1),
+            (model::TransitionAction::ReadGroup, 1),
+        ]
+    }
+
     fn next_transition(&mut self) -> Transition {
         match self.state {
             State::Unauthenticated => Transition {
@@ -341,7 +387,13 @@ impl ActorLatencyMeasurer {
             (_, _, TransitionResult::Ok) => {
                 unreachable!();
             }
-            (_, _, TransitionResult::Error) => {
+            (
+                _,
+                _,
+                TransitionResult::Error
+                | TransitionResult::AuthenticationNeeded
+                | TransitionResult::Rejected,
+            ) => {
                 // If an error occurred we don't do anything, aka we remain on the same state we were before and we try again
             }
         }
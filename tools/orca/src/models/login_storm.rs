@@ -0,0 +1,224 @@
+use crate::model::{
+    self, ActorContext, ActorModel, Transition, TransitionAction, TransitionResult,
+};
+
+use crate::clock::SharedClock;
+use crate::error::Error;
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+
+use async_trait::async_trait;
+
+use std::time::{Duration, Instant};
+
+// How many reads a person does with each session once things have settled.
+const READS_PER_SESSION: u32 = 5;
+
+enum State {
+    Unauthenticated,
+    Authenticated { reads: u32 },
+}
+
+pub struct ActorLoginStorm {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    // The storm delays are relative to the end of the warmup.
+    storm_start: Instant,
+    clock: SharedClock,
+    stormed: bool,
+}
+
+impl ActorLoginStorm {
+    pub fn new(warmup_time: Duration, clock: SharedClock) -> Self {
+        ActorLoginStorm {
+            state: State::Unauthenticated,
+            pending: None,
+            storm_start: clock.now() + warmup_time,
+            clock,
+            stormed: false,
+        }
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorLoginStorm {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(person);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match transition.action {
+            TransitionAction::Login => {
+                // Whatever the outcome, each person only takes part in the storm once.
+                self.stormed = true;
+                model::login(client, person).await
+            }
+            TransitionAction::Logout => model::logout(client, person).await,
+            TransitionAction::ReadSelfAccount => {
+                model::person_get_self_account(client, person, context).await
+            }
+            TransitionAction::Whoami => model::person_whoami(client, context).await,
+            TransitionAction::PrivilegeReauth
+            | TransitionAction::WriteAttributePersonMail
+            | TransitionAction::ReadSelfMemberOf
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::CreateGroup
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::ReadGroup
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo => {
+                return Err(Error::UnsupportedAction(transition.action))
+            }
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+}
+
+impl ActorLoginStorm {
+    pub fn configured_mix(_person: &Person) -> Vec<(TransitionAction, u32)> {
+        vec![
+            (TransitionAction::Login, 1),
+            (TransitionAction::Whoami, 1),
+            (TransitionAction::ReadSelfAccount, READS_PER_SESSION - 1),
+            (TransitionAction::Logout, 1),
+        ]
+    }
+
+    fn next_transition(&mut self, person: &Person) -> Transition {
+        match self.state {
+            State::Unauthenticated => {
+                let delay = match person.login_storm_delay {
+                    // Wait for our slot in the storm.
+                    Some(storm_delay) if !self.stormed => Some(
+                        (self.storm_start + Duration::from_secs(storm_delay))
+                            .saturating_duration_since(self.clock.now()),
+                    ),
+                    _ => Some(Duration::from_secs(5)),
+                };
+                Transition {
+                    delay,
+                    action: TransitionAction::Login,
+                }
+            }
+            // Each session starts by checking who it belongs to.
+            State::Authenticated { reads: 0 } => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::Whoami,
+            },
+            State::Authenticated { reads } if reads < READS_PER_SESSION => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::ReadSelfAccount,
+            },
+            State::Authenticated { .. } => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::Logout,
+            },
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (&self.state, action, result) {
+            (State::Unauthenticated, TransitionAction::Login, TransitionResult::Ok) => {
+                self.state = State::Authenticated { reads: 0 };
+            }
+            (
+                State::Authenticated { reads },
+                TransitionAction::ReadSelfAccount | TransitionAction::Whoami,
+                TransitionResult::Ok,
+            ) => {
+                self.state = State::Authenticated { reads: reads + 1 };
+            }
+            (State::Authenticated { .. }, TransitionAction::Logout, TransitionResult::Ok) => {
+                self.state = State::Unauthenticated;
+            }
+            #[allow(clippy::unreachable)]
+            (_, _, TransitionResult::Ok) => {
+                unreachable!();
+            }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+            (_, _, TransitionResult::Error) => {
+                self.state = State::Unauthenticated;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ActorLoginStorm;
+    use crate::clock::ManualClock;
+    use crate::model::ActorModel;
+    use crate::state::*;
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    #[test]
+    fn test_storm_delay_counts_down_from_the_warmup() {
+        let clock = ManualClock::new();
+        let mut actor = ActorLoginStorm::new(Duration::from_secs(30), clock.clone());
+        let person = Person {
+            preflight_state: PreflightState::Present,
+            username: "person_1".to_string(),
+            display_name: "person 1".to_string(),
+            roles: BTreeSet::new(),
+            credential: Credential::Password {
+                plain: "password".to_string(),
+            },
+            model: Model::LoginStorm,
+            mfa_enrollment: None,
+            login_storm_delay: Some(10),
+            unix_credential: None,
+            login_as: None,
+            read_attributes: None,
+            oauth2_login: None,
+            manages: None,
+            password_expiry: None,
+            registered_credential: Default::default(),
+            provided_password: Default::default(),
+        };
+
+        assert_eq!(actor.next_delay(&person), Some(Duration::from_secs(40)));
+        clock.advance(Duration::from_secs(35));
+        assert_eq!(actor.next_delay(&person), Some(Duration::from_secs(5)));
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(actor.next_delay(&person), Some(Duration::ZERO));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::model::{
+    self, ActorContext, ActorModel, EnrolledCredential, Transition, TransitionAction,
+    TransitionResult,
+};
+
+use crate::clock::SharedClock;
+use crate::error::Error;
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+
+use async_trait::async_trait;
+
+use std::time::{Duration, Instant};
+
+enum State {
+    Unauthenticated,
+    Authenticated,
+    AuthenticatedWithReauth,
+}
+
+pub struct ActorMfaEnrollment {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    // The enrollment delays of the wave are relative to the end of the warmup.
+    wave_start: Instant,
+    clock: SharedClock,
+    enrollment_attempted: bool,
+    enrolled: Option<EnrolledCredential>,
+}
+
+impl ActorMfaEnrollment {
+    pub fn new(warmup_time: Duration, clock: SharedClock) -> Self {
+        ActorMfaEnrollment {
+            state: State::Unauthenticated,
+            pending: None,
+            wave_start: clock.now() + warmup_time,
+            clock,
+            enrollment_attempted: false,
+            enrolled: None,
+        }
+    }
+
+    fn enrollment_due(&self, person: &Person) -> bool {
+        match &person.mfa_enrollment {
+            Some(enrollment) if !self.enrollment_attempted => {
+                self.clock.now() >= self.wave_start + Duration::from_secs(enrollment.delay)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorMfaEnrollment {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(person);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn refresh_session(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        _context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        // Once enrolled, the password alone no longer gets the person a session.
+        let (_, event_records) =
+            model::refresh_session(client, person, self.enrolled.as_mut()).await?;
+        Ok(event_records)
+    }
+
+    async fn reauthenticate(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let (result, event_records) =
+            model::reauthenticate(client, person, self.enrolled.as_mut()).await?;
+        context.has_session = result == TransitionResult::Ok;
+        Ok(event_records)
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        _context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match transition.action {
+            TransitionAction::Login => match self.enrolled.as_mut() {
+                Some(enrolled) => model::login_enrolled(client, person, enrolled).await,
+                None => model::login(client, person).await,
+            },
+            TransitionAction::Logout => model::logout(client, person).await,
+            TransitionAction::PrivilegeReauth => model::privilege_reauth(client, person).await,
+            TransitionAction::EnrollMfa => {
+                // Only try once, a failed enrollment leaves the person on their password.
+                self.enrollment_attempted = true;
+                match person.mfa_enrollment.as_ref().map(|e| &e.method) {
+                    Some(MfaEnrollmentMethod::Totp { clock_skew }) => {
+                        model::person_enroll_totp(client, person, *clock_skew, &mut self.enrolled)
+                            .await
+                    }
+                    Some(MfaEnrollmentMethod::Passkey(properties)) => {
+                        model::person_enroll_passkey(client, person, properties, &mut self.enrolled)
+                            .await
+                    }
+                    None => {
+                        return Err(Error::invalid(format!(
+                            "the person '{}' has no method to enroll in",
+                            person.username
+                        )))
+                    }
+                }
+            }
+            TransitionAction::WriteAttributePersonMail
+            | TransitionAction::ReadSelfAccount
+            | TransitionAction::ReadSelfMemberOf
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::CreateGroup
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::ReadGroup
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::Whoami => return Err(Error::UnsupportedAction(transition.action)),
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+}
+
+impl ActorMfaEnrollment {
+    pub fn configured_mix(_person: &Person) -> Vec<(TransitionAction, u32)> {
+        // The enrollment is only attempted once, the sessions go on after it.
+        vec![(TransitionAction::Login, 1), (TransitionAction::Logout, 1)]
+    }
+
+    fn next_transition(&mut self, person: &Person) -> Transition {
+        match self.state {
+            State::Unauthenticated => Transition {
+                delay: None,
+                action: TransitionAction::Login,
+            },
+            State::Authenticated if self.enrollment_due(person) => Transition {
+                delay: None,
+                action: TransitionAction::PrivilegeReauth,
+            },
+            State::Authenticated => Transition {
+                delay: Some(Duration::from_millis(100)),
+                action: TransitionAction::Logout,
+            },
+            State::AuthenticatedWithReauth => Transition {
+                delay: None,
+                action: TransitionAction::EnrollMfa,
+            },
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (&self.state, action, result) {
+            (State::Unauthenticated, TransitionAction::Login, TransitionResult::Ok) => {
+                self.state = State::Authenticated;
+            }
+            (State::Authenticated, TransitionAction::PrivilegeReauth, TransitionResult::Ok) => {
+                self.state = State::AuthenticatedWithReauth;
+            }
+            // Log out after enrolling, so the next login uses the new credential.
+            (State::AuthenticatedWithReauth, TransitionAction::EnrollMfa, TransitionResult::Ok) => {
+                self.state = State::Authenticated;
+            }
+            (_, TransitionAction::Logout, TransitionResult::Ok) => {
+                self.state = State::Unauthenticated;
+            }
+            #[allow(clippy::unreachable)]
+            (_, _, TransitionResult::Ok) => {
+                unreachable!();
+            }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+            (_, _, TransitionResult::Error) => {
+                self.state = State::Unauthenticated;
+            }
+        }
+    }
+}
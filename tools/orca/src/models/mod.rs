@@ -1,6 +1,19 @@
+pub(crate) mod account_validity;
+pub(crate) mod anonymous;
 pub(crate) mod auth_only;
 pub(crate) mod basic;
+pub(crate) mod delegated_group_admin;
+pub(crate) mod duplicate_login;
+pub(crate) mod group_churn;
+pub(crate) mod group_membership;
 // pub(crate) mod markov;
 pub(crate) mod latency_measurer;
+pub(crate) mod login_storm;
+pub(crate) mod mfa_enrollment;
+pub(crate) mod oauth2;
+pub(crate) mod privileged_admin;
 pub(crate) mod read;
+pub(crate) mod scripted;
+pub(crate) mod session_growth;
+pub(crate) mod sync_agent;
 pub(crate) mod write;
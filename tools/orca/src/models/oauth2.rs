@@ -0,0 +1,227 @@
+use crate::model::{
+    self, ActorContext, ActorModel, AuthorisationCode, Transition, TransitionAction,
+    TransitionResult,
+};
+
+use crate::error::Error;
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+use reqwest::header::HeaderMap;
+
+use async_trait::async_trait;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use std::time::Duration;
+
+enum State {
+    Unauthenticated,
+    Authenticated,
+    Authorised,
+    TokenIssued,
+    ReadUserinfo,
+}
+
+pub struct ActorOauth2 {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    randomised_backoff_time: Duration,
+    // The OAuth2 endpoints aren't part of the client API, so they're called directly.
+    http_client: reqwest::Client,
+    authorisation_code: Option<AuthorisationCode>,
+    access_token: Option<String>,
+}
+
+impl ActorOauth2 {
+    pub fn new(
+        mut cha_rng: ChaCha8Rng,
+        warmup_time_ms: u64,
+        headers: &HeaderMap,
+    ) -> Result<Self, Error> {
+        let max_backoff_time_in_ms = 2 * warmup_time_ms / 3;
+        let randomised_backoff_time =
+            Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
+        let http_client = reqwest::Client::builder()
+            .danger_accept_invalid_hostnames(true)
+            .danger_accept_invalid_certs(true)
+            .default_headers(headers.clone())
+            // The redirects point at the clients, which don't exist.
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|err| Error::http("create the OAuth2 http client", err))?;
+        Ok(ActorOauth2 {
+            state: State::Unauthenticated,
+            pending: None,
+            randomised_backoff_time,
+            http_client,
+            authorisation_code: None,
+            access_token: None,
+        })
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorOauth2 {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(person);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match transition.action {
+            TransitionAction::Login => model::start_session(client, person, context).await,
+            TransitionAction::Logout => model::end_session(client, person, context).await,
+            TransitionAction::Oauth2Authorise => {
+                model::person_oauth2_authorise(
+                    client,
+                    &self.http_client,
+                    person,
+                    &mut self.authorisation_code,
+                )
+                .await
+            }
+            TransitionAction::Oauth2ExchangeCode => {
+                let authorisation_code = self
+                    .authorisation_code
+                    .take()
+                    .ok_or_else(|| Error::invalid("there's no authorisation code to exchange"))?;
+                model::person_oauth2_exchange_code(
+                    client,
+                    &self.http_client,
+                    person,
+                    authorisation_code,
+                    &mut self.access_token,
+                )
+                .await
+            }
+            TransitionAction::Oauth2Userinfo => {
+                let access_token = self.access_token.as_deref().ok_or_else(|| {
+                    Error::invalid("there's no access token to read the userinfo with")
+                })?;
+                model::person_oauth2_userinfo(client, &self.http_client, person, access_token).await
+            }
+            TransitionAction::PrivilegeReauth
+            | TransitionAction::WriteAttributePersonMail
+            | TransitionAction::ReadSelfAccount
+            | TransitionAction::ReadSelfMemberOf
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::CreateGroup
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::ReadGroup
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Whoami => return Err(Error::UnsupportedAction(transition.action)),
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+}
+
+impl ActorOauth2 {
+    pub fn configured_mix(person: &Person) -> Vec<(TransitionAction, u32)> {
+        let mut mix = vec![(TransitionAction::Login, 1), (TransitionAction::Logout, 1)];
+        if person.oauth2_login.is_some() {
+            mix.extend([
+                (TransitionAction::Oauth2Authorise, 1),
+                (TransitionAction::Oauth2ExchangeCode, 1),
+                (TransitionAction::Oauth2Userinfo, 1),
+            ]);
+        }
+        mix
+    }
+
+    fn next_transition(&mut self, person: &Person) -> Transition {
+        match self.state {
+            State::Unauthenticated => Transition {
+                delay: Some(self.randomised_backoff_time),
+                action: TransitionAction::Login,
+            },
+            // Persons that can't sign in to any client only cycle their sessions.
+            State::Authenticated if person.oauth2_login.is_none() => Transition {
+                delay: Some(Duration::from_secs(5)),
+                action: TransitionAction::Logout,
+            },
+            State::Authenticated => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::Oauth2Authorise,
+            },
+            // The client exchanges the code as soon as the browser is redirected to it.
+            State::Authorised => Transition {
+                delay: None,
+                action: TransitionAction::Oauth2ExchangeCode,
+            },
+            State::TokenIssued => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::Oauth2Userinfo,
+            },
+            State::ReadUserinfo => Transition {
+                delay: Some(Duration::from_secs(5)),
+                action: TransitionAction::Logout,
+            },
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (&self.state, action, result) {
+            (State::Unauthenticated, TransitionAction::Login, TransitionResult::Ok) => {
+                self.state = State::Authenticated;
+            }
+            (State::Authenticated, TransitionAction::Oauth2Authorise, TransitionResult::Ok) => {
+                self.state = State::Authorised;
+            }
+            (State::Authorised, TransitionAction::Oauth2ExchangeCode, TransitionResult::Ok) => {
+                self.state = State::TokenIssued;
+            }
+            (State::TokenIssued, TransitionAction::Oauth2Userinfo, TransitionResult::Ok) => {
+                self.state = State::ReadUserinfo;
+            }
+            (_, TransitionAction::Logout, TransitionResult::Ok) => {
+                self.access_token = None;
+                self.state = State::Unauthenticated;
+            }
+            #[allow(clippy::unreachable)]
+            (_, _, TransitionResult::Ok) => {
+                unreachable!();
+            }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+            (_, _, TransitionResult::Error) => {
+                self.authorisation_code = None;
+                self.access_token = None;
+                self.state = State::Unauthenticated;
+            }
+        }
+    }
+}
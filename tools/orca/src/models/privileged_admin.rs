@@ -0,0 +1,200 @@
+use crate::model::{
+    self, ActorContext, ActorModel, ActorRole, Transition, TransitionAction, TransitionResult,
+};
+
+use crate::error::Error;
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+
+use async_trait::async_trait;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+enum State {
+    Unauthenticated,
+    Authenticated,
+    AuthenticatedWithReauth,
+    CreatedGroup,
+}
+
+pub struct ActorPrivilegedAdmin {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    randomised_backoff_time: Duration,
+    group_name_prefix: String,
+    group_index: u64,
+}
+
+impl ActorPrivilegedAdmin {
+    pub fn new(mut cha_rng: ChaCha8Rng, person_name: &str, warmup_time_ms: u64) -> Self {
+        let max_backoff_time_in_ms = 2 * warmup_time_ms / 3;
+        let randomised_backoff_time =
+            Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
+        ActorPrivilegedAdmin {
+            state: State::Unauthenticated,
+            pending: None,
+            randomised_backoff_time,
+            group_name_prefix: format!("{person_name}-sudo-group"),
+            group_index: 0,
+        }
+    }
+
+    fn group_name(&self) -> String {
+        format!("{}-{}", self.group_name_prefix, self.group_index)
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorPrivilegedAdmin {
+    fn next_delay(&mut self, person: &Person) -> Option<Duration> {
+        let transition = self.next_transition(&person.roles);
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match transition.action {
+            TransitionAction::Login => model::start_session(client, person, context).await,
+            TransitionAction::Logout => model::end_session(client, person, context).await,
+            TransitionAction::PrivilegeReauth => model::privilege_reauth(client, person).await,
+            TransitionAction::CreateGroup => {
+                model::person_create_group(client, &self.group_name()).await
+            }
+            TransitionAction::DeleteGroup => {
+                model::person_delete_group(client, &self.group_name()).await
+            }
+            TransitionAction::WriteAttributePersonMail
+            | TransitionAction::ReadSelfAccount
+            | TransitionAction::ReadSelfMemberOf
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::ReadGroup
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::Whoami => return Err(Error::UnsupportedAction(transition.action)),
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+}
+
+impl ActorPrivilegedAdmin {
+    pub fn configured_mix(person: &Person) -> Vec<(TransitionAction, u32)> {
+        if person.roles.contains(&ActorRole::PeopleGroupAdmin) {
+            vec![
+                (TransitionAction::PrivilegeReauth, 1),
+                (TransitionAction::CreateGroup, 1),
+                (TransitionAction::DeleteGroup, 1),
+            ]
+        } else {
+            vec![(TransitionAction::Login, 1), (TransitionAction::Logout, 1)]
+        }
+    }
+
+    fn next_transition(&mut self, roles: &BTreeSet<ActorRole>) -> Transition {
+        match self.state {
+            State::Unauthenticated => Transition {
+                delay: Some(self.randomised_backoff_time),
+                action: TransitionAction::Login,
+            },
+            // Only group admins are allowed to do the sensitive write, everyone else
+            // just cycles their session.
+            State::Authenticated if !roles.contains(&ActorRole::PeopleGroupAdmin) => Transition {
+                delay: Some(Duration::from_secs(5)),
+                action: TransitionAction::Logout,
+            },
+            // Every sensitive operation is preceded by a fresh reauth, the same way an
+            // operator has to confirm their credentials before changing anything.
+            State::Authenticated => Transition {
+                delay: Some(Duration::from_secs(2)),
+                action: TransitionAction::PrivilegeReauth,
+            },
+            State::AuthenticatedWithReauth => Transition {
+                delay: None,
+                action: TransitionAction::CreateGroup,
+            },
+            State::CreatedGroup => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::DeleteGroup,
+            },
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (&self.state, action, result) {
+            (State::Unauthenticated, TransitionAction::Login, TransitionResult::Ok) => {
+                self.state = State::Authenticated;
+            }
+            (State::Authenticated, TransitionAction::PrivilegeReauth, TransitionResult::Ok) => {
+                self.state = State::AuthenticatedWithReauth;
+            }
+            (
+                State::AuthenticatedWithReauth,
+                TransitionAction::CreateGroup,
+                TransitionResult::Ok,
+            ) => {
+                self.state = State::CreatedGroup;
+            }
+            (State::CreatedGroup, TransitionAction::DeleteGroup, TransitionResult::Ok) => {
+                // Use a new name for the next group, in case the delete of this one is
+                // still being replicated when we create the next.
+                self.group_index += 1;
+                self.state = State::Authenticated;
+            }
+            (_, TransitionAction::Logout, TransitionResult::Ok) => {
+                self.state = State::Unauthenticated;
+            }
+            #[allow(clippy::unreachable)]
+            (_, _, TransitionResult::Ok) => {
+                unreachable!();
+            }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+            (State::AuthenticatedWithReauth | State::CreatedGroup, _, TransitionResult::Error) => {
+                // The group may or may not exist, so move on to a fresh name before
+                // starting over.
+                self.group_index += 1;
+                self.state = State::Unauthenticated;
+            }
+            (_, _, TransitionResult::Error) => {
+                self.state = State::Unauthenticated;
+            }
+        }
+    }
+}
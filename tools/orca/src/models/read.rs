@@ -1,4 +1,6 @@
-use crate::model::{self, ActorModel, Transition, TransitionAction, TransitionResult};
+use crate::model::{
+    self, ActorContext, ActorModel, ActorRole, Transition, TransitionAction, TransitionResult,
+};
 
 use crate::error::Error;
 use crate::run::EventRecord;
@@ -18,7 +20,11 @@ enum State {
 
 pub struct ActorReader {
     state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
     randomised_backoff_time: Duration,
+    // Reads rotate between the person's memberships, the groups themselves and whoami.
+    reads: u32,
 }
 
 impl ActorReader {
@@ -28,35 +34,75 @@ impl ActorReader {
             Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
         ActorReader {
             state: State::Unauthenticated,
+            pending: None,
             randomised_backoff_time,
+            reads: 0,
         }
     }
 }
 
 #[async_trait]
 impl ActorModel for ActorReader {
+    fn next_delay(&mut self, _person: &Person) -> Option<Duration> {
+        let transition = self.next_transition();
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
     async fn transition(
         &mut self,
         client: &KanidmClient,
         person: &Person,
+        context: &mut ActorContext,
     ) -> Result<Vec<EventRecord>, Error> {
-        let transition = self.next_transition();
-
-        if let Some(delay) = transition.delay {
-            tokio::time::sleep(delay).await;
-        }
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
 
         // Once we get to here, we want the transition to go ahead.
         let (result, event) = match transition.action {
-            TransitionAction::Login => model::login(client, person).await,
-            TransitionAction::Logout => model::logout(client, person).await,
+            TransitionAction::Login => model::start_session(client, person, context).await,
+            TransitionAction::Logout => model::end_session(client, person, context).await,
             TransitionAction::PrivilegeReauth
             | TransitionAction::WriteAttributePersonMail
             | TransitionAction::ReadSelfAccount
-            | TransitionAction::WriteSelfPassword => return Err(Error::InvalidState),
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::CreateGroup
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo => {
+                return Err(Error::UnsupportedAction(transition.action))
+            }
             TransitionAction::ReadSelfMemberOf => {
-                model::person_get_self_memberof(client, person).await
+                model::person_get_self_memberof(client, person, context).await
             }
+            TransitionAction::ReadGroup => {
+                // Only PII readers are allowed to see who else is in a group.
+                let expand_members = person.roles.contains(&ActorRole::PeoplePiiReader);
+                model::person_read_groups(client, person, expand_members).await
+            }
+            TransitionAction::Whoami => model::person_whoami(client, context).await,
         }?;
 
         self.next_state(transition.action, result);
@@ -66,6 +112,14 @@ impl ActorModel for ActorReader {
 }
 
 impl ActorReader {
+    pub fn configured_mix(_person: &Person) -> Vec<(TransitionAction, u32)> {
+        vec![
+            (TransitionAction::ReadSelfMemberOf, 1),
+            (TransitionAction::ReadGroup, 1),
+            (TransitionAction::Whoami, 1),
+        ]
+    }
+
     fn next_transition(&mut self) -> Transition {
         match self.state {
             State::Unauthenticated => Transition {
@@ -74,7 +128,11 @@ impl ActorReader {
             },
             State::Authenticated => Transition {
                 delay: Some(Duration::from_secs(1)),
-                action: TransitionAction::ReadSelfMemberOf,
+                action: match self.reads % 3 {
+                    0 => TransitionAction::ReadSelfMemberOf,
+                    1 => TransitionAction::ReadGroup,
+                    _ => TransitionAction::Whoami,
+                },
             },
         }
     }
@@ -86,11 +144,19 @@ impl ActorReader {
             (State::Unauthenticated { .. }, TransitionAction::Login, TransitionResult::Ok) => {
                 self.state = State::Authenticated;
             }
-            (State::Authenticated, TransitionAction::ReadSelfMemberOf, TransitionResult::Ok) => {
+            (
+                State::Authenticated,
+                TransitionAction::ReadSelfMemberOf
+                | TransitionAction::ReadGroup
+                | TransitionAction::Whoami,
+                TransitionResult::Ok,
+            ) => {
                 self.state = State::Authenticated;
+                self.reads = self.reads.wrapping_add(1);
             }
             #[allow(clippy::unreachable)]
             (_, _, TransitionResult::Ok) => unreachable!(),
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
 
             (_, _, TransitionResult::Error) => {
                 self.state = State::Unauthenticated {};
@@ -0,0 +1,309 @@
+use crate::model::{
+    self, ActorContext, ActorModel, Transition, TransitionAction, TransitionResult,
+};
+
+use crate::error::Error;
+use crate::profile::{ScriptOrder, ScriptStep, ScriptedProperties};
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+
+use async_trait::async_trait;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use std::time::Duration;
+
+/// The actions a script can be made of. They only need the person to be performed.
+pub const SCRIPTED_ACTIONS: &[TransitionAction] = &[
+    TransitionAction::Login,
+    TransitionAction::Logout,
+    TransitionAction::PrivilegeReauth,
+    TransitionAction::WriteAttributePersonMail,
+    TransitionAction::ReadSelfAccount,
+    TransitionAction::ReadSelfMemberOf,
+    TransitionAction::ReadGroup,
+    TransitionAction::Whoami,
+    TransitionAction::WriteSelfPassword,
+];
+
+pub struct ActorScripted {
+    steps: Vec<ScriptStep>,
+    // Only set for weighted scripts, sequences go through their steps in order.
+    weights: Option<WeightedIndex<u32>>,
+    cha_rng: ChaCha8Rng,
+    // The step being performed, and how many times it's left to be.
+    step: usize,
+    repeats_left: u32,
+    authenticated: bool,
+    started: bool,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    randomised_backoff_time: Duration,
+}
+
+impl ActorScripted {
+    pub fn new(
+        mut cha_rng: ChaCha8Rng,
+        warmup_time_ms: u64,
+        script: &ScriptedProperties,
+    ) -> Result<Self, Error> {
+        let max_backoff_time_in_ms = warmup_time_ms - 1000;
+        let randomised_backoff_time =
+            Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
+        let weights = match script.order {
+            ScriptOrder::Sequence => None,
+            ScriptOrder::Weighted => Some(
+                WeightedIndex::new(script.steps.iter().map(|step| step.weight)).map_err(|err| {
+                    Error::profile(format!("invalid weights in the script: {err}"))
+                })?,
+            ),
+        };
+        Ok(ActorScripted {
+            steps: script.steps.clone(),
+            weights,
+            cha_rng,
+            step: 0,
+            repeats_left: 0,
+            authenticated: false,
+            started: false,
+            pending: None,
+            randomised_backoff_time,
+        })
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorScripted {
+    fn next_delay(&mut self, _person: &Person) -> Option<Duration> {
+        let transition = self.next_transition();
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match transition.action {
+            TransitionAction::Login => model::start_session(client, person, context).await,
+            TransitionAction::Logout => model::end_session(client, person, context).await,
+            TransitionAction::PrivilegeReauth => model::privilege_reauth(client, person).await,
+            TransitionAction::WriteAttributePersonMail => {
+                let mail = format!("{}@example.com", person.username);
+                let values = &[mail.as_str()];
+                model::person_set_self_mail(client, person, values, context).await
+            }
+            TransitionAction::ReadSelfAccount => {
+                model::person_get_self_account(client, person, context).await
+            }
+            TransitionAction::ReadSelfMemberOf => {
+                model::person_get_self_memberof(client, person, context).await
+            }
+            TransitionAction::ReadGroup => model::person_read_groups(client, person, true).await,
+            TransitionAction::Whoami => model::person_whoami(client, context).await,
+            TransitionAction::WriteSelfPassword => {
+                let Some(plain) = person.password() else {
+                    return Err(Error::invalid(format!(
+                        "the person '{}' has no password to set",
+                        person.username
+                    )));
+                };
+                model::person_set_self_password(client, person, plain, context).await
+            }
+            TransitionAction::CreateGroup
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo => {
+                return Err(Error::UnsupportedAction(transition.action))
+            }
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+}
+
+impl ActorScripted {
+    pub fn configured_mix(script: &ScriptedProperties) -> Vec<(TransitionAction, u32)> {
+        // A weighted step is drawn by its weight, and then performed as many times as it
+        // repeats.
+        script
+            .steps
+            .iter()
+            .map(|step| match script.order {
+                ScriptOrder::Sequence => (step.action, step.repeat),
+                ScriptOrder::Weighted => (step.action, step.weight.saturating_mul(step.repeat)),
+            })
+            .collect()
+    }
+
+    fn next_transition(&mut self) -> Transition {
+        // The first login waits for the backoff, so the actors don't all start at once.
+        if !self.started {
+            return Transition {
+                delay: Some(self.randomised_backoff_time),
+                action: TransitionAction::Login,
+            };
+        }
+
+        if self.repeats_left == 0 {
+            self.step = match &self.weights {
+                Some(weights) => weights.sample(&mut self.cha_rng),
+                None => (self.step + 1) % self.steps.len(),
+            };
+            self.repeats_left = self.steps[self.step].repeat;
+        }
+
+        let step = &self.steps[self.step];
+        // Everything but a login needs a session, which the script may not have opened.
+        let action = if !self.authenticated && !matches!(step.action, TransitionAction::Login) {
+            TransitionAction::Login
+        } else {
+            step.action
+        };
+        Transition {
+            delay: Some(Duration::from_millis(step.delay_ms)),
+            action,
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (action, result) {
+            (_, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+            (_, TransitionResult::Error) => {
+                self.authenticated = false;
+            }
+            (TransitionAction::Login, TransitionResult::Ok) if !self.started => {
+                self.started = true;
+                self.authenticated = true;
+                // Start from the first step of a sequence.
+                self.step = self.steps.len() - 1;
+            }
+            (TransitionAction::Login, TransitionResult::Ok)
+                if !matches!(self.steps[self.step].action, TransitionAction::Login) =>
+            {
+                // A login the script didn't ask for, the step still has to be performed.
+                self.authenticated = true;
+            }
+            (TransitionAction::Logout, TransitionResult::Ok) => {
+                self.authenticated = false;
+                self.repeats_left -= 1;
+            }
+            (_, TransitionResult::Ok) => {
+                self.authenticated |= matches!(action, TransitionAction::Login);
+                self.repeats_left -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ActorScripted;
+    use crate::model::{TransitionAction, TransitionResult};
+    use crate::profile::{ScriptOrder, ScriptStep, ScriptedProperties};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_sequence_repeats_steps_and_logs_in_again_after_errors() {
+        let step = |action, repeat| ScriptStep {
+            action,
+            delay_ms: 10,
+            repeat,
+            weight: 1,
+        };
+        let script = ScriptedProperties {
+            order: ScriptOrder::Sequence,
+            steps: vec![
+                step(TransitionAction::ReadSelfMemberOf, 2),
+                step(TransitionAction::Whoami, 1),
+            ],
+        };
+        let Ok(mut actor) = ActorScripted::new(ChaCha8Rng::seed_from_u64(0), 5000, &script) else {
+            panic!("The script is valid");
+        };
+
+        let mut next = |result| {
+            let action = actor.next_transition().action;
+            actor.next_state(action, result);
+            action
+        };
+        assert_eq!(next(TransitionResult::Ok), TransitionAction::Login);
+        assert_eq!(
+            next(TransitionResult::Ok),
+            TransitionAction::ReadSelfMemberOf
+        );
+        assert_eq!(
+            next(TransitionResult::Ok),
+            TransitionAction::ReadSelfMemberOf
+        );
+        assert_eq!(next(TransitionResult::Error), TransitionAction::Whoami);
+        // The session is gone, so it's opened again before the step is retried.
+        assert_eq!(next(TransitionResult::Ok), TransitionAction::Login);
+        assert_eq!(next(TransitionResult::Ok), TransitionAction::Whoami);
+        assert_eq!(
+            next(TransitionResult::Ok),
+            TransitionAction::ReadSelfMemberOf
+        );
+    }
+
+    #[test]
+    fn test_weighted_mix_counts_the_repeats_of_a_step() {
+        let script = ScriptedProperties {
+            order: ScriptOrder::Weighted,
+            steps: vec![
+                ScriptStep {
+                    action: TransitionAction::ReadSelfMemberOf,
+                    delay_ms: 10,
+                    repeat: 3,
+                    weight: 1,
+                },
+                ScriptStep {
+                    action: TransitionAction::Whoami,
+                    delay_ms: 10,
+                    repeat: 1,
+                    weight: 2,
+                },
+            ],
+        };
+        assert_eq!(
+            ActorScripted::configured_mix(&script),
+            vec![
+                (TransitionAction::ReadSelfMemberOf, 3),
+                (TransitionAction::Whoami, 2)
+            ]
+        );
+    }
+}
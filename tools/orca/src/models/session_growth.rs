@@ -0,0 +1,164 @@
+use crate::model::{
+    self, ActorContext, ActorModel, Transition, TransitionAction, TransitionResult,
+};
+
+use crate::error::Error;
+use crate::run::EventRecord;
+use crate::state::*;
+use kanidm_client::KanidmClient;
+
+use async_trait::async_trait;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use std::time::Duration;
+
+// How many reads a person does with each session before opening another one.
+const READS_PER_LOGIN: u32 = 3;
+
+enum State {
+    Unauthenticated,
+    Authenticated { reads: u32 },
+}
+
+pub struct ActorSessionGrowth {
+    state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
+    randomised_backoff_time: Duration,
+}
+
+impl ActorSessionGrowth {
+    pub fn new(mut cha_rng: ChaCha8Rng, warmup_time_ms: u64) -> Self {
+        let max_backoff_time_in_ms = warmup_time_ms - 1000;
+        let randomised_backoff_time =
+            Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
+        ActorSessionGrowth {
+            state: State::Unauthenticated,
+            pending: None,
+            randomised_backoff_time,
+        }
+    }
+}
+
+#[async_trait]
+impl ActorModel for ActorSessionGrowth {
+    fn next_delay(&mut self, _person: &Person) -> Option<Duration> {
+        let transition = self.next_transition();
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
+    async fn transition(
+        &mut self,
+        client: &KanidmClient,
+        person: &Person,
+        context: &mut ActorContext,
+    ) -> Result<Vec<EventRecord>, Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+
+        // Once we get to here, we want the transition to go ahead.
+        let (result, event) = match transition.action {
+            // The previous session is never logged out of, so it stays in the session store
+            // until it expires.
+            TransitionAction::Login => model::login(client, person).await,
+            TransitionAction::Whoami => model::person_whoami(client, context).await,
+            TransitionAction::ReadSelfMemberOf => {
+                model::person_get_self_memberof(client, person, context).await
+            }
+            TransitionAction::Logout
+            | TransitionAction::PrivilegeReauth
+            | TransitionAction::WriteAttributePersonMail
+            | TransitionAction::ReadSelfAccount
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::CreateGroup
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::ReadGroup
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo => {
+                return Err(Error::UnsupportedAction(transition.action))
+            }
+        }?;
+
+        self.next_state(transition.action, result);
+
+        Ok(event)
+    }
+}
+
+impl ActorSessionGrowth {
+    pub fn configured_mix(_person: &Person) -> Vec<(TransitionAction, u32)> {
+        // The reads of a session alternate, starting and ending with a whoami.
+        vec![
+            (TransitionAction::Login, 1),
+            (TransitionAction::Whoami, READS_PER_LOGIN.div_ceil(2)),
+            (TransitionAction::ReadSelfMemberOf, READS_PER_LOGIN / 2),
+        ]
+    }
+
+    fn next_transition(&mut self) -> Transition {
+        match self.state {
+            State::Unauthenticated => Transition {
+                delay: Some(self.randomised_backoff_time),
+                action: TransitionAction::Login,
+            },
+            State::Authenticated { reads } if reads < READS_PER_LOGIN => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: if reads % 2 == 0 {
+                    TransitionAction::Whoami
+                } else {
+                    TransitionAction::ReadSelfMemberOf
+                },
+            },
+            State::Authenticated { .. } => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::Login,
+            },
+        }
+    }
+
+    fn next_state(&mut self, action: TransitionAction, result: TransitionResult) {
+        match (&self.state, action, result) {
+            (_, TransitionAction::Login, TransitionResult::Ok) => {
+                self.state = State::Authenticated { reads: 0 };
+            }
+            (
+                State::Authenticated { reads },
+                TransitionAction::Whoami | TransitionAction::ReadSelfMemberOf,
+                TransitionResult::Ok,
+            ) => {
+                self.state = State::Authenticated { reads: reads + 1 };
+            }
+            #[allow(clippy::unreachable)]
+            (_, _, TransitionResult::Ok) => unreachable!(),
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
+
+            (_, _, TransitionResult::Error) => {
+                self.state = State::Unauthenticated;
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::error::Error;
+use crate::profile::SyncAgentProperties;
+use crate::run::{CohortEventRecord, EventDetail, EventRecord, Failure, Signal};
+use crate::state::*;
+use kanidm_client::KanidmClient;
+use kanidm_proto::scim_v1::{
+    ScimSyncPerson, ScimSyncRequest, ScimSyncRetentionMode, ScimSyncState,
+};
+
+use crossbeam::queue::SegQueue;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+use tokio::sync::broadcast;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Acts like the sync tool of an external IDM, pushing batches of changed persons through
+/// the sync account API. Unlike the other actors this isn't a person, it authenticates with
+/// the token of the sync account and runs on its own interval.
+pub struct ActorSyncAgent {
+    client: KanidmClient,
+    persons: Vec<SyncPerson>,
+    batch_size: usize,
+    interval: Duration,
+    cha_rng: ChaCha8Rng,
+    // The state the server holds for the sync account. If unknown, we ask the server first.
+    from_state: Option<ScimSyncState>,
+    generation: u64,
+}
+
+impl ActorSyncAgent {
+    pub fn new(
+        client: KanidmClient,
+        sync_account: SyncAccount,
+        properties: &SyncAgentProperties,
+        cha_rng: ChaCha8Rng,
+    ) -> Self {
+        ActorSyncAgent {
+            client,
+            persons: sync_account.persons,
+            batch_size: properties.batch_size as usize,
+            interval: Duration::from_secs(properties.interval),
+            cha_rng,
+            from_state: None,
+            generation: 0,
+        }
+    }
+
+    /// Push a batch on the interval from the end of the warmup, until the actors are stopped.
+    pub async fn run(
+        mut self,
+        warmup: Duration,
+        cohort: usize,
+        target: Arc<str>,
+        stats_queue: Arc<SegQueue<CohortEventRecord>>,
+        mut actor_rx: broadcast::Receiver<Signal>,
+    ) -> Result<(), Error> {
+        let mut delay = warmup;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = actor_rx.recv() => break,
+            }
+            delay = self.interval;
+
+            let record = self.push_batch().await?;
+            stats_queue.push(CohortEventRecord {
+                cohort,
+                groups: Arc::from([]),
+                credential: None,
+                person: None,
+                phase: None,
+                target: Some(target.clone()),
+                record,
+            });
+        }
+
+        debug!("Stopped sync agent");
+        Ok(())
+    }
+
+    async fn push_batch(&mut self) -> Result<EventRecord, Error> {
+        let from_state = match self.from_state.take() {
+            Some(from_state) => from_state,
+            None => {
+                let start = Instant::now();
+                match self.client.scim_v1_sync_status().await {
+                    Ok(from_state) => from_state,
+                    Err(client_err) => {
+                        debug!(?client_err);
+                        // The status is only asked for ahead of a push, so the push failed.
+                        return Ok(EventRecord {
+                            start,
+                            duration: Instant::now().duration_since(start),
+                            details: EventDetail::Error(Failure::from_client_error(
+                                EventDetail::SyncUpdate,
+                                &client_err,
+                            )),
+                        });
+                    }
+                }
+            }
+        };
+
+        // A refresh has to carry every entry we own, otherwise the server removes the
+        // missing ones. After that we only send the changed entries.
+        let (details, persons) = match from_state {
+            ScimSyncState::Refresh => (EventDetail::SyncRefresh, self.persons.iter().collect()),
+            ScimSyncState::Active { .. } => (
+                EventDetail::SyncUpdate,
+                self.persons
+                    .choose_multiple(&mut self.cha_rng, self.batch_size)
+                    .collect::<Vec<_>>(),
+            ),
+        };
+
+        self.generation += 1;
+
+        let entries = persons
+            .into_iter()
+            .map(|person| {
+                ScimSyncPerson::builder(
+                    person.uuid,
+                    person.username.clone(),
+                    format!("{} {}", person.display_name, self.generation),
+                )
+                .build()
+                .try_into()
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|json_err| Error::json("serialise sync entries", json_err))?;
+
+        let to_state = ScimSyncState::Active {
+            cookie: self.generation.to_be_bytes().to_vec().into(),
+        };
+
+        let request = ScimSyncRequest {
+            from_state,
+            to_state: to_state.clone(),
+            entries,
+            retain: ScimSyncRetentionMode::Ignore,
+        };
+
+        let start = Instant::now();
+        let result = self.client.scim_v1_sync_update(&request).await;
+        let duration = Instant::now().duration_since(start);
+
+        let details = match result {
+            Ok(()) => {
+                self.from_state = Some(to_state);
+                details
+            }
+            Err(client_err) => {
+                // We don't know what the server holds now, so ask again next time.
+                debug!(?client_err);
+                EventDetail::Error(Failure::from_client_error(details, &client_err))
+            }
+        };
+
+        Ok(EventRecord {
+            start,
+            duration,
+            details,
+        })
+    }
+}
@@ -1,4 +1,6 @@
-use crate::model::{self, ActorModel, Transition, TransitionAction, TransitionResult};
+use crate::model::{
+    self, ActorContext, ActorModel, Transition, TransitionAction, TransitionResult,
+};
 
 use crate::error::Error;
 use crate::run::EventRecord;
@@ -15,11 +17,16 @@ enum State {
     Unauthenticated,
     Authenticated,
     AuthenticatedWithReauth,
+    // A write went through, and we read it back before writing again.
+    Written,
 }
 
 pub struct ActorWriter {
     state: State,
+    // Decided on by next_delay, and performed once the delay is over.
+    pending: Option<Transition>,
     randomised_backoff_time: Duration,
+    writes: u64,
 }
 
 impl ActorWriter {
@@ -29,36 +36,84 @@ impl ActorWriter {
             Duration::from_millis(cha_rng.gen_range(0..max_backoff_time_in_ms));
         ActorWriter {
             state: State::Unauthenticated,
+            pending: None,
             randomised_backoff_time,
+            writes: 0,
         }
     }
 }
 
 #[async_trait]
 impl ActorModel for ActorWriter {
+    fn next_delay(&mut self, _person: &Person) -> Option<Duration> {
+        let transition = self.next_transition();
+        let delay = transition.delay;
+        self.pending = Some(transition);
+        delay
+    }
+
+    fn pending_action(&self) -> Option<TransitionAction> {
+        self.pending.as_ref().map(|transition| transition.action)
+    }
+
+    fn assume_transition(&mut self, result: TransitionResult) -> Result<(), Error> {
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
+        self.next_state(transition.action, result);
+        Ok(())
+    }
+
     async fn transition(
         &mut self,
         client: &KanidmClient,
         person: &Person,
+        context: &mut ActorContext,
     ) -> Result<Vec<EventRecord>, Error> {
-        let transition = self.next_transition();
-
-        if let Some(delay) = transition.delay {
-            tokio::time::sleep(delay).await;
-        }
+        let transition = self.pending.take().ok_or(Error::NoPendingAction)?;
 
         // Once we get to here, we want the transition to go ahead.
         let (result, event) = match transition.action {
-            TransitionAction::Login => model::login(client, person).await,
-            TransitionAction::Logout => model::logout(client, person).await,
+            TransitionAction::Login => model::start_session(client, person, context).await,
+            TransitionAction::Logout => model::end_session(client, person, context).await,
             TransitionAction::PrivilegeReauth => model::privilege_reauth(client, person).await,
             TransitionAction::ReadSelfMemberOf
-            | TransitionAction::ReadSelfAccount
-            | TransitionAction::WriteSelfPassword => return Err(Error::InvalidState),
+            | TransitionAction::WriteSelfPassword
+            | TransitionAction::CreateGroup
+            | TransitionAction::AddGroupMembers
+            | TransitionAction::RenameGroup
+            | TransitionAction::DeleteGroup
+            | TransitionAction::CreatePerson
+            | TransitionAction::ToggleAccountValidity
+            | TransitionAction::LoginValidityCanary
+            | TransitionAction::EnrollMfa
+            | TransitionAction::ReadGroup
+            | TransitionAction::UnixAuth
+            | TransitionAction::ReadStatus
+            | TransitionAction::ReadOauth2Discovery
+            | TransitionAction::ReadOauth2Jwks
+            | TransitionAction::Oauth2Authorise
+            | TransitionAction::Oauth2ExchangeCode
+            | TransitionAction::Oauth2Userinfo
+            | TransitionAction::RemoveGroupMembers
+            | TransitionAction::Whoami => return Err(Error::UnsupportedAction(transition.action)),
             TransitionAction::WriteAttributePersonMail => {
-                let mail = format!("{}@example.com", person.username);
-                let values = &[mail.as_str()];
-                model::person_set_self_mail(client, person, values).await
+                // Each write has a new value, so that reading it back shows it was this one.
+                self.writes += 1;
+                let mail = format!("{}-{}@example.com", person.username, self.writes);
+                let result =
+                    model::person_set_self_mail(client, person, &[mail.as_str()], context).await?;
+                if result.0 == TransitionResult::Ok {
+                    context
+                        .written_attributes
+                        .insert("mail".to_string(), vec![mail]);
+                }
+                Ok(result)
+            }
+            TransitionAction::ReadSelfAccount => {
+                let expected = context
+                    .written_attributes
+                    .get("mail")
+                    .ok_or_else(|| Error::invalid("the mail to verify was never written"))?;
+                model::person_verify_self_attr(client, person, "mail", expected).await
             }
         }?;
 
@@ -69,6 +124,13 @@ impl ActorModel for ActorWriter {
 }
 
 impl ActorWriter {
+    pub fn configured_mix(_person: &Person) -> Vec<(TransitionAction, u32)> {
+        vec![
+            (TransitionAction::WriteAttributePersonMail, 1),
+            (TransitionAction::ReadSelfAccount, 1),
+        ]
+    }
+
     fn next_transition(&mut self) -> Transition {
         match self.state {
             State::Unauthenticated => Transition {
@@ -83,6 +145,10 @@ impl ActorWriter {
                 delay: Some(Duration::from_secs(1)),
                 action: TransitionAction::WriteAttributePersonMail,
             },
+            State::Written => Transition {
+                delay: Some(Duration::from_secs(1)),
+                action: TransitionAction::ReadSelfAccount,
+            },
         }
     }
 
@@ -100,12 +166,16 @@ impl ActorWriter {
                 State::AuthenticatedWithReauth,
                 TransitionAction::WriteAttributePersonMail,
                 TransitionResult::Ok,
-            ) => self.state = State::AuthenticatedWithReauth,
+            ) => self.state = State::Written,
+            (State::Written, TransitionAction::ReadSelfAccount, TransitionResult::Ok) => {
+                self.state = State::AuthenticatedWithReauth
+            }
 
             #[allow(clippy::unreachable)]
             (_, _, TransitionResult::Ok) => {
                 unreachable!();
             }
+            (_, _, TransitionResult::AuthenticationNeeded | TransitionResult::Rejected) => {}
             (_, _, TransitionResult::Error) => {
                 self.state = State::Unauthenticated;
             }
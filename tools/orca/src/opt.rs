@@ -1,8 +1,13 @@
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
+use chrono::{DateTime, FixedOffset};
 use clap::Parser;
 
+use crate::events::{Outcome, OutputFormat};
+use crate::scenario::Scenario;
 use crate::state::Model;
+use crate::summary::SummaryFormat;
 
 #[derive(Debug, Parser)]
 pub struct CommonOpt {
@@ -74,8 +79,16 @@ pub enum OrcaOpt {
         #[clap(long, default_value_t)]
         /// Dump raw data to a separate csv file, defaults to false
         dump_raw_data: bool,
+
+        #[clap(long, value_enum, conflicts_with = "model")]
+        /// Optional built-in scenario to base the profile on, see `orca scenarios list`
+        scenario: Option<Scenario>,
     },
 
+    #[clap(name = "scenarios", subcommand)]
+    /// Explore the built-in scenarios
+    Scenarios(ScenariosOpt),
+
     #[clap(name = "conntest")]
     /// Perform a connection test
     TestConnection {
@@ -98,6 +111,10 @@ pub enum OrcaOpt {
         #[clap(long = "state")]
         /// Path to the state file.
         state_path: PathBuf,
+        #[clap(long)]
+        /// How many threads the persons are generated over. Defaults to the number of
+        /// available cores, and doesn't change the generated state.
+        threads: Option<usize>,
     },
 
     #[clap(name = "populate")]
@@ -108,16 +125,188 @@ pub enum OrcaOpt {
         #[clap(long = "state")]
         /// Path to the state file.
         state_path: PathBuf,
+        #[clap(long)]
+        /// How many connections persons are created over. Defaults to the number of
+        /// available cores.
+        concurrency: Option<usize>,
+        #[clap(long = "checkpoint")]
+        /// Path to a file recording the entries that were populated. If populate is
+        /// interrupted, running it again with the same checkpoint skips them.
+        checkpoint_path: Option<PathBuf>,
+        #[clap(long)]
+        /// Print the entries that would be created (+), deleted (-) or updated (~), without
+        /// changing the server.
+        dry_run: bool,
+    },
+
+    #[clap(name = "bulk-create")]
+    /// Measure how many persons per second the server can create, separately from a test
+    /// run. The persons are left on the server.
+    BulkCreate {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long = "profile")]
+        /// Path to the test profile, used to connect to the server.
+        profile_path: PathBuf,
+        #[clap(long)]
+        /// How many persons to create.
+        persons: u64,
+        #[clap(long, default_value_t = 1)]
+        /// How many creation requests are in flight at once.
+        concurrency: u64,
+        #[clap(long, default_value_t = 1)]
+        /// How many persons each creation request carries.
+        batch_size: u64,
     },
 
+    #[clap(name = "state", subcommand)]
+    /// Work with an existing state file
+    State(StateOpt),
+
+    #[clap(name = "events", subcommand)]
+    /// Work with the raw events a run saved
+    Events(EventsOpt),
+
+    #[clap(name = "model", subcommand)]
+    /// Check the models of a profile without a server
+    Model(ModelOpt),
+
     #[clap(name = "run")]
     /// Run the simulation.
     Run {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long = "state", required = true)]
+        /// Path to the state file. Repeat this to run several states concurrently, each
+        /// reported as its own cohort.
+        state_paths: Vec<PathBuf>,
+        #[clap(long)]
+        /// Start the measured window at this time, such as `2024-05-01T12:00:00Z`, so that
+        /// orca instances launched separately on several hosts measure the same window.
+        start_at: Option<DateTime<FixedOffset>>,
+        #[clap(long, requires = "start_at")]
+        /// Check the local clock against this NTP server, such as `pool.ntp.org:123`,
+        /// before waiting for the start.
+        ntp_server: Option<String>,
+        #[clap(long, default_value_t = 100)]
+        /// How far, in milliseconds, the local clock can be from the NTP server.
+        max_clock_offset_ms: u64,
+        #[clap(long, requires = "workers", conflicts_with = "start_at")]
+        /// Run the persons on `orca worker` instances that connect to this address, such as
+        /// `0.0.0.0:7000`, rather than locally. Their events are reported here.
+        coordinate: Option<SocketAddr>,
+        #[clap(long, requires = "coordinate")]
+        /// How many workers to wait for before starting the run.
+        workers: Option<usize>,
+        #[clap(long = "checkpoint", conflicts_with = "coordinate")]
+        /// Save the progress of the run to this file as it goes. If the file holds the
+        /// progress of an earlier attempt that crashed or was stopped, the run carries on
+        /// with the rest of its test time.
+        checkpoint_path: Option<PathBuf>,
+        #[clap(long, default_value_t = 60, requires = "checkpoint_path")]
+        /// How often, in seconds, to save the progress of the run.
+        checkpoint_interval: u64,
+        #[clap(long = "summary")]
+        /// Write the headline numbers of the run to this file once it completes.
+        summary_path: Option<PathBuf>,
+        #[clap(long, default_value = "json", value_enum, requires = "summary_path")]
+        /// How to write the summary. A JSON summary can be the baseline of a later run.
+        summary_format: SummaryFormat,
+        #[clap(long = "baseline", requires = "summary_path")]
+        /// The JSON summary of an earlier run, which the Markdown summary is compared to.
+        baseline_path: Option<PathBuf>,
+        #[clap(long)]
+        /// Run even if another run has reserved the server, taking its reservation over.
+        force: bool,
+    },
+
+    #[clap(name = "ci")]
+    /// Generate a state from the profile, populate it, run it and compare its summary to a
+    /// baseline, and exit with whether the run passed.
+    Ci {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long = "profile")]
+        /// Path to the test profile.
+        profile_path: PathBuf,
+        #[clap(long, default_value = "orca-ci")]
+        /// The directory the state and the summaries are written to.
+        output_dir: PathBuf,
+        #[clap(long = "baseline")]
+        /// The JSON summary of an earlier run, which this run must not regress from.
+        baseline_path: Option<PathBuf>,
+        #[clap(long, default_value_t = 10.)]
+        /// How much worse than the baseline, in percent, the latencies and the error rate
+        /// may get.
+        max_regression_pct: f64,
+        #[clap(long)]
+        /// Run even if another run has reserved the server, taking its reservation over.
+        force: bool,
+    },
+
+    #[clap(name = "worker")]
+    /// Run a share of the persons of a test for a coordinator, see `run --coordinate`.
+    Worker {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long)]
+        /// The address of the coordinator, such as `orca-1.example.com:7000`.
+        coordinator: String,
+        #[clap(long)]
+        /// How many threads to run the persons on. Defaults to the number of available cores.
+        threads: Option<usize>,
+    },
+
+    #[clap(name = "scaling-curve")]
+    /// Run the workload of a state against an increasing number of persons, extending and
+    /// populating the state between the rounds, and report how the latency scales.
+    ScalingCurve {
         #[clap(flatten)]
         common: CommonOpt,
         #[clap(long = "state")]
-        /// Path to the state file.
+        /// Path to the state file, which is extended in place.
         state_path: PathBuf,
+        #[clap(long, required = true, value_delimiter = ',')]
+        /// The number of persons of each round, in increasing order, such as
+        /// `10000,100000,1000000`.
+        sizes: Vec<u64>,
+        #[clap(long)]
+        /// Run even if another run has reserved the server, taking its reservation over.
+        force: bool,
+    },
+
+    #[clap(name = "self-test")]
+    /// Measure a built-in mock server whose response times are known, and check that the
+    /// measurements match them, to rule out orca itself when the latencies regress.
+    SelfTest {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long, default_value_t = 1000)]
+        /// How many requests to send to the mock server.
+        requests: u64,
+        #[clap(long, default_value_t = 10)]
+        /// How many requests are in flight at once.
+        concurrency: u64,
+        #[clap(long, default_value_t = 10.)]
+        /// How many milliseconds a percentile may be measured above the delay of the server.
+        tolerance_ms: f64,
+    },
+
+    #[clap(name = "benchmark")]
+    /// Run the actors and statistics of a profile against an in-process stub that completes
+    /// every action at once, and report how many events per second orca can sustain here.
+    Benchmark {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long = "profile")]
+        /// Path to the profile.
+        profile_path: PathBuf,
+        #[clap(long, default_value_t = 10)]
+        /// How many of the persons of the profile to run as actors.
+        actors: usize,
+        #[clap(long, default_value_t = 10)]
+        /// How many seconds to run for. The statistics hold every event until the end.
+        duration: u64,
     },
 
     #[clap(name = "version")]
@@ -127,3 +316,116 @@ pub enum OrcaOpt {
         common: CommonOpt,
     },
 }
+
+#[derive(Debug, clap::Subcommand)]
+pub enum ScenariosOpt {
+    #[clap(name = "list")]
+    /// List the built-in scenarios a profile can be based on
+    List {
+        #[clap(flatten)]
+        common: CommonOpt,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum ModelOpt {
+    #[clap(name = "verify")]
+    /// Walk the models of a profile through their transitions as if they all succeeded, and
+    /// print the mix of actions and delays they made, and any transitions they couldn't make
+    Verify {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long = "profile")]
+        /// Path to the profile.
+        profile_path: PathBuf,
+        #[clap(long, default_value_t = 10)]
+        /// How many of the persons of the profile to run the model of.
+        actors: usize,
+        #[clap(long, default_value_t = 1000)]
+        /// How many transitions each actor goes through.
+        steps: usize,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum EventsOpt {
+    #[clap(name = "query")]
+    /// Print the raw events of a run that match all the filters given
+    Query {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long = "input")]
+        /// Path to the raw data a run saved with `dump_raw_data`.
+        input_path: PathBuf,
+        #[clap(long = "event")]
+        /// Only keep this type of event, or the errors of it, by its name or its identifier.
+        /// Repeat this to keep several.
+        events: Vec<String>,
+        #[clap(long)]
+        /// Only keep the events from this many seconds into the test.
+        from_s: Option<u64>,
+        #[clap(long)]
+        /// Only keep the events before this many seconds into the test.
+        to_s: Option<u64>,
+        #[clap(long, value_enum)]
+        /// Only keep the events that succeeded, or the ones that didn't.
+        outcome: Option<Outcome>,
+        #[clap(long)]
+        /// Only keep the events of this cohort.
+        cohort: Option<String>,
+        #[clap(long)]
+        /// Only keep the events of this phase of the load schedule.
+        phase: Option<String>,
+        #[clap(long, default_value = "csv", value_enum)]
+        /// How to print the events.
+        format: OutputFormat,
+        #[clap(long = "output")]
+        /// Write the events to this file rather than to stdout.
+        output_path: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum StateOpt {
+    #[clap(name = "extend")]
+    /// Append more persons to a state file, leaving the existing entries untouched. Run
+    /// `populate` again afterwards to create them.
+    Extend {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long = "state")]
+        /// Path to the state file, which is updated in place.
+        state_path: PathBuf,
+        #[clap(long)]
+        /// How many persons to add.
+        persons: u64,
+    },
+
+    #[clap(name = "stats")]
+    /// Print a summary of the persons, groups and credentials in a state file
+    Stats {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long = "state")]
+        /// Path to the state file.
+        state_path: PathBuf,
+    },
+
+    #[clap(name = "capture")]
+    /// Create a state file from the persons of an existing server. Their credentials are left
+    /// alone, and the actors log in with the passwords of the credential provider.
+    Capture {
+        #[clap(flatten)]
+        common: CommonOpt,
+        #[clap(long = "profile")]
+        /// Path to the test profile, used to connect to the server and to run the test.
+        profile_path: PathBuf,
+        #[clap(long = "state")]
+        /// Path to the state file to create.
+        state_path: PathBuf,
+        #[clap(long)]
+        /// Give the persons placeholder credentials, which replace their real ones when the
+        /// state is populated. Only use this against a copy of the server.
+        reset_credentials: bool,
+    },
+}
@@ -0,0 +1,205 @@
+use crate::profile::{PersonSelectionProperties, PersonSelectionStrategy};
+use crate::state::{Group, Person};
+
+use hashbrown::HashMap;
+use rand::distributions::{Distribution, Uniform, WeightedIndex};
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+
+use std::collections::BTreeMap;
+
+/// Maps the actors of a cohort to its persons.
+pub trait PersonSelection {
+    /// The index of the person each actor runs as, in the order the actors are started.
+    fn select(
+        &self,
+        persons: &[Person],
+        groups: &[Group],
+        actors: usize,
+        rng: &mut ChaCha8Rng,
+    ) -> Vec<usize>;
+}
+
+pub fn from_properties(properties: &PersonSelectionProperties) -> Box<dyn PersonSelection> {
+    match properties.strategy {
+        PersonSelectionStrategy::OneToOne => Box::new(OneToOne),
+        PersonSelectionStrategy::Random => Box::new(Random),
+        PersonSelectionStrategy::Zipf => Box::new(Zipf {
+            exponent: properties.exponent,
+        }),
+        PersonSelectionStrategy::GroupStratified => Box::new(GroupStratified),
+    }
+}
+
+struct OneToOne;
+
+impl PersonSelection for OneToOne {
+    fn select(
+        &self,
+        persons: &[Person],
+        _: &[Group],
+        actors: usize,
+        _: &mut ChaCha8Rng,
+    ) -> Vec<usize> {
+        (0..actors.min(persons.len())).collect()
+    }
+}
+
+struct Random;
+
+impl PersonSelection for Random {
+    fn select(
+        &self,
+        persons: &[Person],
+        _: &[Group],
+        actors: usize,
+        rng: &mut ChaCha8Rng,
+    ) -> Vec<usize> {
+        if persons.is_empty() {
+            return Vec::new();
+        }
+        Uniform::new(0, persons.len())
+            .sample_iter(rng)
+            .take(actors)
+            .collect()
+    }
+}
+
+struct Zipf {
+    exponent: f64,
+}
+
+impl PersonSelection for Zipf {
+    fn select(
+        &self,
+        persons: &[Person],
+        _: &[Group],
+        actors: usize,
+        rng: &mut ChaCha8Rng,
+    ) -> Vec<usize> {
+        // The first person of the state is the hottest.
+        let weights = (1..=persons.len()).map(|rank| 1.0 / (rank as f64).powf(self.exponent));
+        let Ok(distribution) = WeightedIndex::new(weights) else {
+            return Vec::new();
+        };
+        distribution.sample_iter(rng).take(actors).collect()
+    }
+}
+
+struct GroupStratified;
+
+impl PersonSelection for GroupStratified {
+    fn select(
+        &self,
+        persons: &[Person],
+        groups: &[Group],
+        actors: usize,
+        rng: &mut ChaCha8Rng,
+    ) -> Vec<usize> {
+        // A person counts towards the first group they are a member of, and the persons of
+        // no group form a stratum of their own.
+        let mut first_group = HashMap::new();
+        for (group_index, group) in groups.iter().enumerate() {
+            for member in group.members.iter() {
+                first_group.entry(member.as_str()).or_insert(group_index);
+            }
+        }
+        let mut strata: BTreeMap<Option<usize>, Vec<usize>> = BTreeMap::new();
+        for (person_index, person) in persons.iter().enumerate() {
+            strata
+                .entry(first_group.get(person.username.as_str()).copied())
+                .or_default()
+                .push(person_index);
+        }
+
+        // Every stratum gets an actor if there are enough of them, and the rest are shared
+        // in proportion to the size of the strata, the largest remainders first.
+        let guaranteed = if actors >= strata.len() { 1 } else { 0 };
+        let shared = actors - guaranteed * strata.len();
+        let mut counts = strata
+            .values()
+            .map(|members| {
+                let quota = shared as f64 * members.len() as f64 / persons.len() as f64;
+                (guaranteed + quota.floor() as usize, quota.fract())
+            })
+            .collect::<Vec<_>>();
+        let mut remaining = actors - counts.iter().map(|(count, _)| count).sum::<usize>();
+        let mut by_remainder = (0..counts.len()).collect::<Vec<_>>();
+        by_remainder.sort_by(|a, b| counts[*b].1.total_cmp(&counts[*a].1));
+        for stratum in by_remainder.into_iter().cycle() {
+            if remaining == 0 {
+                break;
+            }
+            counts[stratum].0 += 1;
+            remaining -= 1;
+        }
+
+        // Within a stratum, the persons are drawn without replacement until they run out.
+        strata
+            .into_values()
+            .zip(counts)
+            .flat_map(|(mut members, (count, _))| {
+                members.shuffle(rng);
+                members.into_iter().cycle().take(count).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GroupStratified, PersonSelection, Zipf};
+    use crate::model::ActorRole;
+    use crate::state::{Credential, Group, GroupName, Model, Person, PreflightState};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use std::collections::BTreeSet;
+
+    fn person(username: &str) -> Person {
+        Person {
+            preflight_state: PreflightState::Present,
+            username: username.to_string(),
+            display_name: username.to_string(),
+            roles: BTreeSet::new(),
+            credential: Credential::Password {
+                plain: String::new(),
+            },
+            model: Model::Basic,
+            mfa_enrollment: None,
+            login_storm_delay: None,
+            unix_credential: None,
+            login_as: None,
+            read_attributes: None,
+            oauth2_login: None,
+            manages: None,
+            password_expiry: None,
+            registered_credential: Default::default(),
+            provided_password: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_selections_skew_and_stratify_the_persons() {
+        let persons = (0..100)
+            .map(|index| person(&format!("person{index}")))
+            .collect::<Vec<_>>();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        let selected = Zipf { exponent: 1.5 }.select(&persons, &[], 1000, &mut rng);
+        let hottest = selected.iter().filter(|index| **index == 0).count();
+        let coldest = selected.iter().filter(|index| **index == 99).count();
+        assert_eq!(selected.len(), 1000);
+        assert!(hottest > 100 * coldest.max(1));
+
+        // A small group still gets an actor, however few there are.
+        let groups = vec![Group {
+            name: GroupName::RolePeopleSelfReadProfile,
+            preflight_state: PreflightState::Present,
+            role: ActorRole::PeopleSelfReadProfile,
+            members: ["person7".to_string()].into_iter().collect(),
+        }];
+        let selected = GroupStratified.select(&persons, &groups, 4, &mut rng);
+        assert_eq!(selected.len(), 4);
+        assert!(selected.contains(&7));
+    }
+}
@@ -1,13 +1,93 @@
+use crate::credential_provider::{self, CredentialProvider};
 use crate::error::Error;
 use crate::kani;
 use crate::state::*;
-use std::collections::VecDeque;
+use std::collections::{BTreeSet, VecDeque};
 
 use std::sync::atomic::{AtomicU32, Ordering};
 use tokio::sync::Mutex;
 
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+/// How a state is populated into the server.
+#[derive(Debug, Default)]
+pub struct PreflightOptions {
+    /// How many connections persons are created over, defaults to the available parallelism.
+    pub concurrency: Option<usize>,
+    /// Where to record the entries that were populated, so that an interrupted populate
+    /// carries on from where it stopped.
+    pub checkpoint: Option<PathBuf>,
+    /// Print what would change on the server, without changing anything.
+    pub dry_run: bool,
+}
+
+/// The entries a previous populate already applied. Each one is appended to the file as soon
+/// as it's done, so the file is still valid if populate is interrupted.
+struct Checkpoint {
+    applied: BTreeSet<String>,
+    file: Option<std::sync::Mutex<File>>,
+}
+
+impl Checkpoint {
+    fn open(path: Option<&Path>, dry_run: bool) -> Result<Self, Error> {
+        let Some(path) = path else {
+            return Ok(Checkpoint {
+                applied: BTreeSet::new(),
+                file: None,
+            });
+        };
+
+        let applied = match File::open(path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .collect::<Result<BTreeSet<_>, _>>()
+                .map_err(|io_err| Error::file("read the checkpoint", path, io_err))?,
+            Err(io_err) if io_err.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(io_err) => return Err(Error::file("open the checkpoint", path, io_err)),
+        };
+
+        // A dry run only reads the checkpoint, to leave out what was already applied.
+        let file = if dry_run {
+            None
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|io_err| Error::file("open the checkpoint", path, io_err))?;
+            Some(std::sync::Mutex::new(file))
+        };
+
+        if !applied.is_empty() {
+            info!(
+                applied = applied.len(),
+                "Resuming from the checkpoint, the entries it lists are skipped"
+            );
+        }
+
+        Ok(Checkpoint { applied, file })
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.applied.contains(key)
+    }
+
+    fn record(&self, key: &str) -> Result<(), Error> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let mut file = file
+            .lock()
+            .map_err(|_| Error::invalid("a thread writing the checkpoint panicked"))?;
+        writeln!(file, "{key}")
+            .and_then(|_| file.flush())
+            .map_err(|io_err| Error::io(format!("record {key} in the checkpoint"), io_err))
+    }
+}
+
 async fn apply_flags(client: Arc<kani::KanidmOrcaClient>, flags: &[Flag]) -> Result<(), Error> {
     for flag in flags {
         match flag {
@@ -20,25 +100,41 @@ async fn apply_flags(client: Arc<kani::KanidmOrcaClient>, flags: &[Flag]) -> Res
 
 async fn preflight_person(
     client: Arc<kani::KanidmOrcaClient>,
+    credential_provider: Arc<dyn CredentialProvider>,
     person: Person,
 ) -> Result<(), Error> {
     debug!(?person);
 
-    if client.person_exists(&person.username).await? {
-        // Do nothing? Do we need to reset them later?
+    if matches!(person.preflight_state, PreflightState::Absent) {
+        if client.person_exists(&person.username).await? {
+            client.person_delete(&person.username).await?;
+        }
         return Ok(());
+    }
+
+    if client.person_exists(&person.username).await? {
+        // Captured persons still need the placeholder credentials the actors log in with.
+        if !matches!(person.preflight_state, PreflightState::Captured) {
+            // Do nothing? Do we need to reset them later?
+            return Ok(());
+        }
     } else {
         client
             .person_create(&person.username, &person.display_name)
             .await?;
     }
 
-    match &person.credential {
-        Credential::Password { plain } => {
-            client
-                .person_set_primary_password_only(&person.username, plain)
-                .await?;
-        }
+    // Second factors are registered as the run starts, see `Credential`.
+    if let Some(plain) = credential_provider.password(&person).await? {
+        client
+            .person_set_primary_password_only(&person.username, &plain)
+            .await?;
+    }
+
+    if let Some(UnixCredential::Password { plain }) = &person.unix_credential {
+        client
+            .person_set_unix_password(&person.username, plain)
+            .await?;
     }
 
     // For each role we are part of, did we have other permissions required to fulfil that?
@@ -56,6 +152,13 @@ async fn preflight_person(
 }
 
 async fn preflight_group(client: Arc<kani::KanidmOrcaClient>, group: Group) -> Result<(), Error> {
+    if matches!(group.preflight_state, PreflightState::Absent) {
+        if client.group_exists(&group.name.to_string()).await? {
+            client.group_delete(&group.name.to_string()).await?;
+        }
+        return Ok(());
+    }
+
     if client.group_exists(&group.name.to_string()).await? {
         // Do nothing? Do we need to reset them later?
     } else {
@@ -73,43 +176,298 @@ async fn preflight_group(client: Arc<kani::KanidmOrcaClient>, group: Group) -> R
     Ok(())
 }
 
-pub async fn preflight(state: State) -> Result<(), Error> {
+async fn preflight_managed_group(
+    client: Arc<kani::KanidmOrcaClient>,
+    managed_group: ManagedGroup,
+) -> Result<(), Error> {
+    if matches!(managed_group.preflight_state, PreflightState::Absent) {
+        if client.group_exists(&managed_group.name).await? {
+            client.group_delete(&managed_group.name).await?;
+        }
+        return Ok(());
+    }
+
+    if client.group_exists(&managed_group.name).await? {
+        // The manager may have changed since the group was created.
+        client
+            .group_set_managed_by(&managed_group.name, &managed_group.managed_by)
+            .await?;
+    } else {
+        client
+            .managed_group_create(&managed_group.name, &managed_group.managed_by)
+            .await?;
+    }
+
+    // This also removes the person the manager was adding, if the last run stopped with
+    // them in the group.
+    let members = managed_group
+        .members
+        .iter()
+        .map(|s| s.as_str())
+        .collect::<Vec<_>>();
+
+    client
+        .group_set_members(&managed_group.name, members.as_slice())
+        .await
+}
+
+async fn preflight_access_control(
+    client: Arc<kani::KanidmOrcaClient>,
+    access_control: AccessControl,
+) -> Result<(), Error> {
+    // There's no way to delete access controls yet, so absent ones are only not created.
+    if matches!(access_control.preflight_state, PreflightState::Absent)
+        || client.access_control_exists(&access_control.name).await?
+    {
+        // Do nothing? Do we need to reset them later?
+        return Ok(());
+    }
+
+    client
+        .access_control_search_create(
+            &access_control.name,
+            &access_control.receiver.to_string(),
+            &access_control.target.to_string(),
+            access_control.search_attrs.as_slice(),
+        )
+        .await
+}
+
+async fn preflight_oauth2_client(
+    client: Arc<kani::KanidmOrcaClient>,
+    oauth2_client: Oauth2Client,
+) -> Result<(), Error> {
+    if matches!(oauth2_client.preflight_state, PreflightState::Absent) {
+        return Ok(());
+    }
+
+    if client.oauth2_client_exists(&oauth2_client.name).await? {
+        // Do nothing? Do we need to reset them later?
+    } else {
+        client
+            .oauth2_client_create(&oauth2_client.name, &oauth2_client.origin)
+            .await?;
+    }
+
+    // Maps are updated in place, so we can always (re)apply them.
+    for scope_map in oauth2_client.scope_maps.iter() {
+        client
+            .oauth2_client_set_scope_map(
+                &oauth2_client.name,
+                &scope_map.group.to_string(),
+                scope_map.scopes.as_slice(),
+            )
+            .await?;
+    }
+
+    for claim_map in oauth2_client.claim_maps.iter() {
+        client
+            .oauth2_client_set_claim_map(
+                &oauth2_client.name,
+                &claim_map.claim,
+                &claim_map.group.to_string(),
+                claim_map.values.as_slice(),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+async fn preflight_sync_account(
+    client: Arc<kani::KanidmOrcaClient>,
+    sync_account: SyncAccount,
+) -> Result<(), Error> {
+    // The persons of the sync account are pushed by the sync agent during the run.
+    if matches!(sync_account.preflight_state, PreflightState::Absent)
+        || client.sync_account_exists(&sync_account.name).await?
+    {
+        // Do nothing? Do we need to reset them later?
+        Ok(())
+    } else {
+        client.sync_account_create(&sync_account.name).await
+    }
+}
+
+// What populating an entry would do to the server.
+#[derive(Clone, Copy)]
+enum Change {
+    Create,
+    Delete,
+    Update,
+}
+
+fn print_change(change: Change, kind: &str, name: &str) {
+    let sign = match change {
+        Change::Create => '+',
+        Change::Delete => '-',
+        Change::Update => '~',
+    };
+    println!("{sign} {kind} {name}");
+}
+
+// Print the entries of the state that populate would change, leaving out the ones the
+// checkpoint says were already applied.
+async fn diff(
+    client: &kani::KanidmOrcaClient,
+    state: &State,
+    checkpoint: &Checkpoint,
+) -> Result<(), Error> {
+    let mut changes = 0;
+
+    for person in state.persons.iter() {
+        if checkpoint.contains(&format!("person:{}", person.username)) {
+            continue;
+        }
+        let exists = client.person_exists(&person.username).await?;
+        let change = match (&person.preflight_state, exists) {
+            (PreflightState::Absent, true) => Change::Delete,
+            (PreflightState::Absent, false) | (PreflightState::Present, true) => continue,
+            (PreflightState::Present, false) | (PreflightState::Captured, false) => Change::Create,
+            // The credentials of captured persons are replaced.
+            (PreflightState::Captured, true) => Change::Update,
+        };
+        print_change(change, "person", &person.username);
+        changes += 1;
+    }
+
+    let groups = state
+        .groups
+        .iter()
+        .map(|group| ("group", group.name.to_string(), &group.preflight_state))
+        .chain(state.managed_groups.iter().map(|managed_group| {
+            (
+                "managed_group",
+                managed_group.name.clone(),
+                &managed_group.preflight_state,
+            )
+        }));
+    for (kind, name, preflight_state) in groups {
+        if checkpoint.contains(&format!("{kind}:{name}")) {
+            continue;
+        }
+        let exists = client.group_exists(&name).await?;
+        match (preflight_state, exists) {
+            (PreflightState::Absent, true) => print_change(Change::Delete, kind, &name),
+            (PreflightState::Absent, _) | (_, true) => continue,
+            (_, false) => print_change(Change::Create, kind, &name),
+        }
+        changes += 1;
+    }
+
+    for access_control in state.access_controls.iter() {
+        if matches!(access_control.preflight_state, PreflightState::Absent)
+            || checkpoint.contains(&format!("access_control:{}", access_control.name))
+            || client.access_control_exists(&access_control.name).await?
+        {
+            continue;
+        }
+        print_change(Change::Create, "access_control", &access_control.name);
+        changes += 1;
+    }
+
+    for oauth2_client in state.oauth2_clients.iter() {
+        if matches!(oauth2_client.preflight_state, PreflightState::Absent)
+            || checkpoint.contains(&format!("oauth2_client:{}", oauth2_client.name))
+            || client.oauth2_client_exists(&oauth2_client.name).await?
+        {
+            continue;
+        }
+        print_change(Change::Create, "oauth2_client", &oauth2_client.name);
+        changes += 1;
+    }
+
+    if let Some(sync_account) = &state.sync_account {
+        if !matches!(sync_account.preflight_state, PreflightState::Absent)
+            && !checkpoint.contains(&format!("sync_account:{}", sync_account.name))
+            && !client.sync_account_exists(&sync_account.name).await?
+        {
+            print_change(Change::Create, "sync_account", &sync_account.name);
+            changes += 1;
+        }
+    }
+
+    info!(changes, "Dry run, nothing was changed on the server");
+    Ok(())
+}
+
+pub async fn preflight(state: State, options: &PreflightOptions) -> Result<(), Error> {
+    if options.concurrency == Some(0) {
+        return Err(Error::invalid("the concurrency must be at least 1"));
+    }
+
+    let checkpoint = Arc::new(Checkpoint::open(
+        options.checkpoint.as_deref(),
+        options.dry_run,
+    )?);
+
     // Get the admin client.
     let client = Arc::new(kani::KanidmOrcaClient::new(&state.profile).await?);
 
+    if options.dry_run {
+        return diff(&client, &state, &checkpoint).await;
+    }
+
     // Apply any flags if they exist.
     apply_flags(client.clone(), state.preflight_flags.as_slice()).await?;
 
-    let state_persons_len = state.persons.len();
-    let mut tasks = VecDeque::with_capacity(state_persons_len);
+    // While writes are single threaded in Kanidm, searches (such as .exists)
+    // and credential updates are concurrent / parallel. So these parts can be
+    // called in parallel, so we divide up into workers, each with its own connection.
+    let par = options
+        .concurrency
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from));
+    let mut clients = Vec::with_capacity(par);
+    for _ in 0..par {
+        clients.push(Arc::new(kani::KanidmOrcaClient::new(&state.profile).await?));
+    }
+
+    let credential_provider = credential_provider::from_properties(
+        state.profile.credential_provider(),
+        state.profile.seed(),
+    );
 
     // Create persons.
-    for person in state.persons.into_iter() {
-        let c = client.clone();
-        // While writes are single threaded in Kanidm, searches (such as .exists)
-        // and credential updates are concurrent / parallel. So these parts can be
-        // called in parallel, so we divide up into workers.
-        tasks.push_back(preflight_person(c, person))
-    }
+    let tasks: VecDeque<_> = state
+        .persons
+        .into_iter()
+        .filter(|person| !checkpoint.contains(&format!("person:{}", person.username)))
+        .collect();
 
     let tasks = Arc::new(Mutex::new(tasks));
     let counter = Arc::new(AtomicU32::new(0));
-    let par = std::thread::available_parallelism().unwrap();
+    let failed = Arc::new(AtomicU32::new(0));
 
-    let handles: Vec<_> = (0..par.into())
-        .map(|_| {
+    let handles: Vec<_> = clients
+        .into_iter()
+        .map(|worker_client| {
             let tasks_q = tasks.clone();
             let counter_c = counter.clone();
+            let failed_c = failed.clone();
+            let checkpoint_c = checkpoint.clone();
+            let credential_provider_c = credential_provider.clone();
             tokio::spawn(async move {
                 loop {
-                    let maybe_task = async {
+                    let maybe_person = async {
                         let mut guard = tasks_q.lock().await;
                         guard.pop_front()
                     }
                     .await;
 
-                    if let Some(t) = maybe_task {
-                        let _ = t.await;
+                    if let Some(person) = maybe_person {
+                        let key = format!("person:{}", person.username);
+                        // The failed persons aren't recorded, so that the next populate
+                        // retries them.
+                        let applied = preflight_person(
+                            worker_client.clone(),
+                            credential_provider_c.clone(),
+                            person,
+                        )
+                        .await;
+                        if let Err(err) = applied.and_then(|_| checkpoint_c.record(&key)) {
+                            error!("{}", err.report());
+                            failed_c.fetch_add(1, Ordering::Relaxed);
+                        }
                         let was = counter_c.fetch_add(1, Ordering::Relaxed);
                         if was % 1000 == 999 {
                             let order = was + 1;
@@ -128,28 +486,74 @@ pub async fn preflight(state: State) -> Result<(), Error> {
         .collect();
 
     for handle in handles {
-        handle.await.map_err(|tokio_err| {
-            error!(?tokio_err, "Failed to join task");
-            Error::Tokio
-        })?;
+        handle
+            .await
+            .map_err(|tokio_err| Error::tokio("wait for a person to be populated", tokio_err))?;
     }
 
     eprintln!("done");
 
+    // The groups would refer to the persons that are missing.
+    let failed = failed.load(Ordering::Relaxed);
+    if failed > 0 {
+        return Err(Error::invalid(format!(
+            "{failed} persons couldn't be populated, run populate again to retry them"
+        )));
+    }
+
     // Create groups.
     let counter = Arc::new(AtomicU32::new(0));
     let mut tasks = Vec::with_capacity(state.groups.len());
 
     for group in state.groups.into_iter() {
+        let key = format!("group:{}", group.name);
+        if checkpoint.contains(&key) {
+            continue;
+        }
         let c = client.clone();
         // Write operations are single threaded in Kanidm, so we don't need to attempt
         // to parallelise that here.
         // tasks.push(tokio::spawn(preflight_group(c, group)))
-        tasks.push(preflight_group(c, group))
+        tasks.push((key, preflight_group(c, group)))
     }
 
-    for task in tasks {
+    for (key, task) in tasks {
         task.await?;
+        checkpoint.record(&key)?;
+        let was = counter.fetch_add(1, Ordering::Relaxed);
+        if was % 1000 == 999 {
+            let order = was + 1;
+            eprint!("{}", order);
+        } else if was % 100 == 99 {
+            // Since we just added one, this just rolled over.
+            eprint!(".");
+        }
+    }
+
+    eprintln!("done");
+
+    // Create the managed groups. As with groups, these are writes so we don't bother to
+    // parallelise.
+    for managed_group in state.managed_groups.into_iter() {
+        let key = format!("managed_group:{}", managed_group.name);
+        if checkpoint.contains(&key) {
+            continue;
+        }
+        preflight_managed_group(client.clone(), managed_group).await?;
+        checkpoint.record(&key)?;
+    }
+
+    // Create access controls. These refer to the groups, so they have to come after them.
+    let counter = Arc::new(AtomicU32::new(0));
+
+    for access_control in state.access_controls.into_iter() {
+        let key = format!("access_control:{}", access_control.name);
+        if checkpoint.contains(&key) {
+            continue;
+        }
+        // As with groups, these are writes so we don't bother to parallelise.
+        preflight_access_control(client.clone(), access_control).await?;
+        checkpoint.record(&key)?;
         let was = counter.fetch_add(1, Ordering::Relaxed);
         if was % 1000 == 999 {
             let order = was + 1;
@@ -163,6 +567,24 @@ pub async fn preflight(state: State) -> Result<(), Error> {
     eprintln!("done");
 
     // Create integrations.
+    for oauth2_client in state.oauth2_clients.into_iter() {
+        let key = format!("oauth2_client:{}", oauth2_client.name);
+        if checkpoint.contains(&key) {
+            continue;
+        }
+        preflight_oauth2_client(client.clone(), oauth2_client).await?;
+        checkpoint.record(&key)?;
+    }
+
+    if let Some(sync_account) = state.sync_account {
+        let key = format!("sync_account:{}", sync_account.name);
+        if !checkpoint.contains(&key) {
+            preflight_sync_account(client.clone(), sync_account).await?;
+            checkpoint.record(&key)?;
+        }
+    }
+
+    eprintln!("done");
 
     info!("Ready to 🛫");
     Ok(())
@@ -1,11 +1,20 @@
 use crate::error::Error;
+use crate::model::TransitionAction;
+use crate::models::scripted::SCRIPTED_ACTIONS;
+use crate::scenario::Scenario;
 use crate::state::{GroupName, Model};
+use rand::seq::SliceRandom;
 use rand::{thread_rng, Rng};
+use rand_chacha::ChaCha8Rng;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::de::{value, IntoDeserializer};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use toml::value::{Table, Value};
+use tracing_subscriber::EnvFilter;
 
 // Sorry nerds, capping this at 40 bits.
 const ITEM_UPPER_BOUND: u64 = 1 << 40;
@@ -16,54 +25,1126 @@ const DEFAULT_PERSON_COUNT: u64 = 10;
 const DEFAULT_WARMUP_TIME: u64 = 10;
 const DEFAULT_TEST_TIME: Option<u64> = Some(180);
 
+const DEFAULT_MFA_ENROLLMENT_WAVE_TIME: u64 = 60;
+
+const DEFAULT_PASSWORD_EXPIRY_WAVE_TIME: u64 = 60;
+
+const DEFAULT_LOGIN_STORM_FRACTION: f64 = 0.9;
+const DEFAULT_LOGIN_STORM_SPREAD: u64 = 30;
+
+const DEFAULT_DUPLICATE_LOGIN_ACCOUNT_COUNT: u64 = 5;
+
+const DEFAULT_IMPORT_CONCURRENCY: u64 = 1;
+const DEFAULT_IMPORT_BATCH_SIZE: u64 = 50;
+const DEFAULT_IMPORT_DELAY: u64 = 30;
+
+const DEFAULT_LOGGING_FILTER: &str = "orca=debug";
+const DEFAULT_LOGGING_MAX_FILE_SIZE_MB: u64 = 64;
+const DEFAULT_LOGGING_MAX_FILES: u32 = 5;
+
+const DEFAULT_THRESHOLD_WINDOW: u64 = 5;
+
+const DEFAULT_SYNC_AGENT_BATCH_SIZE: u64 = 50;
+const DEFAULT_SYNC_AGENT_INTERVAL: u64 = 10;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GroupProperties {
     pub member_count: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Profile {
-    control_uri: String,
-    admin_password: String,
-    idm_admin_password: String,
-    seed: i64,
-    extra_uris: Vec<String>,
-    // Dimensions of the test to setup.
-    warmup_time: u64,
-    test_time: Option<u64>,
-    group_count: u64,
-    person_count: u64,
-    thread_count: Option<usize>,
-    model: Model,
-    group: BTreeMap<String, GroupProperties>,
-    #[serde(default)]
-    dump_raw_data: bool,
-}
+/// The OAuth2 clients to generate, and how complex their mappings to the test
+/// groups are. Token issuance has to resolve all of these for every request.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Oauth2Properties {
+    #[serde(default)]
+    pub client_count: u64,
+    /// Scope maps per client. Each maps one group, so this is capped at the group count.
+    #[serde(default)]
+    pub scope_map_count: u64,
+    /// Claim maps per client, each with its own claim name.
+    #[serde(default)]
+    pub claim_map_count: u64,
+}
+
+/// Groups whose changes are delegated to a single person each through `entry_managed_by`,
+/// which the delegated group admin model writes to.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ManagedGroupProperties {
+    /// How many managed groups to create. Each is managed by a different person.
+    #[serde(default)]
+    pub count: u64,
+    /// How many persons each managed group starts with, besides the one its manager keeps
+    /// adding and removing.
+    #[serde(default)]
+    pub member_count: u64,
+}
+
+/// An onboarding wave, where part of the population enrolls an MFA credential through a
+/// credential update session while the test is running.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaEnrollmentProperties {
+    /// The fraction of persons (between 0 and 1) that enroll during the run.
+    #[serde(default)]
+    pub fraction: f64,
+    /// Of the persons that enroll, the fraction (between 0 and 1) that enroll a passkey
+    /// rather than TOTP.
+    #[serde(default)]
+    pub passkey_fraction: f64,
+    /// The enrollments are spread randomly over this many seconds after the warmup.
+    #[serde(default = "default_mfa_enrollment_wave_time")]
+    pub wave_time: u64,
+}
+
+fn default_mfa_enrollment_wave_time() -> u64 {
+    DEFAULT_MFA_ENROLLMENT_WAVE_TIME
+}
+
+impl Default for MfaEnrollmentProperties {
+    fn default() -> Self {
+        MfaEnrollmentProperties {
+            fraction: 0.0,
+            passkey_fraction: 0.0,
+            wave_time: default_mfa_enrollment_wave_time(),
+        }
+    }
+}
+
+/// Limits the run is expected to stay within. The windows of the run during which any of
+/// them was breached are reported in a timeline, so they can be matched to server events.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ThresholdProperties {
+    /// The length in seconds of the windows the thresholds are checked over.
+    #[serde(default = "default_threshold_window")]
+    pub window: u64,
+    /// The 95th percentile latency in milliseconds that reads must stay under.
+    #[serde(default)]
+    pub read_latency_ms: Option<u64>,
+    /// The 95th percentile latency in milliseconds that writes must stay under.
+    #[serde(default)]
+    pub write_latency_ms: Option<u64>,
+    /// The fraction of events (between 0 and 1) that are allowed to be errors.
+    #[serde(default)]
+    pub error_rate: Option<f64>,
+}
+
+fn default_threshold_window() -> u64 {
+    DEFAULT_THRESHOLD_WINDOW
+}
+
+impl Default for ThresholdProperties {
+    fn default() -> Self {
+        ThresholdProperties {
+            window: default_threshold_window(),
+            read_latency_ms: None,
+            write_latency_ms: None,
+            error_rate: None,
+        }
+    }
+}
+
+/// Goals for the 99th percentile latency of each type of event over the whole run. Each
+/// goal passes or fails on its own, and the run exits unsuccessfully if any of them fails.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LatencyGoalProperties {
+    #[serde(default)]
+    pub auth_p99_ms: Option<u64>,
+    #[serde(default)]
+    pub read_p99_ms: Option<u64>,
+    #[serde(default)]
+    pub write_p99_ms: Option<u64>,
+}
+
+/// Makes the actors read the responses of the server slowly, like clients on a slow link
+/// or misbehaving clients would. Run it as its own cohort next to a normal one, to see
+/// whether the slow consumers degrade everyone else.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SlowClientProperties {
+    /// The rate at which responses are read, in bytes per second.
+    pub read_bytes_per_second: u64,
+}
+
+/// Puts the actors of a cohort in a region away from the servers, by holding each request
+/// back for a base latency before it reaches the server. Cohorts with different classes
+/// approximate a global spread of users from a single load host.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LatencyClassProperties {
+    /// The name the class is reported under, such as the region it stands for.
+    pub name: String,
+    /// How long (in milliseconds) each request is held back for.
+    pub base_latency_ms: u64,
+}
+
+impl LatencyClassProperties {
+    pub fn base_latency(&self) -> Duration {
+        Duration::from_millis(self.base_latency_ms)
+    }
+}
+
+/// A bulk import of persons that runs alongside the actors, like a migration into a live
+/// server, so the impact of the import on interactive latency can be measured.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportProperties {
+    /// How many persons are imported.
+    pub person_count: u64,
+    /// How many creation requests are in flight at once.
+    #[serde(default = "default_import_concurrency")]
+    pub concurrency: u64,
+    /// How many persons each creation request carries.
+    #[serde(default = "default_import_batch_size")]
+    pub batch_size: u64,
+    /// Seconds after the warmup at which the import starts, so there is a baseline to
+    /// compare with.
+    #[serde(default = "default_import_delay")]
+    pub delay: u64,
+}
+
+fn default_import_concurrency() -> u64 {
+    DEFAULT_IMPORT_CONCURRENCY
+}
+
+fn default_import_batch_size() -> u64 {
+    DEFAULT_IMPORT_BATCH_SIZE
+}
+
+fn default_import_delay() -> u64 {
+    DEFAULT_IMPORT_DELAY
+}
+
+/// Samples how much data the server holds during the test, so that performance drifting
+/// over a long churn heavy test can be correlated with the growth of the data.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DataSizeSamplingProperties {
+    /// Seconds between two samples.
+    pub interval: u64,
+    /// Count the persons and groups over the API. Every entry is listed to count them, so
+    /// this adds some load of its own.
+    #[serde(default)]
+    pub count_entries: bool,
+    /// A command that prints the size of the database in bytes as the first word of its
+    /// output, such as `["du", "-b", "/var/lib/kanidm/kanidm.db"]`. Kanidm doesn't report
+    /// the size of its database over the API.
+    #[serde(default)]
+    pub db_size_command: Option<Vec<String>>,
+    /// Count the sessions held by up to this many persons of the cohort. Kanidm only lists
+    /// the sessions of one account at a time, so the whole session store can't be counted.
+    #[serde(default)]
+    pub count_sessions_of: Option<usize>,
+}
+
+/// Spills the events of the run to disk once too many of them are waiting to be processed,
+/// so that a very high rate of events can't run the load host out of memory. The events are
+/// read back as the statistics are processed at the end of the run.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventSpoolProperties {
+    /// How many events are kept in memory before the rest go to disk.
+    pub max_buffered_events: usize,
+    /// Where the spilled events are written, the temporary directory of the host if unset.
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+}
+
+/// A phase of the load schedule. The phases follow each other from the end of the warmup,
+/// and the actors past the number a phase allows are paused until a later phase lets them
+/// run again.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoadPhaseProperties {
+    /// The name the results of the phase are reported under.
+    pub name: String,
+    /// How long the phase lasts, in seconds.
+    pub duration: u64,
+    /// How many actors run during the phase, or by its end when it ramps.
+    pub actors: usize,
+    /// Move linearly from the actors of the phase before (or none, for the first phase)
+    /// to the actors of this one over the phase, rather than all at once as it starts.
+    #[serde(default)]
+    pub ramp: bool,
+    /// How many actions the actors of the cohort may have in flight at once during the
+    /// phase, on top of the limit of the cohort. They aren't limited if unset.
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+}
+
+/// A command that makes the server take a backup, run at a point of the test so that the
+/// impact of the backup on the actors can be measured. Kanidm has no API to start a backup,
+/// so this is usually something like `["ssh", "idm1", "kanidmd", "database", "backup", ...]`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackupHookProperties {
+    /// The program to run, followed by its arguments.
+    pub command: Vec<String>,
+    /// Seconds after the warmup at which the backup is taken.
+    pub at: u64,
+}
+
+/// A planned part of the test during which the server is expected to fail requests, such as
+/// a failover. The requests that start in it are tallied on their own, and left out of the
+/// thresholds, the latency goals and the rest of the results.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MaintenanceWindowProperties {
+    /// The name the window is reported under.
+    pub name: String,
+    /// Seconds after the warmup at which the window opens.
+    pub at: u64,
+    /// How long the window lasts, in seconds.
+    pub duration: u64,
+}
+
+/// A password expiry wave, where the passwords of part of the population expire while the
+/// test is running, and the persons rotate them as they're forced to at their next login.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordExpiryProperties {
+    /// The fraction of the persons with a password (between 0 and 1) whose password expires.
+    #[serde(default)]
+    pub fraction: f64,
+    /// The expiries are spread randomly over this many seconds after the warmup.
+    #[serde(default = "default_password_expiry_wave_time")]
+    pub wave_time: u64,
+}
+
+fn default_password_expiry_wave_time() -> u64 {
+    DEFAULT_PASSWORD_EXPIRY_WAVE_TIME
+}
+
+impl Default for PasswordExpiryProperties {
+    fn default() -> Self {
+        PasswordExpiryProperties {
+            fraction: 0.0,
+            wave_time: default_password_expiry_wave_time(),
+        }
+    }
+}
+
+/// A "monday morning" login storm, where most of the population logs in within a short
+/// window after the warmup. Only used by the login storm model.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginStormProperties {
+    /// The fraction of persons (between 0 and 1) that take part in the storm.
+    #[serde(default = "default_login_storm_fraction")]
+    pub fraction: f64,
+    /// The storm logins are spread randomly over this many seconds after the warmup.
+    #[serde(default = "default_login_storm_spread")]
+    pub spread: u64,
+}
+
+fn default_login_storm_fraction() -> f64 {
+    DEFAULT_LOGIN_STORM_FRACTION
+}
+
+fn default_login_storm_spread() -> u64 {
+    DEFAULT_LOGIN_STORM_SPREAD
+}
+
+impl Default for LoginStormProperties {
+    fn default() -> Self {
+        LoginStormProperties {
+            fraction: default_login_storm_fraction(),
+            spread: default_login_storm_spread(),
+        }
+    }
+}
+
+/// Many persons logging in as the same few accounts at once. Only used by the duplicate
+/// login model.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DuplicateLoginProperties {
+    /// How many shared accounts the persons are spread over.
+    #[serde(default = "default_duplicate_login_account_count")]
+    pub account_count: u64,
+}
+
+fn default_duplicate_login_account_count() -> u64 {
+    DEFAULT_DUPLICATE_LOGIN_ACCOUNT_COUNT
+}
+
+impl Default for DuplicateLoginProperties {
+    fn default() -> Self {
+        DuplicateLoginProperties {
+            account_count: default_duplicate_login_account_count(),
+        }
+    }
+}
+
+/// How the primary credentials of the persons are split between the credential types, as
+/// fractions of the persons that add up to 1. Persons who set their own password, or enroll
+/// MFA during the run, keep a password either way.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CredentialMixProperties {
+    #[serde(default)]
+    pub password: f64,
+    #[serde(default)]
+    pub password_totp: f64,
+    #[serde(default)]
+    pub passkey: f64,
+}
+
+impl Default for CredentialMixProperties {
+    fn default() -> Self {
+        CredentialMixProperties {
+            password: 1.0,
+            password_totp: 0.0,
+            passkey: 0.0,
+        }
+    }
+}
+
+/// Where the passwords of the persons come from.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialSource {
+    /// The passwords are kept in the state.
+    #[default]
+    State,
+    /// The passwords are derived from the seed and the username whenever they're needed, so
+    /// the state of a very large population doesn't have to hold them.
+    Derived,
+    /// The passwords are fetched from an external store, such as a vault, by running a
+    /// command.
+    Command,
+}
+
+/// How the passwords of the persons are resolved. Unless they're kept in the state, they're
+/// left out of it, and fetched as populate sets them and as the actors start.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CredentialProviderProperties {
+    #[serde(default)]
+    pub source: CredentialSource,
+    /// The command that prints the password of a person, who is given as its last argument.
+    /// Only the first line it prints is taken.
+    #[serde(default)]
+    pub command: Vec<String>,
+}
+
+/// Self reads that request a set of attributes rather than the whole entry, so that the
+/// cost of partial reads can be compared with full ones.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AttributeReadProperties {
+    /// The attribute sets to read. Each chosen person is given one of them.
+    #[serde(default)]
+    pub sets: Vec<Vec<String>>,
+    /// The fraction of persons (between 0 and 1) that read one of the sets, the others keep
+    /// reading their whole entry.
+    #[serde(default)]
+    pub fraction: f64,
+}
+
+/// A log file that gets its own copy of the tracing events, filtered independently of the
+/// terminal output, so that verbose logging doesn't drown out the run.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoggingProperties {
+    /// The file the events are written to. Nothing is written to a file if unset.
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Which events go to the file, with a level per module in the same syntax as
+    /// RUST_LOG. A single actor can be picked with `orca[actor{username=person_1}]=trace`.
+    #[serde(default = "default_logging_filter")]
+    pub filter: String,
+    /// The file is rotated once it grows past this many megabytes.
+    #[serde(default = "default_logging_max_file_size_mb")]
+    pub max_file_size_mb: u64,
+    /// How many rotated files are kept besides the current one.
+    #[serde(default = "default_logging_max_files")]
+    pub max_files: u32,
+}
+
+fn default_logging_filter() -> String {
+    DEFAULT_LOGGING_FILTER.to_string()
+}
+
+fn default_logging_max_file_size_mb() -> u64 {
+    DEFAULT_LOGGING_MAX_FILE_SIZE_MB
+}
+
+fn default_logging_max_files() -> u32 {
+    DEFAULT_LOGGING_MAX_FILES
+}
+
+impl Default for LoggingProperties {
+    fn default() -> Self {
+        LoggingProperties {
+            file: None,
+            filter: default_logging_filter(),
+            max_file_size_mb: default_logging_max_file_size_mb(),
+            max_files: default_logging_max_files(),
+        }
+    }
+}
+
+/// A simulated external IDM (such as FreeIPA or LDAP) that pushes batches of changes
+/// through a sync account while the test is running.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncAgentProperties {
+    /// How many persons the external IDM owns. The agent is disabled when this is 0.
+    #[serde(default)]
+    pub person_count: u64,
+    /// How many of those persons are changed in each pushed batch.
+    #[serde(default = "default_sync_agent_batch_size")]
+    pub batch_size: u64,
+    /// Seconds between two pushed batches.
+    #[serde(default = "default_sync_agent_interval")]
+    pub interval: u64,
+}
+
+fn default_sync_agent_batch_size() -> u64 {
+    DEFAULT_SYNC_AGENT_BATCH_SIZE
+}
+
+fn default_sync_agent_interval() -> u64 {
+    DEFAULT_SYNC_AGENT_INTERVAL
+}
+
+impl Default for SyncAgentProperties {
+    fn default() -> Self {
+        SyncAgentProperties {
+            person_count: 0,
+            batch_size: default_sync_agent_batch_size(),
+            interval: default_sync_agent_interval(),
+        }
+    }
+}
+
+/// How far the clocks of the simulated clients drift from the server when they
+/// compute TOTP codes. Servers accept codes within a small tolerance window, so
+/// a skewed client population produces a realistic amount of rejected codes and
+/// retries.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TotpClockSkew {
+    #[default]
+    None,
+    /// Every actor is off by exactly this many seconds. Can be negative.
+    Fixed { seconds: i64 },
+    /// Each actor picks its own skew uniformly from `-max_seconds..=max_seconds`.
+    Random { max_seconds: u64 },
+}
+
+impl TotpClockSkew {
+    /// Resolve the skew (in seconds) for a single actor.
+    pub fn for_actor(&self, rng: &mut ChaCha8Rng) -> i64 {
+        match self {
+            TotpClockSkew::None => 0,
+            TotpClockSkew::Fixed { seconds } => *seconds,
+            TotpClockSkew::Random { max_seconds } => {
+                let max_seconds = (*max_seconds).min(i64::MAX as u64) as i64;
+                rng.gen_range(-max_seconds..=max_seconds)
+            }
+        }
+    }
+}
+
+/// A report of the throughput, error rate and latency percentiles of every type of event,
+/// for other tools to compare runs with. It's always logged, and written to the files that
+/// are set.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReportProperties {
+    /// Where to write the report as JSON.
+    #[serde(default)]
+    pub json: Option<PathBuf>,
+    /// Where to write the report as CSV, with one row per type of event.
+    #[serde(default)]
+    pub csv: Option<PathBuf>,
+    /// How many seconds at the start of the measured window are left out of the report, on
+    /// top of the warmup, for servers that take longer to settle.
+    #[serde(default)]
+    pub skip_first_secs: u64,
+    /// The length in seconds of the buckets the latency percentiles of each event are also
+    /// computed over, to show how they develop over the test.
+    #[serde(default = "default_report_bucket_secs")]
+    pub bucket_secs: u64,
+    /// Where to write the percentiles of each bucket as CSV. They are always part of the
+    /// JSON report.
+    #[serde(default)]
+    pub series_csv: Option<PathBuf>,
+    /// How many of the most active persons, and of the persons with the most errors, are
+    /// listed at the end of the run, to spot a generated account that skews it. The events
+    /// aren't tallied by person if unset.
+    #[serde(default)]
+    pub top_persons: Option<usize>,
+}
+
+fn default_report_bucket_secs() -> u64 {
+    60
+}
+
+impl Default for ReportProperties {
+    fn default() -> Self {
+        ReportProperties {
+            json: None,
+            csv: None,
+            skip_first_secs: 0,
+            bucket_secs: default_report_bucket_secs(),
+            series_csv: None,
+            top_persons: None,
+        }
+    }
+}
+
+/// An endpoint serving the metrics of the run for Prometheus to scrape while it goes on.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MetricsProperties {
+    /// The address to listen on, such as `127.0.0.1:9464`.
+    pub listen: SocketAddr,
+}
+
+/// The order the steps of a script are performed in.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptOrder {
+    /// One after the other, starting again from the first once the last is done.
+    #[default]
+    Sequence,
+    /// Each step is drawn at random, in proportion to its weight.
+    Weighted,
+}
+
+/// A step of the script, which performs an action one or more times in a row.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScriptStep {
+    pub action: TransitionAction,
+    /// How long to wait before each time the action is performed, in milliseconds.
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// How many times in a row the action is performed.
+    #[serde(default = "default_script_step_count")]
+    pub repeat: u32,
+    /// How likely the step is to be drawn, relative to the others. Only used by weighted
+    /// scripts.
+    #[serde(default = "default_script_step_count")]
+    pub weight: u32,
+}
+
+fn default_script_step_count() -> u32 {
+    1
+}
+
+/// The script the actors of the `scripted` model follow, so that a mix of actions can be
+/// tailored without writing a model. Any action that isn't a login opens a session first if
+/// there's none.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct ScriptedProperties {
+    #[serde(default)]
+    pub order: ScriptOrder,
+    #[serde(default)]
+    pub steps: Vec<ScriptStep>,
+}
+
+/// How the actors of a cohort are mapped to its persons.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PersonSelectionStrategy {
+    /// Every person is run by one actor, in the order of the state.
+    #[default]
+    OneToOne,
+    /// Each actor runs as a person drawn at random, so some persons are run by several
+    /// actors and others by none.
+    Random,
+    /// Like random, but the persons are drawn with a Zipf distribution over their order in
+    /// the state, so that a few hot persons run most of the actors.
+    Zipf,
+    /// The actors are split between the test groups in proportion to their members, so that
+    /// every group is represented however few actors there are.
+    GroupStratified,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PersonSelectionProperties {
+    #[serde(default)]
+    pub strategy: PersonSelectionStrategy,
+    /// How many actors to run. Defaults to the number of persons.
+    #[serde(default)]
+    pub actors: Option<usize>,
+    /// How skewed the Zipf distribution is, the higher the fewer persons get most of the
+    /// actors.
+    #[serde(default = "default_zipf_exponent")]
+    pub exponent: f64,
+}
+
+fn default_zipf_exponent() -> f64 {
+    1.0
+}
+
+impl Default for PersonSelectionProperties {
+    fn default() -> Self {
+        PersonSelectionProperties {
+            strategy: PersonSelectionStrategy::default(),
+            actors: None,
+            exponent: default_zipf_exponent(),
+        }
+    }
+}
+
+/// How the actors react when the server asks them to back off, by answering with 429 Too
+/// Many Requests or 503 Service Unavailable. Whether the next request of the actor came
+/// after the wait or not is always recorded.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackoffProperties {
+    /// Wait before the next request, as a polite client does, rather than carry on.
+    #[serde(default)]
+    pub honour: bool,
+    /// How long the server is taken to ask the actor to wait for, in milliseconds. The client
+    /// API doesn't expose the Retry-After header of the response.
+    #[serde(default = "default_retry_after_ms")]
+    pub retry_after_ms: u64,
+}
+
+fn default_retry_after_ms() -> u64 {
+    1000
+}
+
+impl Default for BackoffProperties {
+    fn default() -> Self {
+        BackoffProperties {
+            honour: false,
+            retry_after_ms: default_retry_after_ms(),
+        }
+    }
+}
+
+/// How the actors retry a request that failed in a way that may not last, such as a dropped
+/// connection or a 5xx response. Only the reads and writes of the persons' own entries, and
+/// of a single group, are retried. Nothing is retried by default.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetryProperties {
+    /// How many times a failed request is tried again, on top of the first attempt.
+    #[serde(default)]
+    pub max_retries: u32,
+    /// How long to wait before each retry, in milliseconds.
+    #[serde(default = "default_retry_backoff_ms")]
+    pub backoff_ms: u64,
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    100
+}
+
+impl Default for RetryProperties {
+    fn default() -> Self {
+        RetryProperties {
+            max_retries: 0,
+            backoff_ms: default_retry_backoff_ms(),
+        }
+    }
+}
+
+/// How actors treat their sessions between the cycles of their model.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionLifetime {
+    /// Log in at the start of every cycle and out at the end of it.
+    #[default]
+    PerCycle,
+    /// Keep the session across cycles, so that most requests ride an existing session. It's
+    /// only replaced once the server rejects it, or when it's refreshed on a cadence.
+    Reuse,
+}
+
+/// What the actors do with their sessions once the run ends. The sessions they abandon stay on
+/// the server until they expire, as those of clients that just went away do.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunEndLogout {
+    /// Leave every session behind.
+    #[default]
+    Dirty,
+    /// Log out of every session.
+    Clean,
+    /// Log out of the sessions of a share of the actors, and leave the rest behind.
+    Mixed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RunEndProperties {
+    #[serde(default)]
+    pub logout: RunEndLogout,
+    /// The share of the actors that log out when `logout` is mixed.
+    #[serde(default)]
+    pub clean_fraction: f64,
+}
+
+impl RunEndProperties {
+    /// Whether an actor logs out at the end of the run. The rng is only drawn from for a mix,
+    /// so that the other choices don't change how the rest of the run is seeded.
+    pub fn logs_out(&self, rng: &mut impl Rng) -> bool {
+        match self.logout {
+            RunEndLogout::Dirty => false,
+            RunEndLogout::Clean => true,
+            RunEndLogout::Mixed => rng.gen_bool(self.clean_fraction),
+        }
+    }
+}
+
+/// What is checked against the server once the load has stopped, so that a long churn run
+/// also tells whether the data came out of it intact.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Assertion {
+    /// Every person of the state that should exist still does.
+    PersonsPresent,
+    /// Every group of the state has exactly the members of the state, no more and no less.
+    GroupMembersMatchState,
+    /// No person or group of the state, nor any entry the actors created, is in the recycle
+    /// bin.
+    RecycleBinClean,
+}
+
+/// The attestation format a generated passkey authenticator presents on registration.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PasskeyAttestation {
+    /// No attestation statement, as most consumer authenticators do.
+    #[default]
+    None,
+    /// A packed attestation statement, signed by a test CA of the authenticator.
+    Packed,
+}
+
+/// The properties of a generated passkey authenticator. The user verification and
+/// attestation take different paths through the WebAuthn verification code on the server.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct PasskeyProperties {
+    /// Whether the credential is discoverable (resident on the authenticator). Only the
+    /// client varies with it, the server registers both kinds the same way.
+    #[serde(default)]
+    pub resident_key: bool,
+    /// Whether the authenticator asserts it has verified the user.
+    #[serde(default = "default_user_verification")]
+    pub user_verification: bool,
+    #[serde(default)]
+    pub attestation: PasskeyAttestation,
+}
+
+fn default_user_verification() -> bool {
+    true
+}
+
+impl Default for PasskeyProperties {
+    fn default() -> Self {
+        PasskeyProperties {
+            resident_key: false,
+            user_verification: default_user_verification(),
+            attestation: PasskeyAttestation::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Profile {
+    control_uri: String,
+    admin_password: String,
+    idm_admin_password: String,
+    seed: i64,
+    extra_uris: Vec<String>,
+    // Dimensions of the test to setup.
+    warmup_time: u64,
+    test_time: Option<u64>,
+    group_count: u64,
+    person_count: u64,
+    thread_count: Option<usize>,
+    model: Model,
+    group: BTreeMap<String, GroupProperties>,
+    #[serde(default)]
+    dump_raw_data: bool,
+    /// Also break the statistics down by the test groups the persons are members of.
+    #[serde(default)]
+    stats_by_group: bool,
+    #[serde(default)]
+    totp_clock_skew: TotpClockSkew,
+    /// The authenticator variations passkey credentials are generated from. Each
+    /// person with a passkey is assigned one of these at random.
+    #[serde(default)]
+    passkey_authenticators: Vec<PasskeyProperties>,
+    /// How many extra access control profiles to create between the test groups. These
+    /// make every access decision on the server evaluate a large set of ACPs.
+    #[serde(default)]
+    access_control_count: u64,
+    /// The fraction of persons (between 0 and 1) that are given a unix password, which
+    /// the auth only model verifies through the unix auth API.
+    #[serde(default)]
+    unix_password_fraction: f64,
+    #[serde(default)]
+    credential_mix: CredentialMixProperties,
+    #[serde(default)]
+    credential_provider: CredentialProviderProperties,
+    #[serde(default)]
+    oauth2: Oauth2Properties,
+    #[serde(default)]
+    managed_groups: ManagedGroupProperties,
+    #[serde(default)]
+    mfa_enrollment: MfaEnrollmentProperties,
+    #[serde(default)]
+    password_expiry: PasswordExpiryProperties,
+    #[serde(default)]
+    sync_agent: SyncAgentProperties,
+    #[serde(default)]
+    login_storm: LoginStormProperties,
+    #[serde(default)]
+    duplicate_login: DuplicateLoginProperties,
+    #[serde(default)]
+    logging: LoggingProperties,
+    #[serde(default)]
+    attribute_reads: AttributeReadProperties,
+    #[serde(default)]
+    thresholds: ThresholdProperties,
+    #[serde(default)]
+    latency_goals: LatencyGoalProperties,
+    #[serde(default)]
+    report: ReportProperties,
+    #[serde(default)]
+    metrics: Option<MetricsProperties>,
+    /// How long (in milliseconds) a single action may take before it's abandoned and
+    /// recorded as a timeout. Actions aren't bounded if unset.
+    #[serde(default)]
+    action_timeout_ms: Option<u64>,
+    /// How many actions the actors of the cohort may have in flight at once, so that a small
+    /// cohort of heavy actions can't take over the server. Actors wait for room before their
+    /// next action. Actions aren't limited if unset.
+    #[serde(default)]
+    max_concurrency: Option<usize>,
+    /// How often (in seconds) every actor renews its session, regardless of what it's
+    /// doing, like long lived agents do. Sessions aren't renewed on a cadence if unset.
+    #[serde(default)]
+    session_refresh_interval: Option<u64>,
+    /// How often (in seconds) every actor that holds a session checks that it's still
+    /// valid, like the agents and dashboards that poll constantly. No keepalives are sent
+    /// if unset.
+    #[serde(default)]
+    keepalive_interval: Option<u64>,
+    #[serde(default)]
+    session_lifetime: SessionLifetime,
+    #[serde(default)]
+    run_end: RunEndProperties,
+    #[serde(default)]
+    backoff: BackoffProperties,
+    #[serde(default)]
+    retry: RetryProperties,
+    #[serde(default)]
+    scripted: ScriptedProperties,
+    #[serde(default)]
+    person_selection: PersonSelectionProperties,
+    /// How often (in seconds) a summary of the last interval is logged during the test, to
+    /// show that a long headless run is still alive. Nothing is logged if unset.
+    #[serde(default)]
+    heartbeat_interval: Option<u64>,
+    #[serde(default)]
+    slow_client: Option<SlowClientProperties>,
+    #[serde(default)]
+    latency_class: Option<LatencyClassProperties>,
+    #[serde(default)]
+    assertions: Vec<Assertion>,
+    /// Headers added to every request of the cohort, such as an `X-Forwarded-For` for a
+    /// proxy or WAF to act on, or a `traceparent` to find its requests in the server logs.
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    import: Option<ImportProperties>,
+    #[serde(default)]
+    backups: Vec<BackupHookProperties>,
+    #[serde(default)]
+    maintenance_windows: Vec<MaintenanceWindowProperties>,
+    #[serde(default)]
+    load_schedule: Vec<LoadPhaseProperties>,
+    #[serde(default)]
+    data_size_sampling: Option<DataSizeSamplingProperties>,
+    #[serde(default)]
+    event_spool: Option<EventSpoolProperties>,
+    /// The built-in scenario the profile is based on, if any.
+    #[serde(default)]
+    scenario: Option<Scenario>,
+}
+
+impl Profile {
+    pub fn control_uri(&self) -> &str {
+        self.control_uri.as_str()
+    }
+
+    pub fn extra_uris(&self) -> &[String] {
+        self.extra_uris.as_slice()
+    }
+
+    pub fn admin_password(&self) -> &str {
+        self.admin_password.as_str()
+    }
+
+    pub fn idm_admin_password(&self) -> &str {
+        self.idm_admin_password.as_str()
+    }
+
+    #[allow(dead_code)]
+    pub fn group_count(&self) -> u64 {
+        self.group_count
+    }
+
+    pub fn person_count(&self) -> u64 {
+        self.person_count
+    }
+
+    pub fn access_control_count(&self) -> u64 {
+        self.access_control_count
+    }
+
+    pub fn unix_password_fraction(&self) -> f64 {
+        self.unix_password_fraction
+    }
+
+    pub fn credential_mix(&self) -> &CredentialMixProperties {
+        &self.credential_mix
+    }
+
+    pub fn credential_provider(&self) -> &CredentialProviderProperties {
+        &self.credential_provider
+    }
+
+    pub fn oauth2(&self) -> &Oauth2Properties {
+        &self.oauth2
+    }
+
+    pub fn mfa_enrollment(&self) -> &MfaEnrollmentProperties {
+        &self.mfa_enrollment
+    }
+
+    pub fn password_expiry(&self) -> &PasswordExpiryProperties {
+        &self.password_expiry
+    }
+
+    pub fn thresholds(&self) -> &ThresholdProperties {
+        &self.thresholds
+    }
+
+    pub fn latency_goals(&self) -> &LatencyGoalProperties {
+        &self.latency_goals
+    }
+
+    pub fn action_timeout(&self) -> Option<Duration> {
+        self.action_timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn max_concurrency(&self) -> Option<usize> {
+        self.max_concurrency
+    }
+
+    pub fn slow_client(&self) -> Option<&SlowClientProperties> {
+        self.slow_client.as_ref()
+    }
+
+    pub fn latency_class(&self) -> Option<&LatencyClassProperties> {
+        self.latency_class.as_ref()
+    }
+
+    pub fn assertions(&self) -> &[Assertion] {
+        self.assertions.as_slice()
+    }
 
-impl Profile {
-    pub fn control_uri(&self) -> &str {
-        self.control_uri.as_str()
+    pub fn headers(&self) -> Result<HeaderMap, Error> {
+        self.headers
+            .iter()
+            .map(|(name, value)| {
+                let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|http_err| {
+                    Error::profile(format!("invalid name of the header '{name}': {http_err}"))
+                })?;
+                let header_value = HeaderValue::from_str(value).map_err(|http_err| {
+                    Error::profile(format!("invalid value of the header '{name}': {http_err}"))
+                })?;
+                Ok((header_name, header_value))
+            })
+            .collect()
     }
 
-    pub fn extra_uris(&self) -> &[String] {
-        self.extra_uris.as_slice()
+    pub fn data_size_sampling(&self) -> Option<&DataSizeSamplingProperties> {
+        self.data_size_sampling.as_ref()
     }
 
-    pub fn admin_password(&self) -> &str {
-        self.admin_password.as_str()
+    pub fn event_spool(&self) -> Option<&EventSpoolProperties> {
+        self.event_spool.as_ref()
     }
 
-    pub fn idm_admin_password(&self) -> &str {
-        self.idm_admin_password.as_str()
+    pub fn load_schedule(&self) -> &[LoadPhaseProperties] {
+        self.load_schedule.as_slice()
     }
 
-    #[allow(dead_code)]
-    pub fn group_count(&self) -> u64 {
-        self.group_count
+    pub fn backups(&self) -> &[BackupHookProperties] {
+        self.backups.as_slice()
     }
 
-    pub fn person_count(&self) -> u64 {
-        self.person_count
+    pub fn maintenance_windows(&self) -> &[MaintenanceWindowProperties] {
+        self.maintenance_windows.as_slice()
+    }
+
+    pub fn import(&self) -> Option<&ImportProperties> {
+        self.import.as_ref()
+    }
+
+    pub fn session_refresh_interval(&self) -> Option<Duration> {
+        self.session_refresh_interval.map(Duration::from_secs)
+    }
+
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        self.keepalive_interval.map(Duration::from_secs)
+    }
+
+    pub fn report(&self) -> &ReportProperties {
+        &self.report
+    }
+
+    pub fn metrics(&self) -> Option<&MetricsProperties> {
+        self.metrics.as_ref()
+    }
+
+    pub fn scripted(&self) -> &ScriptedProperties {
+        &self.scripted
+    }
+
+    pub fn person_selection(&self) -> &PersonSelectionProperties {
+        &self.person_selection
+    }
+
+    /// How many actors the cohort runs, which is the number of persons unless the person
+    /// selection says otherwise.
+    pub fn actor_count(&self) -> u64 {
+        self.person_selection
+            .actors
+            .map_or(self.person_count, |actors| actors as u64)
+    }
+
+    pub fn backoff(&self) -> &BackoffProperties {
+        &self.backoff
+    }
+
+    pub fn retry(&self) -> &RetryProperties {
+        &self.retry
+    }
+
+    pub fn session_lifetime(&self) -> SessionLifetime {
+        self.session_lifetime
+    }
+
+    pub fn run_end(&self) -> &RunEndProperties {
+        &self.run_end
+    }
+
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeat_interval.map(Duration::from_secs)
+    }
+
+    pub fn managed_groups(&self) -> &ManagedGroupProperties {
+        &self.managed_groups
+    }
+
+    pub fn login_storm(&self) -> &LoginStormProperties {
+        &self.login_storm
+    }
+
+    pub fn duplicate_login(&self) -> &DuplicateLoginProperties {
+        &self.duplicate_login
+    }
+
+    pub fn attribute_reads(&self) -> &AttributeReadProperties {
+        &self.attribute_reads
+    }
+
+    pub fn logging(&self) -> &LoggingProperties {
+        &self.logging
+    }
+
+    pub fn sync_agent(&self) -> &SyncAgentProperties {
+        &self.sync_agent
     }
 
     pub fn thread_count(&self) -> Option<usize> {
@@ -97,6 +1178,22 @@ impl Profile {
     pub fn dump_raw_data(&self) -> bool {
         self.dump_raw_data
     }
+
+    pub fn stats_by_group(&self) -> bool {
+        self.stats_by_group
+    }
+
+    pub fn totp_clock_skew(&self) -> TotpClockSkew {
+        self.totp_clock_skew
+    }
+
+    /// Pick the authenticator properties for the next generated passkey.
+    pub fn choose_passkey_properties(&self, rng: &mut ChaCha8Rng) -> PasskeyProperties {
+        self.passkey_authenticators
+            .choose(rng)
+            .copied()
+            .unwrap_or_default()
+    }
 }
 
 pub struct ProfileBuilder {
@@ -113,13 +1210,15 @@ pub struct ProfileBuilder {
     pub thread_count: Option<usize>,
     pub model: Model,
     pub dump_raw_data: bool,
+    pub scenario: Option<Scenario>,
 }
 
 fn validate_u64_bound(value: Option<u64>, default: u64) -> Result<u64, Error> {
     if let Some(v) = value {
         if v > ITEM_UPPER_BOUND {
-            error!("group count exceeds upper bound ({})", ITEM_UPPER_BOUND);
-            Err(Error::ProfileBuilder)
+            Err(Error::profile(format!(
+                "{v} exceeds the upper bound of {ITEM_UPPER_BOUND}"
+            )))
         } else {
             Ok(v)
         }
@@ -151,9 +1250,15 @@ impl ProfileBuilder {
             thread_count,
             model,
             dump_raw_data,
+            scenario: None,
         }
     }
 
+    pub fn scenario(mut self, scenario: Option<Scenario>) -> Self {
+        self.scenario = scenario;
+        self
+    }
+
     pub fn seed(mut self, seed: Option<u64>) -> Self {
         self.seed = seed;
         self
@@ -197,6 +1302,7 @@ impl ProfileBuilder {
             thread_count,
             model,
             dump_raw_data,
+            scenario,
         } = self;
 
         let seed: u64 = seed.unwrap_or_else(|| {
@@ -221,7 +1327,7 @@ impl ProfileBuilder {
             seed as i64
         };
 
-        Ok(Profile {
+        let profile = Profile {
             control_uri,
             admin_password,
             idm_admin_password,
@@ -235,37 +1341,589 @@ impl ProfileBuilder {
             group,
             model,
             dump_raw_data,
-        })
+            stats_by_group: false,
+            totp_clock_skew: TotpClockSkew::default(),
+            passkey_authenticators: Vec::new(),
+            access_control_count: 0,
+            unix_password_fraction: 0.0,
+            credential_mix: CredentialMixProperties::default(),
+            credential_provider: CredentialProviderProperties::default(),
+            oauth2: Oauth2Properties::default(),
+            managed_groups: ManagedGroupProperties::default(),
+            mfa_enrollment: MfaEnrollmentProperties::default(),
+            password_expiry: PasswordExpiryProperties::default(),
+            sync_agent: SyncAgentProperties::default(),
+            login_storm: LoginStormProperties::default(),
+            duplicate_login: DuplicateLoginProperties::default(),
+            logging: LoggingProperties::default(),
+            attribute_reads: AttributeReadProperties::default(),
+            thresholds: ThresholdProperties::default(),
+            latency_goals: LatencyGoalProperties::default(),
+            report: ReportProperties::default(),
+            metrics: None,
+            action_timeout_ms: None,
+            max_concurrency: None,
+            session_refresh_interval: None,
+            keepalive_interval: None,
+            session_lifetime: SessionLifetime::default(),
+            run_end: RunEndProperties::default(),
+            backoff: BackoffProperties::default(),
+            retry: RetryProperties::default(),
+            scripted: ScriptedProperties::default(),
+            person_selection: PersonSelectionProperties::default(),
+            heartbeat_interval: None,
+            slow_client: None,
+            latency_class: None,
+            assertions: Vec::new(),
+            headers: BTreeMap::new(),
+            import: None,
+            backups: Vec::new(),
+            maintenance_windows: Vec::new(),
+            load_schedule: Vec::new(),
+            data_size_sampling: None,
+            event_spool: None,
+            scenario: None,
+        };
+
+        match scenario {
+            Some(scenario) => profile.with_scenario(scenario),
+            None => Ok(profile),
+        }
     }
 }
 
 impl Profile {
+    /// Going through a toml value emits the plain settings before the tables, which
+    /// serializing the struct directly doesn't do.
+    pub fn to_toml_string(&self) -> Result<String, Error> {
+        Value::try_from(self)
+            .and_then(|profile| toml::to_string(&profile))
+            .map_err(|toml_err| Error::toml("serialise the profile", toml_err))
+    }
+
     pub fn write_to_path(&self, path: &Path) -> Result<(), Error> {
-        let file_contents = toml::to_string(self).map_err(|toml_err| {
-            error!(?toml_err);
-            Error::SerdeToml
+        let file_contents = self.to_toml_string()?;
+
+        std::fs::write(path, file_contents)
+            .map_err(|io_err| Error::file("write the profile", path, io_err))
+    }
+
+    /// Base the profile on a scenario, replacing any of its settings that the scenario sets.
+    fn with_scenario(self, scenario: Scenario) -> Result<Profile, Error> {
+        let mut profile = match Value::try_from(self) {
+            Ok(Value::Table(profile)) => profile,
+            Ok(_) => return Err(Error::invalid("the profile isn't a table")),
+            Err(toml_err) => return Err(Error::toml("convert the profile", toml_err)),
+        };
+
+        scenario.apply_over(&mut profile)?;
+        profile.insert(
+            "scenario".to_string(),
+            Value::try_from(scenario)
+                .map_err(|toml_err| Error::toml("convert the scenario", toml_err))?,
+        );
+
+        let profile: Profile = Value::Table(profile).try_into().map_err(|toml_err| {
+            Error::toml(format!("apply the {scenario:?} scenario"), toml_err)
         })?;
+        profile.validate()?;
 
-        std::fs::write(path, file_contents).map_err(|io_err| {
-            error!(?io_err);
-            Error::Io
-        })
+        Ok(profile)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        self.validate_group_names_and_member_count()?;
+        self.validate_mfa_enrollment()?;
+        self.validate_password_expiry()?;
+        self.validate_login_storm()?;
+        self.validate_duplicate_login()?;
+        self.validate_logging()?;
+        self.validate_attribute_reads()?;
+        self.validate_thresholds()?;
+        self.validate_action_timeout()?;
+        self.validate_max_concurrency()?;
+        self.validate_session_refresh_interval()?;
+        self.validate_keepalive_interval()?;
+        self.validate_heartbeat_interval()?;
+        self.validate_run_end()?;
+        self.validate_slow_client()?;
+        self.validate_latency_class()?;
+        self.validate_import()?;
+        self.validate_backups()?;
+        self.validate_maintenance_windows()?;
+        self.validate_load_schedule()?;
+        self.validate_data_size_sampling()?;
+        self.validate_event_spool()?;
+        self.validate_unix_password_fraction()?;
+        self.validate_credential_mix()?;
+        self.validate_credential_provider()?;
+        self.validate_oauth2()?;
+        self.validate_managed_groups()?;
+        self.validate_scripted()?;
+        self.validate_person_selection()?;
+        self.validate_headers()?;
+        self.validate_report()
     }
 
     fn validate_group_names_and_member_count(&self) -> Result<(), Error> {
         for (group_name, group_properties) in self.group.iter() {
             let _ = GroupName::deserialize(group_name.as_str().into_deserializer()).map_err(
-                |_: value::Error| {
-                    error!("Invalid group name provided: {group_name}");
-                    Error::InvalidState
-                },
+                |_: value::Error| Error::profile(format!("invalid group name {group_name}")),
             )?;
             let provided_member_count = group_properties.member_count.unwrap_or_default();
             let max_member_count = self.person_count();
             if provided_member_count > max_member_count {
-                error!("Member count of {group_name} is out of bound: max value is {max_member_count}, but {provided_member_count} was provided");
-                return Err(Error::InvalidState);
+                return Err(Error::profile(format!("member count of {group_name} is out of bound: max value is {max_member_count}, but {provided_member_count} was provided")));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_mfa_enrollment(&self) -> Result<(), Error> {
+        let MfaEnrollmentProperties {
+            fraction,
+            passkey_fraction,
+            ..
+        } = self.mfa_enrollment;
+        for (name, value) in [
+            ("fraction", fraction),
+            ("passkey_fraction", passkey_fraction),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(Error::profile(format!(
+                    "MFA enrollment {name} must be between 0 and 1, but {value} was provided"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_password_expiry(&self) -> Result<(), Error> {
+        let fraction = self.password_expiry.fraction;
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(Error::profile(format!(
+                "password expiry fraction must be between 0 and 1, but {fraction} was provided"
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate_thresholds(&self) -> Result<(), Error> {
+        if self.thresholds.window == 0 {
+            return Err(Error::profile(
+                "the threshold window must be at least one second",
+            ));
+        }
+        if let Some(error_rate) = self.thresholds.error_rate {
+            if !(0.0..=1.0).contains(&error_rate) {
+                return Err(Error::profile(format!("the error rate threshold must be between 0 and 1, but {error_rate} was provided")));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_action_timeout(&self) -> Result<(), Error> {
+        if self.action_timeout_ms == Some(0) {
+            return Err(Error::profile(
+                "the action timeout must be at least one millisecond",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_max_concurrency(&self) -> Result<(), Error> {
+        if self.max_concurrency == Some(0) {
+            return Err(Error::profile(
+                "the cohort must be allowed at least 1 action at once",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_event_spool(&self) -> Result<(), Error> {
+        if let Some(event_spool) = &self.event_spool {
+            if event_spool.max_buffered_events == 0 {
+                return Err(Error::profile(
+                    "the event spool must keep at least one event in memory",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_data_size_sampling(&self) -> Result<(), Error> {
+        if let Some(sampling) = &self.data_size_sampling {
+            if sampling.interval == 0 {
+                return Err(Error::profile(
+                    "the data size sampling interval must be at least 1 second",
+                ));
             }
+            match &sampling.db_size_command {
+                Some(command) if command.is_empty() => {
+                    return Err(Error::profile("the database size command can't be empty"));
+                }
+                None if !sampling.count_entries && sampling.count_sessions_of.is_none() => {
+                    return Err(Error::profile("data size sampling needs count_entries, count_sessions_of or a db_size_command"));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_backups(&self) -> Result<(), Error> {
+        if self.backups.iter().any(|backup| backup.command.is_empty()) {
+            return Err(Error::profile("every backup needs a command to run"));
+        }
+        Ok(())
+    }
+
+    fn validate_maintenance_windows(&self) -> Result<(), Error> {
+        let mut names = BTreeSet::new();
+        for window in self.maintenance_windows.iter() {
+            if window.duration == 0 {
+                return Err(Error::profile(format!(
+                    "the maintenance window {} must last at least 1 second",
+                    window.name
+                )));
+            }
+            if !names.insert(window.name.as_str()) {
+                return Err(Error::profile(format!(
+                    "the maintenance windows need distinct names, {} is used twice",
+                    window.name
+                )));
+            }
+            if self
+                .test_time
+                .is_some_and(|test_time| window.at + window.duration > test_time)
+            {
+                return Err(Error::profile(format!(
+                    "the maintenance window {} ends after the test",
+                    window.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_scripted(&self) -> Result<(), Error> {
+        let steps = &self.scripted.steps;
+        if matches!(self.model, Model::Scripted) && steps.is_empty() {
+            return Err(Error::profile(
+                "the scripted model needs a script with at least one step",
+            ));
+        }
+        for step in steps.iter() {
+            if !SCRIPTED_ACTIONS.contains(&step.action) {
+                return Err(Error::profile(format!(
+                    "the action {:?} can't be part of a script",
+                    step.action
+                )));
+            }
+            if step.repeat == 0 {
+                return Err(Error::profile(format!(
+                    "the {:?} step of the script must be performed at least once",
+                    step.action
+                )));
+            }
+        }
+        if self.scripted.order == ScriptOrder::Weighted && steps.iter().all(|step| step.weight == 0)
+        {
+            return Err(Error::profile(
+                "a weighted script needs a step with a weight above 0",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_headers(&self) -> Result<(), Error> {
+        self.headers().map(|_| ())
+    }
+
+    fn validate_person_selection(&self) -> Result<(), Error> {
+        let person_selection = &self.person_selection;
+        if person_selection.actors == Some(0) {
+            return Err(Error::profile(
+                "the person selection must run at least 1 actor",
+            ));
+        }
+        if person_selection.strategy == PersonSelectionStrategy::OneToOne
+            && self.actor_count() > self.person_count
+        {
+            return Err(Error::profile(format!(
+                "one to one person selection can't run {} actors, there are only {} persons",
+                self.actor_count(),
+                self.person_count
+            )));
+        }
+        if person_selection.exponent <= 0.0 {
+            return Err(Error::profile(
+                "the Zipf exponent of the person selection must be above 0",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_load_schedule(&self) -> Result<(), Error> {
+        let mut names = BTreeSet::new();
+        for phase in self.load_schedule.iter() {
+            if phase.duration == 0 {
+                return Err(Error::profile(format!(
+                    "the phase {} of the load schedule must last at least 1 second",
+                    phase.name
+                )));
+            }
+            if phase.actors as u64 > self.actor_count() {
+                return Err(Error::profile(format!(
+                    "the phase {} can't run {} actors, there are only {}",
+                    phase.name,
+                    phase.actors,
+                    self.actor_count()
+                )));
+            }
+            if phase.max_concurrency == Some(0) {
+                return Err(Error::profile(format!(
+                    "the phase {} must allow at least 1 action at once",
+                    phase.name
+                )));
+            }
+            if !names.insert(phase.name.as_str()) {
+                return Err(Error::profile(format!(
+                    "the phases of the load schedule need distinct names, {} is used twice",
+                    phase.name
+                )));
+            }
+        }
+
+        let schedule_secs: u64 = self.load_schedule.iter().map(|phase| phase.duration).sum();
+        if self
+            .test_time
+            .is_some_and(|test_time| schedule_secs > test_time)
+        {
+            return Err(Error::profile(format!(
+                "the load schedule lasts {schedule_secs}s, longer than the test"
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate_import(&self) -> Result<(), Error> {
+        if let Some(import) = &self.import {
+            if import.person_count == 0 || import.concurrency == 0 || import.batch_size == 0 {
+                return Err(Error::profile(
+                    "the import person count, concurrency and batch size must be at least 1",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_slow_client(&self) -> Result<(), Error> {
+        if let Some(slow_client) = &self.slow_client {
+            if slow_client.read_bytes_per_second == 0 {
+                return Err(Error::profile(
+                    "the slow client read rate must be at least one byte per second",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_latency_class(&self) -> Result<(), Error> {
+        if let Some(latency_class) = &self.latency_class {
+            if latency_class.name.is_empty() {
+                return Err(Error::profile("the latency class must have a name"));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_session_refresh_interval(&self) -> Result<(), Error> {
+        if self.session_refresh_interval == Some(0) {
+            return Err(Error::profile(
+                "the session refresh interval must be at least one second",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_keepalive_interval(&self) -> Result<(), Error> {
+        if self.keepalive_interval == Some(0) {
+            return Err(Error::profile(
+                "the keepalive interval must be at least one second",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_heartbeat_interval(&self) -> Result<(), Error> {
+        if self.heartbeat_interval == Some(0) {
+            return Err(Error::profile(
+                "the heartbeat interval must be at least one second",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_run_end(&self) -> Result<(), Error> {
+        let fraction = self.run_end.clean_fraction;
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(Error::profile(format!("the clean fraction of the run end must be between 0 and 1, but {fraction} was provided")));
+        }
+        Ok(())
+    }
+
+    fn validate_unix_password_fraction(&self) -> Result<(), Error> {
+        let fraction = self.unix_password_fraction;
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(Error::profile(format!(
+                "the unix password fraction must be between 0 and 1, but {fraction} was provided"
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate_credential_mix(&self) -> Result<(), Error> {
+        let mix = &self.credential_mix;
+        let fractions = [mix.password, mix.password_totp, mix.passkey];
+        if fractions
+            .iter()
+            .any(|fraction| !(0.0..=1.0).contains(fraction))
+        {
+            return Err(Error::profile(format!("the fractions of the credential mix must be between 0 and 1, but {mix:?} was provided")));
+        }
+        let total: f64 = fractions.iter().sum();
+        if (total - 1.0).abs() > 1e-6 {
+            return Err(Error::profile(format!(
+                "the fractions of the credential mix must add up to 1, but they add up to {total}"
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate_credential_provider(&self) -> Result<(), Error> {
+        let provider = &self.credential_provider;
+        match provider.source {
+            CredentialSource::Command if provider.command.is_empty() => {
+                Err(Error::profile("the credential provider needs a command to fetch the passwords with"))
+            }
+            CredentialSource::State | CredentialSource::Derived if !provider.command.is_empty() => {
+                Err(Error::profile(format!("the credential provider only runs its command with the command source, not {:?}", provider.source)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn validate_oauth2(&self) -> Result<(), Error> {
+        if matches!(self.model, Model::Oauth2)
+            && (self.oauth2.client_count == 0 || self.oauth2.scope_map_count == 0)
+        {
+            return Err(Error::profile(
+                "the OAuth2 model needs OAuth2 clients with scope maps to sign in to",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_managed_groups(&self) -> Result<(), Error> {
+        let ManagedGroupProperties {
+            count,
+            member_count,
+        } = self.managed_groups;
+        if matches!(self.model, Model::DelegatedGroupAdmin) && count == 0 {
+            return Err(Error::profile(
+                "the delegated group admin model needs managed groups to write to",
+            ));
+        }
+        if count > self.person_count {
+            return Err(Error::profile(format!("each of the {count} managed groups needs its own manager, but there are only {} persons", self.person_count)));
+        }
+        // Besides its members, a group needs a manager and a person to add.
+        if count > 0 && member_count + 2 > self.person_count {
+            return Err(Error::profile(format!("managed groups with {member_count} members need at least {} persons, but there are only {}", member_count + 2,
+                self.person_count)));
+        }
+        Ok(())
+    }
+
+    fn validate_report(&self) -> Result<(), Error> {
+        let skip_first_secs = self.report.skip_first_secs;
+        if self
+            .test_time
+            .is_some_and(|test_time| skip_first_secs >= test_time)
+        {
+            return Err(Error::profile(format!(
+                "the report can't skip the first {skip_first_secs}s, that's the whole test"
+            )));
+        }
+        if self.report.bucket_secs == 0 {
+            return Err(Error::profile(
+                "the report buckets must be at least 1 second long",
+            ));
+        }
+        if self.report.top_persons == Some(0) {
+            return Err(Error::profile(
+                "the report must list at least 1 of the top persons",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_login_storm(&self) -> Result<(), Error> {
+        let fraction = self.login_storm.fraction;
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(Error::profile(format!(
+                "login storm fraction must be between 0 and 1, but {fraction} was provided"
+            )));
+        }
+        Ok(())
+    }
+
+    fn validate_attribute_reads(&self) -> Result<(), Error> {
+        let fraction = self.attribute_reads.fraction;
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err(Error::profile(format!(
+                "the attribute read fraction must be between 0 and 1, but {fraction} was provided"
+            )));
+        }
+        if fraction > 0.0 && self.attribute_reads.sets.is_empty() {
+            return Err(Error::profile(
+                "attribute reads need at least one attribute set to read",
+            ));
+        }
+        if self
+            .attribute_reads
+            .sets
+            .iter()
+            .any(|set| set.is_empty() || set.iter().any(|attr| attr.is_empty()))
+        {
+            return Err(Error::profile(
+                "attribute sets can't be empty, or contain empty attribute names",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_logging(&self) -> Result<(), Error> {
+        if let Err(filter_err) = EnvFilter::try_new(&self.logging.filter) {
+            return Err(Error::profile(format!(
+                "invalid log file filter '{}': {filter_err}",
+                self.logging.filter
+            )));
+        }
+        if self.logging.max_file_size_mb == 0 || self.logging.max_files == 0 {
+            return Err(Error::profile(
+                "the log file size and the number of log files kept must be at least 1",
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_duplicate_login(&self) -> Result<(), Error> {
+        if self.duplicate_login.account_count == 0 {
+            return Err(Error::profile(
+                "the duplicate login account count must be at least 1",
+            ));
         }
         Ok(())
     }
@@ -275,17 +1933,50 @@ impl TryFrom<&Path> for Profile {
     type Error = Error;
 
     fn try_from(path: &Path) -> Result<Self, Self::Error> {
-        let file_contents = std::fs::read_to_string(path).map_err(|io_err| {
-            error!(?io_err);
-            Error::Io
+        let file_contents = std::fs::read_to_string(path)
+            .map_err(|io_err| Error::file("read the profile", path, io_err))?;
+
+        let mut profile: Table = toml::from_str(&file_contents).map_err(|toml_err| {
+            Error::toml(format!("parse the profile '{}'", path.display()), toml_err)
         })?;
 
-        let profile: Profile = toml::from_str(&file_contents).map_err(|toml_err| {
-            error!(?toml_err);
-            Error::SerdeToml
+        // Fill in whatever the profile doesn't set from its scenario.
+        if let Some(scenario) = profile.get("scenario").cloned() {
+            let scenario: Scenario = scenario
+                .try_into()
+                .map_err(|toml_err| Error::toml("read the scenario of the profile", toml_err))?;
+            scenario.apply_under(&mut profile)?;
+        }
+
+        let profile: Profile = Value::Table(profile).try_into().map_err(|toml_err| {
+            Error::toml(format!("read the profile '{}'", path.display()), toml_err)
         })?;
-        profile.validate_group_names_and_member_count()?;
+        profile.validate()?;
 
         Ok(profile)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::TotpClockSkew;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_totp_clock_skew_for_actor() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        assert_eq!(TotpClockSkew::None.for_actor(&mut rng), 0);
+        assert_eq!(
+            TotpClockSkew::Fixed { seconds: -45 }.for_actor(&mut rng),
+            -45
+        );
+
+        let random = TotpClockSkew::Random { max_seconds: 90 };
+        for _ in 0..1000 {
+            let skew = random.for_actor(&mut rng);
+            assert!((-90..=90).contains(&skew));
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use crate::error::Error;
+use crate::profile::ReportProperties;
+use crate::run::EventDetail;
+use crate::schema;
+
+use csv::Writer;
+use hdrhistogram::Histogram;
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// Latencies are recorded in microseconds, up to an hour, to three significant figures.
+const HIGHEST_LATENCY_US: u64 = 3_600_000_000;
+const SIGNIFICANT_FIGURES: u8 = 3;
+// There's a histogram per bucket of each event, so they are kept smaller.
+const SERIES_SIGNIFICANT_FIGURES: u8 = 2;
+
+/// The throughput, error rate and latency percentiles of each type of event over the
+/// measured window, which is written out for other tools to compare runs with.
+pub struct EventReport {
+    skip_first: Duration,
+    bucket_secs: u64,
+    events: BTreeMap<String, EventTimings>,
+    // The latencies of each event over the buckets of the test, by the start of the bucket.
+    series: BTreeMap<(String, u64), Histogram<u64>>,
+}
+
+struct EventTimings {
+    id: &'static str,
+    latencies_us: Histogram<u64>,
+    errors: u64,
+    rejected: u64,
+}
+
+impl EventTimings {
+    fn new(id: &'static str) -> Self {
+        EventTimings {
+            id,
+            latencies_us: Histogram::new_with_bounds(1, HIGHEST_LATENCY_US, SIGNIFICANT_FIGURES)
+                .expect("histogram bounds corrupted"),
+            errors: 0,
+            rejected: 0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventReportRow {
+    pub event_id: &'static str,
+    pub event: String,
+    pub events: u64,
+    pub errors: u64,
+    /// The events the server refused under its access policy, which aren't errors.
+    pub rejected: u64,
+    pub error_rate: f64,
+    pub throughput_per_s: f64,
+    pub mean_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub p999_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+}
+
+/// The latency percentiles of an event over one bucket of the test, so that degradations
+/// that develop over time aren't hidden by the percentiles of the whole test.
+#[derive(Debug, Serialize)]
+pub struct EventSeriesRow {
+    pub event_id: &'static str,
+    pub event: String,
+    pub from_start_s: u64,
+    pub events: u64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    run_id: &'a str,
+    schema_version: u32,
+    measured_s: f64,
+    events: &'a [EventReportRow],
+    series: &'a [EventSeriesRow],
+}
+
+impl EventReport {
+    pub fn new(properties: &ReportProperties) -> Self {
+        EventReport {
+            skip_first: Duration::from_secs(properties.skip_first_secs),
+            bucket_secs: properties.bucket_secs,
+            events: BTreeMap::new(),
+            series: BTreeMap::new(),
+        }
+    }
+
+    /// Count an event that started `from_start` into the measured window.
+    pub fn push(&mut self, details: &EventDetail, from_start: Duration, duration: Duration) {
+        if from_start < self.skip_first {
+            return;
+        }
+
+        let (event, failed, rejected) = match details {
+            // A failure or a rejection counts against the event that was attempted.
+            EventDetail::Error(failure) | EventDetail::Timeout(failure) => {
+                (&failure.event, true, false)
+            }
+            EventDetail::Rejected(failure) => (&failure.event, false, true),
+            // Only a login can conflict with the session an actor already has.
+            EventDetail::LoginConflict => (&EventDetail::Login, true, false),
+            // These describe how the actors behaved, rather than a request to the server.
+            EventDetail::ScheduleLag
+            | EventDetail::PermitWait
+            | EventDetail::BackoffRespected
+            | EventDetail::BackoffViolated
+            | EventDetail::Transition(_)
+            | EventDetail::DataSize(_)
+            | EventDetail::Attempts(_) => return,
+            details => (details, false, false),
+        };
+
+        let id = event.id();
+        let event = format!("{event:?}");
+        let latency_us = (duration.as_micros() as u64).max(1);
+        if !failed && !rejected {
+            let bucket = from_start.as_secs() / self.bucket_secs * self.bucket_secs;
+            self.series
+                .entry((event.clone(), bucket))
+                .or_insert_with(|| {
+                    Histogram::new_with_bounds(1, HIGHEST_LATENCY_US, SERIES_SIGNIFICANT_FIGURES)
+                        .expect("histogram bounds corrupted")
+                })
+                .saturating_record(latency_us);
+        }
+
+        let timings = self
+            .events
+            .entry(event)
+            .or_insert_with(|| EventTimings::new(id));
+        if failed {
+            timings.errors += 1;
+        } else if rejected {
+            timings.rejected += 1;
+        } else {
+            timings.latencies_us.saturating_record(latency_us);
+        }
+    }
+
+    /// The latency percentiles of each type of event, bucket by bucket.
+    pub fn series(&self) -> Vec<EventSeriesRow> {
+        let to_ms = |us: u64| us as f64 / 1000.;
+        self.series
+            .iter()
+            .map(|((event, from_start_s), latencies_us)| EventSeriesRow {
+                event_id: self
+                    .events
+                    .get(event)
+                    .map(|timings| timings.id)
+                    .unwrap_or_default(),
+                event: event.clone(),
+                from_start_s: *from_start_s,
+                events: latencies_us.len(),
+                p50_ms: to_ms(latencies_us.value_at_quantile(0.5)),
+                p95_ms: to_ms(latencies_us.value_at_quantile(0.95)),
+                p99_ms: to_ms(latencies_us.value_at_quantile(0.99)),
+            })
+            .collect()
+    }
+
+    /// One row per type of event, over a measured window that lasted `measured`.
+    pub fn rows(&self, measured: Duration) -> Vec<EventReportRow> {
+        let measured_s = measured.saturating_sub(self.skip_first).as_secs_f64();
+        self.events
+            .iter()
+            .map(|(event, timings)| {
+                let latencies_us = &timings.latencies_us;
+                let events = latencies_us.len();
+                let to_ms = |us: u64| (events > 0).then_some(us as f64 / 1000.);
+                EventReportRow {
+                    event_id: timings.id,
+                    event: event.clone(),
+                    events,
+                    errors: timings.errors,
+                    rejected: timings.rejected,
+                    error_rate: timings.errors as f64
+                        / (events + timings.errors + timings.rejected) as f64,
+                    throughput_per_s: if measured_s > 0. {
+                        events as f64 / measured_s
+                    } else {
+                        0.
+                    },
+                    mean_ms: (events > 0).then_some(latencies_us.mean() / 1000.),
+                    p50_ms: to_ms(latencies_us.value_at_quantile(0.5)),
+                    p95_ms: to_ms(latencies_us.value_at_quantile(0.95)),
+                    p99_ms: to_ms(latencies_us.value_at_quantile(0.99)),
+                    p999_ms: to_ms(latencies_us.value_at_quantile(0.999)),
+                    max_ms: to_ms(latencies_us.max()),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Log the report as a table, and write it to the files the profile asks for.
+pub fn write_report(
+    run_id: &str,
+    rows: &[EventReportRow],
+    series: &[EventSeriesRow],
+    measured: Duration,
+    properties: &ReportProperties,
+) -> Result<(), Error> {
+    let format_ms = |ms: Option<f64>| {
+        ms.map(|ms| format!("{ms:.1}"))
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    info!(
+        "{:<28} {:>9} {:>7} {:>8} {:>8} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "event",
+        "events",
+        "errors",
+        "rejected",
+        "per s",
+        "p50 ms",
+        "p95 ms",
+        "p99 ms",
+        "p999 ms",
+        "max ms"
+    );
+    for row in rows.iter() {
+        info!(
+            "{:<28} {:>9} {:>7} {:>8} {:>8.1} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            row.event,
+            row.events,
+            row.errors,
+            row.rejected,
+            row.throughput_per_s,
+            format_ms(row.p50_ms),
+            format_ms(row.p95_ms),
+            format_ms(row.p99_ms),
+            format_ms(row.p999_ms),
+            format_ms(row.max_ms)
+        );
+    }
+
+    // The series is too long to log, only how each event went from its first bucket to its
+    // last one is.
+    for row in rows.iter() {
+        let mut buckets = series.iter().filter(|bucket| bucket.event == row.event);
+        if let (Some(first), Some(last)) = (buckets.next(), buckets.next_back()) {
+            info!(
+                "{} p95 went from {:.1} ms at {}s to {:.1} ms at {}s",
+                row.event, first.p95_ms, first.from_start_s, last.p95_ms, last.from_start_s
+            );
+        }
+    }
+
+    if let Some(json_path) = &properties.json {
+        info!("Now saving the report as '{}'", json_path.display());
+        let output = std::fs::File::create(json_path)
+            .map_err(|io_err| Error::file("create the report", json_path, io_err))?;
+        let report = JsonReport {
+            run_id,
+            schema_version: schema::SCHEMA_VERSION,
+            measured_s: measured
+                .saturating_sub(Duration::from_secs(properties.skip_first_secs))
+                .as_secs_f64(),
+            events: rows,
+            series,
+        };
+        serde_json::to_writer_pretty(output, &report).map_err(|json_err| {
+            Error::json(
+                format!("write the report '{}'", json_path.display()),
+                json_err,
+            )
+        })?;
+    }
+
+    if let Some(csv_path) = &properties.csv {
+        info!("Now saving the report as '{}'", csv_path.display());
+        let operation = format!("write the report '{}'", csv_path.display());
+        let mut wrt = Writer::from_path(csv_path)
+            .map_err(|csv_err| Error::csv(operation.clone(), csv_err))?;
+        for row in rows.iter() {
+            wrt.serialize(row)
+                .map_err(|csv_err| Error::csv(operation.clone(), csv_err))?;
+        }
+    }
+
+    if let Some(series_csv_path) = &properties.series_csv {
+        info!(
+            "Now saving the report series as '{}'",
+            series_csv_path.display()
+        );
+        let operation = format!("write the report series '{}'", series_csv_path.display());
+        let mut wrt = Writer::from_path(series_csv_path)
+            .map_err(|csv_err| Error::csv(operation.clone(), csv_err))?;
+        for row in series.iter() {
+            wrt.serialize(row)
+                .map_err(|csv_err| Error::csv(operation.clone(), csv_err))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::EventReport;
+    use crate::profile::ReportProperties;
+    use crate::run::{EventDetail, Failure};
+    use std::time::Duration;
+
+    #[test]
+    fn test_report_counts_failures_against_the_attempted_event() {
+        let mut report = EventReport::new(&ReportProperties {
+            skip_first_secs: 10,
+            bucket_secs: 30,
+            ..Default::default()
+        });
+
+        let ms = Duration::from_millis;
+        // Left out, it started before the measured window settled.
+        report.push(&EventDetail::Login, ms(5_000), ms(900));
+        for latency_ms in 1..=100 {
+            report.push(&EventDetail::Login, ms(20_000), ms(latency_ms));
+        }
+        report.push(
+            &EventDetail::Error(Failure::new(EventDetail::Login, "Http", "")),
+            ms(20_000),
+            ms(1),
+        );
+        report.push(
+            &EventDetail::Rejected(Failure::new(EventDetail::Login, "Http", "")),
+            ms(20_000),
+            ms(1),
+        );
+        report.push(
+            &EventDetail::Timeout(Failure::new(EventDetail::Login, "Timeout", "")),
+            ms(20_000),
+            ms(30_000),
+        );
+        report.push(&EventDetail::LoginConflict, ms(20_000), ms(1));
+        report.push(&EventDetail::ScheduleLag, ms(20_000), ms(1));
+
+        // Later on, the logins slow down.
+        for _ in 0..10 {
+            report.push(&EventDetail::Login, ms(50_000), ms(500));
+        }
+
+        let rows = report.rows(Duration::from_secs(60));
+        assert_eq!(rows.len(), 1);
+        let login = &rows[0];
+        assert_eq!((login.event_id, login.event.as_str()), ("login", "Login"));
+        assert_eq!(login.events, 110);
+        assert_eq!(login.errors, 3);
+        assert_eq!(login.rejected, 1);
+        assert_eq!(login.throughput_per_s, 2.2);
+        // The histogram is only exact to three significant figures.
+        let close_to = |ms: Option<f64>, expected: f64| {
+            ms.is_some_and(|ms| (ms - expected).abs() <= expected / 1000.)
+        };
+        assert!(close_to(login.p50_ms, 55.));
+        assert!(close_to(login.max_ms, 500.));
+
+        let series = report.series();
+        assert_eq!(series.len(), 2);
+        assert_eq!((series[0].from_start_s, series[0].events), (0, 100));
+        assert!(close_to(Some(series[0].p99_ms), 99.));
+        assert_eq!((series[1].from_start_s, series[1].events), (30, 10));
+        assert!(close_to(Some(series[1].p50_ms), 500.));
+    }
+}
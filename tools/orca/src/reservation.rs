@@ -0,0 +1,155 @@
+// Reserve the server for a run with a marker group on it, so that a second run started
+// against the same server is refused, rather than doubling the load and invalidating the
+// results of both. Being an entry, the marker is seen by every replica of the server and
+// every host orca runs on. A run that was killed leaves its marker behind, which `--force`
+// takes over.
+use crate::error::Error;
+use crate::kani::KanidmOrcaClient;
+use crate::profile::Profile;
+
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+
+use std::future::Future;
+
+const MARKER_GROUP: &str = "orca_run_reservation";
+
+// What the description of the marker group holds.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Marker {
+    holder: String,
+    since: String,
+    // The start of the measured window of a scheduled run, whose instances on other hosts
+    // share the reservation.
+    #[serde(default)]
+    window: Option<String>,
+}
+
+impl Marker {
+    fn new(window: Option<DateTime<FixedOffset>>) -> Self {
+        let user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "someone".to_string());
+        let host = std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| "an unknown host".to_string());
+        Marker {
+            holder: format!("{user}@{host} (pid {})", std::process::id()),
+            since: Local::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            window: window.map(|window| window.to_rfc3339()),
+        }
+    }
+
+    fn shares_with(&self, other: &Marker) -> bool {
+        self.window.is_some() && self.window == other.window
+    }
+
+    // Who holds the reservation, for the message of a run that is refused. A marker that
+    // wasn't written by orca is shown as it is.
+    fn describe(description: &str) -> String {
+        match serde_json::from_str::<Marker>(description) {
+            Ok(marker) => format!("{} since {}", marker.holder, marker.since),
+            Err(_) => format!("'{description}'"),
+        }
+    }
+}
+
+pub struct Reservation {
+    client: KanidmOrcaClient,
+    // Only the run that placed the marker removes it.
+    owned: bool,
+}
+
+impl Reservation {
+    /// Reserve the server of the profile, or fail if another run holds it. With `force`, the
+    /// reservation is taken over from the other run. The instances of a run scheduled to
+    /// start at `window` share the reservation.
+    pub async fn take(
+        profile: &Profile,
+        window: Option<DateTime<FixedOffset>>,
+        force: bool,
+    ) -> Result<Self, Error> {
+        let client = KanidmOrcaClient::new(profile).await?;
+        let marker = Marker::new(window);
+        let description = serde_json::to_string(&marker)
+            .map_err(|json_err| Error::json("write the reservation of the server", json_err))?;
+
+        if let Some(held) = client.group_description(MARKER_GROUP).await? {
+            let holder = Marker::describe(&held);
+            match serde_json::from_str::<Marker>(&held) {
+                Ok(held) if held.shares_with(&marker) => {
+                    info!("Sharing the reservation of the server with {holder}");
+                    return Ok(Reservation {
+                        client,
+                        owned: false,
+                    });
+                }
+                _ if force => {
+                    warn!("Taking the reservation of the server over from {holder}");
+                    client.group_delete(MARKER_GROUP).await?;
+                }
+                _ => return Err(Error::Reserved { holder }),
+            }
+        }
+
+        if let Err(err) = client
+            .group_create_with_description(MARKER_GROUP, &description)
+            .await
+        {
+            // Another run may have reserved the server since it was checked.
+            return match client.group_description(MARKER_GROUP).await {
+                Ok(Some(held)) => Err(Error::Reserved {
+                    holder: Marker::describe(&held),
+                }),
+                _ => Err(err),
+            };
+        }
+        info!("Reserved the server for {}", marker.holder);
+
+        Ok(Reservation {
+            client,
+            owned: true,
+        })
+    }
+
+    /// Perform `run`, and release the server once it's done, whatever the outcome.
+    pub async fn hold<T>(self, run: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        let result = run.await;
+        self.release().await;
+        result
+    }
+
+    async fn release(self) {
+        if !self.owned {
+            return;
+        }
+        match self.client.group_delete(MARKER_GROUP).await {
+            Ok(()) => info!("Released the server"),
+            Err(err) => error!(
+                "{}, the next run against the server needs --force",
+                err.report()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Marker;
+
+    #[test]
+    fn test_only_scheduled_runs_share_the_reservation() {
+        let window = "2024-05-01T12:00:00Z".parse().ok();
+        let scheduled = Marker::new(window);
+        assert!(scheduled.shares_with(&Marker::new(window)));
+        assert!(!scheduled.shares_with(&Marker::new("2024-05-01T13:00:00Z".parse().ok())));
+        assert!(!Marker::new(None).shares_with(&Marker::new(None)));
+
+        let description = serde_json::to_string(&scheduled).unwrap_or_default();
+        assert!(Marker::describe(&description).starts_with(&scheduled.holder));
+        assert_eq!(Marker::describe("held by hand"), "'held by hand'");
+    }
+}
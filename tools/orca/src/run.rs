@@ -1,47 +1,400 @@
+use crate::assertions;
+use crate::bulk_create::{self, BulkCreateOptions};
+use crate::clock;
+use crate::credential_provider::{self, CredentialProvider};
 use crate::error::Error;
+use crate::kani::KanidmOrcaClient;
+use crate::load_schedule::LoadSchedule;
+use crate::metrics::{self, LiveMetrics};
+use crate::model::{
+    self, ActorContext, EnrolledCredential, RegisteredCredential, TransitionAction,
+    TransitionResult,
+};
+use crate::models::sync_agent::ActorSyncAgent;
+use crate::person_selection;
+use crate::profile::{
+    BackoffProperties, DataSizeSamplingProperties, RetryProperties, ScriptedProperties,
+    SessionLifetime,
+};
+use crate::slow_link;
 use crate::state::*;
-use crate::stats::{BasicStatistics, TestPhase};
+use crate::stats::{BasicStatistics, DataCollector, RunSummary, TestPhase};
 
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, OnceLock};
 
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 
 use crossbeam::queue::{ArrayQueue, SegQueue};
 
-use kanidm_client::{KanidmClient, KanidmClientBuilder};
+use kanidm_client::{ClientError, KanidmClient, KanidmClientBuilder};
+use reqwest::header::HeaderMap;
 
-use serde::Serialize;
-use tokio::sync::broadcast;
+use futures_util::TryFutureExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
+use tracing::Instrument;
 
 use std::time::{Duration, Instant};
 
+/// The settings that every actor of a cohort shares, built once as the cohort starts.
+struct ActorOptions {
+    cohort: usize,
+    stats_queue: Arc<SegQueue<CohortEventRecord>>,
+    rng_seed: u64,
+    warmup_time: Duration,
+    action_timeout: Option<Duration>,
+    session_refresh_interval: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    session_lifetime: SessionLifetime,
+    credential_provider: Arc<dyn CredentialProvider>,
+    registration_client: Option<Arc<KanidmOrcaClient>>,
+    metrics: Option<Arc<LiveMetrics>>,
+    load_schedule: Option<Arc<LoadSchedule>>,
+    // Shared by the actors of the cohort, if it limits how many actions are in flight.
+    cohort_permits: Option<Arc<Semaphore>>,
+    backoff: BackoffProperties,
+    retry: RetryProperties,
+    scripted: ScriptedProperties,
+    headers: HeaderMap,
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn actor_person(
     main_client: KanidmClient,
-    person: Person,
-    stats_queue: Arc<SegQueue<EventRecord>>,
-    mut actor_rx: broadcast::Receiver<Signal>,
-    rng_seed: u64,
+    target: Arc<str>,
     additional_clients: Vec<KanidmClient>,
-    warmup_time: Duration,
+    person: Arc<Person>,
+    groups: Arc<[String]>,
+    actor_index: usize,
+    logout_at_end: bool,
+    mut actor_rx: broadcast::Receiver<Signal>,
+    options: Arc<ActorOptions>,
 ) -> Result<(), Error> {
-    let mut model =
-        person
-            .model
-            .as_dyn_object(rng_seed, additional_clients, &person.username, warmup_time)?;
+    // The password may not be part of the state, and is needed to register a TOTP as well.
+    credential_provider::resolve(options.credential_provider.as_ref(), &person).await?;
+
+    if let Some(registration_client) = &options.registration_client {
+        if person.credential.needs_registration() {
+            // The actors that run as the same person share the registration.
+            person
+                .registered_credential
+                .get_or_try_init(|| register_credential(registration_client, &person))
+                .await?;
+        }
+    }
+
+    let mut model = person.model.as_dyn_object(
+        options.rng_seed,
+        additional_clients,
+        &person.username,
+        options.warmup_time,
+        clock::system(),
+        &options.scripted,
+        &options.headers,
+    )?;
+
+    // Lives for as long as the actor does, so the model can carry state between transitions.
+    let mut context = ActorContext {
+        reuse_session: options.session_lifetime == SessionLifetime::Reuse,
+        retry: options.retry.clone(),
+        ..Default::default()
+    };
+
+    let load_schedule = options.load_schedule.as_deref();
+    let username: Arc<str> = Arc::from(person.username.as_str());
+    let push_events = |events: Vec<EventRecord>| {
+        for record in events.into_iter() {
+            if let Some(metrics) = &options.metrics {
+                metrics.record(&record);
+            }
+            options.stats_queue.push(CohortEventRecord {
+                cohort: options.cohort,
+                groups: groups.clone(),
+                credential: Some(person.credential.kind()),
+                person: Some(username.clone()),
+                phase: load_schedule
+                    .and_then(|load_schedule| load_schedule.phase_name(record.start)),
+                target: Some(target.clone()),
+                record,
+            });
+        }
+    };
+
+    // Counted as running until it returns, whichever way it does.
+    let _running = options
+        .metrics
+        .as_ref()
+        .map(|metrics| metrics.actor_running());
+
+    push_events(model.on_start(&main_client, &person, &mut context).await?);
+
+    // Stagger the refreshes and keepalives by person, so that the actors don't all send
+    // them at once. The seed is the same for every actor, so it can't be used for this.
+    let staggered = |interval: Duration| {
+        let mut hasher = DefaultHasher::new();
+        person.username.hash(&mut hasher);
+        let offset = hasher.finish() % (interval.as_millis() as u64).max(1);
+        Instant::now() + Duration::from_millis(offset)
+    };
+    let session_refresh_interval = options.session_refresh_interval;
+    let keepalive_interval = options.keepalive_interval;
+    let mut next_refresh = session_refresh_interval.map(staggered);
+    let mut next_keepalive = keepalive_interval.map(staggered);
+    let mut password_expires_at = person
+        .password_expiry
+        .as_ref()
+        .map(|expiry| Instant::now() + options.warmup_time + Duration::from_secs(expiry.delay));
+
+    // When the server last asked the actor to back off, and until when.
+    let mut backoff_hint: Option<(Instant, Instant)> = None;
 
     while let Err(broadcast::error::TryRecvError::Empty) = actor_rx.try_recv() {
-        let events = model.transition(&main_client, &person).await?;
+        // The actor is paused for as long as the schedule doesn't need it.
+        if let Some(load_schedule) = load_schedule {
+            if !load_schedule
+                .wait_until_active(actor_index, &mut actor_rx)
+                .await
+            {
+                break;
+            }
+        }
+
+        let delay = model.next_delay(&person);
+        let action = model.pending_action();
+        let intended = if let Some(delay) = delay {
+            let intended = Instant::now() + delay;
+            // The keepalives go on while the actor waits, since that's when they'd be sent.
+            while let (Some(keepalive_at), Some(interval)) = (next_keepalive, keepalive_interval) {
+                if keepalive_at >= intended {
+                    break;
+                }
+                tokio::time::sleep_until(keepalive_at.into()).await;
+                push_events(keepalive(&main_client, &mut context).await?);
+                // A keepalive that is late doesn't make the ones after it bunch up.
+                next_keepalive = Some(Instant::now().max(keepalive_at) + interval);
+            }
+            tokio::time::sleep_until(intended.into()).await;
+            Some(intended)
+        } else {
+            None
+        };
+
+        // If orca can't keep up with the load it generates, actions start late.
+        if let Some(intended) = intended {
+            push_events(vec![EventRecord {
+                start: intended,
+                duration: Instant::now().saturating_duration_since(intended),
+                details: EventDetail::ScheduleLag,
+            }]);
+        }
+
+        // Held until the action is over, so that the cohort and the phase don't have more
+        // actions in flight than they allow.
+        let ready = Instant::now();
+        let permits = acquire_permits(options.cohort_permits.as_ref(), load_schedule).await?;
+        if !permits.is_empty() {
+            push_events(vec![EventRecord {
+                start: ready,
+                duration: Instant::now().duration_since(ready),
+                details: EventDetail::PermitWait,
+            }]);
+        }
+
+        let start = Instant::now();
+        if let Some((hinted_at, until)) = backoff_hint.take() {
+            push_events(vec![if start >= until {
+                EventRecord {
+                    start: hinted_at,
+                    duration: start.duration_since(hinted_at),
+                    details: EventDetail::BackoffRespected,
+                }
+            } else {
+                EventRecord {
+                    start,
+                    duration: until.duration_since(start),
+                    details: EventDetail::BackoffViolated,
+                }
+            }]);
+        }
+        if let Some(action) = action {
+            push_events(vec![EventRecord {
+                start,
+                duration: delay.unwrap_or_default(),
+                details: EventDetail::Transition(action),
+            }]);
+        }
+        let transition = model.transition(&main_client, &person, &mut context);
+        let events = match options.action_timeout {
+            Some(action_timeout) => match tokio::time::timeout(action_timeout, transition).await {
+                Ok(events) => events?,
+                Err(_) => {
+                    debug!("Action of person {} timed out", person.username);
+                    let action = action.ok_or(Error::NoPendingAction)?;
+                    vec![EventRecord {
+                        start,
+                        duration: Instant::now().duration_since(start),
+                        details: EventDetail::Timeout(Failure::new(
+                            action.event(&person),
+                            "Timeout",
+                            format!("{action:?} took longer than {action_timeout:?}"),
+                        )),
+                    }]
+                }
+            },
+            None => transition.await?,
+        };
         debug!("Pushed event to queue!");
-        for event in events.into_iter() {
-            stats_queue.push(event);
+        let authentication_needed = events.iter().any(EventRecord::needs_authentication);
+        let backoff_hinted = events.iter().any(EventRecord::is_backoff_hint);
+        push_events(events);
+        drop(permits);
+
+        if backoff_hinted {
+            let hinted_at = Instant::now();
+            let until = hinted_at + Duration::from_millis(options.backoff.retry_after_ms);
+            backoff_hint = Some((hinted_at, until));
+            if options.backoff.honour {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(until.into()) => {}
+                    _ = actor_rx.recv() => break,
+                }
+            }
         }
+
+        // The model stays where it was, and tries the action again on the new session.
+        if authentication_needed {
+            debug!("Session of person {} was rejected", person.username);
+            context.has_session = false;
+            push_events(
+                model
+                    .reauthenticate(&main_client, &person, &mut context)
+                    .await?,
+            );
+        }
+
+        if let (Some(refresh_at), Some(interval)) = (next_refresh, session_refresh_interval) {
+            if Instant::now() >= refresh_at {
+                push_events(
+                    model
+                        .refresh_session(&main_client, &person, &mut context)
+                        .await?,
+                );
+                next_refresh = Some(refresh_at + interval);
+            }
+        }
+
+        if let (Some(keepalive_at), Some(interval)) = (next_keepalive, keepalive_interval) {
+            if Instant::now() >= keepalive_at {
+                push_events(keepalive(&main_client, &mut context).await?);
+                next_keepalive = Some(Instant::now().max(keepalive_at) + interval);
+            }
+        }
+
+        if password_expires_at.is_some_and(|expires_at| Instant::now() >= expires_at) {
+            let (rotated, events) = rotate_password(&main_client, &person).await?;
+            push_events(events);
+            if rotated {
+                password_expires_at = None;
+            }
+        }
+    }
+
+    push_events(model.on_stop(&main_client, &person, &mut context).await?);
+
+    // Otherwise the session is abandoned, whatever the model was in the middle of.
+    if logout_at_end && main_client.get_token().await.is_some() {
+        context.has_session = false;
+        let (_, events) = model::logout(&main_client, &person).await?;
+        push_events(events);
     }
 
     debug!("Stopped person {}", person.username);
     Ok(())
 }
 
+// The permits are taken in the same order by every actor, so that they can't deadlock. The
+// phase may have changed while the actor waited, in which case they're taken again under the
+// limit of the new one.
+async fn acquire_permits(
+    cohort_permits: Option<&Arc<Semaphore>>,
+    load_schedule: Option<&LoadSchedule>,
+) -> Result<Vec<OwnedSemaphorePermit>, Error> {
+    let phase_permits =
+        || load_schedule.and_then(|load_schedule| load_schedule.phase_permits(Instant::now()));
+    loop {
+        let phase_limit = phase_permits();
+        let mut permits = Vec::new();
+        for limit in cohort_permits.into_iter().chain(phase_limit.as_ref()) {
+            permits.push(limit.clone().acquire_owned().await.map_err(|tokio_err| {
+                Error::tokio("wait for room under the concurrency limit", tokio_err)
+            })?);
+        }
+
+        let same_phase = match (&phase_limit, phase_permits()) {
+            (Some(waited), Some(current)) => Arc::ptr_eq(waited, &current),
+            (waited, current) => waited.is_none() && current.is_none(),
+        };
+        if same_phase {
+            return Ok(permits);
+        }
+    }
+}
+
+// Only an actor that holds a session has one to keep alive. A session the server rejected
+// isn't checked again until the model logs in.
+async fn keepalive(
+    client: &KanidmClient,
+    context: &mut ActorContext,
+) -> Result<Vec<EventRecord>, Error> {
+    if !context.has_session {
+        return Ok(Vec::new());
+    }
+    let (_, events) = model::keepalive(client, context).await?;
+    if events.iter().any(EventRecord::needs_authentication) {
+        context.has_session = false;
+    }
+    Ok(events)
+}
+
+// The actors that run as the same person share the rotation, and only the first of them to
+// get to it rotates the password. A rotation that failed is tried again after the next action.
+async fn rotate_password(
+    client: &KanidmClient,
+    person: &Person,
+) -> Result<(bool, Vec<EventRecord>), Error> {
+    let Some(expiry) = &person.password_expiry else {
+        return Ok((true, Vec::new()));
+    };
+
+    let mut events = Vec::new();
+    let rotation = expiry
+        .done
+        .get_or_try_init(|| async {
+            match model::rotate_password(client, person).await {
+                Ok((TransitionResult::Ok, records)) => {
+                    events = records;
+                    Ok(())
+                }
+                Ok((_, records)) => {
+                    events = records;
+                    Err(None)
+                }
+                Err(err) => Err(Some(err)),
+            }
+        })
+        .await;
+    match rotation {
+        Ok(()) => Ok((true, events)),
+        Err(None) => Ok((false, events)),
+        Err(Some(err)) => Err(err),
+    }
+}
+
 #[derive(Debug)]
 pub struct EventRecord {
     pub start: Instant,
@@ -49,19 +402,357 @@ pub struct EventRecord {
     pub details: EventDetail,
 }
 
-#[derive(Debug, Serialize, Clone)]
+impl EventRecord {
+    fn needs_authentication(&self) -> bool {
+        matches!(&self.details, EventDetail::Error(failure) if failure.needs_authentication())
+    }
+
+    fn is_backoff_hint(&self) -> bool {
+        matches!(&self.details, EventDetail::Error(failure) if failure.is_backoff_hint())
+    }
+}
+
+/// An event, tagged with the cohort of the actor that produced it. Each state that is
+/// run concurrently in the same invocation is its own cohort.
+#[derive(Debug)]
+pub struct CohortEventRecord {
+    pub cohort: usize,
+    /// The test groups the actor is a member of.
+    pub groups: Arc<[String]>,
+    /// The type of credential of the actor, if the event comes from a person.
+    pub credential: Option<&'static str>,
+    /// The username of the actor, if the event comes from a person.
+    pub person: Option<Arc<str>>,
+    /// The phase of the load schedule the event started in, if the cohort has one.
+    pub phase: Option<Arc<str>>,
+    /// The URI of the server the actor sends its requests to, if the event comes from a
+    /// person or the sync agent.
+    pub target: Option<Arc<str>>,
+    pub record: EventRecord,
+}
+
+/// A named state to run, alongside any others in the same invocation.
+pub struct Cohort {
+    pub name: String,
+    pub state: State,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum EventDetail {
     Login,
+    LoginRejectedValidity,
+    AuthInit,
+    AuthBegin,
+    AuthCredential,
+    /// The TOTP of a login sent again with the code of the next time step, after the server
+    /// rejected it.
+    AuthTotpRetry,
     Logout,
+    SessionRefresh,
+    /// A check that the session is still valid, sent in the background.
+    Keepalive,
+    /// A login after the server rejected the session of the actor.
+    Reauthentication,
+    UnixAuth,
     PersonSetSelfMail,
     PersonGetSelfAccount,
+    PersonGetSelfAttribute,
+    PersonWhoami,
     PersonGetSelfMemberOf,
+    PersonGetGroup,
+    PersonGetGroupMembers,
     PersonSetSelfPassword,
+    /// The password of the person expired, and was rotated to a new one.
+    PersonRotatePassword,
     PersonReauth,
     PersonCreateGroup,
+    PersonRenameGroup,
+    PersonDeleteGroup,
     PersonAddGroupMembers,
+    PersonRemoveGroupMembers,
+    PersonCreatePerson,
+    PersonDeletePerson,
+    PersonSetAccountValidity,
+    PersonEnrollTotp,
+    PersonEnrollPasskey,
+    SyncRefresh,
+    SyncUpdate,
+    Oauth2Authorise,
+    Oauth2Token,
+    Oauth2Userinfo,
+    Status,
+    Oauth2Discovery,
+    Oauth2Jwks,
     GroupReplicationDelay,
-    Error,
+    Error(Box<Failure>),
+    /// The server refused the action under its access policy, as it's meant to for an actor
+    /// without the rights to it. This isn't counted as an error.
+    Rejected(Box<Failure>),
+    /// A login as a shared account failed while other actors were logging in as it.
+    LoginConflict,
+    /// The action didn't complete within the action timeout, and was abandoned. It's charged
+    /// to the event the action would have been recorded as.
+    Timeout(Box<Failure>),
+    /// How long after its intended time an action was ready to start.
+    ScheduleLag,
+    /// How long a ready action waited for room under the concurrency limit of its cohort or
+    /// phase.
+    PermitWait,
+    /// The next request of the actor came after the server asked it to back off, this long
+    /// after it asked.
+    BackoffRespected,
+    /// The next request of the actor came this long before the backoff the server asked for
+    /// was over.
+    BackoffViolated,
+    /// An action the model went through, after waiting for as long as the duration.
+    Transition(TransitionAction),
+    /// A bulk import of persons that ran alongside the actors.
+    Import,
+    /// A backup of the server that was taken while the actors ran.
+    Backup,
+    DataSize(Box<DataSizeSample>),
+    /// A request that the actor would have retried if it failed, from the start of its first
+    /// attempt to the end of its last.
+    Attempts(Box<AttemptsSample>),
+}
+
+/// The identifiers of every type of event, see `EventDetail::id`.
+pub const EVENT_IDS: [&str; 53] = [
+    "login",
+    "login_rejected_validity",
+    "auth_init",
+    "auth_begin",
+    "auth_credential",
+    "auth_totp_retry",
+    "logout",
+    "session_refresh",
+    "keepalive",
+    "reauthentication",
+    "unix_auth",
+    "person_set_self_mail",
+    "person_get_self_account",
+    "person_get_self_attribute",
+    "person_whoami",
+    "person_get_self_member_of",
+    "person_get_group",
+    "person_get_group_members",
+    "person_set_self_password",
+    "person_rotate_password",
+    "person_reauth",
+    "person_create_group",
+    "person_rename_group",
+    "person_delete_group",
+    "person_add_group_members",
+    "person_remove_group_members",
+    "person_create_person",
+    "person_delete_person",
+    "person_set_account_validity",
+    "person_enroll_totp",
+    "person_enroll_passkey",
+    "sync_refresh",
+    "sync_update",
+    "oauth2_authorise",
+    "oauth2_token",
+    "oauth2_userinfo",
+    "status",
+    "oauth2_discovery",
+    "oauth2_jwks",
+    "group_replication_delay",
+    "error",
+    "rejected",
+    "login_conflict",
+    "timeout",
+    "schedule_lag",
+    "backoff_respected",
+    "backoff_violated",
+    "transition",
+    "import",
+    "backup",
+    "data_size",
+    "attempts",
+    "permit_wait",
+];
+
+impl EventDetail {
+    /// The identifier the type of event is exported under, which is part of the results
+    /// schema. Unlike the name, it's never changed once released, and a new type of event
+    /// gets a new one, added to `EVENT_IDS`.
+    pub fn id(&self) -> &'static str {
+        match self {
+            EventDetail::Login => "login",
+            EventDetail::LoginRejectedValidity => "login_rejected_validity",
+            EventDetail::AuthInit => "auth_init",
+            EventDetail::AuthBegin => "auth_begin",
+            EventDetail::AuthCredential => "auth_credential",
+            EventDetail::AuthTotpRetry => "auth_totp_retry",
+            EventDetail::Logout => "logout",
+            EventDetail::SessionRefresh => "session_refresh",
+            EventDetail::Keepalive => "keepalive",
+            EventDetail::Reauthentication => "reauthentication",
+            EventDetail::UnixAuth => "unix_auth",
+            EventDetail::PersonSetSelfMail => "person_set_self_mail",
+            EventDetail::PersonGetSelfAccount => "person_get_self_account",
+            EventDetail::PersonGetSelfAttribute => "person_get_self_attribute",
+            EventDetail::PersonWhoami => "person_whoami",
+            EventDetail::PersonGetSelfMemberOf => "person_get_self_member_of",
+            EventDetail::PersonGetGroup => "person_get_group",
+            EventDetail::PersonGetGroupMembers => "person_get_group_members",
+            EventDetail::PersonSetSelfPassword => "person_set_self_password",
+            EventDetail::PersonRotatePassword => "person_rotate_password",
+            EventDetail::PersonReauth => "person_reauth",
+            EventDetail::PersonCreateGroup => "person_create_group",
+            EventDetail::PersonRenameGroup => "person_rename_group",
+            EventDetail::PersonDeleteGroup => "person_delete_group",
+            EventDetail::PersonAddGroupMembers => "person_add_group_members",
+            EventDetail::PersonRemoveGroupMembers => "person_remove_group_members",
+            EventDetail::PersonCreatePerson => "person_create_person",
+            EventDetail::PersonDeletePerson => "person_delete_person",
+            EventDetail::PersonSetAccountValidity => "person_set_account_validity",
+            EventDetail::PersonEnrollTotp => "person_enroll_totp",
+            EventDetail::PersonEnrollPasskey => "person_enroll_passkey",
+            EventDetail::SyncRefresh => "sync_refresh",
+            EventDetail::SyncUpdate => "sync_update",
+            EventDetail::Oauth2Authorise => "oauth2_authorise",
+            EventDetail::Oauth2Token => "oauth2_token",
+            EventDetail::Oauth2Userinfo => "oauth2_userinfo",
+            EventDetail::Status => "status",
+            EventDetail::Oauth2Discovery => "oauth2_discovery",
+            EventDetail::Oauth2Jwks => "oauth2_jwks",
+            EventDetail::GroupReplicationDelay => "group_replication_delay",
+            EventDetail::Error(_) => "error",
+            EventDetail::Rejected(_) => "rejected",
+            EventDetail::LoginConflict => "login_conflict",
+            EventDetail::Timeout(_) => "timeout",
+            EventDetail::ScheduleLag => "schedule_lag",
+            EventDetail::PermitWait => "permit_wait",
+            EventDetail::BackoffRespected => "backoff_respected",
+            EventDetail::BackoffViolated => "backoff_violated",
+            EventDetail::Transition(_) => "transition",
+            EventDetail::Import => "import",
+            EventDetail::Backup => "backup",
+            EventDetail::DataSize(_) => "data_size",
+            EventDetail::Attempts(_) => "attempts",
+        }
+    }
+
+    /// The name the event is recorded as in the raw data. The failures, the mix of actions
+    /// and the data size samples are reported on their own, so only their type is recorded.
+    pub fn name(&self) -> String {
+        match self {
+            EventDetail::Error(_) => "Error".to_string(),
+            EventDetail::Rejected(_) => "Rejected".to_string(),
+            EventDetail::Timeout(_) => "Timeout".to_string(),
+            EventDetail::Transition(_) => "Transition".to_string(),
+            EventDetail::DataSize(_) => "DataSize".to_string(),
+            EventDetail::Attempts(_) => "Attempts".to_string(),
+            details => format!("{details:?}"),
+        }
+    }
+}
+
+/// The attempts a request took, so that the latency of the first attempt can be told apart
+/// from the latency the retries made the request appear to have.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttemptsSample {
+    /// The event each attempt was recorded as.
+    pub event: EventDetail,
+    pub attempts: u32,
+    pub first_attempt: Duration,
+    /// Whether the last attempt succeeded, rather than the retries running out.
+    pub succeeded: bool,
+}
+
+/// How much data the server held at a point of the test. Each part is only known if it
+/// was asked for, and could be sampled.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DataSizeSample {
+    pub persons: Option<u64>,
+    pub groups: Option<u64>,
+    pub db_bytes: Option<u64>,
+    /// The sessions held by the sampled persons.
+    pub sessions: Option<u64>,
+}
+
+/// What went wrong with a failed action, so that the failures can be grouped at the end
+/// of the run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    /// The event the action would have been recorded as if it had succeeded.
+    pub event: EventDetail,
+    pub class: Cow<'static, str>,
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(event: EventDetail, class: &'static str, message: impl Into<String>) -> Box<Self> {
+        Box::new(Failure {
+            event,
+            class: Cow::Borrowed(class),
+            status: None,
+            message: message.into(),
+        })
+    }
+
+    /// Whether the server rejected the session rather than the action itself.
+    pub fn needs_authentication(&self) -> bool {
+        matches!(self.class.as_ref(), "Unauthorized" | "SessionExpired") || self.status == Some(401)
+    }
+
+    /// Whether the server asked the client to wait before its next request.
+    pub fn is_backoff_hint(&self) -> bool {
+        matches!(self.status, Some(429 | 503))
+    }
+
+    /// Whether the server refused the action because the actor isn't allowed to do it.
+    pub fn is_policy_rejection(&self) -> bool {
+        self.status == Some(403)
+    }
+
+    /// Whether the same request may well succeed if it's tried again.
+    pub fn is_transient(&self) -> bool {
+        self.class == "Transport" || matches!(self.status, Some(429 | 500..=599))
+    }
+
+    pub fn from_client_error(event: EventDetail, client_err: &ClientError) -> Box<Self> {
+        let (class, status, message) = match client_err {
+            ClientError::Unauthorized => ("Unauthorized", None, String::new()),
+            ClientError::SessionExpired => ("SessionExpired", None, String::new()),
+            // The operation id lets the failure be found in the server logs.
+            ClientError::Http(status, operation_err, opid) => (
+                "Http",
+                Some(status.as_u16()),
+                match operation_err {
+                    Some(operation_err) => format!("{operation_err:?} (opid {opid})"),
+                    None => format!("opid {opid}"),
+                },
+            ),
+            ClientError::Transport(transport_err) => ("Transport", None, transport_err.to_string()),
+            ClientError::AuthenticationFailed => ("AuthenticationFailed", None, String::new()),
+            ClientError::EmptyResponse => ("EmptyResponse", None, String::new()),
+            ClientError::TotpVerifyFailed(..) => ("TotpVerifyFailed", None, String::new()),
+            ClientError::TotpInvalidSha1(..) => ("TotpInvalidSha1", None, String::new()),
+            ClientError::JsonDecode(_, message) => ("JsonDecode", None, message.clone()),
+            ClientError::InvalidResponseFormat(message) => {
+                ("InvalidResponseFormat", None, message.clone())
+            }
+            ClientError::JsonEncode(json_err) => ("JsonEncode", None, json_err.to_string()),
+            ClientError::SystemError => ("SystemError", None, String::new()),
+            ClientError::ConfigParseIssue(message) => ("ConfigParseIssue", None, message.clone()),
+            ClientError::CertParseIssue(message) => ("CertParseIssue", None, message.clone()),
+            ClientError::UntrustedCertificate(message) => {
+                ("UntrustedCertificate", None, message.clone())
+            }
+            ClientError::InvalidRequest(message) => ("InvalidRequest", None, message.clone()),
+        };
+
+        Box::new(Failure {
+            event,
+            class: Cow::Borrowed(class),
+            status,
+            message,
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -69,12 +760,17 @@ pub enum Signal {
     Stop,
 }
 
-async fn execute_inner(
+/// Wait for the warmup and then the test time, and signal the statistics collector to start
+/// once the warmup is over. Returns when the measured window ended, which is for the caller
+/// to signal, once the collector has all the events of the window. A resumed run has
+/// `elapsed` of its test time behind it already, and its window is started that far back.
+pub(crate) async fn execute_inner(
     warmup: Duration,
     test_time: Option<Duration>,
+    elapsed: Duration,
     mut control_rx: broadcast::Receiver<Signal>,
     stat_ctrl: Arc<ArrayQueue<TestPhase>>,
-) -> Result<(), Error> {
+) -> Result<Instant, Error> {
     // Delay for warmup time.
     tokio::select! {
         _ = tokio::time::sleep(warmup) => {
@@ -89,17 +785,17 @@ async fn execute_inner(
     }
     info!("warmup time passed, statistics will now be collected ...");
 
-    let start = Instant::now();
-    if let Err(crossbeam_err) = stat_ctrl.push(TestPhase::Start(start)) {
-        error!(
-            ?crossbeam_err,
-            "Unable to signal statistics collector to start"
-        );
-        return Err(Error::Crossbeam);
+    let start = Instant::now().checked_sub(elapsed).ok_or_else(|| {
+        Error::invalid(format!(
+            "unable to start the measured window {elapsed:?} back"
+        ))
+    })?;
+    if stat_ctrl.push(TestPhase::Start(start)).is_err() {
+        return Err(Error::Crossbeam("start"));
     }
 
     if let Some(test_time) = test_time {
-        let sleep = tokio::time::sleep(test_time);
+        let sleep = tokio::time::sleep(test_time.saturating_sub(elapsed));
         tokio::pin!(sleep);
         let recv = (control_rx).recv();
         tokio::pin!(recv);
@@ -121,141 +817,767 @@ async fn execute_inner(
         let _ = control_rx.recv().await;
     }
 
-    let end = Instant::now();
-    if let Err(crossbeam_err) = stat_ctrl.push(TestPhase::End(end)) {
-        error!(
-            ?crossbeam_err,
-            "Unable to signal statistics collector to end"
-        );
-        return Err(Error::Crossbeam);
-    }
-
-    Ok(())
+    Ok(Instant::now())
 }
 
-pub async fn execute(state: State, control_rx: broadcast::Receiver<Signal>) -> Result<(), Error> {
-    // Create a statistics queue.
-    let stats_queue = Arc::new(SegQueue::new());
-    let stats_ctrl = Arc::new(ArrayQueue::new(4));
-
-    // Spawn the stats aggregator
-    let c_stats_queue = stats_queue.clone();
-    let c_stats_ctrl = stats_ctrl.clone();
-
-    let node_count = 1 + state.profile.extra_uris().len();
-    let mut dyn_data_collector =
-        BasicStatistics::new(state.persons.len(), state.groups.len(), node_count);
-
-    let dump_raw_data = state.profile.dump_raw_data();
+/// Signal the statistics collector that the measured window ended at `end`.
+pub(crate) fn end_statistics(stat_ctrl: &ArrayQueue<TestPhase>, end: Instant) -> Result<(), Error> {
+    stat_ctrl
+        .push(TestPhase::End(end))
+        .map_err(|_| Error::Crossbeam("end"))
+}
 
-    let stats_task = tokio::task::spawn_blocking(move || {
-        dyn_data_collector.run(c_stats_queue, c_stats_ctrl, dump_raw_data)
-    });
+type ActorTask = tokio::task::JoinHandle<Result<(), Error>>;
 
+/// Start the actors of one cohort. They wait for the warmup on their own.
+async fn start_cohort(
+    mut state: State,
+    cohort: usize,
+    stats_queue: Arc<SegQueue<CohortEventRecord>>,
+    actor_tx: &broadcast::Sender<Signal>,
+    metrics: Option<Arc<LiveMetrics>>,
+    measured_start: Instant,
+    background_tasks: bool,
+) -> Result<Vec<ActorTask>, Error> {
     // Create clients. Note, we actually seed these deterministically too, so that
     // or persons are spread over the clients that exist, in a way that is also
     // deterministic.
     let mut seeded_rng = ChaCha8Rng::seed_from_u64(state.profile.seed());
 
-    let clients = std::iter::once(state.profile.control_uri().to_string())
+    let mut uris = std::iter::once(state.profile.control_uri().to_string())
         .chain(state.profile.extra_uris().iter().cloned())
+        .collect::<Vec<_>>();
+    // The events are reported against the servers, not the proxies of a slow cohort.
+    let targets = uris
+        .iter()
+        .map(|uri| Arc::from(uri.as_str()))
+        .collect::<Vec<Arc<str>>>();
+
+    // A slow or distant cohort reaches the servers through proxies that throttle the
+    // responses, and hold the requests back.
+    let link = slow_link::Link {
+        read_bytes_per_second: state
+            .profile
+            .slow_client()
+            .map(|slow_client| slow_client.read_bytes_per_second),
+        base_latency: state
+            .profile
+            .latency_class()
+            .map(|latency_class| latency_class.base_latency())
+            .unwrap_or_default(),
+    };
+    if link.read_bytes_per_second.is_some() || !link.base_latency.is_zero() {
+        for uri in uris.iter_mut() {
+            *uri = slow_link::start(uri, link).await?;
+        }
+    }
+
+    let headers = state.profile.headers()?;
+    let clients = uris
+        .into_iter()
         .map(|uri| {
             KanidmClientBuilder::new()
                 .address(uri)
                 .danger_accept_invalid_hostnames(true)
                 .danger_accept_invalid_certs(true)
+                .default_headers(headers.clone())
                 .build()
-                .map_err(|err| {
-                    error!(?err, "Unable to create kanidm client");
-                    Error::KanidmClient
-                })
+                .map_err(|err| Error::client("create the kanidm client", err))
         })
         .collect::<Result<Vec<_>, _>>()?;
 
-    let (actor_tx, _actor_rx) = broadcast::channel(1);
+    // The server picks the secrets of the OAuth2 clients, so they can't be part of the state.
+    if state
+        .persons
+        .iter()
+        .any(|person| person.oauth2_login.is_some())
+    {
+        let orca_client = KanidmOrcaClient::new(&state.profile).await?;
+        let mut client_secrets = BTreeMap::new();
+        for person in state.persons.iter_mut() {
+            let Some(oauth2_login) = person.oauth2_login.as_mut() else {
+                continue;
+            };
+            if !client_secrets.contains_key(&oauth2_login.client) {
+                let client_secret = orca_client
+                    .oauth2_client_basic_secret(&oauth2_login.client)
+                    .await?;
+                client_secrets.insert(oauth2_login.client.clone(), client_secret);
+            }
+            oauth2_login.client_secret = client_secrets.get(&oauth2_login.client).cloned();
+        }
+    }
+
+    // Only needed if some persons have a second factor to register.
+    let registration_client = if state
+        .persons
+        .iter()
+        .any(|person| person.credential.needs_registration())
+    {
+        Some(Arc::new(KanidmOrcaClient::new(&state.profile).await?))
+    } else {
+        None
+    };
+
+    // The same persons are sampled every time, so that their sessions can be followed.
+    let session_sample = state
+        .profile
+        .data_size_sampling()
+        .and_then(|sampling| sampling.count_sessions_of)
+        .map(|count| {
+            state
+                .persons
+                .iter()
+                .take(count)
+                .map(|person| person.username.clone())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let load_schedule = (!state.profile.load_schedule().is_empty()).then(|| {
+        Arc::new(LoadSchedule::new(
+            state.profile.load_schedule(),
+            measured_start,
+        ))
+    });
+
+    let options = Arc::new(ActorOptions {
+        cohort,
+        stats_queue: stats_queue.clone(),
+        rng_seed: state.profile.seed(),
+        warmup_time: state.profile.warmup_time(),
+        action_timeout: state.profile.action_timeout(),
+        session_refresh_interval: state.profile.session_refresh_interval(),
+        keepalive_interval: state.profile.keepalive_interval(),
+        session_lifetime: state.profile.session_lifetime(),
+        credential_provider: credential_provider::from_properties(
+            state.profile.credential_provider(),
+            state.profile.seed(),
+        ),
+        registration_client,
+        metrics,
+        load_schedule,
+        cohort_permits: state
+            .profile
+            .max_concurrency()
+            .map(|max_concurrency| Arc::new(Semaphore::new(max_concurrency))),
+        backoff: state.profile.backoff().clone(),
+        retry: state.profile.retry().clone(),
+        scripted: state.profile.scripted().clone(),
+        headers,
+    });
+
+    // Decide which person each actor runs as. Several actors may share a person.
+    let person_selection = state.profile.person_selection();
+    let actor_count = person_selection.actors.unwrap_or(state.persons.len());
+    let mut selection_rng = ChaCha8Rng::seed_from_u64(state.profile.seed());
+    let selected = person_selection::from_properties(person_selection).select(
+        &state.persons,
+        &state.groups,
+        actor_count,
+        &mut selection_rng,
+    );
+    let persons = state.persons.into_iter().map(Arc::new).collect::<Vec<_>>();
 
     // Start the actors
-    let mut tasks = Vec::with_capacity(state.persons.len());
-    for person in state.persons.into_iter() {
+    let mut tasks = Vec::with_capacity(selected.len());
+    for (actor_index, person_index) in selected.into_iter().enumerate() {
+        let person = persons[person_index].clone();
         // this is not super efficient but we don't really care as we are not even inside the warmup time window, so we're not in a hurry
         let mut cloned_clients: Vec<KanidmClient> = clients
             .iter()
             .map(|client| {
-                client.new_session().map_err(|err| {
-                    error!(?err, "Unable to create a new kanidm client session");
-                    Error::KanidmClient
-                })
+                client
+                    .new_session()
+                    .map_err(|err| Error::client("create a new kanidm client session", err))
             })
             .collect::<Result<Vec<_>, _>>()?;
         let main_client_index = seeded_rng.gen_range(0..cloned_clients.len());
         let main_client = cloned_clients.remove(main_client_index);
+        let logout_at_end = state.profile.run_end().logs_out(&mut seeded_rng);
         //note that cloned_clients now contains all other clients except the first one
 
-        let c_stats_queue = stats_queue.clone();
+        let groups = state
+            .groups
+            .iter()
+            .filter(|group| group.members.contains(&person.username))
+            .map(|group| group.name.to_string())
+            .collect();
+
+        // The span lets the events of chosen actors be filtered by their username.
+        let span = info_span!("actor", username = person.username.as_str());
+        let username = person.username.clone();
+        tasks.push(tokio::spawn(
+            actor_person(
+                main_client,
+                targets[main_client_index].clone(),
+                cloned_clients,
+                person,
+                groups,
+                actor_index,
+                logout_at_end,
+                actor_tx.subscribe(),
+                options.clone(),
+            )
+            .map_err(|err| Error::actor(username, err))
+            .instrument(span),
+        ))
+    }
+
+    // The sync agent runs alongside the persons, using the token of its sync account.
+    if let Some(sync_account) = state.sync_account {
+        let orca_client = KanidmOrcaClient::new(&state.profile).await?;
+        let token = orca_client
+            .sync_account_generate_token(&sync_account.name)
+            .await?;
 
-        let c_actor_rx = actor_tx.subscribe();
+        let sync_client = clients[0]
+            .new_session()
+            .map_err(|err| Error::client("create a new kanidm client session", err))?;
+        sync_client.set_token(token).await;
 
-        tasks.push(tokio::spawn(actor_person(
-            main_client,
-            person,
-            c_stats_queue,
-            c_actor_rx,
-            state.profile.seed(),
-            cloned_clients,
+        let sync_agent = ActorSyncAgent::new(
+            sync_client,
+            sync_account,
+            state.profile.sync_agent(),
+            ChaCha8Rng::seed_from_u64(state.profile.seed()),
+        );
+
+        tasks.push(tokio::spawn(sync_agent.run(
+            state.profile.warmup_time(),
+            cohort,
+            targets[0].clone(),
+            stats_queue.clone(),
+            actor_tx.subscribe(),
+        )));
+    }
+
+    // When the actors are spread over several orca instances, only one of them runs the
+    // tasks that act on the server as a whole.
+    if !background_tasks {
+        return Ok(tasks);
+    }
+
+    if let Some(data_size_sampling) = state.profile.data_size_sampling() {
+        tasks.push(tokio::spawn(sample_data_size(
+            KanidmOrcaClient::new(&state.profile).await?,
+            data_size_sampling.clone(),
+            session_sample,
             state.profile.warmup_time(),
-        )))
+            cohort,
+            stats_queue.clone(),
+            actor_tx.subscribe(),
+        )));
+    }
+
+    for backup in state.profile.backups().iter() {
+        tasks.push(tokio::spawn(run_backup_hook(
+            backup.command.clone(),
+            state.profile.warmup_time() + Duration::from_secs(backup.at),
+            cohort,
+            stats_queue.clone(),
+            actor_tx.subscribe(),
+        )));
+    }
+
+    // The import runs alongside the persons, once they have had some time to settle.
+    if let Some(import) = state.profile.import() {
+        let orca_client = Arc::new(KanidmOrcaClient::new(&state.profile).await?);
+        let options = BulkCreateOptions {
+            person_count: import.person_count,
+            concurrency: import.concurrency,
+            batch_size: import.batch_size,
+        };
+        let delay = state.profile.warmup_time() + Duration::from_secs(import.delay);
+
+        tasks.push(tokio::spawn(import_persons(
+            orca_client,
+            options,
+            delay,
+            cohort,
+            stats_queue,
+            actor_tx.subscribe(),
+        )));
+    }
+
+    Ok(tasks)
+}
+
+/// Register the second factor of the person for this run. This can't be done when the state
+/// is generated, since the server picks the TOTP secrets and a soft passkey only lives in
+/// memory. It's done as the actor starts, so it's part of the warmup.
+async fn register_credential(
+    client: &KanidmOrcaClient,
+    person: &Person,
+) -> Result<RegisteredCredential, Error> {
+    let enrolled = match &person.credential {
+        Credential::Password { .. } => {
+            return Err(Error::invalid(format!(
+                "the person '{}' has no second factor to register",
+                person.username
+            )));
+        }
+        Credential::PasswordTotp { clock_skew, .. } => EnrolledCredential::PasswordTotp {
+            secret: client
+                .person_register_password_totp(
+                    &person.username,
+                    person.password().unwrap_or_default(),
+                )
+                .await?,
+            clock_skew: *clock_skew,
+        },
+        Credential::Passkey(properties) => EnrolledCredential::Passkey(Box::new(
+            client
+                .person_register_passkey(&person.username, *properties)
+                .await?,
+        )),
+    };
+    Ok(RegisteredCredential::new(enrolled))
+}
+
+/// Sample how much data the server holds on an interval from the end of the warmup, so
+/// that drifting performance can be correlated with data growth.
+async fn sample_data_size(
+    client: KanidmOrcaClient,
+    properties: DataSizeSamplingProperties,
+    session_sample: Vec<String>,
+    warmup: Duration,
+    cohort: usize,
+    stats_queue: Arc<SegQueue<CohortEventRecord>>,
+    mut actor_rx: broadcast::Receiver<Signal>,
+) -> Result<(), Error> {
+    let mut delay = warmup;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = actor_rx.recv() => return Ok(()),
+        }
+        delay = Duration::from_secs(properties.interval);
+
+        let start = Instant::now();
+        let mut sample = DataSizeSample::default();
+        // A failed sample leaves a gap, rather than stopping the test.
+        if properties.count_entries {
+            match client.entry_counts().await {
+                Ok((persons, groups)) => {
+                    sample.persons = Some(persons);
+                    sample.groups = Some(groups);
+                }
+                Err(err) => error!("{}", err.report()),
+            }
+        }
+        if let Some(command) = &properties.db_size_command {
+            sample.db_bytes = db_size(command).await;
+        }
+        if !session_sample.is_empty() {
+            let mut sessions = Some(0);
+            for username in session_sample.iter() {
+                let count = client
+                    .person_session_count(username)
+                    .await
+                    .inspect_err(|err| error!("{}", err.report()))
+                    .ok();
+                sessions = sessions
+                    .zip(count)
+                    .map(|(sessions, count)| sessions + count);
+            }
+            sample.sessions = sessions;
+        }
+
+        stats_queue.push(CohortEventRecord {
+            cohort,
+            groups: Arc::from([]),
+            credential: None,
+            person: None,
+            phase: None,
+            target: None,
+            record: EventRecord {
+                start,
+                duration: Instant::now().duration_since(start),
+                details: EventDetail::DataSize(Box::new(sample)),
+            },
+        });
+    }
+}
+
+// Runs the command, and takes the first word of its output as the size in bytes, so that
+// the output of commands like `du -b` can be used as is.
+async fn db_size(command: &[String]) -> Option<u64> {
+    let (program, args) = command.split_first()?;
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|io_err| {
+            error!(?io_err, ?command, "Unable to run the database size command");
+        })
+        .ok()?;
+
+    if !output.status.success() {
+        warn!(?command, status = %output.status, "The database size command failed");
+        return None;
+    }
+
+    let db_bytes = String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .and_then(|word| word.parse().ok());
+    if db_bytes.is_none() {
+        warn!(?command, "The database size command didn't print a size");
+    }
+    db_bytes
+}
+
+/// Run a backup hook after a delay, recording how long it took as a backup event. A backup
+/// that has started is always waited for, so that the server isn't left mid-backup.
+async fn run_backup_hook(
+    command: Vec<String>,
+    delay: Duration,
+    cohort: usize,
+    stats_queue: Arc<SegQueue<CohortEventRecord>>,
+    mut actor_rx: broadcast::Receiver<Signal>,
+) -> Result<(), Error> {
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => {}
+        _ = actor_rx.recv() => return Ok(()),
     }
 
-    let warmup = state.profile.warmup_time();
-    let test_time = state.profile.test_time();
+    let Some((program, args)) = command.split_first() else {
+        return Err(Error::profile("the backup hook has no command"));
+    };
+
+    info!(?command, "Starting a backup");
+    let start = Instant::now();
+    let status = tokio::process::Command::new(program)
+        .args(args)
+        .status()
+        .await;
+    let duration = Instant::now().duration_since(start);
+
+    let details = match status {
+        Ok(status) if status.success() => EventDetail::Backup,
+        Ok(status) => {
+            warn!(?command, %status, "The backup hook failed");
+            EventDetail::Error(Failure::new(
+                EventDetail::Backup,
+                "Hook",
+                format!("exited with {status}"),
+            ))
+        }
+        Err(io_err) => {
+            error!(?io_err, ?command, "Unable to run the backup hook");
+            EventDetail::Error(Failure::new(
+                EventDetail::Backup,
+                "Hook",
+                io_err.to_string(),
+            ))
+        }
+    };
+    stats_queue.push(CohortEventRecord {
+        cohort,
+        groups: Arc::from([]),
+        credential: None,
+        person: None,
+        phase: None,
+        target: None,
+        record: EventRecord {
+            start,
+            duration,
+            details,
+        },
+    });
+
+    Ok(())
+}
+
+/// Import persons in bulk after a delay, recording the time it took as an import event.
+/// The import is abandoned if the test ends first.
+async fn import_persons(
+    client: Arc<KanidmOrcaClient>,
+    options: BulkCreateOptions,
+    delay: Duration,
+    cohort: usize,
+    stats_queue: Arc<SegQueue<CohortEventRecord>>,
+    mut actor_rx: broadcast::Receiver<Signal>,
+) -> Result<(), Error> {
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => {}
+        _ = actor_rx.recv() => return Ok(()),
+    }
+
+    let start = Instant::now();
+    let result = tokio::select! {
+        result = bulk_create::execute(client, &options) => result?,
+        _ = actor_rx.recv() => {
+            warn!("The test ended before the import completed");
+            return Ok(());
+        }
+    };
+
+    info!(
+        "Imported {} persons at {:.1} persons/sec",
+        result.created(),
+        result.persons_per_second()
+    );
+    stats_queue.push(CohortEventRecord {
+        cohort,
+        groups: Arc::from([]),
+        credential: None,
+        person: None,
+        phase: None,
+        target: None,
+        record: EventRecord {
+            start,
+            duration: Instant::now().duration_since(start),
+            details: EventDetail::Import,
+        },
+    });
+
+    Ok(())
+}
+
+/// Run the test, and keep its headline numbers in `summary` once the statistics have been
+/// processed. They are kept even if the run then misses one of its latency goals.
+pub async fn execute_with_summary(
+    cohorts: Vec<Cohort>,
+    control_rx: broadcast::Receiver<Signal>,
+    summary: Arc<OnceLock<RunSummary>>,
+) -> Result<(), Error> {
+    let data_collector = statistics(&cohorts, summary);
+    execute_with_collector(cohorts, control_rx, data_collector, true, Duration::ZERO).await
+}
+
+/// The statistics collector of a run, set up from its cohorts.
+pub(crate) fn statistics(
+    cohorts: &[Cohort],
+    summary: Arc<OnceLock<RunSummary>>,
+) -> Box<dyn DataCollector + Send> {
+    let node_count = cohorts
+        .iter()
+        .flat_map(|cohort| {
+            std::iter::once(cohort.state.profile.control_uri())
+                .chain(cohort.state.profile.extra_uris().iter().map(String::as_str))
+        })
+        .collect::<BTreeSet<_>>()
+        .len();
+    let person_count = cohorts
+        .iter()
+        .map(|cohort| cohort.state.persons.len())
+        .sum();
+    let group_count = cohorts.iter().map(|cohort| cohort.state.groups.len()).sum();
+    let cohort_names = cohorts
+        .iter()
+        .map(|cohort| (cohort.name.clone(), *cohort.state.profile.model()))
+        .collect();
+    // The persons of a model may go through different actions, with their roles for example,
+    // so its mix is the average of theirs.
+    let mut configured_mix: BTreeMap<(String, TransitionAction), f64> = BTreeMap::new();
+    let mut model_persons: BTreeMap<String, usize> = BTreeMap::new();
+    for cohort in cohorts.iter() {
+        let scripted = cohort.state.profile.scripted();
+        for person in cohort.state.persons.iter() {
+            let model = format!("{:?}", person.model);
+            let mix = person.model.configured_mix(person, scripted);
+            let total = mix.iter().map(|(_, weight)| weight).sum::<u32>().max(1);
+            for (action, weight) in mix {
+                *configured_mix.entry((model.clone(), action)).or_default() +=
+                    weight as f64 / total as f64;
+            }
+            *model_persons.entry(model).or_default() += 1;
+        }
+    }
+    for ((model, _), share) in configured_mix.iter_mut() {
+        *share /= model_persons.get(model).copied().unwrap_or(1) as f64;
+    }
+    let latency_classes = cohorts
+        .iter()
+        .map(|cohort| {
+            cohort
+                .state
+                .profile
+                .latency_class()
+                .map(|latency_class| latency_class.name.clone())
+        })
+        .collect();
+    let stats_by_group = cohorts
+        .iter()
+        .any(|cohort| cohort.state.profile.stats_by_group());
+    // The thresholds, goals, maintenance windows, report and spool of the first state apply
+    // to the whole run.
+    let thresholds = cohorts
+        .first()
+        .map(|cohort| cohort.state.profile.thresholds().clone())
+        .unwrap_or_default();
+    let latency_goals = cohorts
+        .first()
+        .map(|cohort| cohort.state.profile.latency_goals().clone())
+        .unwrap_or_default();
+    let report = cohorts
+        .first()
+        .map(|cohort| cohort.state.profile.report().clone())
+        .unwrap_or_default();
+    let heartbeat_interval = cohorts
+        .first()
+        .and_then(|cohort| cohort.state.profile.heartbeat_interval());
+    let maintenance_windows = cohorts
+        .first()
+        .map(|cohort| cohort.state.profile.maintenance_windows().to_vec())
+        .unwrap_or_default();
+    let event_spool = cohorts
+        .first()
+        .and_then(|cohort| cohort.state.profile.event_spool().cloned());
+    BasicStatistics::new(
+        person_count,
+        group_count,
+        node_count,
+        cohort_names,
+        configured_mix,
+        latency_classes,
+        stats_by_group,
+        thresholds,
+        latency_goals,
+        maintenance_windows,
+        report,
+        heartbeat_interval,
+        event_spool,
+        summary,
+    )
+}
+
+/// Run the actors of the cohorts, and hand their events to the data collector. Only one of
+/// the orca instances that run a test together runs the `background_tasks`, such as
+/// imports and backups. A run resumed from a checkpoint carries on `elapsed` into its
+/// test time and load schedule.
+pub(crate) async fn execute_with_collector(
+    cohorts: Vec<Cohort>,
+    control_rx: broadcast::Receiver<Signal>,
+    mut dyn_data_collector: Box<dyn DataCollector + Send>,
+    background_tasks: bool,
+    elapsed: Duration,
+) -> Result<(), Error> {
+    // Create a statistics queue.
+    let stats_queue = Arc::new(SegQueue::new());
+    let stats_ctrl = Arc::new(ArrayQueue::new(4));
+
+    // Spawn the stats aggregator
+    let c_stats_queue = stats_queue.clone();
+    let c_stats_ctrl = stats_ctrl.clone();
+
+    let metrics_listen = cohorts
+        .first()
+        .and_then(|cohort| cohort.state.profile.metrics())
+        .map(|metrics| metrics.listen);
+
+    let dump_raw_data = cohorts
+        .iter()
+        .any(|cohort| cohort.state.profile.dump_raw_data());
+
+    // All the cohorts share the same test window, which has to fit the longest of them.
+    let warmup = cohorts
+        .iter()
+        .map(|cohort| cohort.state.profile.warmup_time())
+        .max()
+        .unwrap_or_default();
+    let test_time = cohorts
+        .iter()
+        .map(|cohort| cohort.state.profile.test_time())
+        .collect::<Option<Vec<_>>>()
+        .and_then(|test_times| test_times.into_iter().max());
+
+    let stats_task = tokio::task::spawn_blocking(move || {
+        dyn_data_collector.run(c_stats_queue, c_stats_ctrl, dump_raw_data)
+    });
+
+    let (actor_tx, _actor_rx) = broadcast::channel(1);
+
+    let live_metrics = match metrics_listen {
+        Some(listen) => {
+            let live_metrics = Arc::new(LiveMetrics::default());
+            metrics::start(listen, live_metrics.clone()).await?;
+            Some(live_metrics)
+        }
+        None => None,
+    };
+
+    // The load schedules start along with the measured window.
+    let measured_start = (Instant::now() + warmup)
+        .checked_sub(elapsed)
+        .ok_or_else(|| {
+            Error::invalid(format!(
+                "unable to start the measured window {elapsed:?} back"
+            ))
+        })?;
+
+    // Checked once the load has stopped, by the same instance that runs the background tasks.
+    let mut expected = Vec::new();
+    if background_tasks {
+        for cohort in cohorts.iter() {
+            expected.extend(assertions::Expected::new(&cohort.name, &cohort.state).await?);
+        }
+    }
+
+    let mut tasks = Vec::new();
+    for (cohort_index, Cohort { state, .. }) in cohorts.into_iter().enumerate() {
+        tasks.extend(
+            start_cohort(
+                state,
+                cohort_index,
+                stats_queue.clone(),
+                &actor_tx,
+                live_metrics.clone(),
+                measured_start,
+                background_tasks,
+            )
+            .await?,
+        );
+    }
 
     // We run a separate test inner so we don't have to worry about
     // task spawn/join within our logic.
     let c_stats_ctrl = stats_ctrl.clone();
     // Don't ? this, we want to stash the result so we cleanly stop all the workers
     // before returning the inner test result.
-    let test_result = execute_inner(warmup, test_time, control_rx, c_stats_ctrl).await;
+    let test_result = execute_inner(warmup, test_time, elapsed, control_rx, c_stats_ctrl)
+        .await
+        .and_then(|end| end_statistics(&stats_ctrl, end));
 
     info!("stopping stats");
 
     // The statistics collector has been working in the BG, and was likely told
     // to end by now, but if not (due to an error) send a signal to stop immediately.
-    if let Err(crossbeam_err) = stats_ctrl.push(TestPhase::StopNow) {
-        error!(
-            ?crossbeam_err,
-            "Unable to signal statistics collector to stop"
-        );
-        return Err(Error::Crossbeam);
+    if stats_ctrl.push(TestPhase::StopNow).is_err() {
+        return Err(Error::Crossbeam("stop"));
     }
 
     info!("stopping workers");
 
     // Test workers to stop
-    actor_tx.send(Signal::Stop).map_err(|broadcast_err| {
-        error!(?broadcast_err, "Unable to signal workers to stop");
-        Error::Tokio
-    })?;
+    actor_tx
+        .send(Signal::Stop)
+        .map_err(|broadcast_err| Error::tokio("signal the actors to stop", broadcast_err))?;
 
     info!("joining workers");
 
     // Join all the tasks.
     for task in tasks {
-        task.await.map_err(|tokio_err| {
-            error!(?tokio_err, "Failed to join task");
-            Error::Tokio
-        })??;
+        task.await
+            .map_err(|tokio_err| Error::tokio("wait for an actor", tokio_err))??;
         // The double ? isn't a mistake, it's because this is Result<Result<T, E>, E>
         // and flatten is nightly.
     }
 
-    // By this point the stats task should have been told to halt and rejoin.
-    stats_task.await.map_err(|tokio_err| {
-        error!(?tokio_err, "Failed to join statistics task");
-        Error::Tokio
-    })??;
-    // Not an error, two ? to handle the inner data collector error.
+    // By this point the stats task should have been told to halt and rejoin. The collector
+    // misses the latency goals, so its result is kept for the assertions to be checked too.
+    let collected = stats_task
+        .await
+        .map_err(|tokio_err| Error::tokio("wait for the statistics collector", tokio_err))?;
+    let measured = test_result.and(collected);
 
+    if matches!(measured, Ok(()) | Err(Error::LatencyGoalMissed)) && !expected.is_empty() {
+        info!("checking assertions");
+        let asserted = assertions::verify(expected).await;
+        return assertions::outcome(measured, asserted);
+    }
     // Complete!
-
-    test_result
+    measured
 }
@@ -0,0 +1,144 @@
+use crate::error::Error;
+use crate::generate;
+use crate::populate;
+use crate::run::{self, Cohort, Signal};
+use crate::state::State;
+use crate::stats::RunSummary;
+
+use chrono::Local;
+use csv::Writer;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// One round of a scaling curve, run against a state of the given size.
+#[derive(Serialize)]
+struct ScalingPoint {
+    person_count: usize,
+    read_events: usize,
+    read_95_ms: Option<f64>,
+    // How the read latency compares with the first round.
+    read_95_growth: Option<f64>,
+    write_events: usize,
+    write_95_ms: Option<f64>,
+    auth_events: usize,
+    auth_95_ms: Option<f64>,
+    error_events: usize,
+}
+
+impl ScalingPoint {
+    fn new(summary: &RunSummary, first: Option<&ScalingPoint>) -> Self {
+        let read_95_growth = match first {
+            Some(first) => first
+                .read_95_ms
+                .zip(summary.read_95_ms)
+                .map(|(first, read_95_ms)| read_95_ms / first),
+            None => summary.read_95_ms.map(|_| 1.),
+        };
+        ScalingPoint {
+            person_count: summary.person_count,
+            read_events: summary.read_events,
+            read_95_ms: summary.read_95_ms,
+            read_95_growth,
+            write_events: summary.write_events,
+            write_95_ms: summary.write_95_ms,
+            auth_events: summary.auth_events,
+            auth_95_ms: summary.auth_95_ms,
+            error_events: summary.error_events,
+        }
+    }
+}
+
+/// Run the workload of the state against an increasing number of persons. Before each round
+/// the state is extended to the next size and populated, so the server keeps the persons of
+/// the earlier rounds. The extended state is written back to its path as it grows.
+pub async fn execute(
+    state_path: &Path,
+    sizes: &[u64],
+    control_rx: broadcast::Receiver<Signal>,
+) -> Result<(), Error> {
+    if sizes.is_empty() || sizes.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(Error::invalid(
+            "the sizes of a scaling curve have to be increasing",
+        ));
+    }
+
+    let name = state_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| state_path.display().to_string());
+
+    let mut points: Vec<ScalingPoint> = Vec::with_capacity(sizes.len());
+    for size in sizes.iter().copied() {
+        let mut state = State::try_from(state_path)?;
+        let person_count = state.persons.len() as u64;
+        if size < person_count {
+            return Err(Error::invalid(format!(
+                "the state already has {person_count} persons, more than the round of {size}"
+            )));
+        }
+        if size > person_count {
+            info!("Extending the state from {person_count} to {size} persons");
+            generate::extend(&mut state, size - person_count)?;
+            state.write_to_path(state_path)?;
+        }
+
+        info!("Populating {size} persons");
+        populate::preflight(state, &populate::PreflightOptions::default()).await?;
+
+        info!("Running the workload against {size} persons");
+        let cohorts = vec![Cohort {
+            name: name.clone(),
+            state: State::try_from(state_path)?,
+        }];
+        let summary = Arc::new(OnceLock::new());
+        match run::execute_with_summary(cohorts, control_rx.resubscribe(), summary.clone()).await {
+            // A missed goal is part of the curve, rather than a reason to stop it.
+            Ok(()) | Err(Error::LatencyGoalMissed) => {}
+            Err(err) => return Err(err),
+        }
+
+        let Some(summary) = summary.get() else {
+            return Err(Error::invalid(format!(
+                "the round of {size} persons recorded no statistics"
+            )));
+        };
+        let point = ScalingPoint::new(summary, points.first());
+        points.push(point);
+    }
+
+    let format_ms = |ms: Option<f64>| {
+        ms.map(|ms| format!("{ms:.1} ms"))
+            .unwrap_or_else(|| "-".to_string())
+    };
+    for point in points.iter() {
+        info!(
+            "{} persons: read 95% {} (x{}), write 95% {}, auth 95% {}, {} errors",
+            point.person_count,
+            format_ms(point.read_95_ms),
+            point
+                .read_95_growth
+                .map(|growth| format!("{growth:.2}"))
+                .unwrap_or_else(|| "-".to_string()),
+            format_ms(point.write_95_ms),
+            format_ms(point.auth_95_ms),
+            point.error_events
+        );
+    }
+
+    let filepath = format!("orca-scaling-curve-{}.csv", Local::now().to_rfc3339());
+    info!("Now saving the scaling curve as '{filepath}'");
+
+    let operation = format!("write the scaling curve '{filepath}'");
+    let mut wrt =
+        Writer::from_path(&filepath).map_err(|csv_err| Error::csv(operation.clone(), csv_err))?;
+
+    for point in points.iter() {
+        wrt.serialize(point)
+            .map_err(|csv_err| Error::csv(operation.clone(), csv_err))?;
+    }
+
+    Ok(())
+}
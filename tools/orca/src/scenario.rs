@@ -0,0 +1,108 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use toml::value::{Table, Value};
+
+/// A curated, named set of profile settings for a well known kind of load. A profile
+/// selects one with `scenario = "<name>"`, and any setting in the profile itself takes
+/// precedence over the one from the scenario.
+#[derive(clap::ValueEnum, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Scenario {
+    /// Most of the population logs in within a short window, then settles into normal traffic
+    LoginStorm,
+    /// Persons continuously read their memberships, against a large set of access controls
+    ReadHeavyDirectory,
+    /// Persons sign in to many OAuth2 clients with scope and claim maps, through the code flow
+    OauthDominant,
+    /// A large fleet of hosts resolving accounts and group memberships
+    PosixFleet,
+    /// Group admins keep creating, renaming and deleting groups while others cycle sessions
+    AdminChurn,
+    /// Many persons log in as the same few shared accounts at once, racing for their sessions
+    DuplicateLogin,
+    /// Persons log in and read while a bulk import of persons runs, like a live migration
+    MigrationRehearsal,
+    /// A large population keeps logging in without logging out, growing the session store
+    SessionGrowth,
+}
+
+impl Scenario {
+    fn preset(self) -> &'static str {
+        match self {
+            Scenario::LoginStorm => include_str!("../scenarios/login_storm.toml"),
+            Scenario::ReadHeavyDirectory => include_str!("../scenarios/read_heavy_directory.toml"),
+            Scenario::OauthDominant => include_str!("../scenarios/oauth_dominant.toml"),
+            Scenario::PosixFleet => include_str!("../scenarios/posix_fleet.toml"),
+            Scenario::AdminChurn => include_str!("../scenarios/admin_churn.toml"),
+            Scenario::DuplicateLogin => include_str!("../scenarios/duplicate_login.toml"),
+            Scenario::MigrationRehearsal => {
+                include_str!("../scenarios/migration_rehearsal.toml")
+            }
+            Scenario::SessionGrowth => include_str!("../scenarios/session_growth.toml"),
+        }
+    }
+
+    fn preset_table(self) -> Result<Table, Error> {
+        toml::from_str(self.preset()).map_err(|toml_err| {
+            Error::toml(
+                format!("read the preset of the {self:?} scenario"),
+                toml_err,
+            )
+        })
+    }
+
+    /// Fill in the settings of the scenario that are missing from the profile.
+    pub fn apply_under(self, profile: &mut Table) -> Result<(), Error> {
+        merge_missing(profile, self.preset_table()?);
+        Ok(())
+    }
+
+    /// Replace the settings of the profile with the ones from the scenario. This is used
+    /// when a profile is first created, so that the scenario wins over the defaults.
+    pub fn apply_over(self, profile: &mut Table) -> Result<(), Error> {
+        let mut preset = self.preset_table()?;
+        merge_missing(&mut preset, std::mem::take(profile));
+        *profile = preset;
+        Ok(())
+    }
+}
+
+// Recursively copy the keys of `from` that `into` doesn't have.
+fn merge_missing(into: &mut Table, from: Table) {
+    for (key, value) in from {
+        match (into.get_mut(&key), value) {
+            (Some(Value::Table(into_table)), Value::Table(from_table)) => {
+                merge_missing(into_table, from_table)
+            }
+            (Some(_), _) => {}
+            (None, value) => {
+                into.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Scenario;
+    use crate::profile::Profile;
+    use clap::ValueEnum;
+
+    #[test]
+    fn test_scenario_presets_apply_to_sample_profile() {
+        let sample: toml::value::Table =
+            toml::from_str(include_str!("../profile-sample.toml")).unwrap();
+
+        for scenario in Scenario::value_variants() {
+            let mut profile = sample.clone();
+            // Leave the settings the scenarios provide up to them.
+            profile.remove("model");
+            profile.remove("person_count");
+            profile.insert("group".to_string(), toml::Value::Table(Default::default()));
+            assert!(scenario.apply_under(&mut profile).is_ok());
+            let profile: Profile = toml::Value::Table(profile).try_into().unwrap();
+            assert!(profile.validate().is_ok());
+            assert!(profile.to_toml_string().is_ok());
+        }
+    }
+}
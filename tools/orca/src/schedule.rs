@@ -0,0 +1,126 @@
+use crate::error::Error;
+use crate::run::Signal;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
+
+use std::time::Duration;
+
+// The seconds from the NTP epoch (1900) to the unix epoch (1970).
+const NTP_UNIX_OFFSET_S: f64 = 2_208_988_800.;
+// Version 4, client mode.
+const NTP_CLIENT_HEADER: u8 = 0b0010_0011;
+const NTP_PACKET_LEN: usize = 48;
+const NTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait until the run has to start for its measured window to begin at `start_at`, that is
+/// one warmup before it. This lets orca instances on several hosts measure the same window
+/// without a coordinator, as long as their clocks agree.
+pub async fn wait_for_start(
+    start_at: DateTime<FixedOffset>,
+    warmup: Duration,
+    control_rx: &mut broadcast::Receiver<Signal>,
+) -> Result<(), Error> {
+    let warmup_start = start_at
+        - chrono::Duration::from_std(warmup)
+            .map_err(|_| Error::profile("the warmup is too long to schedule"))?;
+    let Ok(delay) = warmup_start
+        .with_timezone(&Utc)
+        .signed_duration_since(Utc::now())
+        .to_std()
+    else {
+        return Err(Error::invalid(format!(
+            "the measured window can't start at {start_at}, the warmup would have had to start at {warmup_start}"
+        )));
+    };
+
+    info!(
+        "Waiting {}s, for the measured window to start at {start_at}",
+        delay.as_secs()
+    );
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => Ok(()),
+        _ = control_rx.recv() => Err(Error::Interrupt),
+    }
+}
+
+/// Check that the local clock is within `max_offset` of an NTP server, since a scheduled
+/// start is only as simultaneous as the clocks of the hosts.
+pub async fn check_clock(ntp_server: &str, max_offset: Duration) -> Result<(), Error> {
+    let offset_s = tokio::time::timeout(NTP_TIMEOUT, clock_offset(ntp_server))
+        .await
+        .map_err(|elapsed| {
+            Error::tokio(
+                format!("get an answer from the NTP server {ntp_server}"),
+                elapsed,
+            )
+        })??;
+
+    if offset_s.abs() > max_offset.as_secs_f64() {
+        return Err(Error::invalid(format!(
+            "the local clock is {:.1} ms away from the NTP server {ntp_server}, more than the {} ms allowed",
+            offset_s * 1000.,
+            max_offset.as_millis()
+        )));
+    }
+
+    info!(%ntp_server, "The local clock is {:.1} ms away from the NTP server", offset_s * 1000.);
+    Ok(())
+}
+
+// The offset of the NTP server clock from the local one, in seconds, from a single SNTP
+// exchange.
+async fn clock_offset(ntp_server: &str) -> Result<f64, Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|io_err| Error::io("open a socket for NTP", io_err))?;
+    socket
+        .connect(ntp_server)
+        .await
+        .map_err(|io_err| Error::io(format!("reach the NTP server {ntp_server}"), io_err))?;
+
+    let mut request = [0u8; NTP_PACKET_LEN];
+    request[0] = NTP_CLIENT_HEADER;
+
+    let sent = unix_now_s();
+    socket.send(&request).await.map_err(|io_err| {
+        Error::io(
+            format!("send the request to the NTP server {ntp_server}"),
+            io_err,
+        )
+    })?;
+
+    let mut response = [0u8; NTP_PACKET_LEN];
+    let len = socket.recv(&mut response).await.map_err(|io_err| {
+        Error::io(
+            format!("receive the response of the NTP server {ntp_server}"),
+            io_err,
+        )
+    })?;
+    let received = unix_now_s();
+
+    if len < NTP_PACKET_LEN {
+        return Err(Error::invalid(format!(
+            "the response of the NTP server {ntp_server} is too short"
+        )));
+    }
+
+    let server_received = ntp_timestamp_s(&response[32..40]);
+    let server_sent = ntp_timestamp_s(&response[40..48]);
+    Ok(((server_received - sent) + (server_sent - received)) / 2.)
+}
+
+fn unix_now_s() -> f64 {
+    Utc::now().timestamp_micros() as f64 / 1_000_000.
+}
+
+// An NTP timestamp is 32 bits of seconds since 1900, then 32 bits of fraction.
+fn ntp_timestamp_s(bytes: &[u8]) -> f64 {
+    let mut seconds = [0u8; 4];
+    let mut fraction = [0u8; 4];
+    seconds.copy_from_slice(&bytes[..4]);
+    fraction.copy_from_slice(&bytes[4..8]);
+    u32::from_be_bytes(seconds) as f64 + u32::from_be_bytes(fraction) as f64 / 2f64.powi(32)
+        - NTP_UNIX_OFFSET_S
+}
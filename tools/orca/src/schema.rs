@@ -0,0 +1,143 @@
+// The identifiers the results of a run are exported under, so that they can be loaded into a
+// long-term warehouse. The names shown in the logs and the reports may change between
+// releases, but an identifier is never renamed or given to something else once released.
+// Each run writes a manifest next to its results, which lists the identifiers it used.
+use crate::error::Error;
+use crate::model::ACTION_IDS;
+use crate::run::EVENT_IDS;
+
+use serde::Serialize;
+
+/// The version of the results schema, which only changes when an identifier or a column is
+/// removed or changes meaning. Adding one doesn't change it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// What each identifier column of the exports holds.
+const COLUMNS: [(&str, &str); 7] = [
+    (
+        "run_id",
+        "A UUID that is unique to the run, shared by all the files it wrote",
+    ),
+    (
+        "cohort_id",
+        "The cohort, from its name with everything but letters and digits replaced by _",
+    ),
+    ("phase_id", "The phase of the load schedule, as for cohorts"),
+    (
+        "event_id",
+        "The type of event, one of event_types, which is only ever added to",
+    ),
+    (
+        "error_event_id",
+        "The type of event that failed or was rejected, one of event_types",
+    ),
+    (
+        "action_id",
+        "The action of a model, one of action_types, which is only ever added to",
+    ),
+    (
+        "id",
+        "In the breakdown, the cohort, group, credential, target, latency class or phase",
+    ),
+];
+
+/// The identifier of a cohort, a phase or anything else that is named in the profile.
+pub fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct Column {
+    name: &'static str,
+    description: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct NamedId {
+    pub id: String,
+    pub name: String,
+}
+
+impl NamedId {
+    pub fn new(name: &str) -> Self {
+        NamedId {
+            id: identifier(name),
+            name: name.to_string(),
+        }
+    }
+}
+
+/// What a run wrote, and the identifiers it wrote it with.
+#[derive(Serialize)]
+pub struct Manifest {
+    schema_version: u32,
+    run_id: String,
+    started_at: String,
+    files: Vec<String>,
+    cohorts: Vec<NamedId>,
+    phases: Vec<NamedId>,
+    event_types: &'static [&'static str],
+    action_types: &'static [&'static str],
+    columns: Vec<Column>,
+}
+
+impl Manifest {
+    pub fn new(
+        run_id: String,
+        started_at: String,
+        files: Vec<String>,
+        cohorts: Vec<NamedId>,
+        phases: Vec<NamedId>,
+    ) -> Self {
+        Manifest {
+            schema_version: SCHEMA_VERSION,
+            run_id,
+            started_at,
+            files,
+            cohorts,
+            phases,
+            event_types: &EVENT_IDS,
+            action_types: &ACTION_IDS,
+            columns: COLUMNS
+                .iter()
+                .map(|(name, description)| Column { name, description })
+                .collect(),
+        }
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        info!("Now saving the results schema as '{path}'");
+        let output = std::fs::File::create(path)
+            .map_err(|io_err| Error::file("create the results schema", path, io_err))?;
+        serde_json::to_writer_pretty(output, self)
+            .map_err(|json_err| Error::json(format!("write the results schema '{path}'"), json_err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::identifier;
+    use crate::model::ACTION_IDS;
+    use crate::run::EVENT_IDS;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_identifiers_are_distinct_and_plain() {
+        assert_eq!(identifier("Tenant-A (Basic)"), "tenant_a__basic_");
+        for known_ids in [&EVENT_IDS[..], &ACTION_IDS[..]] {
+            let ids = known_ids.iter().collect::<BTreeSet<_>>();
+            assert_eq!(ids.len(), known_ids.len());
+            assert!(known_ids.iter().all(|id| id
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')));
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// Run the measurement pipeline against a local mock server whose response times are known, so
+// that a regression in the timers or the aggregation can be told apart from one in the server.
+use crate::error::Error;
+use crate::model;
+use crate::profile::ReportProperties;
+use crate::report::{EventReport, EventReportRow};
+use crate::run::EventDetail;
+
+use kanidm_client::KanidmClientBuilder;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Out of every hundred responses, how many are delayed by how much, in milliseconds. The
+// percentiles sit well inside the steps, so they are exactly these delays.
+const DELAYS: [(u64, u64); 3] = [(90, 20), (8, 50), (2, 200)];
+const EXPECTED_P50_MS: f64 = 20.;
+const EXPECTED_P95_MS: f64 = 50.;
+const EXPECTED_P99_MS: f64 = 200.;
+
+fn delay_of(request: u64) -> Duration {
+    let mut slot = request % 100;
+    for (count, delay_ms) in DELAYS {
+        if slot < count {
+            return Duration::from_millis(delay_ms);
+        }
+        slot -= count;
+    }
+    Duration::ZERO
+}
+
+async fn start_mock_server() -> Result<String, Error> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|io_err| Error::io("bind the mock server", io_err))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|io_err| Error::io("get the address of the mock server", io_err))?;
+
+    let requests = Arc::new(AtomicU64::new(0));
+    tokio::spawn(async move {
+        loop {
+            let connection = match listener.accept().await {
+                Ok((connection, _)) => connection,
+                Err(io_err) => {
+                    error!(?io_err, "Mock server stopped accepting connections");
+                    return;
+                }
+            };
+            let requests = requests.clone();
+            tokio::spawn(async move {
+                if let Err(io_err) = serve(connection, &requests).await {
+                    debug!(?io_err, "Mock server connection closed");
+                }
+            });
+        }
+    });
+
+    Ok(format!("http://{local_addr}"))
+}
+
+// Every request is a GET without a body, and is answered on the same connection.
+async fn serve(mut connection: TcpStream, requests: &AtomicU64) -> std::io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    loop {
+        let read = connection.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..read]);
+        while let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            buf.drain(..end + 4);
+            tokio::time::sleep(delay_of(requests.fetch_add(1, Ordering::Relaxed))).await;
+            connection
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 4\r\n\r\ntrue")
+                .await?;
+        }
+    }
+}
+
+// What is wrong with the measured latencies. They can't be below the delays of the server,
+// and can only be above them by the time it takes to get a response on the loopback.
+fn discrepancies(row: &EventReportRow, requests: u64, tolerance_ms: f64) -> Vec<String> {
+    let mut discrepancies = Vec::new();
+    if row.events != requests || row.errors != 0 {
+        discrepancies.push(format!(
+            "{} events and {} errors were measured out of {requests} requests",
+            row.events, row.errors
+        ));
+    }
+    for (percentile, measured, expected) in [
+        ("p50", row.p50_ms, EXPECTED_P50_MS),
+        ("p95", row.p95_ms, EXPECTED_P95_MS),
+        ("p99", row.p99_ms, EXPECTED_P99_MS),
+    ] {
+        match measured {
+            Some(measured) if measured >= expected && measured <= expected + tolerance_ms => {}
+            Some(measured) => discrepancies.push(format!(
+                "{percentile} was measured as {measured} ms, against {expected} ms"
+            )),
+            None => discrepancies.push(format!("{percentile} wasn't measured")),
+        }
+    }
+    discrepancies
+}
+
+/// Time `requests` requests to the mock server, `concurrency` at a time, and check that the
+/// percentiles come out as the delays of the server.
+pub async fn run(requests: u64, concurrency: u64, tolerance_ms: f64) -> Result<(), Error> {
+    let uri = start_mock_server().await?;
+    info!(%uri, "Started the mock server");
+
+    let client = Arc::new(
+        KanidmClientBuilder::new()
+            .address(uri)
+            .build()
+            .map_err(|err| Error::client("create the kanidm client", err))?,
+    );
+    let http_client = reqwest::Client::new();
+
+    let started = Instant::now();
+    let mut tasks = Vec::new();
+    for worker in 0..concurrency {
+        let client = client.clone();
+        let http_client = http_client.clone();
+        // The requests are shared out as evenly as they go.
+        let share = requests / concurrency + u64::from(worker < requests % concurrency);
+        tasks.push(tokio::spawn(async move {
+            let mut events = Vec::new();
+            for _ in 0..share {
+                let (_, records) =
+                    model::anonymous_get(&client, &http_client, "/status", EventDetail::Status)
+                        .await?;
+                events.extend(records);
+            }
+            Ok::<_, Error>(events)
+        }));
+    }
+
+    let mut report = EventReport::new(&ReportProperties::default());
+    for task in tasks {
+        let events = task
+            .await
+            .map_err(|tokio_err| Error::tokio("wait for an actor", tokio_err))??;
+        for event in events {
+            report.push(
+                &event.details,
+                event.start.duration_since(started),
+                event.duration,
+            );
+        }
+    }
+
+    let rows = report.rows(started.elapsed());
+    let discrepancies = match rows.as_slice() {
+        [row] => {
+            info!(
+                p50_ms = ?row.p50_ms,
+                p95_ms = ?row.p95_ms,
+                p99_ms = ?row.p99_ms,
+                "Measured the mock server, which answers in {EXPECTED_P50_MS}, {EXPECTED_P95_MS} and {EXPECTED_P99_MS} ms at these percentiles"
+            );
+            discrepancies(row, requests, tolerance_ms)
+        }
+        rows => vec![format!(
+            "{} types of event were measured, rather than one",
+            rows.len()
+        )],
+    };
+
+    if discrepancies.is_empty() {
+        info!("The measurements match the mock server");
+        Ok(())
+    } else {
+        for discrepancy in discrepancies.iter() {
+            error!("The measurements don't match the mock server: {discrepancy}");
+        }
+        Err(Error::invalid(format!(
+            "{} of the measurements don't match the mock server",
+            discrepancies.len()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{delay_of, discrepancies};
+    use crate::report::EventReportRow;
+    use std::time::Duration;
+
+    #[test]
+    fn test_discrepancies_bound_the_measured_percentiles() {
+        let p99_count = (0..100)
+            .filter(|request| delay_of(*request) == Duration::from_millis(200))
+            .count();
+        assert_eq!(p99_count, 2);
+
+        let row = |p50_ms, errors| EventReportRow {
+            event_id: "status",
+            event: "Status".to_string(),
+            events: 1000 - errors,
+            errors,
+            rejected: 0,
+            error_rate: 0.,
+            throughput_per_s: 0.,
+            mean_ms: None,
+            p50_ms: Some(p50_ms),
+            p95_ms: Some(51.),
+            p99_ms: Some(201.),
+            p999_ms: None,
+            max_ms: None,
+        };
+        assert!(discrepancies(&row(21., 0), 1000, 5.).is_empty());
+        // Faster than the server can answer is as wrong as too slow.
+        assert_eq!(discrepancies(&row(19., 0), 1000, 5.).len(), 1);
+        assert_eq!(discrepancies(&row(26., 0), 1000, 5.).len(), 1);
+        assert_eq!(discrepancies(&row(21., 1), 1000, 5.).len(), 1);
+    }
+}
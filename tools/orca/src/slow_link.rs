@@ -0,0 +1,154 @@
+use crate::error::Error;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use std::time::Duration;
+
+// How many times a second the throttled side is read from.
+const READS_PER_SECOND: u64 = 10;
+
+/// How the link between the clients and the server is slowed down.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Link {
+    /// The rate at which the responses of the server are read, if they are throttled.
+    pub read_bytes_per_second: Option<u64>,
+    /// How long the requests are held back for before they reach the server.
+    pub base_latency: Duration,
+}
+
+/// Start a local proxy in front of the server at `uri`, that holds the requests back and
+/// reads the responses of the server no faster than the configured rate, as a client on a
+/// slow or distant link would. This returns the URI the clients should connect to instead.
+/// TLS is passed through as is.
+pub async fn start(uri: &str, link: Link) -> Result<String, Error> {
+    let Some((scheme, rest)) = uri.split_once("://") else {
+        return Err(Error::invalid(format!(
+            "unable to parse the server URI '{uri}'"
+        )));
+    };
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let upstream = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        let port = if scheme == "http" { 80 } else { 443 };
+        format!("{authority}:{port}")
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|io_err| Error::io("bind the slow link proxy", io_err))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|io_err| Error::io("get the address of the slow link proxy", io_err))?;
+
+    tokio::spawn(async move {
+        loop {
+            let client = match listener.accept().await {
+                Ok((client, _)) => client,
+                Err(io_err) => {
+                    error!(?io_err, "Slow link proxy stopped accepting connections");
+                    return;
+                }
+            };
+            let upstream = upstream.clone();
+            tokio::spawn(async move {
+                if let Err(io_err) = proxy(client, &upstream, link).await {
+                    debug!(?io_err, "Slow link connection closed");
+                }
+            });
+        }
+    });
+
+    debug!(%local_addr, %uri, "Started slow link proxy");
+    Ok(format!("{scheme}://{local_addr}{path}"))
+}
+
+async fn proxy(client: TcpStream, upstream: &str, link: Link) -> std::io::Result<()> {
+    let server = TcpStream::connect(upstream).await?;
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut server_read, server_write) = server.into_split();
+
+    let requests = async {
+        let mut server_write = server_write;
+        if link.base_latency.is_zero() {
+            tokio::io::copy(&mut client_read, &mut server_write).await?;
+        } else {
+            copy_delayed(client_read, &mut server_write, link.base_latency).await?;
+        }
+        server_write.shutdown().await
+    };
+    let responses = async {
+        match link.read_bytes_per_second {
+            Some(read_bytes_per_second) => {
+                copy_throttled(server_read, &mut client_write, read_bytes_per_second).await
+            }
+            None => {
+                tokio::io::copy(&mut server_read, &mut client_write).await?;
+                client_write.shutdown().await
+            }
+        }
+    };
+
+    // Either side closing ends the connection.
+    tokio::select! {
+        result = requests => result,
+        result = responses => result,
+    }
+}
+
+// Reading in small chunks and waiting between them leaves the rest of the response
+// queued on the server, just like a slow consumer does.
+async fn copy_throttled(
+    mut from: OwnedReadHalf,
+    to: &mut OwnedWriteHalf,
+    read_bytes_per_second: u64,
+) -> std::io::Result<()> {
+    let mut buf = vec![0; (read_bytes_per_second / READS_PER_SECOND).max(1) as usize];
+    loop {
+        let read = from.read(&mut buf).await?;
+        if read == 0 {
+            return to.shutdown().await;
+        }
+        to.write_all(&buf[..read]).await?;
+        tokio::time::sleep(Duration::from_secs_f64(
+            read as f64 / read_bytes_per_second as f64,
+        ))
+        .await;
+    }
+}
+
+// Every chunk is held back for the same time from when it was read, so a request is delayed
+// once however many chunks it takes, and the requests behind it aren't delayed any further.
+async fn copy_delayed(
+    mut from: OwnedReadHalf,
+    to: &mut OwnedWriteHalf,
+    base_latency: Duration,
+) -> std::io::Result<()> {
+    let (chunk_tx, mut chunk_rx) = mpsc::unbounded_channel();
+    let reads = async move {
+        let mut buf = vec![0; 16 * 1024];
+        loop {
+            let read = from.read(&mut buf).await?;
+            // Closing the channel passes the end of the requests on once the rest is written.
+            if read == 0
+                || chunk_tx
+                    .send((Instant::now(), buf[..read].to_vec()))
+                    .is_err()
+            {
+                return Ok::<_, std::io::Error>(());
+            }
+        }
+    };
+    let writes = async {
+        while let Some((read_at, chunk)) = chunk_rx.recv().await {
+            tokio::time::sleep_until(read_at + base_latency).await;
+            to.write_all(&chunk).await?;
+        }
+        Ok(())
+    };
+    tokio::try_join!(reads, writes).map(|_| ())
+}
@@ -0,0 +1,280 @@
+// Hold the events of a run until the statistics process them at the end of it. Past the
+// number of events the profile allows in memory, the rest are written to a file of JSON lines,
+// and read back after the ones in memory, so that they're processed in the order they came.
+use crate::distributed::WireEvent;
+use crate::error::Error;
+use crate::profile::EventSpoolProperties;
+use crate::run::CohortEventRecord;
+
+use uuid::Uuid;
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+pub struct EventSpool {
+    started: Instant,
+    max_buffered: usize,
+    directory: PathBuf,
+    buffered: Vec<CohortEventRecord>,
+    spilled: Option<SpoolFile>,
+}
+
+impl EventSpool {
+    /// Without properties every event is kept in memory.
+    pub fn new(started: Instant, properties: Option<&EventSpoolProperties>) -> Self {
+        EventSpool {
+            started,
+            max_buffered: properties
+                .map_or(usize::MAX, |properties| properties.max_buffered_events),
+            directory: properties
+                .and_then(|properties| properties.directory.clone())
+                .unwrap_or_else(std::env::temp_dir),
+            buffered: Vec::new(),
+            spilled: None,
+        }
+    }
+
+    pub fn push(&mut self, cohort_event_record: CohortEventRecord) -> Result<(), Error> {
+        // The events from before the start of the test are left out of the results anyway, so
+        // they aren't worth a place in memory. On disk they couldn't be timed from the start.
+        if cohort_event_record.record.start < self.started {
+            return Ok(());
+        }
+        if self.buffered.len() < self.max_buffered {
+            self.buffered.push(cohort_event_record);
+            return Ok(());
+        }
+
+        let spilled = match &mut self.spilled {
+            Some(spilled) => spilled,
+            None => {
+                let spilled = SpoolFile::create(&self.directory)?;
+                warn!(
+                    "More than {} events are waiting to be processed, spilling the rest to '{}'",
+                    self.max_buffered,
+                    spilled.path.display()
+                );
+                self.spilled.insert(spilled)
+            }
+        };
+        spilled.write(&WireEvent::new(&cohort_event_record, self.started))
+    }
+
+    /// The events in the order they were pushed, those in memory followed by those on disk.
+    pub fn into_records(
+        self,
+    ) -> Result<impl Iterator<Item = Result<CohortEventRecord, Error>>, Error> {
+        let started = self.started;
+        let spilled = self
+            .spilled
+            .map(|spilled| {
+                info!(
+                    "Reading back the {} events spilled to '{}'",
+                    spilled.events,
+                    spilled.path.display()
+                );
+                spilled.into_reader()
+            })
+            .transpose()?;
+
+        let spilled = spilled.into_iter().flatten().map(move |line| {
+            let line = line.map_err(|io_err| Error::io("read the spilled events", io_err))?;
+            let event: WireEvent = serde_json::from_str(&line)
+                .map_err(|json_err| Error::json("read a spilled event", json_err))?;
+            Ok(event.into_record(started))
+        });
+        Ok(self.buffered.into_iter().map(Ok).chain(spilled))
+    }
+}
+
+// The file the events past the limit are written to, which is removed once they're read back
+// or the run stops.
+struct SpoolFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    events: usize,
+}
+
+impl SpoolFile {
+    fn create(directory: &std::path::Path) -> Result<Self, Error> {
+        let path = directory.join(format!("orca-spool-{}.jsonl", Uuid::new_v4()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|io_err| Error::file("create the event spool", &path, io_err))?;
+        Ok(SpoolFile {
+            path,
+            writer: BufWriter::new(file),
+            events: 0,
+        })
+    }
+
+    fn write(&mut self, event: &WireEvent) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.writer, event)
+            .map_err(|json_err| Error::json("write to the event spool", json_err))?;
+        self.writer
+            .write_all(b"\n")
+            .map_err(|io_err| Error::io("write to the event spool", io_err))?;
+        self.events += 1;
+        Ok(())
+    }
+
+    fn into_reader(mut self) -> Result<SpoolReader, Error> {
+        self.writer
+            .flush()
+            .map_err(|io_err| Error::io("write to the event spool", io_err))?;
+        let mut file = self
+            .writer
+            .get_ref()
+            .try_clone()
+            .map_err(|io_err| Error::io("read the event spool", io_err))?;
+        file.seek(SeekFrom::Start(0))
+            .map_err(|io_err| Error::io("read the event spool", io_err))?;
+        Ok(SpoolReader {
+            lines: BufReader::new(file).lines(),
+            _spooled: self,
+        })
+    }
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        if let Err(io_err) = std::fs::remove_file(&self.path) {
+            warn!(?io_err, path = ?self.path, "Unable to remove the event spool");
+        }
+    }
+}
+
+struct SpoolReader {
+    lines: Lines<BufReader<File>>,
+    // Removes the file once the events are read.
+    _spooled: SpoolFile,
+}
+
+impl Iterator for SpoolReader {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lines.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EventSpool;
+    use crate::profile::EventSpoolProperties;
+    use crate::run::{CohortEventRecord, EventDetail, EventRecord};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_spilled_events_are_read_back_in_order() {
+        let directory =
+            std::env::temp_dir().join(format!("orca-spool-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&directory).expect("The directory is created");
+        let started = Instant::now();
+        let mut spool = EventSpool::new(
+            started,
+            Some(&EventSpoolProperties {
+                max_buffered_events: 2,
+                directory: Some(directory.clone()),
+            }),
+        );
+        for cohort in 0..5 {
+            let pushed = spool.push(CohortEventRecord {
+                cohort,
+                groups: Arc::from([]),
+                credential: Some("password"),
+                person: Some(Arc::from("person_1")),
+                phase: None,
+                target: None,
+                record: EventRecord {
+                    start: started + Duration::from_secs(cohort as u64),
+                    duration: Duration::from_millis(20),
+                    details: EventDetail::Login,
+                },
+            });
+            assert!(pushed.is_ok());
+        }
+        assert_eq!(
+            std::fs::read_dir(&directory)
+                .expect("The directory is read")
+                .count(),
+            1
+        );
+
+        let Ok(records) = spool
+            .into_records()
+            .and_then(|records| records.collect::<Result<Vec<_>, _>>())
+        else {
+            panic!("The spilled events are read back");
+        };
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.cohort)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+        assert_eq!(records[4].record.start, started + Duration::from_secs(4));
+        assert!(matches!(records[4].record.details, EventDetail::Login));
+        // The spilled events are removed once they're read back.
+        assert_eq!(
+            std::fs::read_dir(&directory)
+                .expect("The directory is read")
+                .count(),
+            0
+        );
+        std::fs::remove_dir(&directory).expect("The directory is removed");
+    }
+
+    #[test]
+    fn test_events_from_before_the_start_are_dropped() {
+        let before = Instant::now();
+        let started = before + Duration::from_secs(60);
+        let mut spool = EventSpool::new(
+            started,
+            Some(&EventSpoolProperties {
+                max_buffered_events: 2,
+                directory: None,
+            }),
+        );
+        // Left in memory, the events of the warmup would take the place of the measured ones
+        // and push those to disk.
+        for (cohort, start) in [(0, before), (1, before), (2, started), (3, started)] {
+            let pushed = spool.push(CohortEventRecord {
+                cohort,
+                groups: Arc::from([]),
+                credential: None,
+                person: None,
+                phase: None,
+                target: None,
+                record: EventRecord {
+                    start,
+                    duration: Duration::from_millis(20),
+                    details: EventDetail::Login,
+                },
+            });
+            assert!(pushed.is_ok());
+        }
+        assert!(spool.spilled.is_none());
+
+        let Ok(records) = spool
+            .into_records()
+            .and_then(|records| records.collect::<Result<Vec<_>, _>>())
+        else {
+            panic!("The buffered events are read back");
+        };
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.cohort)
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+}
@@ -1,15 +1,21 @@
+use crate::clock::SharedClock;
 use crate::error::Error;
-use crate::model::{ActorModel, ActorRole};
+use crate::model::{ActorModel, ActorRole, RegisteredCredential, TransitionAction};
 use crate::models;
-use crate::profile::Profile;
+use crate::profile::{PasskeyProperties, Profile, ScriptedProperties};
 use core::fmt::Display;
 use kanidm_client::KanidmClient;
 use rand::SeedableRng;
 use rand_chacha::ChaCha8Rng;
+use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 use std::time::Duration;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
 /// A serialisable state representing the content of a kanidm database and potential
 /// test content that can be created and modified.
 ///
@@ -22,19 +28,28 @@ pub struct State {
     pub preflight_flags: Vec<Flag>,
     pub persons: Vec<Person>,
     pub groups: Vec<Group>,
-    pub thread_count: Option<usize>, // oauth_clients: Vec<Oauth2Clients>,
+    #[serde(default)]
+    pub access_controls: Vec<AccessControl>,
+    #[serde(default)]
+    pub oauth2_clients: Vec<Oauth2Client>,
+    #[serde(default)]
+    pub managed_groups: Vec<ManagedGroup>,
+    #[serde(default)]
+    pub sync_account: Option<SyncAccount>,
+    pub thread_count: Option<usize>,
+    /// Where the generation left off in the seeded RNG stream, so that extending the
+    /// state continues the stream rather than repeating it.
+    #[serde(default)]
+    pub rng_word_pos: Option<u128>,
 }
 
 impl State {
     pub fn write_to_path(&self, path: &Path) -> Result<(), Error> {
-        let output = std::fs::File::create(path).map_err(|io_err| {
-            error!(?io_err);
-            Error::Io
-        })?;
+        let output = std::fs::File::create(path)
+            .map_err(|io_err| Error::file("create the state", path, io_err))?;
 
         serde_json::to_writer(output, self).map_err(|json_err| {
-            error!(?json_err);
-            Error::SerdeJson
+            Error::json(format!("write the state to '{}'", path.display()), json_err)
         })
     }
 }
@@ -43,14 +58,14 @@ impl TryFrom<&Path> for State {
     type Error = Error;
 
     fn try_from(path: &Path) -> Result<Self, Self::Error> {
-        let input = std::fs::File::open(path).map_err(|io_err| {
-            error!(?io_err);
-            Error::Io
-        })?;
+        let input = std::fs::File::open(path)
+            .map_err(|io_err| Error::file("open the state", path, io_err))?;
 
         serde_json::from_reader(input).map_err(|json_err| {
-            error!(?json_err);
-            Error::SerdeJson
+            Error::json(
+                format!("read the state from '{}'", path.display()),
+                json_err,
+            )
         })
     }
 }
@@ -66,6 +81,9 @@ pub enum PreflightState {
     #[default]
     Present,
     Absent,
+    /// Captured from an existing server. The entry is kept, but its credentials are
+    /// replaced with the placeholders of the state.
+    Captured,
 }
 
 /// A model defines *how* an actors makes it's choices. For example the choices
@@ -91,16 +109,59 @@ pub enum Model {
     Writer,
     /// This model adds empty group to a sever and measures how long it takes before they are replicated to the other servers
     LatencyMeasurer,
+    /// This model acts as an admin persona that re-authenticates for privileges before every
+    /// sensitive write, so the reauth flow is measured separately from the write itself
+    PrivilegedAdmin,
+    /// This model acts as a group admin that keeps creating, renaming and deleting groups
+    /// containing the role groups, so memberof is rewritten while the other actors read it
+    GroupChurn,
+    /// This model acts as a group admin that keeps adding itself to and removing itself
+    /// from a group it manages, reading the group in between, while everyone else reads
+    /// the groups they are a member of
+    GroupMembership,
+    /// This model acts as an account policy admin that opens and closes the validity window
+    /// of a canary person, checking that logins outside of the window are rejected
+    AccountValidity,
+    /// This model cycles logins, and enrolls the MFA credential of persons that are part
+    /// of the onboarding wave, logging in with it from then on
+    MfaEnrollment,
+    /// This is a preset that logs in most of the population within a short window after
+    /// the warmup (a "monday morning" login storm), after which everyone settles into
+    /// reading their own account between logins
+    LoginStorm,
+    /// Many persons log in as the same small set of shared accounts at once, probing the
+    /// locking and session issuance of a single entry under concurrent authentication
+    DuplicateLogin,
+    /// Persons sign in to OAuth2 clients through the authorisation code flow, exchange the
+    /// code for a token and read their userinfo with it, like the traffic of an IdP
+    Oauth2,
+    /// Persons that manage a group through `entry_managed_by` keep adding a person to and
+    /// removing them from it, reading it in between, while everyone else reads the groups
+    /// they are a member of
+    DelegatedGroupAdmin,
+    /// Actors keep reading the endpoints that don't need a session, the status and the
+    /// OAuth2 discovery metadata and keys, like misconfigured clients polling them
+    Anonymous,
+    /// Persons keep logging in without ever logging out, reading in between, so that the
+    /// session store grows for as long as the test runs
+    SessionGrowth,
+    /// The actors follow the script of the profile, a sequence or weighted set of actions,
+    /// so that a mix of actions can be tailored without writing a model
+    Scripted,
 }
 
 impl Model {
-    pub fn as_dyn_object(
+    #[allow(clippy::too_many_arguments)]
+    pub fn as_dyn_object<'a>(
         self,
         rng_seed: u64,
         additional_clients: Vec<KanidmClient>,
-        person_name: &str,
+        person_name: &'a str,
         warmup_time: Duration,
-    ) -> Result<Box<dyn ActorModel + Send + '_>, Error> {
+        clock: SharedClock,
+        scripted: &ScriptedProperties,
+        headers: &HeaderMap,
+    ) -> Result<Box<dyn ActorModel + Send + 'a>, Error> {
         let cha_rng = ChaCha8Rng::seed_from_u64(rng_seed);
         let warmup_time_as_ms = warmup_time.as_millis() as u64;
         Ok(match self {
@@ -116,15 +177,216 @@ impl Model {
                     warmup_time_as_ms,
                 )?)
             }
+            Model::PrivilegedAdmin => {
+                Box::new(models::privileged_admin::ActorPrivilegedAdmin::new(
+                    cha_rng,
+                    person_name,
+                    warmup_time_as_ms,
+                ))
+            }
+            Model::GroupChurn => Box::new(models::group_churn::ActorGroupChurn::new(
+                cha_rng,
+                person_name,
+                warmup_time_as_ms,
+            )),
+            Model::GroupMembership => {
+                Box::new(models::group_membership::ActorGroupMembership::new(
+                    cha_rng,
+                    person_name,
+                    warmup_time_as_ms,
+                ))
+            }
+            Model::AccountValidity => {
+                Box::new(models::account_validity::ActorAccountValidity::new(
+                    cha_rng,
+                    person_name,
+                    warmup_time_as_ms,
+                ))
+            }
+            Model::MfaEnrollment => Box::new(models::mfa_enrollment::ActorMfaEnrollment::new(
+                warmup_time,
+                clock,
+            )),
+            Model::LoginStorm => Box::new(models::login_storm::ActorLoginStorm::new(
+                warmup_time,
+                clock,
+            )),
+            Model::DuplicateLogin => Box::new(models::duplicate_login::ActorDuplicateLogin::new(
+                warmup_time,
+                clock,
+            )),
+            Model::Oauth2 => Box::new(models::oauth2::ActorOauth2::new(
+                cha_rng,
+                warmup_time_as_ms,
+                headers,
+            )?),
+            Model::Anonymous => Box::new(models::anonymous::ActorAnonymous::new(
+                cha_rng,
+                warmup_time_as_ms,
+                headers,
+            )?),
+            Model::Scripted => {
+                // Each person draws its own steps, rather than all of them the same ones.
+                let mut hasher = DefaultHasher::new();
+                person_name.hash(&mut hasher);
+                Box::new(models::scripted::ActorScripted::new(
+                    ChaCha8Rng::seed_from_u64(rng_seed ^ hasher.finish()),
+                    warmup_time_as_ms,
+                    scripted,
+                )?)
+            }
+            Model::SessionGrowth => Box::new(models::session_growth::ActorSessionGrowth::new(
+                cha_rng,
+                warmup_time_as_ms,
+            )),
+            Model::DelegatedGroupAdmin => Box::new(
+                models::delegated_group_admin::ActorDelegatedGroupAdmin::new(
+                    cha_rng,
+                    warmup_time_as_ms,
+                ),
+            ),
         })
     }
+
+    /// The actions the model goes through for the person once it has settled into its
+    /// cycle, each weighted by how often it comes up in the cycle. This is the mix the
+    /// actions of a run are expected to follow, leaving out the first login and the errors.
+    pub fn configured_mix(
+        self,
+        person: &Person,
+        scripted: &ScriptedProperties,
+    ) -> Vec<(TransitionAction, u32)> {
+        match self {
+            Model::AuthOnly => models::auth_only::ActorAuthOnly::configured_mix(person),
+            Model::Basic => models::basic::ActorBasic::configured_mix(person),
+            Model::Reader => models::read::ActorReader::configured_mix(person),
+            Model::Writer => models::write::ActorWriter::configured_mix(person),
+            Model::LatencyMeasurer => {
+                models::latency_measurer::ActorLatencyMeasurer::configured_mix(person)
+            }
+            Model::PrivilegedAdmin => {
+                models::privileged_admin::ActorPrivilegedAdmin::configured_mix(person)
+            }
+            Model::GroupChurn => models::group_churn::ActorGroupChurn::configured_mix(person),
+            Model::GroupMembership => {
+                models::group_membership::ActorGroupMembership::configured_mix(person)
+            }
+            Model::AccountValidity => {
+                models::account_validity::ActorAccountValidity::configured_mix(person)
+            }
+            Model::MfaEnrollment => {
+                models::mfa_enrollment::ActorMfaEnrollment::configured_mix(person)
+            }
+            Model::LoginStorm => models::login_storm::ActorLoginStorm::configured_mix(person),
+            Model::DuplicateLogin => {
+                models::duplicate_login::ActorDuplicateLogin::configured_mix(person)
+            }
+            Model::Oauth2 => models::oauth2::ActorOauth2::configured_mix(person),
+            Model::Anonymous => models::anonymous::ActorAnonymous::configured_mix(person),
+            Model::Scripted => models::scripted::ActorScripted::configured_mix(scripted),
+            Model::SessionGrowth => {
+                models::session_growth::ActorSessionGrowth::configured_mix(person)
+            }
+            Model::DelegatedGroupAdmin => {
+                models::delegated_group_admin::ActorDelegatedGroupAdmin::configured_mix(person)
+            }
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Credential {
+    /// The password is left out of the state when it comes from a credential provider.
+    Password {
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        plain: String,
+    },
+    /// A password with a TOTP. The server picks the TOTP secret, so it's registered as the
+    /// run starts instead of being part of the state.
+    PasswordTotp {
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        plain: String,
+        /// How far (in seconds) the clock of the person is off when generating a code.
+        #[serde(default)]
+        clock_skew: i64,
+    },
+    /// A soft passkey, whose key only lives in the memory of orca. A new one is registered
+    /// as the run starts, replacing the one of the previous run.
+    Passkey(PasskeyProperties),
+}
+
+impl Credential {
+    pub fn password(&self) -> Option<&str> {
+        match self {
+            Credential::Password { plain } | Credential::PasswordTotp { plain, .. } => Some(plain),
+            Credential::Passkey(_) => None,
+        }
+    }
+
+    /// The name of the type of the credential, which the statistics are broken down by.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Credential::Password { .. } => "password",
+            Credential::PasswordTotp { .. } => "password_totp",
+            Credential::Passkey(_) => "passkey",
+        }
+    }
+
+    /// The kind of credential `kind` returns under this name, for the events that are read
+    /// back from another orca instance.
+    pub fn kind_named(name: &str) -> Option<&'static str> {
+        ["password", "password_totp", "passkey"]
+            .into_iter()
+            .find(|kind| *kind == name)
+    }
+
+    /// Leave the password out, for it to be fetched from the credential provider instead.
+    pub fn forget_password(&mut self) {
+        if let Credential::Password { plain } | Credential::PasswordTotp { plain, .. } = self {
+            plain.clear();
+        }
+    }
+
+    /// Whether the credential has a second factor that has to be registered as the run starts.
+    pub fn needs_registration(&self) -> bool {
+        !matches!(self, Credential::Password { .. })
+    }
+}
+
+/// The credential of the posix extension of a person, which is separate from their
+/// primary credential.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum UnixCredential {
     Password { plain: String },
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub enum MfaEnrollmentMethod {
+    Totp { clock_skew: i64 },
+    Passkey(PasskeyProperties),
+}
+
+/// An MFA credential that a person enrolls while the test is running.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaEnrollment {
+    /// Seconds after the warmup at which the person starts the enrollment.
+    pub delay: u64,
+    pub method: MfaEnrollmentMethod,
+}
+
+/// A password that expires while the test is running, and has to be rotated by the person.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordExpiry {
+    /// Seconds after the warmup at which the password expires.
+    pub delay: u64,
+    /// The password it's rotated to.
+    pub rotated: String,
+    /// Set once the password was rotated during this run. It's rotated once, however many
+    /// actors run as the person.
+    #[serde(skip)]
+    pub done: OnceCell<()>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Person {
     pub preflight_state: PreflightState,
@@ -133,6 +395,75 @@ pub struct Person {
     pub roles: BTreeSet<ActorRole>,
     pub credential: Credential,
     pub model: Model,
+    #[serde(default)]
+    pub mfa_enrollment: Option<MfaEnrollment>,
+    /// Seconds after the warmup at which the person logs in as part of a login storm.
+    #[serde(default)]
+    pub login_storm_delay: Option<u64>,
+    #[serde(default)]
+    pub unix_credential: Option<UnixCredential>,
+    /// The shared account the person logs in as instead of their own, so that many actors
+    /// race to authenticate as the same account.
+    #[serde(default)]
+    pub login_as: Option<SharedLogin>,
+    /// The attributes the person reads of their own entry, instead of the whole entry.
+    #[serde(default)]
+    pub read_attributes: Option<Vec<String>>,
+    /// The OAuth2 client the person signs in to in the OAuth2 model, or reads the metadata
+    /// of in the anonymous model.
+    #[serde(default)]
+    pub oauth2_login: Option<Oauth2Login>,
+    /// The group the person manages through `entry_managed_by`, in the delegated group
+    /// admin model.
+    #[serde(default)]
+    pub manages: Option<GroupManagement>,
+    #[serde(default)]
+    pub password_expiry: Option<PasswordExpiry>,
+    /// The second factor of the credential, once it has been registered for this run. It's
+    /// registered once, however many actors run as the person.
+    #[serde(skip)]
+    pub registered_credential: OnceCell<RegisteredCredential>,
+    /// The password, once it has been fetched from the credential provider for this run.
+    #[serde(skip)]
+    pub provided_password: OnceCell<Option<String>>,
+}
+
+impl Person {
+    /// The password the person logs in with, which is the one it was rotated to once it
+    /// expired, or the one the credential provider gave.
+    pub fn password(&self) -> Option<&str> {
+        match (&self.password_expiry, self.provided_password.get()) {
+            (Some(expiry), _) if expiry.done.initialized() => Some(expiry.rotated.as_str()),
+            (_, Some(provided)) => provided.as_deref(),
+            _ => self.credential.password(),
+        }
+    }
+}
+
+/// An account whose credential is handed out to several persons.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SharedLogin {
+    pub username: String,
+    pub credential: Credential,
+}
+
+/// An OAuth2 client that maps a scope to one of the groups of a person, so the person can
+/// sign in to it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Oauth2Login {
+    pub client: String,
+    pub redirect_uri: String,
+    /// The basic secret of the client, which the server picks, so it's fetched as the run
+    /// starts.
+    #[serde(skip)]
+    pub client_secret: Option<String>,
+}
+
+/// The group a person manages, and the person they keep adding to and removing from it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GroupManagement {
+    pub group: String,
+    pub member: String,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -143,6 +474,69 @@ pub struct Group {
     pub members: BTreeSet<String>,
 }
 
+/// A group whose changes are delegated to one person through `entry_managed_by`, rather
+/// than to the members of a role group, so that writing to it goes through the access
+/// paths of entry managers.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManagedGroup {
+    pub name: String,
+    pub preflight_state: PreflightState,
+    pub managed_by: String,
+    pub members: BTreeSet<String>,
+}
+
+/// A custom access control profile that lets the members of one test group search
+/// a set of attributes of the persons in another test group.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessControl {
+    pub name: String,
+    pub preflight_state: PreflightState,
+    pub receiver: GroupName,
+    pub target: GroupName,
+    pub search_attrs: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Oauth2ScopeMap {
+    pub group: GroupName,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Oauth2ClaimMap {
+    pub claim: String,
+    pub group: GroupName,
+    pub values: Vec<String>,
+}
+
+/// An OAuth2 client (resource server) and the mappings that decide which scopes and
+/// claims the members of each test group are issued.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Oauth2Client {
+    pub name: String,
+    pub preflight_state: PreflightState,
+    pub origin: String,
+    pub scope_maps: Vec<Oauth2ScopeMap>,
+    pub claim_maps: Vec<Oauth2ClaimMap>,
+}
+
+/// A person owned by the simulated external IDM. It only exists on the server once the
+/// sync agent has pushed it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncPerson {
+    pub uuid: Uuid,
+    pub username: String,
+    pub display_name: String,
+}
+
+/// The sync account the simulated external IDM pushes its changes through.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncAccount {
+    pub name: String,
+    pub preflight_state: PreflightState,
+    pub persons: Vec<SyncPerson>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, Default, Ord, Eq, PartialEq, PartialOrd)]
 #[serde(rename_all = "snake_case")]
 #[allow(clippy::enum_variant_names)]
@@ -154,6 +548,7 @@ pub enum GroupName {
     RolePeopleSelfReadProfile,
     RolePeopleSelfReadMemberOf,
     RolePeopleGroupAdmin,
+    RolePeopleAccountPolicyAdmin,
 }
 
 impl Display for GroupName {
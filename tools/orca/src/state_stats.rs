@@ -0,0 +1,180 @@
+use crate::state::{Credential, MfaEnrollmentMethod, State, UnixCredential};
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+/// A summary of the distributions in a state file, so that a generated dataset can be
+/// sanity checked without reading through the JSON.
+pub struct StateStats {
+    person_count: usize,
+    models: BTreeMap<String, usize>,
+    credentials: BTreeMap<&'static str, usize>,
+    roles: BTreeMap<String, usize>,
+    group_sizes: BTreeMap<u32, usize>,
+    username_lengths: LengthStats,
+    display_name_lengths: LengthStats,
+    access_control_count: usize,
+    oauth2_client_count: usize,
+    sync_person_count: usize,
+}
+
+impl From<&State> for StateStats {
+    fn from(state: &State) -> Self {
+        let mut models = BTreeMap::new();
+        let mut credentials = BTreeMap::new();
+        let mut roles = BTreeMap::new();
+        let mut username_lengths = LengthStats::default();
+        let mut display_name_lengths = LengthStats::default();
+
+        for person in state.persons.iter() {
+            *models.entry(format!("{:?}", person.model)).or_default() += 1;
+
+            let primary = match person.credential {
+                Credential::Password { .. } => "password",
+                Credential::PasswordTotp { .. } => "password and totp",
+                Credential::Passkey(_) => "passkey",
+            };
+            *credentials.entry(primary).or_default() += 1;
+            if let Some(UnixCredential::Password { .. }) = person.unix_credential {
+                *credentials.entry("unix password").or_default() += 1;
+            }
+            if let Some(mfa_enrollment) = &person.mfa_enrollment {
+                let enrolled = match mfa_enrollment.method {
+                    MfaEnrollmentMethod::Totp { .. } => "enrolls totp",
+                    MfaEnrollmentMethod::Passkey(_) => "enrolls passkey",
+                };
+                *credentials.entry(enrolled).or_default() += 1;
+            }
+
+            for role in person.roles.iter() {
+                *roles.entry(format!("{role:?}")).or_default() += 1;
+            }
+
+            username_lengths.push(person.username.chars().count());
+            display_name_lengths.push(person.display_name.chars().count());
+        }
+
+        let mut group_sizes = BTreeMap::new();
+        for group in state.groups.iter() {
+            *group_sizes
+                .entry(size_bucket(group.members.len()))
+                .or_default() += 1;
+        }
+
+        StateStats {
+            person_count: state.persons.len(),
+            models,
+            credentials,
+            roles,
+            group_sizes,
+            username_lengths,
+            display_name_lengths,
+            access_control_count: state.access_controls.len(),
+            oauth2_client_count: state.oauth2_clients.len(),
+            sync_person_count: state
+                .sync_account
+                .as_ref()
+                .map(|sync_account| sync_account.persons.len())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Display for StateStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "persons: {}", self.person_count)?;
+
+        writeln!(f, "models:")?;
+        for (model, count) in self.models.iter() {
+            writeln!(f, "  {:<24} {}", model, count)?;
+        }
+
+        writeln!(f, "credentials:")?;
+        for (credential, count) in self.credentials.iter() {
+            writeln!(f, "  {:<24} {}", credential, count)?;
+        }
+
+        writeln!(f, "roles:")?;
+        for (role, count) in self.roles.iter() {
+            writeln!(f, "  {:<24} {}", role, count)?;
+        }
+
+        writeln!(f, "group sizes:")?;
+        for (bucket, count) in self.group_sizes.iter() {
+            writeln!(f, "  {:<24} {}", size_bucket_label(*bucket), count)?;
+        }
+
+        writeln!(f, "username length:         {}", self.username_lengths)?;
+        writeln!(f, "display name length:     {}", self.display_name_lengths)?;
+        writeln!(f, "access controls:         {}", self.access_control_count)?;
+        writeln!(f, "oauth2 clients:          {}", self.oauth2_client_count)?;
+        write!(f, "sync persons:            {}", self.sync_person_count)
+    }
+}
+
+#[derive(Default)]
+struct LengthStats {
+    count: usize,
+    total: usize,
+    min: Option<usize>,
+    max: usize,
+}
+
+impl LengthStats {
+    fn push(&mut self, length: usize) {
+        self.count += 1;
+        self.total += length;
+        self.min = Some(self.min.map_or(length, |min| min.min(length)));
+        self.max = self.max.max(length);
+    }
+}
+
+impl Display for LengthStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mean = if self.count > 0 {
+            self.total as f64 / self.count as f64
+        } else {
+            0.
+        };
+        write!(
+            f,
+            "min {}, mean {:.1}, max {}",
+            self.min.unwrap_or_default(),
+            mean,
+            self.max
+        )
+    }
+}
+
+// Group sizes are bucketed by powers of two, with empty groups in their own bucket.
+fn size_bucket(size: usize) -> u32 {
+    match size {
+        0 => 0,
+        size => size.ilog2() + 1,
+    }
+}
+
+fn size_bucket_label(bucket: u32) -> String {
+    match bucket {
+        0 => "0".to_string(),
+        1 => "1".to_string(),
+        bucket => format!("{}-{}", 1u64 << (bucket - 1), (1u64 << bucket) - 1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{size_bucket, size_bucket_label};
+
+    #[test]
+    fn test_group_size_buckets() {
+        let labels = [0, 1, 2, 3, 4, 7, 8, 1000]
+            .into_iter()
+            .map(|size| size_bucket_label(size_bucket(size)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            ["0", "1", "2-3", "2-3", "4-7", "4-7", "8-15", "512-1023"]
+        );
+    }
+}
@@ -1,15 +1,41 @@
+use crate::clock::{self, SharedClock};
 use crate::error::Error;
-use crate::run::{EventDetail, EventRecord};
-use chrono::Local;
+use crate::model::TransitionAction;
+use crate::profile::{
+    EventSpoolProperties, LatencyGoalProperties, MaintenanceWindowProperties, ReportProperties,
+    ThresholdProperties,
+};
+use crate::report::{self, EventReport};
+use crate::run::{
+    AttemptsSample, CohortEventRecord, DataSizeSample, EventDetail, EventRecord, Failure,
+};
+use crate::schema::{self, Manifest, NamedId};
+use crate::spool::EventSpool;
+use crate::state::Model;
+use chrono::{DateTime, Local};
 use crossbeam::queue::{ArrayQueue, SegQueue};
 use csv::Writer;
-use serde::Serialize;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, OnceLock};
 use std::thread;
 use std::time::{Duration, Instant};
+use uuid::Uuid;
 
 use mathru::statistics::distrib::{Continuous, Normal};
 
+// An action that starts later than this after its intended time counts as late.
+const LATE_ACTION_TOLERANCE_MS: f64 = 10.;
+// Above this fraction of late actions, orca itself was likely the bottleneck.
+const LATE_ACTION_WARN_FRACTION: f64 = 0.01;
+// A target whose latency or error rate is this many times the median of the targets is out
+// of line with the rest of the pool.
+const TARGET_IMBALANCE_FACTOR: f64 = 2.;
+// Below this error rate, a target isn't flagged however few errors the others have.
+const TARGET_IMBALANCE_MIN_ERROR_RATE: f64 = 0.01;
+
 #[derive(Debug)]
 pub enum TestPhase {
     Start(Instant),
@@ -17,10 +43,21 @@ pub enum TestPhase {
     StopNow,
 }
 
+impl TestPhase {
+    /// What the statistics collector is told to do, worded to follow "signal it to".
+    pub fn signal(&self) -> &'static str {
+        match self {
+            TestPhase::Start(_) => "start",
+            TestPhase::End(_) => "end",
+            TestPhase::StopNow => "stop",
+        }
+    }
+}
+
 pub trait DataCollector {
     fn run(
         &mut self,
-        stats_queue: Arc<SegQueue<EventRecord>>,
+        stats_queue: Arc<SegQueue<CohortEventRecord>>,
         ctrl: Arc<ArrayQueue<TestPhase>>,
         dump_raw_data: bool,
     ) -> Result<(), Error>;
@@ -31,22 +68,85 @@ enum OpKind {
     ReadOp,
     ReplicationDelay,
     Auth, //TODO! does this make sense?
+    // One step of a login, which is counted once as a whole by its Login event.
+    AuthStep(&'static str),
+    // A failed step of a login, whose failure is counted once as a whole by its Login event.
+    AuthStepError(&'static str),
+    // A check of the session sent in the background, rather than a read of the person.
+    Keepalive,
     Error,
+    Rejected,
+    Conflict,
+    Timeout,
+    ScheduleLag,
+    PermitWait,
+    Backoff { respected: bool },
+    // A task that runs alongside the actors, such as an import or a backup.
+    Background(&'static str),
+    DataSize(Box<DataSizeSample>),
+    Attempts(Box<AttemptsSample>),
+    Transition(TransitionAction),
 }
 
 impl From<EventDetail> for OpKind {
     fn from(value: EventDetail) -> Self {
         match value {
-            EventDetail::PersonGetSelfMemberOf | EventDetail::PersonGetSelfAccount => {
-                OpKind::ReadOp
-            }
+            EventDetail::PersonGetSelfMemberOf
+            | EventDetail::PersonGetSelfAccount
+            | EventDetail::PersonGetSelfAttribute
+            | EventDetail::PersonWhoami
+            | EventDetail::PersonGetGroup
+            | EventDetail::PersonGetGroupMembers
+            | EventDetail::Oauth2Userinfo
+            | EventDetail::Status
+            | EventDetail::Oauth2Discovery
+            | EventDetail::Oauth2Jwks => OpKind::ReadOp,
             EventDetail::PersonSetSelfMail
             | EventDetail::PersonSetSelfPassword
+            | EventDetail::PersonRotatePassword
             | EventDetail::PersonCreateGroup
-            | EventDetail::PersonAddGroupMembers => OpKind::WriteOp,
-            EventDetail::Login | EventDetail::Logout | EventDetail::PersonReauth => OpKind::Auth,
+            | EventDetail::PersonRenameGroup
+            | EventDetail::PersonDeleteGroup
+            | EventDetail::PersonAddGroupMembers
+            | EventDetail::PersonRemoveGroupMembers
+            | EventDetail::PersonCreatePerson
+            | EventDetail::PersonDeletePerson
+            | EventDetail::PersonSetAccountValidity
+            | EventDetail::PersonEnrollTotp
+            | EventDetail::PersonEnrollPasskey
+            | EventDetail::SyncRefresh
+            | EventDetail::SyncUpdate => OpKind::WriteOp,
+            EventDetail::Login
+            | EventDetail::LoginRejectedValidity
+            | EventDetail::Logout
+            | EventDetail::SessionRefresh
+            | EventDetail::Reauthentication
+            | EventDetail::UnixAuth
+            | EventDetail::PersonReauth
+            | EventDetail::Oauth2Authorise
+            | EventDetail::Oauth2Token => OpKind::Auth,
+            EventDetail::AuthInit => OpKind::AuthStep("init"),
+            EventDetail::AuthBegin => OpKind::AuthStep("begin"),
+            EventDetail::AuthCredential => OpKind::AuthStep("credential"),
+            EventDetail::AuthTotpRetry => OpKind::AuthStep("totp_retry"),
+            EventDetail::Keepalive => OpKind::Keepalive,
             EventDetail::GroupReplicationDelay => OpKind::ReplicationDelay,
-            EventDetail::Error => OpKind::Error,
+            EventDetail::Error(failure) => match OpKind::from(failure.event) {
+                OpKind::AuthStep(step) => OpKind::AuthStepError(step),
+                _ => OpKind::Error,
+            },
+            EventDetail::Rejected(_) => OpKind::Rejected,
+            EventDetail::LoginConflict => OpKind::Conflict,
+            EventDetail::Timeout(_) => OpKind::Timeout,
+            EventDetail::ScheduleLag => OpKind::ScheduleLag,
+            EventDetail::PermitWait => OpKind::PermitWait,
+            EventDetail::BackoffRespected => OpKind::Backoff { respected: true },
+            EventDetail::BackoffViolated => OpKind::Backoff { respected: false },
+            EventDetail::Import => OpKind::Background("import"),
+            EventDetail::Backup => OpKind::Background("backup"),
+            EventDetail::DataSize(sample) => OpKind::DataSize(sample),
+            EventDetail::Attempts(sample) => OpKind::Attempts(sample),
+            EventDetail::Transition(action) => OpKind::Transition(action),
         }
     }
 }
@@ -54,19 +154,76 @@ pub struct BasicStatistics {
     person_count: usize,
     group_count: usize,
     node_count: usize,
+    cohorts: Vec<(String, Model)>,
+    // The share of its actions each model is configured to spend on an action, see
+    // `Model::configured_mix`.
+    configured_mix: BTreeMap<(String, TransitionAction), f64>,
+    // The latency class of each cohort, if it's in one.
+    latency_classes: Vec<Option<String>>,
+    stats_by_group: bool,
+    thresholds: ThresholdProperties,
+    latency_goals: LatencyGoalProperties,
+    maintenance_windows: Vec<MaintenanceWindowProperties>,
+    report: ReportProperties,
+    heartbeat_interval: Option<Duration>,
+    event_spool: Option<EventSpoolProperties>,
+    summary: Arc<OnceLock<RunSummary>>,
+}
+
+/// The headline numbers of a run, so that runs can be compared with each other.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub person_count: usize,
+    pub read_events: usize,
+    pub read_95_ms: Option<f64>,
+    pub write_events: usize,
+    pub write_95_ms: Option<f64>,
+    pub auth_events: usize,
+    pub auth_95_ms: Option<f64>,
+    pub error_events: usize,
+    /// The actions the server refused under its access policy, which aren't errors.
+    #[serde(default)]
+    pub rejected_events: usize,
+    /// Left out of the summaries of the runs from before there were identifiers.
+    #[serde(default)]
+    pub run_id: Option<String>,
+    #[serde(default)]
+    pub schema_version: u32,
 }
 
 impl BasicStatistics {
-    #[allow(clippy::new_ret_no_self)]
+    #[allow(clippy::new_ret_no_self, clippy::too_many_arguments)]
     pub fn new(
         person_count: usize,
         group_count: usize,
         node_count: usize,
+        cohorts: Vec<(String, Model)>,
+        configured_mix: BTreeMap<(String, TransitionAction), f64>,
+        latency_classes: Vec<Option<String>>,
+        stats_by_group: bool,
+        thresholds: ThresholdProperties,
+        latency_goals: LatencyGoalProperties,
+        maintenance_windows: Vec<MaintenanceWindowProperties>,
+        report: ReportProperties,
+        heartbeat_interval: Option<Duration>,
+        event_spool: Option<EventSpoolProperties>,
+        summary: Arc<OnceLock<RunSummary>>,
     ) -> Box<dyn DataCollector + Send> {
         Box::new(BasicStatistics {
             person_count,
             group_count,
             node_count,
+            cohorts,
+            configured_mix,
+            latency_classes,
+            stats_by_group,
+            thresholds,
+            latency_goals,
+            maintenance_windows,
+            report,
+            heartbeat_interval,
+            event_spool,
+            summary,
         })
     }
 }
@@ -74,7 +231,7 @@ impl BasicStatistics {
 impl DataCollector for BasicStatistics {
     fn run(
         &mut self,
-        stats_queue: Arc<SegQueue<EventRecord>>,
+        stats_queue: Arc<SegQueue<CohortEventRecord>>,
         ctrl: Arc<ArrayQueue<TestPhase>>,
         dump_raw_data: bool,
     ) -> Result<(), Error> {
@@ -88,24 +245,35 @@ impl DataCollector for BasicStatistics {
                     break start;
                 }
                 Some(TestPhase::End(_)) => {
-                    error!("invalid state");
-                    // Invalid state.
-                    return Err(Error::InvalidState);
+                    return Err(Error::invalid(
+                        "the statistics collector was told to end before it started",
+                    ));
                 }
                 Some(TestPhase::StopNow) => {
                     // We have been told to stop immediately.
                     return Ok(());
                 }
-                None => thread::sleep(Duration::from_millis(100)),
+                None => {
+                    // The events of the warmup are left out of the results, so they're dropped
+                    // as they come rather than held until the end of the test.
+                    while stats_queue.pop().is_some() {}
+                    thread::sleep(Duration::from_millis(100))
+                }
             }
         };
 
-        // Due to the design of this collector, we don't do anything until the end of the test.
+        // Due to the design of this collector, we don't do anything until the end of the test,
+        // other than draining the events early for the heartbeat.
+        let mut heartbeat = self
+            .heartbeat_interval
+            .map(|interval| Heartbeat::new(start, interval, clock::system()));
+        let mut received = EventSpool::new(start, self.event_spool.as_ref());
         let end = loop {
             match ctrl.pop() {
                 Some(TestPhase::Start(_)) => {
-                    // Invalid state.
-                    return Err(Error::InvalidState);
+                    return Err(Error::invalid(
+                        "the statistics collector was told to start twice",
+                    ));
                 }
                 Some(TestPhase::End(end)) => {
                     break end;
@@ -115,7 +283,21 @@ impl DataCollector for BasicStatistics {
                     // We have been told to stop immediately.
                     return Ok(());
                 }
-                None => thread::sleep(Duration::from_millis(100)),
+                None => {
+                    // The spool has to see the events as they come to keep them in bounds.
+                    if heartbeat.is_some() || self.event_spool.is_some() {
+                        while let Some(cohort_event_record) = stats_queue.pop() {
+                            if let Some(heartbeat) = heartbeat.as_mut() {
+                                heartbeat.push(&cohort_event_record.record);
+                            }
+                            received.push(cohort_event_record)?;
+                        }
+                    }
+                    if let Some(line) = heartbeat.as_mut().and_then(Heartbeat::beat_if_due) {
+                        info!("{line}");
+                    }
+                    thread::sleep(Duration::from_millis(100))
+                }
             }
         };
 
@@ -124,23 +306,181 @@ impl DataCollector for BasicStatistics {
         let mut readop_times = Vec::new();
         let mut writeop_times = Vec::new();
         let mut replication_delays = Vec::new();
+        let mut auth_times = Vec::new();
+        let mut timeout_events = 0;
+        let mut conflict_events = 0;
+        let mut rejected_events = 0;
+        let mut failures: BTreeMap<FailureKey, FailureGroup> = BTreeMap::new();
+        let mut schedule_lags = Vec::new();
+        let mut offered_load: BTreeMap<u64, OfferedLoadWindow> = BTreeMap::new();
         let mut raw_stats = Vec::new();
+        let mut cohort_timings: Vec<BreakdownTimings> =
+            self.cohorts.iter().map(|_| Default::default()).collect();
+        let mut group_timings: BTreeMap<String, BreakdownTimings> = BTreeMap::new();
+        let mut credential_timings: BTreeMap<&'static str, BreakdownTimings> = BTreeMap::new();
+        let mut target_timings: BTreeMap<Arc<str>, BreakdownTimings> = BTreeMap::new();
+        let mut latency_class_timings: BTreeMap<&str, BreakdownTimings> = BTreeMap::new();
+        // In the order the phases were first seen, which is the order of the schedule.
+        let mut phase_timings: Vec<(Arc<str>, BreakdownTimings)> = Vec::new();
+        let mut window_timings: BTreeMap<u64, BreakdownTimings> = BTreeMap::new();
+        let mut background_tasks = Vec::new();
+        let mut data_sizes = Vec::new();
+        let mut transition_mix: BTreeMap<(String, TransitionAction), TransitionMix> =
+            BTreeMap::new();
+        let mut backoff_compliance: BTreeMap<usize, BackoffCompliance> = BTreeMap::new();
+        let mut oauth2_times: BTreeMap<&'static str, Vec<f64>> = BTreeMap::new();
+        let mut oauth2_errors: BTreeMap<&'static str, usize> = BTreeMap::new();
+        let mut auth_step_times: BTreeMap<&'static str, Vec<f64>> = BTreeMap::new();
+        let mut auth_step_errors: BTreeMap<&'static str, usize> = BTreeMap::new();
+        let mut keepalive_times = Vec::new();
+        let mut permit_waits = Vec::new();
+        let mut retry_timings: BTreeMap<&'static str, RetryTimings> = BTreeMap::new();
+        let mut person_activity: BTreeMap<Arc<str>, PersonActivity> = BTreeMap::new();
+        let mut maintenance = self
+            .maintenance_windows
+            .iter()
+            .map(MaintenanceTally::new)
+            .collect::<Vec<_>>();
+        let mut event_report = EventReport::new(&self.report);
+        // Identifies the run in every file it writes.
+        let run_id = Uuid::new_v4().to_string();
 
         // We will drain this now.
-        while let Some(event_record) = stats_queue.pop() {
-            if event_record.start < start || event_record.start > end {
+        let mut received = received.into_records()?;
+        while let Some(CohortEventRecord {
+            cohort,
+            groups,
+            credential,
+            person,
+            phase,
+            target,
+            record: event_record,
+        }) = received.next().transpose()?.or_else(|| stats_queue.pop())
+        {
+            let Some(window) =
+                measured_window(event_record.start, start, end, self.thresholds.window)
+            else {
                 // Skip event, outside of the test time window
                 continue;
-            }
+            };
 
             if dump_raw_data {
                 raw_stats.push(SerializableEventRecord::from_event_record(
+                    &run_id,
                     &event_record,
+                    self.cohorts
+                        .get(cohort)
+                        .map(|(name, _)| name.clone())
+                        .unwrap_or_default(),
+                    phase.as_deref(),
                     start,
                 ));
             }
 
-            match OpKind::from(event_record.details) {
+            // The requests of a maintenance window are expected to fail, and are kept out of
+            // the rest of the results.
+            let from_start = event_record.start.duration_since(start);
+            if let Some(tally) = maintenance
+                .iter_mut()
+                .find(|tally| tally.contains(from_start))
+            {
+                if tally.push(&event_record.details) {
+                    continue;
+                }
+            }
+
+            // A failed login step is reported with the steps, the login it broke off is the
+            // failure.
+            if let EventDetail::Error(failure) = &event_record.details {
+                if !matches!(OpKind::from(failure.event.clone()), OpKind::AuthStep(_)) {
+                    failures
+                        .entry(failure_key(failure))
+                        .or_insert_with(|| FailureGroup::new(&run_id, failure))
+                        .count += 1;
+                }
+            }
+
+            let duration = event_record.duration.as_secs_f64();
+
+            // Each step of the OAuth2 flow is also reported on its own.
+            match &event_record.details {
+                EventDetail::Error(failure) => {
+                    if let Some(step) = oauth2_step(&failure.event) {
+                        *oauth2_errors.entry(step).or_default() += 1;
+                    }
+                }
+                details => {
+                    if let Some(step) = oauth2_step(details) {
+                        oauth2_times.entry(step).or_default().push(duration);
+                    }
+                }
+            }
+
+            event_report.push(
+                &event_record.details,
+                event_record.start.duration_since(start),
+                event_record.duration,
+            );
+
+            let op_kind = OpKind::from(event_record.details);
+
+            if let Some(timings) = cohort_timings.get_mut(cohort) {
+                timings.push(&op_kind, duration);
+            }
+            window_timings
+                .entry(window)
+                .or_default()
+                .push(&op_kind, duration);
+
+            if let Some(credential) = credential {
+                credential_timings
+                    .entry(credential)
+                    .or_default()
+                    .push(&op_kind, duration);
+            }
+
+            if let Some(Some(latency_class)) = self.latency_classes.get(cohort) {
+                latency_class_timings
+                    .entry(latency_class)
+                    .or_default()
+                    .push(&op_kind, duration);
+            }
+
+            if let (Some(person), Some(_)) = (person, self.report.top_persons) {
+                person_activity
+                    .entry(person)
+                    .or_default()
+                    .push(&op_kind, duration);
+            }
+
+            if let Some(target) = target {
+                target_timings
+                    .entry(target)
+                    .or_default()
+                    .push(&op_kind, duration);
+            }
+
+            if let Some(phase) = phase {
+                let index = match phase_timings.iter().position(|(name, _)| *name == phase) {
+                    Some(index) => index,
+                    None => {
+                        phase_timings.push((phase, Default::default()));
+                        phase_timings.len() - 1
+                    }
+                };
+                phase_timings[index].1.push(&op_kind, duration);
+            }
+
+            if self.stats_by_group {
+                for group in groups.iter() {
+                    group_timings
+                        .entry(group.clone())
+                        .or_default()
+                        .push(&op_kind, duration);
+                }
+            }
+
+            match op_kind {
                 OpKind::ReadOp => {
                     readop_times.push(event_record.duration.as_secs_f64());
                 }
@@ -150,17 +490,92 @@ impl DataCollector for BasicStatistics {
                 OpKind::ReplicationDelay => {
                     replication_delays.push(event_record.duration.as_secs_f64())
                 }
-                OpKind::Auth => {}
+                OpKind::Auth => {
+                    auth_times.push(event_record.duration.as_secs_f64());
+                }
+                OpKind::AuthStep(step) => {
+                    auth_step_times.entry(step).or_default().push(duration);
+                }
+                OpKind::AuthStepError(step) => {
+                    *auth_step_errors.entry(step).or_default() += 1;
+                }
+                OpKind::Keepalive => {
+                    keepalive_times.push(duration);
+                }
+                OpKind::PermitWait => {
+                    permit_waits.push(duration * 1000.);
+                }
                 OpKind::Error => {}
+                OpKind::Rejected => {
+                    rejected_events += 1;
+                }
+                OpKind::Conflict => {
+                    conflict_events += 1;
+                }
+                OpKind::Timeout => {
+                    timeout_events += 1;
+                }
+                OpKind::ScheduleLag => {
+                    let lag_ms = duration * 1000.;
+                    schedule_lags.push(lag_ms);
+                    offered_load
+                        .entry(window)
+                        .or_insert_with(|| OfferedLoadWindow {
+                            from_start_s: window * self.thresholds.window,
+                            ..Default::default()
+                        })
+                        .push(lag_ms);
+                }
+                OpKind::Backoff { respected } => {
+                    let compliance = backoff_compliance.entry(cohort).or_default();
+                    if respected {
+                        compliance.respected += 1;
+                    } else {
+                        compliance.violated += 1;
+                        compliance.early_s += duration;
+                    }
+                }
+                OpKind::Background(task) => {
+                    let from_start_s = event_record.start.duration_since(start).as_secs_f64();
+                    background_tasks.push((task, from_start_s, from_start_s + duration));
+                }
+                OpKind::Transition(action) => {
+                    let model = self
+                        .cohorts
+                        .get(cohort)
+                        .map(|(_, model)| format!("{model:?}"))
+                        .unwrap_or_default();
+                    let mix = transition_mix.entry((model, action)).or_default();
+                    mix.count += 1;
+                    mix.dwell_s += duration;
+                }
+                OpKind::DataSize(sample) => {
+                    data_sizes.push(DataSizeRow {
+                        from_start_s: event_record.start.duration_since(start).as_secs(),
+                        persons: sample.persons,
+                        groups: sample.groups,
+                        db_bytes: sample.db_bytes,
+                        sessions: sample.sessions,
+                        readop_95_ms: None,
+                    });
+                }
+                OpKind::Attempts(sample) => {
+                    retry_timings
+                        .entry(sample.event.id())
+                        .or_insert_with(|| RetryTimings::new(&sample.event))
+                        .push(&sample, duration);
+                }
             }
         }
 
         if readop_times.is_empty() && writeop_times.is_empty() && replication_delays.is_empty() {
-            error!("For some weird reason no valid data was recorded in this benchmark, bailing out...");
-            return Err(Error::InvalidState);
+            return Err(Error::invalid(
+                "no valid data was recorded in this benchmark",
+            ));
         }
 
         let stats = StatsContainer::new(
+            run_id.clone(),
             &readop_times,
             &writeop_times,
             &replication_delays,
@@ -198,50 +613,1380 @@ impl DataCollector for BasicStatistics {
         info!("SD: {} seconds", stats.replication_delay_sd);
         info!("95%: {}", stats.replication_delay_95);
 
+        let measured = end.duration_since(start);
+        report::write_report(
+            &run_id,
+            &event_report.rows(measured),
+            &event_report.series(),
+            measured,
+            &self.report,
+        )?;
+
+        if timeout_events > 0 {
+            warn!("{timeout_events} actions timed out and were abandoned");
+        }
+
+        if conflict_events > 0 {
+            warn!("{conflict_events} logins failed while racing for a shared account");
+        }
+
+        if rejected_events > 0 {
+            info!("{rejected_events} actions were rejected by the access policy of the server");
+        }
+
+        for tally in maintenance.iter() {
+            info!(
+                "maintenance window {} from {}s to {}s: {} requests, {} errors, left out of the results",
+                tally.name,
+                tally.from.as_secs(),
+                tally.to.as_secs(),
+                tally.requests,
+                tally.errors
+            );
+        }
+
         let now = Local::now();
+
+        // The wall clock time of the test start, so breaches can be matched to server logs.
+        let wall_start = now
+            - chrono::Duration::from_std(Instant::now().duration_since(start)).unwrap_or_default();
+        let breaches = ThresholdBreach::find(&self.thresholds, &window_timings, wall_start);
+
         let filepath = format!("orca-run-{}.csv", now.to_rfc3339());
+        // Every file of the run, for the manifest of its schema.
+        let mut files = Vec::new();
 
         info!("Now saving stats as '{filepath}'");
 
-        let mut wrt = Writer::from_path(filepath).map_err(|_| Error::Io)?;
-        wrt.serialize(stats).map_err(|_| Error::Io)?;
+        files.push(filepath.clone());
+        let mut wrt = Writer::from_path(&filepath)
+            .map_err(|csv_err| Error::csv(format!("create '{filepath}'"), csv_err))?;
+        wrt.serialize(stats)
+            .map_err(|csv_err| Error::csv(format!("write '{filepath}'"), csv_err))?;
+
+        // With a single target there's nothing to compare it to.
+        let target_stats = target_timings
+            .iter()
+            .filter(|_| target_timings.len() > 1)
+            .map(|(name, timings)| BreakdownStats::new("target", name, name.to_string(), timings))
+            .collect::<Vec<_>>();
+        for imbalance in target_imbalances(&target_stats) {
+            warn!(
+                "target {} is out of line with the others, {}: {} against a median of {}",
+                imbalance.target, imbalance.measure, imbalance.value, imbalance.median
+            );
+        }
+
+        // With a single cohort, no groups, a single type of credential, target or latency class
+        // and no load schedule the breakdown would only repeat the totals.
+        if self.cohorts.len() > 1
+            || self.stats_by_group
+            || credential_timings.len() > 1
+            || !target_stats.is_empty()
+            || latency_class_timings.len() > 1
+            || !phase_timings.is_empty()
+        {
+            let breakdown = self
+                .cohorts
+                .iter()
+                .zip(cohort_timings.iter())
+                .map(|((name, model), timings)| {
+                    BreakdownStats::new("cohort", name, format!("{name} ({model:?})"), timings)
+                })
+                .chain(group_timings.iter().map(|(name, timings)| {
+                    BreakdownStats::new("group", name, name.clone(), timings)
+                }))
+                .chain(
+                    credential_timings
+                        .iter()
+                        .filter(|_| credential_timings.len() > 1)
+                        .map(|(name, timings)| {
+                            BreakdownStats::new("credential", name, name.to_string(), timings)
+                        }),
+                )
+                .chain(target_stats)
+                .chain(latency_class_timings.iter().map(|(name, timings)| {
+                    BreakdownStats::new("latency class", name, name.to_string(), timings)
+                }))
+                .chain(phase_timings.iter().map(|(name, timings)| {
+                    BreakdownStats::new("phase", name, name.to_string(), timings)
+                }))
+                .collect::<Vec<_>>();
+
+            for stats in breakdown.iter() {
+                info!(
+                    "{} {}: {} reads (mean: {} seconds, 95%: {}), {} writes (mean: {} seconds, 95%: {}), {} auth events, {} errors, {} rejected, {} conflicts, {} timeouts",
+                    stats.breakdown,
+                    stats.name,
+                    stats.read_events,
+                    stats.read_mean,
+                    stats.read_95,
+                    stats.write_events,
+                    stats.write_mean,
+                    stats.write_95,
+                    stats.auth_events,
+                    stats.error_events,
+                    stats.rejected_events,
+                    stats.conflict_events,
+                    stats.timeout_events
+                );
+            }
+
+            let breakdown_filepath = format!("orca-run-{}-breakdown.csv", now.to_rfc3339());
+            info!("Now saving the breakdown as '{breakdown_filepath}'");
+
+            files.push(breakdown_filepath.clone());
+            let mut wrt = Writer::from_path(&breakdown_filepath)
+                .map_err(|csv_err| Error::csv(format!("create '{breakdown_filepath}'"), csv_err))?;
+
+            for stats in breakdown.iter() {
+                wrt.serialize(stats).map_err(|csv_err| {
+                    Error::csv(format!("write '{breakdown_filepath}'"), csv_err)
+                })?;
+            }
+        }
+
+        let error_events: usize = failures.values().map(|failure| failure.count).sum();
+
+        if !failures.is_empty() {
+            // The most common failures are the ones worth looking at first.
+            let mut failures = failures.into_values().collect::<Vec<_>>();
+            failures.sort_by_key(|failure| Reverse(failure.count));
+
+            warn!("{error_events} actions failed:");
+            for failure in failures.iter() {
+                warn!(
+                    "{} x {} {} (status: {}), e.g. {}",
+                    failure.count,
+                    failure.event,
+                    failure.class,
+                    failure
+                        .status
+                        .map(|status| status.to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    failure.example
+                );
+            }
+
+            let failures_filepath = format!("orca-run-{}-failures.csv", now.to_rfc3339());
+            info!("Now saving the failures as '{failures_filepath}'");
+
+            files.push(failures_filepath.clone());
+            let mut wrt = Writer::from_path(&failures_filepath)
+                .map_err(|csv_err| Error::csv(format!("create '{failures_filepath}'"), csv_err))?;
+
+            for failure in failures.iter() {
+                wrt.serialize(failure).map_err(|csv_err| {
+                    Error::csv(format!("write '{failures_filepath}'"), csv_err)
+                })?;
+            }
+        }
+
+        if !breaches.is_empty() {
+            for breach in breaches.iter() {
+                warn!(
+                    "{} threshold breached from {}s to {}s into the test (at {}), worst: {} against a limit of {}",
+                    breach.threshold,
+                    breach.from_start_s,
+                    breach.to_start_s,
+                    breach.start_time,
+                    breach.worst,
+                    breach.limit
+                );
+            }
+
+            let breaches_filepath = format!("orca-run-{}-breaches.csv", now.to_rfc3339());
+            info!("Now saving the threshold breaches as '{breaches_filepath}'");
+
+            files.push(breaches_filepath.clone());
+            let mut wrt = Writer::from_path(&breaches_filepath)
+                .map_err(|csv_err| Error::csv(format!("create '{breaches_filepath}'"), csv_err))?;
+
+            for breach in breaches.iter() {
+                wrt.serialize(breach).map_err(|csv_err| {
+                    Error::csv(format!("write '{breaches_filepath}'"), csv_err)
+                })?;
+            }
+        }
+
+        if !schedule_lags.is_empty() {
+            let late_actions = schedule_lags
+                .iter()
+                .filter(|lag_ms| **lag_ms > LATE_ACTION_TOLERANCE_MS)
+                .count();
+            info!(
+                "Scheduled {} actions, {} started late (95%: {} ms, max: {} ms)",
+                schedule_lags.len(),
+                late_actions,
+                percentile(&schedule_lags, 0.95).unwrap_or_default(),
+                percentile(&schedule_lags, 1.).unwrap_or_default()
+            );
+            if late_actions as f64 > schedule_lags.len() as f64 * LATE_ACTION_WARN_FRACTION {
+                warn!("Orca couldn't keep up with the load it was asked to generate, the results may reflect the load generator rather than the server");
+            }
+
+            let offered_load_filepath = format!("orca-run-{}-offered-load.csv", now.to_rfc3339());
+            info!("Now saving the offered load as '{offered_load_filepath}'");
+
+            files.push(offered_load_filepath.clone());
+            let mut wrt = Writer::from_path(&offered_load_filepath).map_err(|csv_err| {
+                Error::csv(format!("create '{offered_load_filepath}'"), csv_err)
+            })?;
+
+            for window in offered_load.values() {
+                wrt.serialize(window).map_err(|csv_err| {
+                    Error::csv(format!("write '{offered_load_filepath}'"), csv_err)
+                })?;
+            }
+        }
+
+        if !permit_waits.is_empty() {
+            info!(
+                "{} actions waited for room under a concurrency limit (95%: {} ms, max: {} ms)",
+                permit_waits
+                    .iter()
+                    .filter(|wait_ms| **wait_ms > LATE_ACTION_TOLERANCE_MS)
+                    .count(),
+                percentile(&permit_waits, 0.95).unwrap_or_default(),
+                percentile(&permit_waits, 1.).unwrap_or_default()
+            );
+        }
+
+        if !keepalive_times.is_empty() {
+            info!(
+                "Sent {} keepalives alongside the actions (mean: {:.1} ms, 95%: {:.1} ms)",
+                keepalive_times.len(),
+                keepalive_times.iter().sum::<f64>() / keepalive_times.len() as f64 * 1000.,
+                percentile(&keepalive_times, 0.95).unwrap_or_default() * 1000.
+            );
+        }
+
+        for (task, from_start_s, to_start_s) in background_tasks.iter().copied() {
+            info!("The {task} ran from {from_start_s:.0}s to {to_start_s:.0}s into the test");
+            for impact in BackgroundImpact::evaluate(
+                from_start_s,
+                to_start_s,
+                &window_timings,
+                self.thresholds.window,
+            ) {
+                match (impact.during_95_ms, impact.outside_95_ms) {
+                    (Some(during_95_ms), Some(outside_95_ms)) => info!(
+                        "{} 95% during the {task}: {} ms, against {} ms outside of it ({:+.1}%)",
+                        impact.event_type,
+                        during_95_ms,
+                        outside_95_ms,
+                        (during_95_ms / outside_95_ms - 1.) * 100.
+                    ),
+                    _ => warn!(
+                        "{} latency during the {task} can't be compared, there were no events either during or outside of it",
+                        impact.event_type
+                    ),
+                }
+            }
+        }
+
+        if let (Some(first), Some(last)) = (data_sizes.first(), data_sizes.last()) {
+            info!(
+                "Data size from {}s to {}s into the test: persons {}, groups {}, database bytes {}, sampled sessions {}",
+                first.from_start_s,
+                last.from_start_s,
+                trend(first.persons, last.persons),
+                trend(first.groups, last.groups),
+                trend(first.db_bytes, last.db_bytes),
+                trend(first.sessions, last.sessions)
+            );
+
+            // Each sample is put next to the read latency of its window, to see whether
+            // the latency follows the growth of the data.
+            for row in data_sizes.iter_mut() {
+                row.readop_95_ms = window_timings
+                    .get(&(row.from_start_s / self.thresholds.window))
+                    .and_then(|timings| percentile(&timings.readop_times, 0.95))
+                    .map(|p95| p95 * 1000.);
+            }
+
+            let data_size_filepath = format!("orca-run-{}-data-size.csv", now.to_rfc3339());
+            info!("Now saving the data size samples as '{data_size_filepath}'");
+
+            files.push(data_size_filepath.clone());
+            let mut wrt = Writer::from_path(&data_size_filepath)
+                .map_err(|csv_err| Error::csv(format!("create '{data_size_filepath}'"), csv_err))?;
+
+            for row in data_sizes.iter() {
+                wrt.serialize(row).map_err(|csv_err| {
+                    Error::csv(format!("write '{data_size_filepath}'"), csv_err)
+                })?;
+            }
+        }
+
+        for (cohort, compliance) in backoff_compliance.iter() {
+            let name = self
+                .cohorts
+                .get(*cohort)
+                .map(|(name, _)| name.as_str())
+                .unwrap_or_default();
+            let mean_early_ms = if compliance.violated > 0 {
+                compliance.early_s * 1000. / compliance.violated as f64
+            } else {
+                0.
+            };
+            info!(
+                "Backoff of cohort {name}: {} times respected, {} times violated, on average {mean_early_ms:.0} ms early",
+                compliance.respected,
+                compliance.violated
+            );
+        }
+
+        if !transition_mix.is_empty() {
+            let mut rows = Vec::with_capacity(transition_mix.len());
+            let mut model_totals: BTreeMap<&str, usize> = BTreeMap::new();
+            for ((model, _), mix) in transition_mix.iter() {
+                *model_totals.entry(model.as_str()).or_default() += mix.count;
+            }
+            // The actions the models were configured to go through but never did are
+            // reported too.
+            let keys = transition_mix
+                .keys()
+                .chain(
+                    self.configured_mix
+                        .keys()
+                        .filter(|(model, _)| model_totals.contains_key(model.as_str())),
+                )
+                .collect::<BTreeSet<_>>();
+            for key in keys {
+                let (model, action) = key;
+                let mix = transition_mix.get(key);
+                let count = mix.map_or(0, |mix| mix.count);
+                rows.push(TransitionMixRow {
+                    run_id: run_id.clone(),
+                    model: model.clone(),
+                    action_id: action.id(),
+                    action: format!("{action:?}"),
+                    count,
+                    share: count as f64
+                        / model_totals.get(model.as_str()).copied().unwrap_or(1) as f64,
+                    configured_share: self.configured_mix.get(key).copied().unwrap_or_default(),
+                    mean_dwell_s: mix.map(|mix| mix.dwell_s / mix.count as f64),
+                });
+            }
+
+            for (model, total) in model_totals.iter() {
+                let mix = rows
+                    .iter()
+                    .filter(|row| row.model == *model)
+                    .map(|row| {
+                        format!(
+                            "{} {:.1}% (configured {:.1}%, dwell {})",
+                            row.action,
+                            row.share * 100.,
+                            row.configured_share * 100.,
+                            row.mean_dwell_s
+                                .map(|dwell_s| format!("{dwell_s:.2} s"))
+                                .unwrap_or_else(|| "-".to_string())
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                info!("{model} went through {total} actions: {mix}");
+            }
+
+            let transitions_filepath = format!("orca-run-{}-transitions.csv", now.to_rfc3339());
+            info!("Now saving the mix of actions as '{transitions_filepath}'");
+
+            files.push(transitions_filepath.clone());
+            let mut wrt = Writer::from_path(&transitions_filepath).map_err(|csv_err| {
+                Error::csv(format!("create '{transitions_filepath}'"), csv_err)
+            })?;
+
+            for row in rows.iter() {
+                wrt.serialize(row).map_err(|csv_err| {
+                    Error::csv(format!("write '{transitions_filepath}'"), csv_err)
+                })?;
+            }
+        }
+
+        if !oauth2_times.is_empty() || !oauth2_errors.is_empty() {
+            let rows = step_rows(&oauth2_times, &oauth2_errors);
+
+            for row in rows.iter() {
+                info!(
+                    "OAuth2 {}: {} events, {} errors (mean: {:.1} ms, 95%: {})",
+                    row.step,
+                    row.events,
+                    row.errors,
+                    row.mean_ms,
+                    row.p95_ms
+                        .map(|p95_ms| format!("{p95_ms:.1} ms"))
+                        .unwrap_or_else(|| "-".to_string())
+                );
+            }
+
+            let oauth2_filepath = format!("orca-run-{}-oauth2.csv", now.to_rfc3339());
+            info!("Now saving the OAuth2 steps as '{oauth2_filepath}'");
+
+            files.push(oauth2_filepath.clone());
+            let mut wrt = Writer::from_path(&oauth2_filepath)
+                .map_err(|csv_err| Error::csv(format!("create '{oauth2_filepath}'"), csv_err))?;
+
+            for row in rows.iter() {
+                wrt.serialize(row)
+                    .map_err(|csv_err| Error::csv(format!("write '{oauth2_filepath}'"), csv_err))?;
+            }
+        }
+
+        if !auth_step_times.is_empty() || !auth_step_errors.is_empty() {
+            let rows = step_rows(&auth_step_times, &auth_step_errors);
+
+            for row in rows.iter() {
+                info!(
+                    "Login step {}: {} events, {} errors (mean: {:.1} ms, 95%: {})",
+                    row.step,
+                    row.events,
+                    row.errors,
+                    row.mean_ms,
+                    row.p95_ms
+                        .map(|p95_ms| format!("{p95_ms:.1} ms"))
+                        .unwrap_or_else(|| "-".to_string())
+                );
+            }
+
+            let auth_steps_filepath = format!("orca-run-{}-auth-steps.csv", now.to_rfc3339());
+            info!("Now saving the login steps as '{auth_steps_filepath}'");
+
+            files.push(auth_steps_filepath.clone());
+            let mut wrt = Writer::from_path(&auth_steps_filepath).map_err(|csv_err| {
+                Error::csv(format!("create '{auth_steps_filepath}'"), csv_err)
+            })?;
+
+            for row in rows.iter() {
+                wrt.serialize(row).map_err(|csv_err| {
+                    Error::csv(format!("write '{auth_steps_filepath}'"), csv_err)
+                })?;
+            }
+        }
+
+        if !retry_timings.is_empty() {
+            let rows = retry_timings
+                .iter()
+                .map(|(event_id, timings)| RetryRow::new(run_id.clone(), event_id, timings))
+                .collect::<Vec<_>>();
+
+            for row in rows.iter() {
+                info!(
+                    "{}: {} requests, {} retried, {} gave up, first attempt 95%: {}, final success 95%: {}",
+                    row.event,
+                    row.requests,
+                    row.retried,
+                    row.gave_up,
+                    row.first_attempt_p95_ms
+                        .map(|p95_ms| format!("{p95_ms:.1} ms"))
+                        .unwrap_or_else(|| "-".to_string()),
+                    row.final_success_p95_ms
+                        .map(|p95_ms| format!("{p95_ms:.1} ms"))
+                        .unwrap_or_else(|| "-".to_string())
+                );
+            }
+
+            let retries_filepath = format!("orca-run-{}-retries.csv", now.to_rfc3339());
+            info!("Now saving the retries as '{retries_filepath}'");
+
+            files.push(retries_filepath.clone());
+            let mut wrt = Writer::from_path(&retries_filepath)
+                .map_err(|csv_err| Error::csv(format!("create '{retries_filepath}'"), csv_err))?;
+
+            for row in rows.iter() {
+                wrt.serialize(row).map_err(|csv_err| {
+                    Error::csv(format!("write '{retries_filepath}'"), csv_err)
+                })?;
+            }
+        }
+
+        if let Some(top) = self.report.top_persons {
+            let rows = PersonRow::top(&person_activity, top);
+            for row in rows.iter() {
+                info!(
+                    "{} person {}: {} events, {} errors, mean: {}",
+                    row.ranking,
+                    row.person,
+                    row.events,
+                    row.errors,
+                    row.mean_ms
+                        .map(|mean_ms| format!("{mean_ms:.1} ms"))
+                        .unwrap_or_else(|| "-".to_string())
+                );
+            }
+
+            let persons_filepath = format!("orca-run-{}-persons.csv", now.to_rfc3339());
+            info!("Now saving the top persons as '{persons_filepath}'");
+
+            files.push(persons_filepath.clone());
+            let mut wrt = Writer::from_path(&persons_filepath)
+                .map_err(|csv_err| Error::csv(format!("create '{persons_filepath}'"), csv_err))?;
+
+            for row in rows.iter() {
+                wrt.serialize(row).map_err(|csv_err| {
+                    Error::csv(format!("write '{persons_filepath}'"), csv_err)
+                })?;
+            }
+        }
+
+        let to_ms = |op_times: &[f64]| percentile(op_times, 0.95).map(|p95| p95 * 1000.);
+        // Only set once per run, so this can't fail.
+        let _ = self.summary.set(RunSummary {
+            person_count: self.person_count,
+            read_events: readop_times.len(),
+            read_95_ms: to_ms(&readop_times),
+            write_events: writeop_times.len(),
+            write_95_ms: to_ms(&writeop_times),
+            auth_events: auth_times.len(),
+            auth_95_ms: to_ms(&auth_times),
+            error_events,
+            rejected_events,
+            run_id: Some(run_id.clone()),
+            schema_version: schema::SCHEMA_VERSION,
+        });
+
+        let goal_results = LatencyGoalResult::evaluate(
+            &self.latency_goals,
+            &auth_times,
+            &readop_times,
+            &writeop_times,
+        );
+        for goal in goal_results.iter() {
+            match goal.p99_ms {
+                Some(p99_ms) if goal.passed => info!(
+                    "{} latency goal passed, 99%: {} ms against a goal of {} ms",
+                    goal.event_type, p99_ms, goal.goal_ms
+                ),
+                Some(p99_ms) => warn!(
+                    "{} latency goal failed, 99%: {} ms against a goal of {} ms",
+                    goal.event_type, p99_ms, goal.goal_ms
+                ),
+                None => warn!(
+                    "{} latency goal of {} ms not checked, no events were recorded",
+                    goal.event_type, goal.goal_ms
+                ),
+            }
+        }
 
         if dump_raw_data {
             let raw_data_filepath = format!("orca-run-{}-raw.csv", now.to_rfc3339());
             info!("Now saving raw data as '{raw_data_filepath}'");
 
-            let mut wrt = Writer::from_path(raw_data_filepath).map_err(|_| Error::Io)?;
+            files.push(raw_data_filepath.clone());
+            let mut wrt = Writer::from_path(&raw_data_filepath)
+                .map_err(|csv_err| Error::csv(format!("create '{raw_data_filepath}'"), csv_err))?;
 
             for record in raw_stats.iter() {
-                wrt.serialize(record).map_err(|_| Error::Io)?;
+                wrt.serialize(record).map_err(|csv_err| {
+                    Error::csv(format!("write '{raw_data_filepath}'"), csv_err)
+                })?;
             }
         }
 
+        // The report goes where the profile asks, rather than next to the rest.
+        files.extend(
+            [&self.report.json, &self.report.csv, &self.report.series_csv]
+                .into_iter()
+                .flatten()
+                .map(|path| path.display().to_string()),
+        );
+        Manifest::new(
+            run_id,
+            wall_start.to_rfc3339(),
+            files,
+            self.cohorts
+                .iter()
+                .map(|(name, _)| NamedId::new(name))
+                .collect(),
+            phase_timings
+                .iter()
+                .map(|(name, _)| NamedId::new(name))
+                .collect(),
+        )
+        .write(&format!("orca-run-{}-schema.json", now.to_rfc3339()))?;
+
         debug!("Ended statistics collector");
 
+        if goal_results.iter().any(|goal| !goal.passed) {
+            return Err(Error::LatencyGoalMissed);
+        }
+
         Ok(())
     }
 }
 
 #[derive(Serialize)]
 struct SerializableEventRecord {
+    run_id: String,
+    cohort_id: String,
+    cohort: String,
+    phase_id: Option<String>,
+    phase: Option<String>,
     time_from_start_ms: u128,
     duration_ms: u128,
-    details: EventDetail,
+    event_id: &'static str,
+    details: String,
+    // The event an error, a rejection or a timeout would have been recorded as, had it
+    // succeeded.
+    error_event_id: Option<&'static str>,
+    error_event: Option<String>,
 }
 
 impl SerializableEventRecord {
-    fn from_event_record(event_record: &EventRecord, test_start: Instant) -> Self {
+    fn from_event_record(
+        run_id: &str,
+        event_record: &EventRecord,
+        cohort: String,
+        phase: Option<&str>,
+        test_start: Instant,
+    ) -> Self {
+        let failed_event = match &event_record.details {
+            EventDetail::Error(failure)
+            | EventDetail::Rejected(failure)
+            | EventDetail::Timeout(failure) => Some(&failure.event),
+            _ => None,
+        };
         SerializableEventRecord {
+            run_id: run_id.to_string(),
+            cohort_id: schema::identifier(&cohort),
+            cohort,
+            phase_id: phase.map(schema::identifier),
+            phase: phase.map(str::to_string),
             time_from_start_ms: event_record.start.duration_since(test_start).as_millis(),
             duration_ms: event_record.duration.as_millis(),
-            details: event_record.details.clone(),
+            event_id: event_record.details.id(),
+            details: event_record.details.name(),
+            error_event_id: failed_event.map(EventDetail::id),
+            error_event: failed_event.map(|event| format!("{event:?}")),
+        }
+    }
+}
+
+/// The events of a slice of the actors, such as a cohort or the members of a group.
+#[derive(Default)]
+struct BreakdownTimings {
+    readop_times: Vec<f64>,
+    writeop_times: Vec<f64>,
+    auth_times: Vec<f64>,
+    error_events: usize,
+    rejected_events: usize,
+    conflict_events: usize,
+    timeout_events: usize,
+}
+
+impl BreakdownTimings {
+    fn push(&mut self, op_kind: &OpKind, duration: f64) {
+        match op_kind {
+            OpKind::ReadOp => self.readop_times.push(duration),
+            OpKind::WriteOp => self.writeop_times.push(duration),
+            OpKind::Auth => self.auth_times.push(duration),
+            OpKind::Error => self.error_events += 1,
+            OpKind::Rejected => self.rejected_events += 1,
+            OpKind::Conflict => self.conflict_events += 1,
+            OpKind::Timeout => self.timeout_events += 1,
+            OpKind::ReplicationDelay
+            | OpKind::AuthStep(_)
+            | OpKind::AuthStepError(_)
+            | OpKind::Keepalive
+            | OpKind::ScheduleLag
+            | OpKind::PermitWait
+            | OpKind::Backoff { .. }
+            | OpKind::Background(_)
+            | OpKind::DataSize(_)
+            | OpKind::Attempts(_)
+            | OpKind::Transition(_) => {}
+        }
+    }
+}
+
+// Computes the value a threshold is checked against for a window, if there is one.
+type WindowValue = fn(&BreakdownTimings) -> Option<f64>;
+
+// Computes the value the targets are compared on, if a target has one.
+type TargetValue = fn(&BreakdownStats) -> Option<f64>;
+
+/// A time window of the test during which one of the thresholds was breached.
+#[derive(Serialize)]
+struct ThresholdBreach {
+    threshold: &'static str,
+    start_time: String,
+    from_start_s: u64,
+    to_start_s: u64,
+    worst: f64,
+    limit: f64,
+}
+
+impl ThresholdBreach {
+    fn find(
+        thresholds: &ThresholdProperties,
+        window_timings: &BTreeMap<u64, BreakdownTimings>,
+        wall_start: DateTime<Local>,
+    ) -> Vec<Self> {
+        // Each threshold is checked against a value computed for every window. The latencies
+        // are compared in milliseconds, as they are configured.
+        let checks: [(&'static str, Option<f64>, WindowValue); 3] = [
+            (
+                "read latency",
+                thresholds.read_latency_ms.map(|limit| limit as f64),
+                |timings| percentile(&timings.readop_times, 0.95).map(|p95| p95 * 1000.),
+            ),
+            (
+                "write latency",
+                thresholds.write_latency_ms.map(|limit| limit as f64),
+                |timings| percentile(&timings.writeop_times, 0.95).map(|p95| p95 * 1000.),
+            ),
+            ("error rate", thresholds.error_rate, |timings| {
+                let events = timings.readop_times.len()
+                    + timings.writeop_times.len()
+                    + timings.auth_times.len()
+                    + timings.error_events
+                    + timings.rejected_events
+                    + timings.conflict_events
+                    + timings.timeout_events;
+                // Conflicting and timed out actions failed just like ones that returned an error.
+                // A rejection by the access policy is what the server is meant to do.
+                let failures =
+                    timings.error_events + timings.conflict_events + timings.timeout_events;
+                (events > 0).then(|| failures as f64 / events as f64)
+            }),
+        ];
+
+        let mut breaches = Vec::new();
+        for (threshold, limit, value_of) in checks {
+            let Some(limit) = limit else {
+                continue;
+            };
+
+            let mut current: Option<ThresholdBreach> = None;
+            for (window, timings) in window_timings.iter() {
+                let from_start_s = window * thresholds.window;
+                let to_start_s = from_start_s + thresholds.window;
+
+                match value_of(timings).filter(|value| *value > limit) {
+                    // Extend the breach if it carries on from the previous window.
+                    Some(value) => match current.as_mut() {
+                        Some(breach) if breach.to_start_s == from_start_s => {
+                            breach.to_start_s = to_start_s;
+                            breach.worst = breach.worst.max(value);
+                        }
+                        _ => {
+                            breaches.extend(current.take());
+                            let start_time =
+                                wall_start + chrono::Duration::seconds(from_start_s as i64);
+                            current = Some(ThresholdBreach {
+                                threshold,
+                                start_time: start_time.to_rfc3339(),
+                                from_start_s,
+                                to_start_s,
+                                worst: value,
+                                limit,
+                            });
+                        }
+                    },
+                    None => breaches.extend(current.take()),
+                }
+            }
+            breaches.extend(current);
+        }
+
+        breaches.sort_by_key(|breach| breach.from_start_s);
+        breaches
+    }
+}
+
+pub(crate) fn percentile(op_times: &[f64], quantile: f64) -> Option<f64> {
+    if op_times.is_empty() {
+        return None;
+    }
+    let mut op_times = op_times.to_vec();
+    op_times.sort_by(f64::total_cmp);
+    let rank = ((op_times.len() as f64) * quantile).ceil() as usize;
+    op_times.get(rank.saturating_sub(1)).copied()
+}
+
+// Failures are grouped by the event that failed, the class of error and the HTTP status.
+type FailureKey = (&'static str, Cow<'static, str>, Option<u16>);
+
+fn failure_key(failure: &Failure) -> FailureKey {
+    (failure.event.id(), failure.class.clone(), failure.status)
+}
+
+/// The failures of the run that share an event, error class and status, with the message
+/// of the first of them as an example.
+#[derive(Serialize)]
+struct FailureGroup {
+    run_id: String,
+    event_id: &'static str,
+    event: String,
+    class: Cow<'static, str>,
+    status: Option<u16>,
+    count: usize,
+    example: String,
+}
+
+impl FailureGroup {
+    fn new(run_id: &str, failure: &Failure) -> Self {
+        FailureGroup {
+            run_id: run_id.to_string(),
+            event_id: failure.event.id(),
+            event: format!("{:?}", failure.event),
+            class: failure.class.clone(),
+            status: failure.status,
+            count: 0,
+            example: failure.message.clone(),
+        }
+    }
+}
+
+/// A periodic summary of the events of the last interval, which shows that a long headless
+/// run is still alive.
+struct Heartbeat {
+    start: Instant,
+    interval: Duration,
+    last_beat: Instant,
+    clock: SharedClock,
+    events: usize,
+    errors: usize,
+    interval_events: usize,
+    interval_times: Vec<f64>,
+}
+
+impl Heartbeat {
+    fn new(start: Instant, interval: Duration, clock: SharedClock) -> Self {
+        Heartbeat {
+            start,
+            interval,
+            last_beat: clock.now(),
+            clock,
+            events: 0,
+            errors: 0,
+            interval_events: 0,
+            interval_times: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, event_record: &EventRecord) {
+        if event_record.start < self.start {
+            return;
+        }
+        match &event_record.details {
+            EventDetail::Error(_) | EventDetail::LoginConflict | EventDetail::Timeout(_) => {
+                self.errors += 1
+            }
+            // These aren't requests made by the actors.
+            EventDetail::GroupReplicationDelay
+            | EventDetail::ScheduleLag
+            | EventDetail::PermitWait
+            | EventDetail::BackoffRespected
+            | EventDetail::BackoffViolated
+            | EventDetail::Import
+            | EventDetail::Backup
+            | EventDetail::DataSize(_)
+            | EventDetail::Attempts(_)
+            | EventDetail::Transition(_) => return,
+            _ => self
+                .interval_times
+                .push(event_record.duration.as_secs_f64()),
+        }
+        self.events += 1;
+        self.interval_events += 1;
+    }
+
+    fn beat_if_due(&mut self) -> Option<String> {
+        let now = self.clock.now();
+        let since_last_beat = now.saturating_duration_since(self.last_beat);
+        if since_last_beat < self.interval {
+            return None;
+        }
+
+        let line = format!(
+            "heartbeat: {}s elapsed, {} events, {:.1} rps, {} errors, 95% of the last {}s: {}",
+            now.saturating_duration_since(self.start).as_secs(),
+            self.events,
+            self.interval_events as f64 / since_last_beat.as_secs_f64(),
+            self.errors,
+            since_last_beat.as_secs(),
+            percentile(&self.interval_times, 0.95)
+                .map(|p95| format!("{:.1} ms", p95 * 1000.))
+                .unwrap_or_else(|| "-".to_string())
+        );
+
+        self.last_beat = now;
+        self.interval_events = 0;
+        self.interval_times.clear();
+        Some(line)
+    }
+}
+
+// The window of the test an event falls in, or None if it's outside of the measured part of
+// the test, such as during the warmup.
+fn measured_window(event_start: Instant, start: Instant, end: Instant, window: u64) -> Option<u64> {
+    if event_start < start || event_start > end {
+        return None;
+    }
+    Some(event_start.duration_since(start).as_secs() / window)
+}
+
+/// How closely the actions started in a window of the test kept to their intended schedule.
+#[derive(Default, Serialize)]
+struct OfferedLoadWindow {
+    from_start_s: u64,
+    scheduled: usize,
+    late: usize,
+    max_lag_ms: f64,
+}
+
+impl OfferedLoadWindow {
+    fn push(&mut self, lag_ms: f64) {
+        self.scheduled += 1;
+        if lag_ms > LATE_ACTION_TOLERANCE_MS {
+            self.late += 1;
+        }
+        self.max_lag_ms = self.max_lag_ms.max(lag_ms);
+    }
+}
+
+/// Whether the actors of a cohort waited as long as the server asked them to.
+#[derive(Default)]
+struct BackoffCompliance {
+    respected: usize,
+    violated: usize,
+    // Added up over the violations.
+    early_s: f64,
+}
+
+/// How often a model went through an action, and how long it waited before it.
+#[derive(Default)]
+struct TransitionMix {
+    count: usize,
+    dwell_s: f64,
+}
+
+#[derive(Serialize)]
+struct TransitionMixRow {
+    run_id: String,
+    model: String,
+    action_id: &'static str,
+    action: String,
+    count: usize,
+    // Out of all the actions of the model.
+    share: f64,
+    // What the share would be if the model followed its configured mix exactly.
+    configured_share: f64,
+    mean_dwell_s: Option<f64>,
+}
+
+/// The latency of one step of a flow, such as the OAuth2 authorisation code flow or a login.
+#[derive(Serialize)]
+struct StepRow {
+    step: &'static str,
+    events: usize,
+    errors: usize,
+    mean_ms: f64,
+    p95_ms: Option<f64>,
+}
+
+struct RetryTimings {
+    event: String,
+    requests: usize,
+    retried: usize,
+    gave_up: usize,
+    first_attempt_times: Vec<f64>,
+    // From the start of the first attempt, of the requests that succeeded in the end.
+    final_success_times: Vec<f64>,
+}
+
+impl RetryTimings {
+    fn new(event: &EventDetail) -> Self {
+        RetryTimings {
+            event: format!("{event:?}"),
+            requests: 0,
+            retried: 0,
+            gave_up: 0,
+            first_attempt_times: Vec::new(),
+            final_success_times: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, sample: &AttemptsSample, duration: f64) {
+        self.requests += 1;
+        if sample.attempts > 1 {
+            self.retried += 1;
+        }
+        self.first_attempt_times
+            .push(sample.first_attempt.as_secs_f64());
+        if sample.succeeded {
+            self.final_success_times.push(duration);
+        } else {
+            self.gave_up += 1;
+        }
+    }
+}
+
+/// The latency of the first attempt of the requests of an event, next to the latency they
+/// had once retried to success. Retries can hide a slower server behind the latter.
+#[derive(Serialize)]
+struct RetryRow {
+    run_id: String,
+    event_id: &'static str,
+    event: String,
+    requests: usize,
+    retried: usize,
+    gave_up: usize,
+    first_attempt_p50_ms: Option<f64>,
+    first_attempt_p95_ms: Option<f64>,
+    first_attempt_p99_ms: Option<f64>,
+    final_success_p50_ms: Option<f64>,
+    final_success_p95_ms: Option<f64>,
+    final_success_p99_ms: Option<f64>,
+}
+
+impl RetryRow {
+    fn new(run_id: String, event_id: &'static str, timings: &RetryTimings) -> Self {
+        let to_ms = |op_times: &[f64], quantile| {
+            percentile(op_times, quantile).map(|seconds| seconds * 1000.)
+        };
+        RetryRow {
+            run_id,
+            event_id,
+            event: timings.event.clone(),
+            requests: timings.requests,
+            retried: timings.retried,
+            gave_up: timings.gave_up,
+            first_attempt_p50_ms: to_ms(&timings.first_attempt_times, 0.5),
+            first_attempt_p95_ms: to_ms(&timings.first_attempt_times, 0.95),
+            first_attempt_p99_ms: to_ms(&timings.first_attempt_times, 0.99),
+            final_success_p50_ms: to_ms(&timings.final_success_times, 0.5),
+            final_success_p95_ms: to_ms(&timings.final_success_times, 0.95),
+            final_success_p99_ms: to_ms(&timings.final_success_times, 0.99),
+        }
+    }
+}
+
+/// The requests that started during a maintenance window.
+struct MaintenanceTally {
+    name: String,
+    from: Duration,
+    to: Duration,
+    requests: usize,
+    errors: usize,
+}
+
+impl MaintenanceTally {
+    fn new(window: &MaintenanceWindowProperties) -> Self {
+        MaintenanceTally {
+            name: window.name.clone(),
+            from: Duration::from_secs(window.at),
+            to: Duration::from_secs(window.at + window.duration),
+            requests: 0,
+            errors: 0,
+        }
+    }
+
+    fn contains(&self, from_start: Duration) -> bool {
+        (self.from..self.to).contains(&from_start)
+    }
+
+    /// Count the event if it's a request, and return whether it was.
+    fn push(&mut self, details: &EventDetail) -> bool {
+        match details {
+            EventDetail::Error(_) | EventDetail::LoginConflict | EventDetail::Timeout(_) => {
+                self.errors += 1
+            }
+            // These aren't requests made by the actors.
+            EventDetail::GroupReplicationDelay
+            | EventDetail::ScheduleLag
+            | EventDetail::PermitWait
+            | EventDetail::BackoffRespected
+            | EventDetail::BackoffViolated
+            | EventDetail::Import
+            | EventDetail::Backup
+            | EventDetail::DataSize(_)
+            | EventDetail::Attempts(_)
+            | EventDetail::Transition(_) => return false,
+            _ => {}
+        }
+        self.requests += 1;
+        true
+    }
+}
+
+/// The requests of a single person over the measured window.
+#[derive(Default)]
+struct PersonActivity {
+    events: usize,
+    errors: usize,
+    // Of the requests that didn't fail.
+    timed: usize,
+    total_s: f64,
+}
+
+impl PersonActivity {
+    fn push(&mut self, op_kind: &OpKind, duration: f64) {
+        match op_kind {
+            OpKind::ReadOp | OpKind::WriteOp | OpKind::Auth | OpKind::Rejected => {
+                self.timed += 1;
+                self.total_s += duration;
+            }
+            OpKind::Error | OpKind::Conflict | OpKind::Timeout => self.errors += 1,
+            OpKind::ReplicationDelay
+            | OpKind::AuthStep(_)
+            | OpKind::AuthStepError(_)
+            | OpKind::Keepalive
+            | OpKind::ScheduleLag
+            | OpKind::PermitWait
+            | OpKind::Backoff { .. }
+            | OpKind::Background(_)
+            | OpKind::DataSize(_)
+            | OpKind::Attempts(_)
+            | OpKind::Transition(_) => return,
+        }
+        self.events += 1;
+    }
+}
+
+/// A person that stood out from the others, either by how many requests they made or by
+/// how many of those failed.
+#[derive(Serialize)]
+struct PersonRow {
+    ranking: &'static str,
+    person: String,
+    events: usize,
+    errors: usize,
+    mean_ms: Option<f64>,
+}
+
+impl PersonRow {
+    fn new(ranking: &'static str, person: &str, activity: &PersonActivity) -> Self {
+        PersonRow {
+            ranking,
+            person: person.to_string(),
+            events: activity.events,
+            errors: activity.errors,
+            mean_ms: (activity.timed > 0).then(|| activity.total_s * 1000. / activity.timed as f64),
+        }
+    }
+
+    /// The `top` most active persons, followed by the `top` persons with the most errors.
+    fn top(activity: &BTreeMap<Arc<str>, PersonActivity>, top: usize) -> Vec<Self> {
+        let mut by_events = activity.iter().collect::<Vec<_>>();
+        by_events.sort_by_key(|(_, activity)| Reverse(activity.events));
+        let mut by_errors = activity
+            .iter()
+            .filter(|(_, activity)| activity.errors > 0)
+            .collect::<Vec<_>>();
+        by_errors.sort_by_key(|(_, activity)| Reverse(activity.errors));
+
+        by_events
+            .into_iter()
+            .take(top)
+            .map(|(person, activity)| PersonRow::new("most active", person, activity))
+            .chain(
+                by_errors
+                    .into_iter()
+                    .take(top)
+                    .map(|(person, activity)| PersonRow::new("most errors", person, activity)),
+            )
+            .collect()
+    }
+}
+
+fn step_rows(
+    times: &BTreeMap<&'static str, Vec<f64>>,
+    errors: &BTreeMap<&'static str, usize>,
+) -> Vec<StepRow> {
+    // A step may have failed every time it was attempted.
+    let steps: BTreeSet<&'static str> = times.keys().chain(errors.keys()).copied().collect();
+    steps
+        .into_iter()
+        .map(|step| {
+            let times = times.get(step).map(Vec::as_slice).unwrap_or_default();
+            StepRow {
+                step,
+                events: times.len(),
+                errors: errors.get(step).copied().unwrap_or_default(),
+                mean_ms: times.iter().sum::<f64>() / times.len().max(1) as f64 * 1000.,
+                p95_ms: percentile(times, 0.95).map(|p95| p95 * 1000.),
+            }
+        })
+        .collect()
+}
+
+fn oauth2_step(details: &EventDetail) -> Option<&'static str> {
+    match details {
+        EventDetail::Oauth2Authorise => Some("authorise"),
+        EventDetail::Oauth2Token => Some("token"),
+        EventDetail::Oauth2Userinfo => Some("userinfo"),
+        EventDetail::Oauth2Discovery => Some("discovery"),
+        EventDetail::Oauth2Jwks => Some("jwks"),
+        _ => None,
+    }
+}
+
+/// How much data the server held at a point of the test, and how fast reads were then.
+#[derive(Serialize)]
+struct DataSizeRow {
+    from_start_s: u64,
+    persons: Option<u64>,
+    groups: Option<u64>,
+    db_bytes: Option<u64>,
+    sessions: Option<u64>,
+    readop_95_ms: Option<f64>,
+}
+
+// A part that wasn't sampled both times has no trend.
+fn trend(first: Option<u64>, last: Option<u64>) -> String {
+    match (first, last) {
+        (Some(first), Some(last)) => {
+            format!("{first} -> {last} ({:+})", last as i64 - first as i64)
+        }
+        _ => "-".to_string(),
+    }
+}
+
+/// How the latency of an interactive event type while a background task ran, such as an
+/// import or a backup, compares with the rest of the test.
+struct BackgroundImpact {
+    event_type: &'static str,
+    during_95_ms: Option<f64>,
+    outside_95_ms: Option<f64>,
+}
+
+impl BackgroundImpact {
+    // The events are only known by the window they fall in, so a window that overlaps the
+    // task at all counts as during it.
+    fn evaluate(
+        from_start_s: f64,
+        to_start_s: f64,
+        window_timings: &BTreeMap<u64, BreakdownTimings>,
+        window: u64,
+    ) -> Vec<Self> {
+        let mut during = BreakdownTimings::default();
+        let mut outside = BreakdownTimings::default();
+        for (index, timings) in window_timings.iter() {
+            let window_start_s = (index * window) as f64;
+            let window_end_s = window_start_s + window as f64;
+            let target = if window_start_s < to_start_s && window_end_s > from_start_s {
+                &mut during
+            } else {
+                &mut outside
+            };
+            target.readop_times.extend_from_slice(&timings.readop_times);
+            target.auth_times.extend_from_slice(&timings.auth_times);
+        }
+
+        let to_ms = |op_times: &[f64]| percentile(op_times, 0.95).map(|p95| p95 * 1000.);
+        vec![
+            BackgroundImpact {
+                event_type: "read",
+                during_95_ms: to_ms(&during.readop_times),
+                outside_95_ms: to_ms(&outside.readop_times),
+            },
+            BackgroundImpact {
+                event_type: "auth",
+                during_95_ms: to_ms(&during.auth_times),
+                outside_95_ms: to_ms(&outside.auth_times),
+            },
+        ]
+    }
+}
+
+/// The outcome of one of the latency goals over the whole run.
+struct LatencyGoalResult {
+    event_type: &'static str,
+    p99_ms: Option<f64>,
+    goal_ms: u64,
+    passed: bool,
+}
+
+impl LatencyGoalResult {
+    fn evaluate(
+        goals: &LatencyGoalProperties,
+        auth_times: &[f64],
+        readop_times: &[f64],
+        writeop_times: &[f64],
+    ) -> Vec<Self> {
+        [
+            ("auth", goals.auth_p99_ms, auth_times),
+            ("read", goals.read_p99_ms, readop_times),
+            ("write", goals.write_p99_ms, writeop_times),
+        ]
+        .into_iter()
+        .filter_map(|(event_type, goal_ms, op_times)| {
+            let goal_ms = goal_ms?;
+            let p99_ms = percentile(op_times, 0.99).map(|p99| p99 * 1000.);
+            // Without any events there's nothing the goal could have been missed by.
+            let passed = p99_ms.map_or(true, |p99_ms| p99_ms <= goal_ms as f64);
+            Some(LatencyGoalResult {
+                event_type,
+                p99_ms,
+                goal_ms,
+                passed,
+            })
+        })
+        .collect()
+    }
+}
+
+#[derive(Serialize)]
+struct BreakdownStats {
+    breakdown: &'static str,
+    id: String,
+    name: String,
+    read_events: usize,
+    read_mean: f64,
+    read_95: f64,
+    write_events: usize,
+    write_mean: f64,
+    write_95: f64,
+    auth_events: usize,
+    error_events: usize,
+    rejected_events: usize,
+    conflict_events: usize,
+    timeout_events: usize,
+}
+
+impl BreakdownStats {
+    fn events(&self) -> usize {
+        self.read_events
+            + self.write_events
+            + self.auth_events
+            + self.error_events
+            + self.rejected_events
+            + self.conflict_events
+            + self.timeout_events
+    }
+
+    // The cohorts are named with their model, which isn't part of their identifier.
+    fn new(breakdown: &'static str, id: &str, name: String, timings: &BreakdownTimings) -> Self {
+        let (read_events, read_mean, _, _, read_95) =
+            StatsContainer::compute_stats_from_timings_vec(&timings.readop_times);
+        let (write_events, write_mean, _, _, write_95) =
+            StatsContainer::compute_stats_from_timings_vec(&timings.writeop_times);
+
+        BreakdownStats {
+            breakdown,
+            id: schema::identifier(id),
+            name,
+            read_events,
+            read_mean,
+            read_95,
+            write_events,
+            write_mean,
+            write_95,
+            auth_events: timings.auth_times.len(),
+            error_events: timings.error_events,
+            rejected_events: timings.rejected_events,
+            conflict_events: timings.conflict_events,
+            timeout_events: timings.timeout_events,
         }
     }
 }
 
 #[derive(Serialize)]
 struct StatsContainer {
+    run_id: String,
     node_count: usize,
     person_count: usize,
     group_count: usize,
@@ -271,6 +2016,7 @@ type Percentile95 = f64;
 
 impl StatsContainer {
     fn new(
+        run_id: String,
         readop_times: &Vec<f64>,
         writeop_times: &Vec<f64>,
         replication_delays: &Vec<f64>,
@@ -293,6 +2039,7 @@ impl StatsContainer {
         ) = Self::compute_stats_from_timings_vec(replication_delays);
 
         StatsContainer {
+            run_id,
             person_count,
             group_count,
             node_count,
@@ -330,3 +2077,257 @@ impl StatsContainer {
         }
     }
 }
+
+/// A target that did noticeably worse than the others it shares the load with, such as an
+/// unhealthy replica behind a load balancer.
+struct TargetImbalance {
+    target: String,
+    measure: &'static str,
+    value: f64,
+    median: f64,
+}
+
+// Compare each target to the median of the targets, on every measure they have events for.
+fn target_imbalances(targets: &[BreakdownStats]) -> Vec<TargetImbalance> {
+    let measures: [(&'static str, TargetValue, f64); 3] = [
+        (
+            "read 95%",
+            |stats| (stats.read_events > 0).then_some(stats.read_95),
+            0.,
+        ),
+        (
+            "write 95%",
+            |stats| (stats.write_events > 0).then_some(stats.write_95),
+            0.,
+        ),
+        (
+            "error rate",
+            |stats| {
+                (stats.events() > 0).then(|| {
+                    (stats.error_events + stats.timeout_events) as f64 / stats.events() as f64
+                })
+            },
+            TARGET_IMBALANCE_MIN_ERROR_RATE,
+        ),
+    ];
+
+    let mut imbalances = Vec::new();
+    for (measure, value_of, floor) in measures {
+        let values = targets
+            .iter()
+            .filter_map(|stats| value_of(stats).map(|value| (stats, value)))
+            .collect::<Vec<_>>();
+        if values.len() < 2 {
+            continue;
+        }
+        let Some(median) = percentile(
+            &values.iter().map(|(_, value)| *value).collect::<Vec<_>>(),
+            0.5,
+        ) else {
+            continue;
+        };
+        imbalances.extend(
+            values
+                .into_iter()
+                .filter(|(_, value)| *value > floor && *value > median * TARGET_IMBALANCE_FACTOR)
+                .map(|(stats, value)| TargetImbalance {
+                    target: stats.name.clone(),
+                    measure,
+                    value,
+                    median,
+                }),
+        );
+    }
+    imbalances
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        measured_window, target_imbalances, BreakdownStats, BreakdownTimings, Heartbeat,
+        MaintenanceTally, OpKind, PersonActivity, PersonRow, RetryRow, RetryTimings,
+    };
+    use crate::clock::{Clock, ManualClock};
+    use crate::model::TransitionAction;
+    use crate::profile::MaintenanceWindowProperties;
+    use crate::run::{AttemptsSample, EventDetail, EventRecord, Failure};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_maintenance_window_takes_only_its_requests() {
+        let mut tally = MaintenanceTally::new(&MaintenanceWindowProperties {
+            name: "failover".to_string(),
+            at: 600,
+            duration: 300,
+        });
+        assert!(!tally.contains(Duration::from_secs(599)));
+        assert!(tally.contains(Duration::from_secs(600)));
+        assert!(!tally.contains(Duration::from_secs(900)));
+
+        assert!(tally.push(&EventDetail::Error(Failure::new(
+            EventDetail::Login,
+            "Transport",
+            "connection refused"
+        ))));
+        assert!(tally.push(&EventDetail::PersonWhoami));
+        // The rest of the results still see how the actors behaved.
+        assert!(!tally.push(&EventDetail::ScheduleLag));
+        assert_eq!((tally.requests, tally.errors), (2, 1));
+    }
+
+    #[test]
+    fn test_top_persons_single_out_the_pathological_account() {
+        let mut activity: BTreeMap<Arc<str>, PersonActivity> = BTreeMap::new();
+        for index in 0..10 {
+            let person = activity
+                .entry(Arc::from(format!("person_{index}")))
+                .or_default();
+            for _ in 0..10 {
+                person.push(&OpKind::ReadOp, 0.010);
+            }
+        }
+        let pathological = activity.entry(Arc::from("person_3")).or_default();
+        for _ in 0..40 {
+            pathological.push(&OpKind::Error, 0.500);
+        }
+        activity
+            .entry(Arc::from("person_5"))
+            .or_default()
+            .push(&OpKind::Transition(TransitionAction::Login), 1.);
+
+        let rows = PersonRow::top(&activity, 2);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].person, "person_3");
+        assert_eq!(rows[0].events, 50);
+        // The errors don't count towards the latency of the requests that went through.
+        assert!(rows[0]
+            .mean_ms
+            .is_some_and(|mean_ms| (mean_ms - 10.).abs() < 0.001));
+        assert_eq!(rows[1].events, 10);
+        assert_eq!(
+            (rows[2].ranking, rows[2].person.as_str()),
+            ("most errors", "person_3")
+        );
+    }
+
+    #[test]
+    fn test_measured_window_excludes_warmup() {
+        let clock = ManualClock::new();
+        let warmup_event = clock.now();
+        clock.advance(Duration::from_secs(30));
+        let start = clock.now();
+        let end = start + Duration::from_secs(100);
+
+        assert_eq!(measured_window(warmup_event, start, end, 10), None);
+        assert_eq!(measured_window(start, start, end, 10), Some(0));
+        assert_eq!(
+            measured_window(start + Duration::from_secs(25), start, end, 10),
+            Some(2)
+        );
+        assert_eq!(
+            measured_window(end + Duration::from_secs(1), start, end, 10),
+            None
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_only_beats_once_the_interval_passed() {
+        let clock = ManualClock::new();
+        let mut heartbeat = Heartbeat::new(clock.now(), Duration::from_secs(60), clock.clone());
+
+        heartbeat.push(&EventRecord {
+            start: clock.now(),
+            duration: Duration::from_millis(20),
+            details: EventDetail::PersonWhoami,
+        });
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(heartbeat.beat_if_due(), None);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            heartbeat.beat_if_due().as_deref(),
+            Some(
+                "heartbeat: 60s elapsed, 1 events, 0.0 rps, 0 errors, 95% of the last 60s: 20.0 ms"
+            )
+        );
+        assert_eq!(heartbeat.beat_if_due(), None);
+    }
+
+    #[test]
+    fn test_target_imbalances_flag_the_unhealthy_replica() {
+        let target = |name: &str, read_95, error_events| BreakdownStats {
+            breakdown: "target",
+            id: name.to_string(),
+            name: name.to_string(),
+            read_events: 100,
+            read_mean: read_95 / 2.,
+            read_95,
+            write_events: 0,
+            write_mean: 0.,
+            write_95: 0.,
+            auth_events: 0,
+            error_events,
+            rejected_events: 0,
+            conflict_events: 0,
+            timeout_events: 0,
+        };
+        let targets = [
+            target("https://idm-1", 0.010, 0),
+            target("https://idm-2", 0.012, 1),
+            target("https://idm-3", 0.050, 20),
+        ];
+
+        let imbalances = target_imbalances(&targets);
+        assert_eq!(imbalances.len(), 2);
+        assert!(imbalances
+            .iter()
+            .all(|imbalance| imbalance.target == "https://idm-3"));
+        assert_eq!(imbalances[0].measure, "read 95%");
+        assert_eq!(imbalances[1].measure, "error rate");
+    }
+
+    #[test]
+    fn test_login_steps_and_keepalives_stay_out_of_the_aggregates() {
+        let mut timings = BreakdownTimings::default();
+        for details in [
+            EventDetail::AuthInit,
+            EventDetail::AuthBegin,
+            EventDetail::AuthCredential,
+            EventDetail::Login,
+            EventDetail::Keepalive,
+        ] {
+            timings.push(&OpKind::from(details), 0.010);
+        }
+        assert_eq!(timings.auth_times.len(), 1);
+        assert!(timings.readop_times.is_empty());
+    }
+
+    #[test]
+    fn test_retries_keep_the_first_attempt_apart() {
+        let mut timings = RetryTimings::new(&EventDetail::PersonWhoami);
+        let sample = |attempts, first_attempt_ms, succeeded| AttemptsSample {
+            event: EventDetail::PersonWhoami,
+            attempts,
+            first_attempt: Duration::from_millis(first_attempt_ms),
+            succeeded,
+        };
+        timings.push(&sample(1, 10, true), 0.010);
+        // A slow first attempt that failed, and was retried to a fast success.
+        timings.push(&sample(2, 900, true), 0.920);
+        timings.push(&sample(3, 800, false), 1.700);
+
+        let row = RetryRow::new("run".to_string(), "person_whoami", &timings);
+        assert_eq!(
+            (row.event_id, row.event.as_str()),
+            ("person_whoami", "PersonWhoami")
+        );
+        assert_eq!(row.requests, 3);
+        assert_eq!(row.retried, 2);
+        assert_eq!(row.gave_up, 1);
+        assert_eq!(row.first_attempt_p99_ms, Some(900.));
+        assert_eq!(row.final_success_p50_ms, Some(10.));
+        assert_eq!(row.final_success_p99_ms, Some(920.));
+    }
+}
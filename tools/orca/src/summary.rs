@@ -0,0 +1,197 @@
+use crate::error::Error;
+use crate::stats::RunSummary;
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::path::Path;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SummaryFormat {
+    /// The summary as is, which a later run can be compared against.
+    #[default]
+    Json,
+    /// A table of the headline numbers, compared to the baseline, to paste into the
+    /// discussion of a merge request.
+    Markdown,
+}
+
+/// Load the summary of an earlier run, written with the JSON format.
+pub fn read_baseline(path: &Path) -> Result<RunSummary, Error> {
+    let file = File::open(path)
+        .map_err(|io_err| Error::file("open the baseline summary", path, io_err))?;
+    serde_json::from_reader(file).map_err(|json_err| {
+        Error::json(
+            format!("read the baseline summary '{}'", path.display()),
+            json_err,
+        )
+    })
+}
+
+pub fn write(
+    path: &Path,
+    format: SummaryFormat,
+    summary: &RunSummary,
+    baseline: Option<&RunSummary>,
+) -> Result<(), Error> {
+    let contents = match format {
+        SummaryFormat::Json => serde_json::to_string_pretty(summary)
+            .map_err(|json_err| Error::json("serialise the summary", json_err))?,
+        SummaryFormat::Markdown => markdown(summary, baseline),
+    };
+
+    info!("Now saving the summary as '{}'", path.display());
+    std::fs::write(path, contents).map_err(|io_err| Error::file("write the summary", path, io_err))
+}
+
+// The name of each row, and its value in a summary.
+type Metric = (&'static str, fn(&RunSummary) -> Option<f64>);
+
+const METRICS: [Metric; 9] = [
+    ("Persons", |summary| Some(summary.person_count as f64)),
+    ("Read events", |summary| Some(summary.read_events as f64)),
+    ("Read p95 (ms)", |summary| summary.read_95_ms),
+    ("Write events", |summary| Some(summary.write_events as f64)),
+    ("Write p95 (ms)", |summary| summary.write_95_ms),
+    ("Auth events", |summary| Some(summary.auth_events as f64)),
+    ("Auth p95 (ms)", |summary| summary.auth_95_ms),
+    ("Errors", |summary| Some(summary.error_events as f64)),
+    ("Rejected", |summary| Some(summary.rejected_events as f64)),
+];
+
+// The latencies a regression is looked for in.
+const LATENCIES: [Metric; 3] = [METRICS[2], METRICS[4], METRICS[6]];
+
+fn error_rate(summary: &RunSummary) -> f64 {
+    let events = summary.read_events
+        + summary.write_events
+        + summary.auth_events
+        + summary.error_events
+        + summary.rejected_events;
+    summary.error_events as f64 / events.max(1) as f64
+}
+
+/// What got worse than in the baseline by more than `max_regression_pct` percent, out of the
+/// 95th percentile latencies and the share of the events that failed.
+pub fn regressions(
+    summary: &RunSummary,
+    baseline: &RunSummary,
+    max_regression_pct: f64,
+) -> Vec<String> {
+    let limit = 1. + max_regression_pct / 100.;
+    let mut regressions = Vec::new();
+    for (name, value) in LATENCIES {
+        if let (Some(current), Some(previous)) = (value(summary), value(baseline)) {
+            if current > previous * limit {
+                regressions.push(format!(
+                    "{name} went from {} to {}",
+                    format_value(Some(previous)),
+                    format_value(Some(current))
+                ));
+            }
+        }
+    }
+    // Any error is a regression from a baseline that had none.
+    let (current, previous) = (error_rate(summary), error_rate(baseline));
+    if current > 0. && current > previous * limit {
+        regressions.push(format!(
+            "The error rate went from {:.2}% to {:.2}%",
+            previous * 100.,
+            current * 100.
+        ));
+    }
+    regressions
+}
+
+fn format_value(value: Option<f64>) -> String {
+    match value {
+        // Counts are whole, and latencies don't need more than this.
+        Some(value) if value.fract() == 0. => format!("{value:.0}"),
+        Some(value) => format!("{value:.2}"),
+        None => "-".to_string(),
+    }
+}
+
+fn markdown(summary: &RunSummary, baseline: Option<&RunSummary>) -> String {
+    let mut table = String::from("### Orca run summary\n\n");
+    match baseline {
+        Some(baseline) => {
+            table.push_str("| Metric | This run | Baseline | Change |\n");
+            table.push_str("| --- | ---: | ---: | ---: |\n");
+            for (name, value) in METRICS {
+                let (current, previous) = (value(summary), value(baseline));
+                let change = match (current, previous) {
+                    (Some(current), Some(previous)) if previous != 0. => {
+                        format!("{:+.1}%", (current - previous) / previous * 100.)
+                    }
+                    _ => "-".to_string(),
+                };
+                let _ = writeln!(
+                    table,
+                    "| {name} | {} | {} | {change} |",
+                    format_value(current),
+                    format_value(previous)
+                );
+            }
+        }
+        None => {
+            table.push_str("| Metric | This run |\n");
+            table.push_str("| --- | ---: |\n");
+            for (name, value) in METRICS {
+                let _ = writeln!(table, "| {name} | {} |", format_value(value(summary)));
+            }
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::{markdown, regressions};
+    use crate::stats::RunSummary;
+
+    #[test]
+    fn test_markdown_compares_to_the_baseline() {
+        let summary = |read_95_ms, error_events| RunSummary {
+            person_count: 100,
+            read_events: 1000,
+            read_95_ms,
+            write_events: 0,
+            write_95_ms: None,
+            auth_events: 10,
+            auth_95_ms: Some(20.),
+            error_events,
+            rejected_events: 0,
+            run_id: None,
+            schema_version: 0,
+        };
+        let table = markdown(&summary(Some(12.5), 3), Some(&summary(Some(10.), 0)));
+
+        assert!(table.contains("| Read p95 (ms) | 12.50 | 10 | +25.0% |"));
+        assert!(table.contains("| Write p95 (ms) | - | - | - |"));
+        // There's no change to speak of from nothing.
+        assert!(table.contains("| Errors | 3 | 0 | - |"));
+    }
+
+    #[test]
+    fn test_regressions_exceed_the_tolerance() {
+        let summary = |read_95_ms, error_events| RunSummary {
+            person_count: 100,
+            read_events: 1000,
+            read_95_ms,
+            write_events: 0,
+            write_95_ms: None,
+            auth_events: 10,
+            auth_95_ms: Some(20.),
+            error_events,
+            rejected_events: 0,
+            run_id: None,
+            schema_version: 0,
+        };
+        let baseline = summary(Some(10.), 0);
+
+        assert!(regressions(&summary(Some(10.5), 0), &baseline, 10.).is_empty());
+        let found = regressions(&summary(Some(12.), 1), &baseline, 10.);
+        assert_eq!(found.len(), 2);
+        assert!(found[0].starts_with("Read p95 (ms) went from 10 to 12"));
+    }
+}